@@ -1,16 +1,48 @@
+mod config;
+
 use std::ffi::OsString;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgAction, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueHint};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use color_eyre::{Help, SectionExt};
+use postgresfixture::cluster::LogRotation;
+use postgresfixture::registry::{self, Registry};
+use postgresfixture::version::VersionSet;
 
 /// Work with ephemeral PostgreSQL clusters.
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 pub struct Cli {
+    #[clap(flatten)]
+    pub logging: LoggingArgs,
+
+    /// Print information as JSON, for scripts and editors. This applies to
+    /// `runtimes`, `status`, `list`, `clusters`, `snapshot list`, and
+    /// `install --list`.
+    #[clap(long = "json", global = true)]
+    pub json: bool,
+
     #[clap(subcommand)]
     pub command: Commands,
 }
 
+impl Cli {
+    /// Parse the command line, taking defaults from the project's
+    /// configuration file, if there is one; see [`config::Config`]. On error,
+    /// e.g. a bad argument, this prints a message and exits, like
+    /// [`Parser::parse`].
+    pub fn parse_with_config() -> Result<Self> {
+        let mut command = Self::command();
+        if let Some(path) = config::Config::find() {
+            command = config::Config::load(&path)?.apply(command);
+        }
+        let matches = command.get_matches();
+        Ok(Self::from_arg_matches(&matches).unwrap_or_else(|err| err.exit()))
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Start a psql shell, creating and starting the cluster as necessary.
@@ -20,27 +52,73 @@ pub enum Commands {
         cluster: ClusterArgs,
 
         #[clap(flatten)]
-        database: DatabaseArgs,
+        mode: ModeArgs,
+
+        #[clap(flatten)]
+        listen: ListenArgs,
+
+        #[clap(flatten)]
+        database: DatabasesArgs,
 
         #[clap(flatten)]
         lifecycle: LifecycleArgs,
+
+        /// Run the given SQL then exit, rather than starting an interactive
+        /// shell. May be given more than once. This stops at the first error,
+        /// and exits with a non-zero status.
+        #[clap(short = 'c', long = "command", value_name = "SQL", display_order = 101)]
+        commands: Vec<String>,
+
+        /// Arguments to pass to psql, after `--`, e.g. `-- -c 'SELECT 1'
+        /// --tuples-only`.
+        #[clap(value_name = "PSQL-ARGUMENTS", last = true)]
+        args: Vec<OsString>,
     },
 
     /// Execute an arbitrary command, creating and starting the cluster as
     /// necessary.
+    ///
+    /// The command runs with `PGDATA`, `PGHOST`, `PGPORT`, `PGDATABASE`, and
+    /// `PGUSER` set, and with `DATABASE_URL` set to a connection URI, so that
+    /// it can connect to the cluster.
+    ///
+    /// With `--detach`, the cluster is left running if the command succeeds,
+    /// e.g. to run migrations then use the cluster with other tools; use `stop`
+    /// to stop it later. As with `start --detach`, a cluster left running this
+    /// way is not considered to be in use.
     #[clap(display_order = 2)]
     Exec {
         #[clap(flatten)]
         cluster: ClusterArgs,
 
         #[clap(flatten)]
-        database: DatabaseArgs,
+        mode: ModeArgs,
+
+        #[clap(flatten)]
+        listen: ListenArgs,
+
+        #[clap(flatten)]
+        database: DatabasesArgs,
 
         #[clap(flatten)]
         lifecycle: LifecycleArgs,
 
+        /// Leave the cluster running if the command succeeds.
+        #[clap(long = "detach", conflicts_with = "destroy", display_order = 101)]
+        detach: bool,
+
+        /// Run the command in the given directory rather than the current
+        /// directory.
+        #[clap(
+            long = "workdir",
+            value_name = "DIR",
+            value_hint = ValueHint::DirPath,
+            display_order = 102
+        )]
+        workdir: Option<PathBuf>,
+
         /// The executable to invoke. By default it will start a shell.
-        #[clap(env = "SHELL", value_name = "COMMAND")]
+        #[clap(env = "SHELL", value_name = "COMMAND", value_hint = ValueHint::CommandName)]
         command: OsString,
 
         /// Arguments to pass to the executable.
@@ -52,41 +130,860 @@ pub enum Commands {
     ///
     /// The runtime shown on the line beginning with `=>` is the default, i.e.
     /// the runtime that will be used when creating a new cluster.
+    ///
+    /// With `--for DIR`, the runtime on the line beginning with `=>` is instead
+    /// the runtime that will be used with the existing cluster in DIR.
+    ///
+    /// With `--json`, this also says where each runtime was found.
+    ///
+    /// With `--strategy`, only the given strategies are used to find runtimes,
+    /// which can help to figure out why a runtime is or isn't found.
+    #[clap(display_order = 3)]
+    Runtimes {
+        /// Show which runtime will be used with the existing cluster in the
+        /// given data directory, or why none can be.
+        #[clap(long = "for", value_name = "DIR", display_order = 1)]
+        for_datadir: Option<PathBuf>,
+
+        /// Find runtimes using only these strategies, in this order of
+        /// precedence. May be given more than once, or as a comma-separated
+        /// list, e.g. `--strategy path,platform`. By default all strategies
+        /// are used, and a runtime named in the environment, or a version
+        /// pinned in the environment, takes precedence.
+        #[clap(
+            long = "strategy",
+            value_name = "STRATEGY",
+            value_delimiter = ',',
+            display_order = 2
+        )]
+        strategies: Vec<RuntimeSource>,
+    },
+
+    /// List databases in the cluster, creating and starting the cluster as
+    /// necessary.
+    #[clap(display_order = 4)]
+    List {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+    },
+
+    /// Create the cluster, and any requested databases, without starting a
+    /// shell or command.
+    ///
+    /// This is useful for doing the slow work of creating a cluster ahead of
+    /// time, e.g. in a CI setup step. The cluster is left stopped. Creating
+    /// databases, or using `--mode`, requires starting the cluster briefly.
+    #[clap(display_order = 5)]
+    Init {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        #[clap(flatten)]
+        mode: ModeArgs,
+
+        /// A database to create. May be given more than once.
+        #[clap(
+            short = 'd',
+            long = "database",
+            value_name = "DATABASE",
+            display_order = 2
+        )]
+        databases: Vec<String>,
+
+        /// Print the commands that would be run, and the files and directories
+        /// that would be changed, without doing any of it.
+        #[clap(long = "dry-run", display_order = 100)]
+        dry_run: bool,
+    },
+
+    /// Start the cluster, creating it as necessary, and print connection
+    /// details.
+    ///
+    /// This runs until interrupted, then stops the cluster, unless it's in use
+    /// by another `shell` or `exec`. With `--detach` this exits immediately,
+    /// leaving the cluster running; use `stop` to stop it later. Note that a
+    /// detached cluster is not considered to be in use, so another `shell` or
+    /// `exec` using the same cluster will stop it when it finishes.
+    #[clap(display_order = 6)]
+    Start {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        #[clap(flatten)]
+        mode: ModeArgs,
+
+        #[clap(flatten)]
+        listen: ListenArgs,
+
+        #[clap(flatten)]
+        database: DatabasesArgs,
+
+        #[clap(flatten)]
+        lifecycle: LifecycleArgs,
+
+        /// Exit once the cluster has started, leaving it running.
+        #[clap(long = "detach", conflicts_with = "destroy", display_order = 101)]
+        detach: bool,
+
+        /// Print the commands that would be run, and the files and directories
+        /// that would be changed, without doing any of it.
+        #[clap(long = "dry-run", display_order = 102)]
+        dry_run: bool,
+    },
+
+    /// Print the status of the cluster, e.g. whether it exists and is running.
+    ///
+    /// This does not create or start the cluster.
+    #[clap(display_order = 7)]
+    Status {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+    },
+
+    /// Print the cluster's server log.
+    #[clap(display_order = 8)]
+    Logs {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        /// Keep printing new lines as they are written to the log.
+        #[clap(short = 'f', long = "follow", display_order = 100)]
+        follow: bool,
+
+        /// Print only the last N lines of the log (before following).
+        #[clap(short = 'n', long = "lines", value_name = "N", display_order = 101)]
+        lines: Option<usize>,
+
+        /// Print only lines logged since the given time. This is either a
+        /// duration ago, e.g. `90s`, `10m`, `2h`, or `1d`, or a date and time
+        /// in UTC, e.g. `2024-06-01` or `2024-06-01 13:45`.
+        #[clap(long = "since", value_name = "TIME", value_parser = parse_since, display_order = 102)]
+        since: Option<Since>,
+    },
+
+    /// Stop the cluster, unless it's in use.
+    ///
+    /// This is for cleaning up after a cluster has been left running, e.g. by
+    /// `start --detach` or by a shell that crashed. A cluster that's in use by
+    /// another `shell` or `exec` is left running unless `--force` is given.
+    #[clap(display_order = 9)]
+    Stop {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        /// Stop the cluster even if it's in use.
+        #[clap(long = "force", display_order = 100)]
+        force: bool,
+
+        /// Print the commands that would be run, and the files and directories
+        /// that would be changed, without doing any of it.
+        #[clap(long = "dry-run", display_order = 101)]
+        dry_run: bool,
+    },
+
+    /// Destroy the cluster, unless it's in use.
+    ///
+    /// WARNING: This will DELETE THE DATA DIRECTORY. You will be asked to
+    /// confirm this unless `--yes` is given.
+    #[clap(display_order = 10)]
+    Destroy {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        /// Do not ask for confirmation.
+        #[clap(short = 'y', long = "yes", display_order = 100)]
+        yes: bool,
+
+        /// Print the commands that would be run, and the files and directories
+        /// that would be changed, without doing any of it.
+        #[clap(long = "dry-run", display_order = 101)]
+        dry_run: bool,
+    },
+
+    /// Create databases in the cluster, creating and starting the cluster as
+    /// necessary.
+    #[clap(display_order = 11)]
+    Createdb {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        /// Do not fail if a database already exists.
+        #[clap(long = "if-not-exists", display_order = 100)]
+        if_not_exists: bool,
+
+        /// The names of the databases to create.
+        #[clap(value_name = "DATABASE", required = true)]
+        databases: Vec<String>,
+    },
+
+    /// Drop databases from the cluster, starting the cluster as necessary.
+    ///
+    /// WARNING: This will DELETE THE DATABASES' DATA.
+    #[clap(display_order = 12)]
+    Dropdb {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        /// Do not fail if a database does not exist.
+        #[clap(long = "if-exists", display_order = 100)]
+        if_exists: bool,
+
+        /// The names of the databases to drop.
+        #[clap(value_name = "DATABASE", required = true)]
+        databases: Vec<String>,
+    },
+
+    /// Print environment variables for connecting to the cluster.
+    ///
+    /// The output can be evaluated by a POSIX shell, e.g. `eval
+    /// "$(postgresfixture env)"`, so that other tools use the cluster. The
+    /// cluster must already exist and is not started unless `--start` is given.
+    #[clap(display_order = 13)]
+    Env {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        #[clap(flatten)]
+        database: DatabaseArgs,
+
+        /// Start the cluster, creating it and the database as necessary, and
+        /// leave it running.
+        #[clap(long = "start", display_order = 100)]
+        start: bool,
+    },
+
+    /// Print a connection URI for a database in the cluster.
+    ///
+    /// The URI connects over the cluster's Unix socket. With `--tcp` it
+    /// connects over TCP instead, which requires the cluster to be running and
+    /// listening on TCP.
+    #[clap(display_order = 14)]
+    Uri {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        #[clap(flatten)]
+        database: DatabaseArgs,
+
+        /// Print a URI that connects over TCP.
+        #[clap(long = "tcp", display_order = 100)]
+        tcp: bool,
+    },
+
+    /// Dump a database in the cluster to a file, starting the cluster as
+    /// necessary.
+    ///
+    /// The dump is made by `pg_dump` in its custom archive format, which can
+    /// be loaded with `restore`.
+    #[clap(display_order = 15)]
+    Dump {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        #[clap(flatten)]
+        database: DatabaseArgs,
+
+        /// The file to write the dump to.
+        #[clap(
+            short = 'o',
+            long = "output",
+            value_name = "FILE",
+            value_hint = ValueHint::FilePath,
+            display_order = 100
+        )]
+        output: PathBuf,
+    },
+
+    /// Restore a dump into a database in the cluster, creating and starting
+    /// the cluster, and creating the database, as necessary.
+    ///
+    /// The dump is loaded by `pg_restore`, so it must be in one of `pg_dump`'s
+    /// archive formats, e.g. as written by `dump`. This stops at the first
+    /// error, e.g. if the database already contains objects in the dump.
+    #[clap(display_order = 16)]
+    Restore {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        #[clap(flatten)]
+        database: DatabaseArgs,
+
+        /// The file to read the dump from.
+        #[clap(
+            short = 'i',
+            long = "input",
+            value_name = "FILE",
+            value_hint = ValueHint::FilePath,
+            display_order = 100
+        )]
+        input: PathBuf,
+    },
+
+    /// Upgrade the cluster to a newer major version of PostgreSQL, unless it's
+    /// in use.
+    ///
+    /// This uses `pg_upgrade`, which needs the runtimes for both the cluster's
+    /// current version and the new version. If the runtime for the current
+    /// version is not found automatically, e.g. because it has been removed by
+    /// a system upgrade, install it somewhere and give its path with
+    /// `--runtime`. The cluster is stopped first. Use `--dry-run` to check
+    /// that the cluster can be upgraded without changing it.
+    #[clap(display_order = 17)]
+    Upgrade {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        /// The PostgreSQL runtime to upgrade to, as a version constraint, e.g.
+        /// `16`, or the path to a runtime's `bin` directory, as for
+        /// `--runtime`.
+        #[clap(long = "to", value_name = "VERSION|PATH", value_parser = parse_runtime, display_order = 100)]
+        to: RuntimeChoice,
+
+        /// Check that the cluster can be upgraded, but do not upgrade it.
+        #[clap(long = "dry-run", display_order = 101)]
+        dry_run: bool,
+    },
+
+    /// Back up the cluster with `pg_basebackup`, starting the cluster as
+    /// necessary.
+    ///
+    /// The backup is written in tar format to a new directory. It includes the
+    /// WAL needed to start a cluster from it: extract `base.tar` into an empty
+    /// directory and start a cluster there.
+    #[clap(display_order = 18)]
+    Backup {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        /// The directory to write the backup to. It must not exist or must be
+        /// empty.
+        #[clap(
+            short = 'o',
+            long = "output",
+            value_name = "DIR",
+            value_hint = ValueHint::DirPath,
+            display_order = 100
+        )]
+        output: PathBuf,
+    },
+
+    /// List named clusters.
+    ///
+    /// Named clusters are kept in a registry in `postgresfixture/clusters.toml`
+    /// in `$XDG_DATA_HOME` or `~/.local/share`. A cluster is registered when
+    /// it's created with `--name`, e.g. `init --name NAME`, and is forgotten
+    /// when it's destroyed. Use a named cluster with `--name NAME`, or make it
+    /// the default with `use NAME`; it is then shown on the line beginning with
+    /// `=>`.
+    #[clap(display_order = 19)]
+    Clusters,
+
+    /// Use the named cluster by default.
+    ///
+    /// The named cluster is used when neither `--name` nor `--datadir` is
+    /// given, nor `PGDATA` set, and no `datadir` is configured for the project.
+    #[clap(display_order = 20)]
+    Use {
+        /// The name of a registered cluster; see `clusters`.
+        #[clap(
+            id = "cluster_name",
+            value_name = "NAME",
+            required_unless_present = "none"
+        )]
+        name: Option<String>,
+
+        /// Use no named cluster by default, i.e. go back to using `cluster`.
+        #[clap(long = "none", conflicts_with = "cluster_name", display_order = 1)]
+        none: bool,
+    },
+
+    /// Save, restore, and list snapshots of the cluster.
+    ///
+    /// A snapshot is a copy of the cluster's data directory, kept in a
+    /// directory alongside it, e.g. `cluster.snapshots/NAME` for a cluster in
+    /// `cluster`. Saving or restoring a snapshot stops the cluster first, and
+    /// fails if the cluster is in use.
+    #[clap(display_order = 21)]
+    Snapshot {
+        #[clap(subcommand)]
+        command: SnapshotCommands,
+    },
+
+    /// Clean up after clusters.
+    ///
+    /// This removes stale lock files, i.e. those for clusters that no longer
+    /// exist, and reports clusters whose server exited without shutting down
+    /// cleanly, e.g. because it crashed or was killed. With `--prune`, it also
+    /// destroys clusters in the given directory that have not been used for a
+    /// while, unless they are in use or running. With `--orphans`, it destroys
+    /// orphaned clusters, e.g. those left behind by test runs that were killed.
+    #[clap(display_order = 22)]
+    Gc {
+        #[clap(flatten)]
+        lock: LockArgs,
+
+        /// Destroy clusters in the given directory, i.e. its subdirectories
+        /// that contain clusters, that have not been used for a while. See
+        /// `--older-than`.
+        ///
+        /// WARNING: This will DELETE THOSE CLUSTERS' DATA DIRECTORIES.
+        #[clap(
+            long = "prune",
+            value_name = "DIR",
+            value_hint = ValueHint::DirPath,
+            requires = "older_than",
+            display_order = 100
+        )]
+        prune: Option<PathBuf>,
+
+        /// Destroy orphaned clusters in the given directory or, by default, in
+        /// the system's temporary directory and RAM-backed directory, e.g.
+        /// `/dev/shm`. These are clusters created by postgresfixture, as a
+        /// library or from the command line, whose creating process has gone
+        /// and which have not been used for an hour. Orphans that are running
+        /// are stopped first; those in use are left alone.
+        ///
+        /// Clusters are looked for in the directory's subdirectories, and in
+        /// their subdirectories.
+        ///
+        /// WARNING: This will DELETE THOSE CLUSTERS' DATA DIRECTORIES.
+        #[clap(
+            long = "orphans",
+            value_name = "DIR",
+            value_hint = ValueHint::DirPath,
+            num_args = 0..=1,
+            display_order = 102
+        )]
+        orphans: Option<Option<PathBuf>>,
+
+        /// With `--prune`, destroy clusters not used for this many days.
+        #[clap(
+            long = "older-than",
+            value_name = "DAYS",
+            requires = "prune",
+            value_parser = parse_days,
+            display_order = 101
+        )]
+        older_than: Option<Duration>,
+    },
+
+    /// Download and install a PostgreSQL runtime, or list the versions that
+    /// can be installed.
+    ///
+    /// Runtimes are installed into `postgresfixture/runtimes` in
+    /// `$XDG_CACHE_HOME` or `~/.cache`, from where they are found like any
+    /// other runtime, e.g. by `runtimes`. They are prebuilt binaries from the
+    /// embedded-postgres-binaries project.
+    #[cfg(feature = "download")]
+    #[clap(display_order = 23)]
+    Install {
+        /// The version to install, e.g. `16.3`, or a major version, e.g. `16`,
+        /// to install its latest release.
+        #[clap(value_name = "VERSION", required_unless_present = "list")]
+        version: Option<postgresfixture::version::PartialVersion>,
+
+        /// List the versions that can be installed, and whether each has been
+        /// installed already.
+        #[clap(long = "list", conflicts_with = "version", display_order = 1)]
+        list: bool,
+    },
+
+    /// Print a completion script for the given shell.
+    ///
+    /// For example, in Bash, run `source <(postgresfixture completions bash)`,
+    /// or save the output into your shell's completions directory. Paths, e.g.
+    /// for `--datadir`, and commands, e.g. for `exec`, are completed by the
+    /// shell; database names are not completed.
+    #[clap(display_order = 24)]
+    Completions {
+        /// The shell to print completions for.
+        #[clap(value_name = "SHELL")]
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotCommands {
+    /// Save a snapshot of the cluster.
+    #[clap(display_order = 1)]
+    Save {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        /// The name of the snapshot. An existing snapshot is not replaced.
+        #[clap(value_name = "NAME", value_parser = parse_snapshot_name)]
+        snapshot: String,
+    },
+
+    /// Replace the cluster with a snapshot.
+    ///
+    /// WARNING: Changes made to the cluster since the snapshot was saved are
+    /// LOST, unless saved in another snapshot.
+    #[clap(display_order = 2)]
+    Restore {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+
+        /// The name of the snapshot.
+        #[clap(value_name = "NAME", value_parser = parse_snapshot_name)]
+        snapshot: String,
+    },
+
+    /// List the cluster's snapshots, with the time each was saved, in UTC.
     #[clap(display_order = 3)]
-    Runtimes,
+    List {
+        #[clap(flatten)]
+        cluster: ClusterArgs,
+    },
+}
+
+/// Cluster names are used as directory names; see [`registry::check_name`].
+fn parse_cluster_name(s: &str) -> Result<String, String> {
+    registry::check_name(s)
+        .map(|()| s.to_owned())
+        .map_err(|err| err.to_string())
+}
+
+/// Snapshot names are used as directory names, so must be a single, ordinary
+/// path component.
+fn parse_snapshot_name(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.starts_with('.') || s.contains(std::path::MAIN_SEPARATOR) {
+        Err(format!(
+            "snapshot names must not be empty, start with `.`, or contain `{}`",
+            std::path::MAIN_SEPARATOR
+        ))
+    } else {
+        Ok(s.to_owned())
+    }
+}
+
+#[derive(Args)]
+pub struct LoggingArgs {
+    /// Log more. Repeat for more detail, e.g. `-vv` to see the commands that
+    /// are run and how long they take.
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Log less. Repeat to log nothing at all.
+    #[clap(
+        short = 'q',
+        long = "quiet",
+        action = ArgAction::Count,
+        global = true,
+        conflicts_with = "verbose"
+    )]
+    pub quiet: u8,
+
+    /// The format of log messages, which are written to stderr.
+    #[clap(
+        long = "log-format",
+        value_name = "FORMAT",
+        default_value = "text",
+        global = true
+    )]
+    pub log_format: LogFormat,
+}
+
+impl LoggingArgs {
+    /// The most detailed level of log messages to write. By default this is
+    /// warnings and errors.
+    pub fn level(&self) -> tracing_subscriber::filter::LevelFilter {
+        use tracing_subscriber::filter::LevelFilter;
+        let levels = [
+            LevelFilter::OFF,
+            LevelFilter::ERROR,
+            LevelFilter::WARN,
+            LevelFilter::INFO,
+            LevelFilter::DEBUG,
+            LevelFilter::TRACE,
+        ];
+        let level = (2 + usize::from(self.verbose)).saturating_sub(usize::from(self.quiet));
+        levels[level.min(levels.len() - 1)]
+    }
+}
+
+/// A strategy for finding runtimes; see `runtimes --strategy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum RuntimeSource {
+    /// The runtime named in `POSTGRESFIXTURE_BINDIR`.
+    Env,
+    /// Runtimes listed in `runtimes.toml`.
+    Config,
+    /// Runtimes on `PATH`.
+    Path,
+    /// Runtimes in platform-specific locations, or matching
+    /// `POSTGRESFIXTURE_RUNTIME_GLOBS`.
+    Platform,
+    /// Runtimes installed by pgenv.
+    Pgenv,
+    /// Runtimes installed by asdf.
+    Asdf,
+    /// Runtimes installed by mise.
+    Mise,
+    /// Runtimes downloaded by `install`.
+    #[cfg(feature = "download")]
+    Download,
+}
+
+impl RuntimeSource {
+    /// All strategies, in the order of precedence used by default.
+    pub const ALL: &'static [Self] = &[
+        Self::Env,
+        Self::Config,
+        Self::Path,
+        Self::Platform,
+        Self::Pgenv,
+        Self::Asdf,
+        Self::Mise,
+        #[cfg(feature = "download")]
+        Self::Download,
+    ];
+}
+
+impl std::fmt::Display for RuntimeSource {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use clap::ValueEnum;
+        match self.to_possible_value() {
+            Some(value) => write!(fmt, "{}", value.get_name()),
+            None => write!(fmt, "{self:?}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable text.
+    Text,
+    /// One JSON object per line.
+    Json,
 }
 
 #[derive(Args)]
 pub struct ClusterArgs {
-    /// The directory in which to place, or find, the cluster.
+    /// The directory in which to place, or find, the cluster. The default is
+    /// `cluster`, unless a named cluster has been chosen with `use`.
     #[clap(
+        id = "config.datadir",
         short = 'D',
         long = "datadir",
         env = "PGDATA",
         value_name = "PGDATA",
-        default_value = "cluster",
+        value_hint = ValueHint::DirPath,
         display_order = 1
     )]
-    pub dir: PathBuf,
+    pub dir: Option<PathBuf>,
+
+    /// The name of a cluster in the registry of named clusters; see
+    /// `clusters`. This takes precedence over `--datadir`.
+    ///
+    /// A cluster is registered with this name when it's created, e.g. by
+    /// `init --name NAME`, in the directory given by `--datadir` or, by
+    /// default, in `postgresfixture/clusters/NAME` in `$XDG_DATA_HOME` or
+    /// `~/.local/share`.
+    #[clap(
+        id = "cluster_name",
+        long = "name",
+        value_name = "NAME",
+        value_parser = parse_cluster_name,
+        display_order = 2
+    )]
+    pub name: Option<String>,
+
+    /// The PostgreSQL runtime to use.
+    ///
+    /// This is either a version constraint, e.g. `15` or `>=13, <16`, or the
+    /// path to a runtime's `bin` directory, which must contain a `/`, e.g.
+    /// `/usr/lib/postgresql/15/bin`. It is used to choose a runtime when
+    /// creating a cluster, and to find a runtime for an existing cluster, in
+    /// which case it must also match the cluster's version. By default the
+    /// highest version available is used.
+    #[clap(
+        id = "config.runtime",
+        long = "runtime",
+        value_name = "VERSION|PATH",
+        value_parser = parse_runtime,
+        display_order = 3
+    )]
+    pub runtime: Option<RuntimeChoice>,
+
+    /// How long to wait, in seconds, for the cluster to start before giving
+    /// up. The default is 60 seconds.
+    #[clap(long = "timeout", value_name = "SECONDS", display_order = 7)]
+    pub timeout: Option<u64>,
+
+    /// Rotate the cluster's server log when starting the cluster, if it was
+    /// last written on an earlier day (`daily`) or is at least the given size,
+    /// e.g. `10MB`. Up to 3 rotated logs are kept, e.g. `postmaster.log.1`.
+    #[clap(
+        id = "config.log_rotate",
+        long = "log-rotate",
+        value_name = "daily|SIZE",
+        value_parser = parse_log_rotation,
+        display_order = 9
+    )]
+    pub log_rotate: Option<LogRotation>,
+
+    #[clap(flatten)]
+    pub lock: LockArgs,
+
+    /// Place the cluster's lock file beside its data directory, e.g.
+    /// `cluster.lock` for a cluster in `cluster`, rather than in the lock
+    /// directory. The lock file is then removed with the cluster, and processes
+    /// need only agree on where the cluster is.
+    #[clap(
+        long = "lock-beside-datadir",
+        env = "POSTGRESFIXTURE_LOCK_BESIDE_DATADIR",
+        conflicts_with = "lock_dir",
+        display_order = 8
+    )]
+    pub lock_beside_datadir: bool,
+}
+
+impl ClusterArgs {
+    /// The directory in which to place, or find, the cluster: that of the
+    /// cluster named with `--name`, else `--datadir`, else that of the current
+    /// named cluster, else `cluster`.
+    pub fn datadir(&self) -> Result<PathBuf> {
+        let registry = || -> Result<Registry> {
+            match registry::file() {
+                Some(path) => Registry::load(&path)
+                    .wrap_err("Could not load registry of named clusters")
+                    .with_section(|| format!("{}", path.display()).header("Registry:")),
+                None => Ok(Registry::default()),
+            }
+        };
+        match (&self.name, &self.dir) {
+            (Some(name), dir) => match (registry()?.get(name), dir) {
+                (Some(datadir), _) => Ok(datadir.to_owned()),
+                (None, Some(dir)) => Ok(dir.clone()),
+                (None, None) => registry::default_datadir(name)
+                    .ok_or_else(|| eyre!("Could not find a directory for named clusters"))
+                    .suggestion("Set XDG_DATA_HOME or HOME, or use --datadir"),
+            },
+            (None, Some(dir)) => Ok(dir.clone()),
+            (None, None) => Ok(registry()?.current().map_or_else(
+                || PathBuf::from("cluster"),
+                |(_, datadir)| datadir.to_owned(),
+            )),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct LockArgs {
+    /// The directory in which to place lock files for clusters. The default is
+    /// the system's temporary directory.
+    ///
+    /// Every process using a cluster must use the same lock directory, so this
+    /// should be on a filesystem that all of them can see, and that is not
+    /// cleaned while clusters are in use.
+    #[clap(
+        long = "lock-dir",
+        env = "POSTGRESFIXTURE_LOCK_DIR",
+        value_name = "DIR",
+        value_hint = ValueHint::DirPath,
+        display_order = 8
+    )]
+    pub lock_dir: Option<PathBuf>,
+}
 
+impl LockArgs {
+    /// The directory in which to place lock files.
+    pub fn dir(&self) -> PathBuf {
+        self.lock_dir
+            .clone()
+            .unwrap_or_else(postgresfixture::lock::default_dir)
+    }
+}
+
+/// A choice of runtime; see [`ClusterArgs::runtime`].
+#[derive(Clone, Debug)]
+pub enum RuntimeChoice {
+    /// Choose from runtimes matching the given versions.
+    Matching(VersionSet),
+    /// Use the runtime in the given `bin` directory.
+    Bindir(PathBuf),
+}
+
+fn parse_runtime(s: &str) -> Result<RuntimeChoice, String> {
+    if s.contains(std::path::MAIN_SEPARATOR) {
+        Ok(RuntimeChoice::Bindir(s.into()))
+    } else {
+        s.parse()
+            .map(RuntimeChoice::Matching)
+            .map_err(|err| format!("{err}; a path must contain a `/`"))
+    }
+}
+
+#[derive(Args)]
+pub struct ModeArgs {
     /// Run the cluster in a "safer" or "faster" mode.
     ///
     /// DANGER! Choosing "faster-but-less-safe" makes the cluster faster but it
     /// can lead to unrecoverable data corruption in the event of a power
     /// failure or system crash.
     ///
-    /// The mode is STICKY. Running with a mode reconfigures the cluster, and it
-    /// will continue to run in that mode. To find out which mode the cluster is
-    /// configured for, open a `psql` shell (e.g. `postgresfixture shell`) and
-    /// run `SHOW fsync; SHOW full_page_writes; SHOW synchronous_commit;`.
-    #[clap(long = "mode", display_order = 4)]
+    /// The "safer" and "faster" modes are STICKY. Running with one of them
+    /// reconfigures the cluster, and it will continue to run in that mode. To
+    /// find out which mode the cluster is configured for, open a `psql` shell
+    /// (e.g. `postgresfixture shell`) and run `SHOW fsync; SHOW
+    /// full_page_writes; SHOW synchronous_commit;`.
+    ///
+    /// The "faster-until-stopped" mode is not sticky: it leaves the cluster's
+    /// configuration alone and applies the same settings only until the cluster
+    /// is next stopped. It has no effect if the cluster is already running.
+    #[clap(id = "config.mode", long = "mode", display_order = 4)]
     pub mode: Option<Mode>,
 }
 
+#[derive(Args)]
+pub struct ListenArgs {
+    /// Listen for connections over TCP on the given addresses.
+    ///
+    /// This is as for PostgreSQL's `listen_addresses` setting, e.g.
+    /// `localhost`, `*`, or a comma-separated list of host names and IP
+    /// addresses. The connection URI is printed once the cluster has started.
+    /// This has no effect if the cluster is already running.
+    #[clap(
+        id = "config.listen",
+        long = "listen",
+        value_name = "ADDRESSES",
+        display_order = 5
+    )]
+    pub addresses: Option<String>,
+
+    /// Listen for connections over TCP on the given port.
+    ///
+    /// By default, or when this is 0, a free port is chosen. When `--listen`
+    /// is not given, this listens on `localhost`.
+    #[clap(
+        id = "config.port",
+        long = "port",
+        value_name = "PORT",
+        display_order = 6
+    )]
+    pub port: Option<u16>,
+}
+
+impl ListenArgs {
+    /// The addresses and port on which to listen for TCP connections, if any.
+    pub fn tcp(&self) -> Option<(&str, u16)> {
+        if self.addresses.is_none() && self.port.is_none() {
+            None
+        } else {
+            Some((
+                self.addresses.as_deref().unwrap_or("localhost"),
+                self.port.unwrap_or(0),
+            ))
+        }
+    }
+}
+
 #[derive(Args)]
 pub struct DatabaseArgs {
     /// The database to connect to.
     #[clap(
+        id = "config.database",
         short = 'd',
         long = "database",
         env = "PGDATABASE",
@@ -97,6 +994,36 @@ pub struct DatabaseArgs {
     pub name: String,
 }
 
+#[derive(Args)]
+pub struct DatabasesArgs {
+    /// The database to connect to. May be given more than once, or as a
+    /// comma-separated list, to ensure that several databases exist; the first
+    /// is the one connected to.
+    #[clap(
+        id = "config.database",
+        short = 'd',
+        long = "database",
+        env = "PGDATABASE",
+        value_name = "PGDATABASE",
+        value_delimiter = ',',
+        default_value = "postgres",
+        display_order = 2
+    )]
+    pub names: Vec<String>,
+}
+
+impl DatabasesArgs {
+    /// The database to connect to.
+    pub fn name(&self) -> &str {
+        self.names.first().map_or("postgres", String::as_str)
+    }
+
+    /// All the databases that should exist.
+    pub fn names(&self) -> Vec<&str> {
+        self.names.iter().map(String::as_str).collect()
+    }
+}
+
 #[derive(Args)]
 pub struct LifecycleArgs {
     /// Destroy the cluster after use. WARNING: This will DELETE THE DATA
@@ -105,6 +1032,81 @@ pub struct LifecycleArgs {
     pub destroy: bool,
 }
 
+/// A time from which to print log lines; see [`Commands::Logs`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Since {
+    /// A duration before now.
+    Ago(Duration),
+    /// A date, with an optional time, in the form `YYYY-MM-DD HH:MM:SS`. It
+    /// can be truncated after the date, hour, or minute.
+    At(String),
+}
+
+fn parse_since(s: &str) -> Result<Since, String> {
+    let s = s.trim();
+    let invalid = || format!("expected a duration like 10m, or a date and time, not {s:?}");
+    if let Some(unit) = s.chars().last().filter(char::is_ascii_alphabetic) {
+        let count: u64 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+        let seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        count
+            .checked_mul(seconds)
+            .map(|secs| Since::Ago(Duration::from_secs(secs)))
+            .ok_or_else(invalid)
+    } else {
+        // Accept `T` between the date and time, as in ISO 8601, but log lines
+        // use a space.
+        let at = s.replacen('T', " ", 1);
+        let template = "0000-00-00 00:00:00";
+        let valid = matches!(at.len(), 10 | 13 | 16 | 19)
+            && at.chars().zip(template.chars()).all(|(c, t)| {
+                if t == '0' {
+                    c.is_ascii_digit()
+                } else {
+                    c == t
+                }
+            });
+        if valid {
+            Ok(Since::At(at))
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+fn parse_days(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("expected a number of days, not {s:?}");
+    let days: u64 = s.trim().parse().map_err(|_| invalid())?;
+    days.checked_mul(24 * 60 * 60)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+fn parse_log_rotation(s: &str) -> Result<LogRotation, String> {
+    if s.eq_ignore_ascii_case("daily") {
+        return Ok(LogRotation::Daily);
+    }
+    let invalid = || format!("expected `daily` or a size like 10MB, not {s:?}");
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let count: u64 = s[..digits].parse().map_err(|_| invalid())?;
+    let scale: u64 = match s[digits..].trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    count
+        .checked_mul(scale)
+        .map(LogRotation::Size)
+        .ok_or_else(invalid)
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
 pub enum Mode {
     /// Resets fsync, full_page_writes, and synchronous_commit to defaults.
@@ -114,4 +1116,8 @@ pub enum Mode {
     /// Disable fsync, full_page_writes, and synchronous_commit. DANGER!
     #[value(name = "faster-but-less-safe", alias = "fast")]
     Fast,
+
+    /// As faster-but-less-safe, but only until the cluster is stopped. DANGER!
+    #[value(name = "faster-until-stopped", alias = "fast-until-stopped")]
+    FastUntilStopped,
 }
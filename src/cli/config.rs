@@ -0,0 +1,130 @@
+//! Project-level defaults for the command line, from `.postgresfixture.toml`.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Result, WrapErr};
+use color_eyre::{Section, SectionExt};
+use serde::Deserialize;
+
+/// The name of the configuration file. It's found by looking in the current
+/// directory and then in each of its ancestors.
+pub const FILENAME: &str = ".postgresfixture.toml";
+
+/// Defaults for command-line options, e.g.
+///
+/// ```toml
+/// datadir = "tmp/cluster"  # Relative to this file.
+/// database = "myapp"
+/// mode = "fast"
+/// runtime = ">=14, <17"
+/// listen = "localhost"
+/// port = 5433
+/// log_rotate = "daily"
+/// ```
+///
+/// Options given on the command line, or in the environment, take precedence.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Default for `--datadir`. A relative path is relative to the directory
+    /// containing the configuration file.
+    pub datadir: Option<PathBuf>,
+    /// Default for `--database`.
+    pub database: Option<String>,
+    /// Default for `--mode`.
+    pub mode: Option<String>,
+    /// Default for `--runtime`. A relative path is relative to the directory
+    /// containing the configuration file.
+    pub runtime: Option<String>,
+    /// Default for `--listen`.
+    pub listen: Option<String>,
+    /// Default for `--port`.
+    pub port: Option<u16>,
+    /// Default for `--log-rotate`.
+    pub log_rotate: Option<String>,
+}
+
+impl Config {
+    /// Find the configuration file for the current directory, if there is one.
+    pub fn find() -> Option<PathBuf> {
+        env::current_dir().ok().and_then(|cwd| {
+            cwd.ancestors()
+                .map(|dir| dir.join(FILENAME))
+                .find(|path| path.is_file())
+        })
+    }
+
+    /// Load configuration from the given file, resolving relative paths.
+    pub fn load(path: &Path) -> Result<Self> {
+        let section = || format!("{}", path.display()).header("Configuration file:");
+        let content = fs::read_to_string(path)
+            .wrap_err("Could not read configuration file")
+            .with_section(section)?;
+        let mut config: Self = toml::from_str(&content)
+            .wrap_err("Could not parse configuration file")
+            .with_section(section)?;
+        if let Some(dir) = path.parent() {
+            config.datadir = config.datadir.map(|datadir| dir.join(datadir));
+            // Only paths contain a `/`; see `super::parse_runtime`.
+            config.runtime = config.runtime.map(|runtime| {
+                if runtime.contains(std::path::MAIN_SEPARATOR) && Path::new(&runtime).is_relative()
+                {
+                    dir.join(runtime).to_string_lossy().into_owned()
+                } else {
+                    runtime
+                }
+            });
+        }
+        Ok(config)
+    }
+
+    /// Apply this configuration as defaults to the arguments of `command` and
+    /// its subcommands. Values are parsed and checked as if they had been
+    /// given on the command line.
+    ///
+    /// Only arguments with an ID of the form `config.{key}`, e.g.
+    /// `config.datadir`, receive a default for `{key}`, so an argument does not
+    /// pick up a default merely because it shares a name with a key.
+    pub fn apply(&self, command: clap::Command) -> clap::Command {
+        let defaults: Vec<(&str, String)> = [
+            (
+                "config.datadir",
+                self.datadir
+                    .as_ref()
+                    .map(|d| d.to_string_lossy().into_owned()),
+            ),
+            ("config.database", self.database.clone()),
+            ("config.mode", self.mode.clone()),
+            ("config.runtime", self.runtime.clone()),
+            ("config.listen", self.listen.clone()),
+            ("config.port", self.port.map(|port| port.to_string())),
+            ("config.log_rotate", self.log_rotate.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(id, value)| value.map(|value| (id, value)))
+        .collect();
+
+        apply_defaults(command, &defaults)
+    }
+}
+
+/// Set the given defaults on the arguments, by ID, of each subcommand of
+/// `command`, and of their subcommands, e.g. `snapshot save`.
+fn apply_defaults(command: clap::Command, defaults: &[(&str, String)]) -> clap::Command {
+    let names: Vec<String> = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_owned())
+        .collect();
+    names.into_iter().fold(command, |command, name| {
+        command.mut_subcommand(name, |mut subcommand| {
+            for (id, value) in defaults {
+                if subcommand.get_arguments().any(|arg| arg.get_id() == id) {
+                    subcommand = subcommand.mut_arg(id, |arg| arg.default_value(value));
+                }
+            }
+            apply_defaults(subcommand, defaults)
+        })
+    })
+}
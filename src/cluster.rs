@@ -1,22 +1,38 @@
 //! Create, start, introspect, stop, and destroy PostgreSQL clusters.
 
 mod error;
+mod metrics;
+mod set;
 
 #[cfg(test)]
 mod tests;
 
+use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
-use std::os::unix::prelude::OsStringExt;
+use std::os::unix::prelude::{OsStrExt, OsStringExt};
+use std::panic::RefUnwindSafe;
 use std::path::{Path, PathBuf};
-use std::process::{Command, ExitStatus};
-use std::{env, fs, io};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fmt, fs, io};
 
 use nix::errno::Errno;
 use shell_quote::sh::escape_into;
 
 use crate::runtime;
+use crate::util;
 use crate::version;
 pub use error::ClusterError;
+pub use metrics::{Metrics, Phase, Timing};
+pub use set::ClusterSet;
+
+/// The size of `sun_path` in `struct sockaddr_un`, which limits the length of
+/// the path to a Unix socket, including its terminating NUL.
+#[cfg(target_os = "linux")]
+const SOCKET_PATH_MAX: usize = 108;
+#[cfg(not(target_os = "linux"))]
+const SOCKET_PATH_MAX: usize = 104;
 
 /// Representation of a PostgreSQL cluster.
 ///
@@ -32,31 +48,277 @@ pub struct Cluster {
     datadir: PathBuf,
     /// How to select the PostgreSQL installation to use with this cluster.
     strategy: Box<dyn runtime::Strategy>,
+    /// The addresses and port on which to listen for TCP connections, if
+    /// any. See [`Cluster::with_tcp`].
+    tcp: Option<(String, u16)>,
+    /// Settings to pass to the server when starting this cluster. See
+    /// [`Cluster::with_setting`].
+    settings: Vec<(String, String)>,
+    /// Environment variables to set for the server when starting this
+    /// cluster. See [`Cluster::with_server_env`].
+    server_env: Vec<(OsString, OsString)>,
+    /// How long to wait for the cluster to start. See
+    /// [`Cluster::with_start_timeout`].
+    start_timeout: Option<Duration>,
+    /// Where the server writes its log. See [`Cluster::with_log`].
+    log: Option<Log>,
+    /// When to rotate the log file. See [`Cluster::with_log_rotation`].
+    log_rotation: Option<LogRotation>,
+    /// How to retry connecting. See [`Cluster::with_connect_retry`].
+    connect_retry: Option<ConnectRetry>,
+    /// Whether changes to the cluster are forbidden. See
+    /// [`Cluster::with_read_only`].
+    read_only: bool,
+    /// How long things have taken so far. See [`Cluster::metrics`].
+    metrics: Mutex<Metrics>,
+    /// Called with each duration as it is recorded. See
+    /// [`Cluster::with_metrics_callback`].
+    metrics_callback: Option<Box<dyn Fn(Phase, Duration) + RefUnwindSafe>>,
 }
 
+// Methods like `_start` do the work for their public counterparts, e.g. `start`,
+// which add tracing and interpret errors like `EAGAIN`.
+#[allow(clippy::used_underscore_items)]
 impl Cluster {
     /// Represent a cluster at the given path.
+    ///
+    /// Fails with [`ClusterError::NotADataDirectory`] if the path exists but is
+    /// not a directory. Other problems with the directory are found when
+    /// creating or starting the cluster; see [`check_datadir`].
     pub fn new<P: AsRef<Path>, S: runtime::Strategy>(
         datadir: P,
         strategy: S,
     ) -> Result<Self, ClusterError> {
+        let datadir = datadir.as_ref();
+        if datadir.exists() && !datadir.is_dir() {
+            return Err(ClusterError::NotADataDirectory(
+                datadir.to_owned(),
+                "it is not a directory".into(),
+            ));
+        }
         Ok(Self {
-            datadir: datadir.as_ref().to_owned(),
+            datadir: datadir.to_owned(),
             strategy: Box::new(strategy),
+            tcp: None,
+            settings: Vec::new(),
+            server_env: Vec::new(),
+            start_timeout: None,
+            log: None,
+            log_rotation: None,
+            connect_retry: None,
+            read_only: false,
+            metrics: Mutex::default(),
+            metrics_callback: None,
         })
     }
 
+    /// Listen for connections over TCP, as well as on the cluster's Unix
+    /// socket, when starting this cluster.
+    ///
+    /// The addresses are as for PostgreSQL's `listen_addresses` setting, e.g.
+    /// `localhost`, `*`, or a comma-separated list of host names and IP
+    /// addresses. When `port` is 0, a free port is chosen each time the
+    /// cluster is started; use [`Self::port`] to find out which.
+    ///
+    /// This has no effect on a cluster that's already running.
+    #[must_use]
+    pub fn with_tcp<A: Into<String>>(mut self, addresses: A, port: u16) -> Self {
+        self.tcp = Some((addresses.into(), port));
+        self
+    }
+
+    /// Set a PostgreSQL setting, e.g. `fsync`, on the server's command line
+    /// when starting this cluster.
+    ///
+    /// Unlike `ALTER SYSTEM`, this does not change the cluster's configuration:
+    /// the setting applies only until the cluster is stopped. It also takes
+    /// precedence over the cluster's configuration files.
+    ///
+    /// This has no effect on a cluster that's already running.
+    #[must_use]
+    pub fn with_setting<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.settings.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set an environment variable, e.g. `LC_MESSAGES`, or options for a
+    /// sanitizer in a custom build, for the server when starting this cluster.
+    /// These are also set for `pg_ctl`, which starts the server, and take
+    /// precedence over this process's environment. `PGDATA` and `PGHOST` are
+    /// always set to the cluster's data directory.
+    ///
+    /// This has no effect on a cluster that's already running.
+    #[must_use]
+    pub fn with_server_env<N: Into<OsString>, V: Into<OsString>>(
+        mut self,
+        name: N,
+        value: V,
+    ) -> Self {
+        self.server_env.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set the cluster's [`Durability`] on the server's command line when
+    /// starting this cluster. As with [`Self::with_setting`], this applies only
+    /// until the cluster is stopped; use [`Self::set_durability`] to change the
+    /// cluster's configuration.
+    ///
+    /// This has no effect on a cluster that's already running.
+    #[must_use]
+    pub fn with_durability(self, durability: Durability) -> Self {
+        durability
+            .settings()
+            .iter()
+            .fold(self, |cluster, (name, value)| {
+                cluster.with_setting(*name, *value)
+            })
+    }
+
+    /// Wait at most this long for the cluster to start, rather than `pg_ctl`'s
+    /// default of 60 seconds. This is rounded up to the nearest second.
+    ///
+    /// If the server does not start in time, [`Self::start`] fails with
+    /// [`ClusterError::StartTimedOut`], but the server may yet start. Its log,
+    /// in [`Self::logfile`], may say why it was slow.
+    #[must_use]
+    pub fn with_start_timeout(mut self, timeout: Duration) -> Self {
+        self.start_timeout = Some(timeout);
+        self
+    }
+
+    /// Choose where the server writes its log when starting this cluster,
+    /// rather than to [`Self::logfile`]'s default of `postmaster.log` in the
+    /// data directory.
+    ///
+    /// This has no effect on a cluster that's already running.
+    #[must_use]
+    pub fn with_log(mut self, log: Log) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    /// Rotate the log file, i.e. [`Self::logfile`], when starting this cluster
+    /// if it has become too big or too old. Up to 3 rotated files are kept,
+    /// named like `postmaster.log.1`, with `.1` being the most recent.
+    ///
+    /// This is meant for long-lived clusters that are started and stopped
+    /// often. Rotation happens only when starting the cluster, so a cluster
+    /// that runs for a long time may need [`Log::Collector`] instead.
+    #[must_use]
+    pub fn with_log_rotation(mut self, rotation: LogRotation) -> Self {
+        self.log_rotation = Some(rotation);
+        self
+    }
+
+    /// Retry when [`Self::connect`] fails because the server is not yet
+    /// accepting connections, e.g. just after it was started by another
+    /// process, waiting longer between each attempt. Other errors, e.g. for a
+    /// database that does not exist, are not retried.
+    #[must_use]
+    pub fn with_connect_retry(mut self, retry: ConnectRetry) -> Self {
+        self.connect_retry = Some(retry);
+        self
+    }
+
+    /// Forbid changes to this cluster, e.g. for tools that should only inspect
+    /// or query a developer's existing cluster. Methods that would create,
+    /// destroy, repair, upgrade, or restore the cluster, create or drop
+    /// databases, or change its configuration with `ALTER SYSTEM`, fail with
+    /// [`ClusterError::ReadOnly`]. An existing cluster can still be started
+    /// and stopped.
+    ///
+    /// This does not stop changes made over connections to the cluster, e.g.
+    /// from [`Self::connect`].
+    #[must_use]
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Fail with [`ClusterError::ReadOnly`] if this cluster is read-only. The
+    /// operation is named in the error.
+    fn check_writable(&self, operation: &'static str) -> Result<(), ClusterError> {
+        if self.read_only {
+            Err(ClusterError::ReadOnly(operation))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Call `callback` with the duration of each [`Phase`] as it is recorded,
+    /// e.g. to report how much of a test suite's time is spent on fixtures.
+    /// This is in addition to [`Self::metrics`].
+    #[must_use]
+    pub fn with_metrics_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(Phase, Duration) + RefUnwindSafe + 'static,
+    {
+        self.metrics_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// How long things have taken with this cluster so far, e.g. running
+    /// `initdb` or waiting for the server to start:
+    ///
+    /// ```rust
+    /// # use postgresfixture::prelude::*;
+    /// # use postgresfixture::cluster::Phase;
+    /// let data_dir = tempdir::TempDir::new("data")?;
+    /// let cluster = Cluster::new(&data_dir, runtime::strategy::default())?;
+    /// cluster.start()?;
+    /// cluster.stop()?;
+    /// let metrics = cluster.metrics();
+    /// assert_eq!(metrics.get(Phase::Initdb).count, 1);
+    /// assert_eq!(metrics.get(Phase::Start).count, 1);
+    /// assert_eq!(metrics.get(Phase::Stop).count, 1);
+    /// # Ok::<(), ClusterError>(())
+    /// ```
+    ///
+    /// The functions in the [`coordinate`][`crate::coordinate`] module also
+    /// record how long they wait for locks, as [`Phase::LockWait`].
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Record that `phase` took `duration`, in [`Self::metrics`] and with the
+    /// callback given to [`Self::with_metrics_callback`], if any.
+    pub fn record_timing(&self, phase: Phase, duration: Duration) {
+        tracing::debug!(%phase, ?duration, "recorded timing");
+        self.metrics
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record(phase, duration);
+        if let Some(callback) = &self.metrics_callback {
+            callback(phase, duration);
+        }
+    }
+
+    /// The data directory of this cluster.
+    ///
+    /// The directory does not necessarily exist.
+    pub fn datadir(&self) -> &Path {
+        &self.datadir
+    }
+
     /// Determine the runtime to use with this cluster.
-    fn runtime(&self) -> Result<runtime::Runtime, ClusterError> {
+    ///
+    /// For a cluster that does not yet exist this is the runtime that
+    /// [`create`][`Self::create`] will use, so it can be checked beforehand,
+    /// e.g. with [`Version::is_eol`][`version::Version::is_eol`] to warn about
+    /// creating a cluster with an end-of-life version of PostgreSQL.
+    pub fn runtime(&self) -> Result<runtime::Runtime, ClusterError> {
         match version(self)? {
             None => self
                 .strategy
                 .fallback()
-                .ok_or_else(|| ClusterError::RuntimeDefaultNotFound),
+                .ok_or(ClusterError::RuntimeDefaultNotFound),
             Some(version) => self
                 .strategy
                 .select(&version)
-                .ok_or_else(|| ClusterError::RuntimeNotFound(version)),
+                .ok_or(ClusterError::RuntimeNotFound(version)),
         }
     }
 
@@ -64,6 +326,7 @@ impl Cluster {
     /// referring to this cluster.
     fn ctl(&self) -> Result<Command, ClusterError> {
         let mut command = self.runtime()?.execute("pg_ctl");
+        command.envs(self.server_env.iter().map(|(name, value)| (name, value)));
         command.env("PGDATA", &self.datadir);
         command.env("PGHOST", &self.datadir);
         Ok(command)
@@ -74,7 +337,7 @@ impl Cluster {
     /// Tries to distinguish carefully between "definitely running", "definitely
     /// not running", and "don't know". The latter results in [`ClusterError`].
     pub fn running(&self) -> Result<bool, ClusterError> {
-        let output = self.ctl()?.arg("status").output()?;
+        let output = run(self.ctl()?.arg("status"))?;
         let code = match output.status.code() {
             // Killed by signal; return early.
             None => return Err(ClusterError::Other(output)),
@@ -89,8 +352,8 @@ impl Cluster {
         // masking errors from insufficient permissions or missing
         // executables, for example.
         let running = match runtime.version {
-            // PostgreSQL 10.x and later.
-            version::Version::Post10(_major, _minor) => {
+            // PostgreSQL 10.x and later, including prereleases.
+            version::Version::Post10(..) | version::Version::Post10Pre(..) => {
                 // PostgreSQL 10
                 // https://www.postgresql.org/docs/10/static/app-pg-ctl.html
                 match code {
@@ -174,14 +437,36 @@ impl Cluster {
         self.datadir.join("postmaster.pid")
     }
 
+    /// The port on which this cluster is listening, as recorded in its PID
+    /// file. This is also the port number in the name of its Unix socket, so
+    /// it's needed even when not using TCP. Returns [`None`] when the cluster
+    /// is not running.
+    pub fn port(&self) -> Result<Option<u16>, ClusterError> {
+        match fs::read_to_string(self.pidfile()) {
+            // The port is on the fourth line.
+            Ok(content) => Ok(content
+                .lines()
+                .nth(3)
+                .and_then(|line| line.trim().parse().ok())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     /// Return the path to the log file used in this cluster.
     ///
-    /// The log file does not necessarily exist.
+    /// The log file does not necessarily exist, and it's not written to at all
+    /// when logging to [`Log::Stderr`]. When using [`Log::Collector`] this file
+    /// receives only the server's output from before the collector starts.
     pub fn logfile(&self) -> PathBuf {
-        self.datadir.join("postmaster.log")
+        match &self.log {
+            Some(Log::File(path)) => path.clone(),
+            _ => self.datadir.join("postmaster.log"),
+        }
     }
 
     /// Create the cluster if it does not already exist.
+    #[tracing::instrument(skip(self), fields(datadir = %self.datadir.display()))]
     pub fn create(&self) -> Result<State, ClusterError> {
         match self._create() {
             Err(ClusterError::UnixError(Errno::EAGAIN)) if exists(self) => Ok(Unmodified),
@@ -195,24 +480,121 @@ impl Cluster {
             // Nothing more to do; the cluster is already in place.
             Ok(Unmodified)
         } else {
+            // Check that the runtime is complete, and that the directory is
+            // not something else, before making any changes.
+            self.check_writable("create")?;
+            check_datadir(&self.datadir)?;
+            let runtime = self.runtime()?;
+            runtime.validate()?;
             // Create the cluster and report back that we did so.
             fs::create_dir_all(&self.datadir)?;
-            #[allow(clippy::suspicious_command_arg_space)]
-            self.ctl()?
-                .arg("init")
-                .arg("-s")
-                .arg("-o")
-                // Passing multiple flags in a single `arg(...)` is
-                // intentional. These constitute the single value for the
-                // `-o` flag above.
-                .arg("-E utf8 --locale C -A trust")
-                .env("TZ", "UTC")
-                .output()?;
+            let started = Instant::now();
+            let output = run(&mut init(&runtime, &self.datadir))?;
+            self.record_timing(Phase::Initdb, started.elapsed());
+            if !output.status.success() {
+                return Err(ClusterError::InitdbFailed(output));
+            }
+            write_owner(&self.datadir)?;
             Ok(Modified)
         }
     }
 
+    /// A [`Command`] that will start the cluster with `pg_ctl`. This fails if
+    /// the path to the server's Unix socket would be too long.
+    fn start_command(&self) -> Result<Command, ClusterError> {
+        // The server's Unix socket goes in the data directory; check that its
+        // path, with the longest possible port number, is not too long.
+        let socket = self.datadir.join(".s.PGSQL.65535");
+        if socket.as_os_str().len() >= SOCKET_PATH_MAX {
+            return Err(ClusterError::SocketPathTooLong(socket));
+        }
+        // Next, invoke `pg_ctl` to start the cluster.
+        // pg_ctl options:
+        //  -l <file> -- log file.
+        //  -s -- no informational messages.
+        //  -w -- wait until startup is complete.
+        //  -t <secs> -- how long to wait.
+        // postgres options:
+        //  -h <arg> -- host name; empty arg means Unix socket only.
+        //  -p <port> -- port number, for TCP and the Unix socket.
+        //  -k -- socket directory.
+        //  -c name=value -- a setting.
+        let mut options: Vec<u8> = b"-h "[..].into();
+        match &self.tcp {
+            None => options.extend(b"''"),
+            Some((addresses, port)) => {
+                escape_into(addresses, &mut options);
+                let port = match port {
+                    // Pick a free port. Another process could take it before
+                    // the server starts, but that's unlikely.
+                    0 => std::net::TcpListener::bind(("localhost", 0))?
+                        .local_addr()?
+                        .port(),
+                    port => *port,
+                };
+                options.extend(format!(" -p {port}").bytes());
+            }
+        }
+        options.extend(b" -k ");
+        escape_into(&self.datadir, &mut options);
+        // Settings from the log configuration come first so that those from
+        // `with_setting` can override them.
+        let log_settings = self.log.iter().flat_map(Log::settings);
+        let settings = self
+            .settings
+            .iter()
+            .map(|(name, value)| (name.as_str(), OsString::from(value)));
+        for (name, value) in log_settings.chain(settings) {
+            let mut setting = OsString::from(name);
+            setting.push("=");
+            setting.push(value);
+            options.extend(b" -c ");
+            escape_into(setting, &mut options);
+        }
+        let mut command = self.ctl()?;
+        command.arg("start");
+        if let Some(Log::Stderr) = self.log {
+            // Without `-l`, the server writes to `pg_ctl`'s stdout; point that
+            // at this process's stderr. We must not capture it: the server
+            // would hold the pipe open, and we'd wait for it forever.
+            command.stdout(io::stderr()).stderr(Stdio::inherit());
+        } else {
+            command.arg("-l").arg(self.logfile());
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        command
+            .arg("-s")
+            .arg("-w")
+            .arg("-o")
+            .arg(OsString::from_vec(options));
+        if self.start_timeout.is_some() {
+            let secs = self.effective_start_timeout().as_secs();
+            command.arg("-t").arg(secs.to_string());
+        }
+        Ok(command)
+    }
+
+    /// How long `pg_ctl` waits for the cluster to start: the timeout given to
+    /// [`Self::with_start_timeout`], rounded up to the nearest second, else
+    /// `PGCTLTIMEOUT` from the environment, else 60 seconds.
+    fn effective_start_timeout(&self) -> Duration {
+        match self.start_timeout {
+            Some(timeout) => {
+                let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+                Duration::from_secs(secs.max(1))
+            }
+            None => Duration::from_secs(
+                env::var("PGCTLTIMEOUT")
+                    .ok()
+                    .and_then(|secs| secs.trim().parse().ok())
+                    .filter(|&secs| secs > 0)
+                    .unwrap_or(60),
+            ),
+        }
+    }
+
     /// Start the cluster if it's not already running.
+    #[tracing::instrument(skip(self), fields(datadir = %self.datadir.display()))]
     pub fn start(&self) -> Result<State, ClusterError> {
         match self._start() {
             Err(ClusterError::UnixError(Errno::EAGAIN)) if self.running()? => Ok(Unmodified),
@@ -229,105 +611,763 @@ impl Cluster {
             // We didn't start this cluster; say so.
             return Ok(Unmodified);
         }
-        // Next, invoke `pg_ctl` to start the cluster.
-        // pg_ctl options:
-        //  -l <file> -- log file.
-        //  -s -- no informational messages.
-        //  -w -- wait until startup is complete.
-        // postgres options:
-        //  -h <arg> -- host name; empty arg means Unix socket only.
-        //  -k -- socket directory.
-        self.ctl()?
-            .arg("start")
-            .arg("-l")
-            .arg(self.logfile())
-            .arg("-s")
-            .arg("-w")
-            .arg("-o")
-            .arg({
-                let mut arg = b"-h '' -k "[..].into();
-                escape_into(&self.datadir, &mut arg);
-                OsString::from_vec(arg)
-            })
-            .output()?;
+        check_datadir(&self.datadir)?;
+        let mut command = self.start_command()?;
+        if let (Some(rotation), None | Some(Log::File(_))) = (self.log_rotation, &self.log) {
+            rotate_log(&self.logfile(), rotation)?;
+        }
+        let started = Instant::now();
+        let output = self.run_until_ready(&mut command, self.effective_start_timeout())?;
+        self.record_timing(Phase::Start, started.elapsed());
+        if let Some(output) = output {
+            check(output)?;
+        }
         // We did actually start the cluster; say so.
         Ok(Modified)
     }
 
+    /// Run `pg_ctl start`, i.e. `command`, but stop waiting for it as soon as
+    /// the server is ready. Depending on its version, `pg_ctl` checks only
+    /// every 100ms or every second, whereas this checks every few milliseconds.
+    /// Until then, `pg_ctl` still reports if the server fails to start. Returns
+    /// [`None`] when the server was ready first.
+    ///
+    /// When the server is not ready within `timeout`, this fails with
+    /// [`ClusterError::StartTimedOut`]. That's decided here, from the elapsed
+    /// time, rather than from `pg_ctl`'s output, which may be translated.
+    fn run_until_ready(
+        &self,
+        command: &mut Command,
+        timeout: Duration,
+    ) -> Result<Option<Output>, ClusterError> {
+        tracing::debug!(?command, "running command");
+        let started = Instant::now();
+        // Give `pg_ctl` a moment to give up by itself before we give up on it.
+        let deadline = started + timeout + Duration::from_secs(1);
+        let mut child = command.spawn()?;
+        let mut delay = Duration::from_millis(1);
+        loop {
+            if child.try_wait()?.is_some() {
+                let output = child.wait_with_output()?;
+                tracing::debug!(status = %output.status, elapsed = ?started.elapsed(), "command finished");
+                if !output.status.success() && started.elapsed() >= timeout {
+                    return Err(ClusterError::StartTimedOut(timeout));
+                }
+                return Ok(Some(output));
+            }
+            if self.ready()? {
+                tracing::debug!(elapsed = ?started.elapsed(), "server ready; not waiting for command");
+                // `pg_ctl` forks the postmaster and, in recent versions,
+                // detaches it into its own session. Either way, `kill` signals
+                // only `pg_ctl`'s own PID, not its process group, so the
+                // server keeps running. `pg_ctl` may also have exited already.
+                let _ = child.kill();
+                child.wait()?;
+                return Ok(None);
+            }
+            if Instant::now() >= deadline {
+                tracing::debug!(elapsed = ?started.elapsed(), "server not ready in time; not waiting for command");
+                let _ = child.kill();
+                child.wait()?;
+                return Err(ClusterError::StartTimedOut(timeout));
+            }
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(Duration::from_millis(10));
+        }
+    }
+
     /// Connect to this cluster.
+    ///
+    /// This is only available with the `client` feature.
+    #[cfg(feature = "client")]
     pub fn connect(&self, database: &str) -> Result<postgres::Client, ClusterError> {
-        let user = &env::var("USER").unwrap_or_else(|_| "USER-not-set".to_string());
-        let host = self.datadir.to_string_lossy(); // postgres crate API limitation.
-        let client = postgres::Client::configure()
-            .user(user)
-            .dbname(database)
-            .host(&host)
-            .connect(postgres::NoTls)?;
+        self.connect_with(database, &ConnectOptions::default())
+    }
+
+    /// Connect to this cluster with the given options, e.g. to set
+    /// `application_name` so that the connection can be found in
+    /// `pg_stat_activity`:
+    ///
+    /// ```rust
+    /// # use postgresfixture::prelude::*;
+    /// # use postgresfixture::cluster::ConnectOptions;
+    /// # let data_dir = tempdir::TempDir::new("data")?;
+    /// # let cluster = Cluster::new(&data_dir, runtime::strategy::default())?;
+    /// # cluster.start()?;
+    /// let options = ConnectOptions {
+    ///     application_name: Some("my-test".into()),
+    ///     ..ConnectOptions::default()
+    /// };
+    /// let mut client = cluster.connect_with("template1", &options)?;
+    /// let row = client.query_one("SELECT current_setting('application_name')", &[])?;
+    /// assert_eq!(row.get::<_, String>(0), "my-test");
+    /// # cluster.stop()?;
+    /// # Ok::<(), ClusterError>(())
+    /// ```
+    ///
+    /// This is only available with the `client` feature.
+    #[cfg(feature = "client")]
+    pub fn connect_with(
+        &self,
+        database: &str,
+        options: &ConnectOptions,
+    ) -> Result<postgres::Client, ClusterError> {
+        let started = Instant::now();
+        let client = match self.connect_retry {
+            None => self.connect_once(database, options)?,
+            Some(retry) => {
+                let deadline = started + retry.timeout;
+                let mut delay = retry.initial_delay;
+                loop {
+                    match self.connect_once(database, options) {
+                        Err(ClusterError::DatabaseError(err))
+                            if connect_error_is_transient(&err) =>
+                        {
+                            let remaining = deadline.saturating_duration_since(Instant::now());
+                            if remaining.is_zero() {
+                                return Err(err.into());
+                            }
+                            tracing::debug!(?delay, %err, "server not accepting connections; retrying");
+                            std::thread::sleep(delay.min(remaining));
+                            delay = (delay * 2).min(retry.max_delay);
+                        }
+                        result => break result?,
+                    }
+                }
+            }
+        };
+        self.record_timing(Phase::Connect, started.elapsed());
         Ok(client)
     }
 
+    /// Connect to this cluster once, without retrying. The port is read from
+    /// `postmaster.pid` each time, since the server may not have written it.
+    #[cfg(feature = "client")]
+    fn connect_once(
+        &self,
+        database: &str,
+        options: &ConnectOptions,
+    ) -> Result<postgres::Client, ClusterError> {
+        let user = &user();
+        let host = self.datadir.to_string_lossy(); // postgres crate API limitation.
+        let mut config = postgres::Client::configure();
+        config.user(user).dbname(database).host(&host);
+        if let Some(port) = self.port()? {
+            config.port(port);
+        }
+        options.configure(&mut config);
+        Ok(config.connect(postgres::NoTls)?)
+    }
+
+    /// A connection URI for the given database in this cluster, e.g. for
+    /// `DATABASE_URL`. This connects over the cluster's Unix socket, as the
+    /// same user as [`Self::connect`]. When the cluster is running, the URI
+    /// includes its [port][`Self::port`].
+    pub fn url(&self, database: &str) -> String {
+        let user = user();
+        let port = match self.port() {
+            Ok(Some(port)) => format!("&port={port}"),
+            _ => String::new(),
+        };
+        format!(
+            "postgresql://{user}@/{database}?host={host}{port}",
+            user = util::percent_encode(user.as_bytes()),
+            database = util::percent_encode(database.as_bytes()),
+            host = util::percent_encode(self.datadir.as_os_str().as_bytes()),
+        )
+    }
+
+    /// A connection URI for the given database in this cluster that connects
+    /// over TCP, or [`None`] if the cluster is not listening on TCP. This
+    /// connects to the cluster to find out where it's listening, so the
+    /// cluster must be running.
+    ///
+    /// When the cluster listens on all addresses, e.g. `*`, the URI refers to
+    /// `localhost`.
+    pub fn url_tcp(&self, database: &str) -> Result<Option<String>, ClusterError> {
+        let rows = self.query(
+            "template1",
+            "SELECT current_setting('listen_addresses'), current_setting('port')",
+        )?;
+        let (listen, port) = match rows.as_slice() {
+            [row] if row.len() == 2 => (&row[0], &row[1]),
+            _ => return Ok(None),
+        };
+        let host = match listen.split(',').map(str::trim).find(|a| !a.is_empty()) {
+            None => return Ok(None),
+            Some("*" | "0.0.0.0" | "::") => "localhost".to_owned(),
+            Some(address) if address.contains(':') => format!("[{address}]"),
+            Some(address) => address.to_owned(),
+        };
+        let user = user();
+        Ok(Some(format!(
+            "postgresql://{user}@{host}:{port}/{database}",
+            user = util::percent_encode(user.as_bytes()),
+            database = util::percent_encode(database.as_bytes()),
+        )))
+    }
+
     /// Run `psql` against this cluster, in the given database.
     pub fn shell(&self, database: &str) -> Result<ExitStatus, ClusterError> {
+        self.shell_with_args::<&str>(database, &[])
+    }
+
+    /// Run `psql` against this cluster, in the given database, with additional
+    /// arguments, e.g. `["-c", "SELECT 1", "--tuples-only"]`.
+    pub fn shell_with_args<T: AsRef<OsStr>>(
+        &self,
+        database: &str,
+        args: &[T],
+    ) -> Result<ExitStatus, ClusterError> {
+        Ok(self.shell_command(database, args)?.spawn()?.wait()?)
+    }
+
+    /// A [`Command`] that will run `psql` against this cluster, as
+    /// [`Self::shell_with_args`] does, but without running it, e.g. so that
+    /// the caller can manage the child process itself.
+    pub fn shell_command<T: AsRef<OsStr>>(
+        &self,
+        database: &str,
+        args: &[T],
+    ) -> Result<Command, ClusterError> {
         let mut command = self.runtime()?.execute("psql");
         command.arg("--quiet");
+        command.args(args);
         command.env("PGDATA", &self.datadir);
         command.env("PGHOST", &self.datadir);
         command.env("PGDATABASE", database);
-        Ok(command.spawn()?.wait()?)
+        if let Some(port) = self.port()? {
+            command.env("PGPORT", port.to_string());
+        }
+        Ok(command)
     }
 
     /// Run the given command against this cluster.
     ///
-    /// The command is run with the `PGDATA`, `PGHOST`, and `PGDATABASE`
-    /// environment variables set appropriately.
+    /// The command is run with the `PGDATA`, `PGHOST`, `PGDATABASE`, and
+    /// `PGUSER` environment variables set appropriately, and `PGPORT` when the
+    /// cluster is running. Since many applications and frameworks read a
+    /// connection URI rather than these, `DATABASE_URL` is also set, to
+    /// [`Self::url`].
     pub fn exec<T: AsRef<OsStr>>(
         &self,
         database: &str,
         command: T,
         args: &[T],
     ) -> Result<ExitStatus, ClusterError> {
+        Ok(self
+            .exec_command(database, command, args)?
+            .spawn()?
+            .wait()?)
+    }
+
+    /// A [`Command`] that will run the given command against this cluster, as
+    /// [`Self::exec`] does, but without running it, e.g. so that the caller
+    /// can manage the child process itself.
+    pub fn exec_command<T: AsRef<OsStr>>(
+        &self,
+        database: &str,
+        command: T,
+        args: &[T],
+    ) -> Result<Command, ClusterError> {
         let mut command = self.runtime()?.command(command);
         command.args(args);
         command.env("PGDATA", &self.datadir);
         command.env("PGHOST", &self.datadir);
         command.env("PGDATABASE", database);
-        Ok(command.spawn()?.wait()?)
+        command.env("PGUSER", user());
+        command.env("DATABASE_URL", self.url(database));
+        if let Some(port) = self.port()? {
+            command.env("PGPORT", port.to_string());
+        }
+        Ok(command)
+    }
+
+    /// Check that this cluster is running and answering queries, as a
+    /// supervision loop or readiness probe might. This connects and runs a
+    /// trivial query, then checks what `check` asks for, e.g. that the server
+    /// is not in recovery:
+    ///
+    /// ```rust
+    /// # use postgresfixture::prelude::*;
+    /// # use postgresfixture::cluster::HealthCheck;
+    /// let data_dir = tempdir::TempDir::new("data")?;
+    /// let cluster = Cluster::new(&data_dir, runtime::strategy::default())?;
+    /// cluster.start()?;
+    /// let check = HealthCheck { primary: true, ..HealthCheck::default() };
+    /// let health = cluster.health_check(&check)?;
+    /// assert!(!health.in_recovery);
+    /// cluster.stop()?;
+    /// assert!(cluster.health_check(&check).is_err());
+    /// # Ok::<(), ClusterError>(())
+    /// ```
+    ///
+    /// When a check fails this returns [`ClusterError::Unhealthy`].
+    pub fn health_check(&self, check: &HealthCheck) -> Result<Health, ClusterError> {
+        let rows = self.query(
+            "template1",
+            "SELECT 1, current_setting('server_version'), pg_is_in_recovery()",
+        )?;
+        let health = match rows.as_slice() {
+            [row] if row.len() == 3 && row[0] == "1" => {
+                Health { version: row[1].parse()?, in_recovery: row[2] == "t" }
+            }
+            _ => {
+                return Err(ClusterError::Unhealthy(format!(
+                    "unexpected result: {rows:?}"
+                )))
+            }
+        };
+        match check.version {
+            Some(expected) if !expected.compatible(health.version) => {
+                Err(ClusterError::Unhealthy(format!(
+                    "server is running PostgreSQL {}, not {expected}",
+                    health.version
+                )))
+            }
+            _ if check.primary && health.in_recovery => {
+                Err(ClusterError::Unhealthy("server is in recovery".into()))
+            }
+            _ => Ok(health),
+        }
+    }
+
+    /// Wait until this cluster accepts connections, or until `deadline`, e.g.
+    /// when using a cluster that another process is starting.
+    ///
+    /// Like `pg_ctl`, this watches the cluster's PID file, in which PostgreSQL
+    /// 10 and later record when the server is ready. With older versions it
+    /// tries to connect to the server's Unix socket. Neither needs a client
+    /// program nor the `client` feature.
+    ///
+    /// Fails with [`ClusterError::StartTimedOut`] if the cluster is not ready
+    /// by the deadline.
+    pub fn wait_until_ready(&self, deadline: Instant) -> Result<(), ClusterError> {
+        let started = Instant::now();
+        let mut delay = Duration::from_millis(1);
+        while !self.ready()? {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(ClusterError::StartTimedOut(started.elapsed()));
+            }
+            std::thread::sleep(delay.min(remaining));
+            delay = (delay * 2).min(Duration::from_millis(50));
+        }
+        self.record_timing(Phase::Ready, started.elapsed());
+        Ok(())
+    }
+
+    /// Is the server accepting connections? See [`Self::wait_until_ready`].
+    fn ready(&self) -> Result<bool, ClusterError> {
+        let content = match fs::read_to_string(self.pidfile()) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        if stale_pid(&self.datadir)?.is_some() {
+            return Ok(false);
+        }
+        let lines: Vec<&str> = content.lines().map(str::trim).collect();
+        match version(self)? {
+            // The server's status is on the eighth line, from PostgreSQL 10.
+            Some(version) if version.major() >= 10 => {
+                Ok(matches!(lines.get(7), Some(&("ready" | "standby"))))
+            }
+            // The port is on the fourth line.
+            _ => match lines.get(3).and_then(|line| line.parse::<u16>().ok()) {
+                Some(port) => {
+                    let socket = self.datadir.join(format!(".s.PGSQL.{port}"));
+                    Ok(std::os::unix::net::UnixStream::connect(socket).is_ok())
+                }
+                None => Ok(false),
+            },
+        }
     }
 
     /// The names of databases in this cluster.
     pub fn databases(&self) -> Result<Vec<String>, ClusterError> {
-        let mut conn = self.connect("template1")?;
-        let rows = conn.query(
+        let rows = self.query(
+            "template1",
             "SELECT datname FROM pg_catalog.pg_database ORDER BY datname",
-            &[],
         )?;
-        let datnames: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
-        Ok(datnames)
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| row.into_iter().next())
+            .collect())
     }
 
-    /// Create the named database.
-    pub fn createdb(&self, database: &str) -> Result<(), ClusterError> {
+    /// Details of the databases in this cluster, ordered by name.
+    pub fn databases_detailed(&self) -> Result<Vec<Database>, ClusterError> {
+        let rows = self.query(
+            "template1",
+            "SELECT d.datname,
+                    pg_catalog.pg_get_userbyid(d.datdba),
+                    pg_catalog.pg_database_size(d.oid),
+                    pg_catalog.pg_encoding_to_char(d.encoding)::text
+               FROM pg_catalog.pg_database d
+              ORDER BY d.datname",
+        )?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| match <[String; 4]>::try_from(row) {
+                Ok([name, owner, size, encoding]) => {
+                    Some(Database { name, owner, size: size.parse().unwrap_or(0), encoding })
+                }
+                Err(_) => None,
+            })
+            .collect())
+    }
+
+    /// The disk space used by this cluster.
+    ///
+    /// The total is the size of the data directory, which can be found whether
+    /// or not the cluster is running. The size of each database, as reported by
+    /// PostgreSQL's `pg_database_size`, can only be found when it is running;
+    /// otherwise [`DiskUsage::databases`] is empty.
+    pub fn disk_usage(&self) -> Result<DiskUsage, ClusterError> {
+        let total = util::dir_size(&self.datadir)?;
+        let databases = if self.running()? {
+            self.query(
+                "template1",
+                "SELECT datname, pg_catalog.pg_database_size(oid)
+                   FROM pg_catalog.pg_database
+                  ORDER BY datname",
+            )?
+            .into_iter()
+            .filter_map(|row| match <[String; 2]>::try_from(row) {
+                Ok([name, size]) => Some((name, size.parse().unwrap_or(0))),
+                Err(_) => None,
+            })
+            .collect()
+        } else {
+            BTreeMap::new()
+        };
+        Ok(DiskUsage { total, databases })
+    }
+
+    /// The current value of every setting in this cluster, by name, as from
+    /// `SHOW ALL`, e.g. `fsync` is `on` or `off`.
+    pub fn settings(&self) -> Result<BTreeMap<String, String>, ClusterError> {
+        Ok(self
+            .query("template1", "SHOW ALL")?
+            .into_iter()
+            .filter_map(|row| {
+                let mut row = row.into_iter();
+                Some((row.next()?, row.next()?))
+            })
+            .collect())
+    }
+
+    /// The current value of the named setting in this cluster, as from `SHOW`,
+    /// or [`None`] if there's no such setting. The name is case-insensitive.
+    pub fn setting(&self, name: &str) -> Result<Option<String>, ClusterError> {
+        let statement = format!(
+            "SELECT pg_catalog.current_setting(name) FROM pg_catalog.pg_settings \
+              WHERE lower(name) = lower({})",
+            util::quote_literal(name),
+        );
+        Ok(self
+            .query("template1", &statement)?
+            .into_iter()
+            .find_map(|row| row.into_iter().next()))
+    }
+
+    /// Change settings in this cluster's configuration with `ALTER SYSTEM`,
+    /// then reload the configuration. A value of [`None`] resets the setting
+    /// to its default.
+    ///
+    /// Before reloading, this checks the configuration files for errors, and
+    /// afterwards it waits for the new settings to take effect. Settings that
+    /// can only be changed by restarting the server fail with
+    /// [`ClusterError::SettingsNotApplied`], though they remain in the
+    /// configuration and take effect when the server is next started. These
+    /// checks need PostgreSQL 9.5 or later; with earlier versions this only
+    /// changes the settings and reloads.
+    pub fn alter_system_and_reload(
+        &self,
+        settings: &[(&str, Option<&str>)],
+    ) -> Result<(), ClusterError> {
+        self.check_writable("alter system")?;
+        for (name, value) in settings {
+            let name = util::quote_identifier(name);
+            let statement = match value {
+                Some(value) => format!("ALTER SYSTEM SET {name} = {}", util::quote_literal(value)),
+                None => format!("ALTER SYSTEM RESET {name}"),
+            };
+            self.query("template1", &statement)?;
+        }
+        let checkable = self
+            .setting("server_version_num")?
+            .and_then(|version| version.parse::<u32>().ok())
+            .is_some_and(|version| version >= 90500);
+        if !checkable {
+            self.query("template1", "SELECT pg_catalog.pg_reload_conf()")?;
+            return Ok(());
+        }
+        // Settings that need a restart cannot be applied by reloading, and
+        // `pg_file_settings` says so; those are dealt with below.
+        let errors: Vec<String> = self
+            .query(
+                "template1",
+                "SELECT concat_ws(': ', f.sourcefile || ':' || f.sourceline, f.name, f.error)
+                   FROM pg_catalog.pg_file_settings f
+                   LEFT JOIN pg_catalog.pg_settings s ON s.name = f.name
+                  WHERE f.error IS NOT NULL
+                    AND s.context IS DISTINCT FROM 'postmaster'
+                  ORDER BY f.seqno",
+            )?
+            .into_iter()
+            .filter_map(|row| row.into_iter().next())
+            .collect();
+        if !errors.is_empty() {
+            return Err(ClusterError::ConfigurationInvalid(errors));
+        }
+        let mut not_applied = self.settings_needing_restart(settings)?;
+        self.query("template1", "SELECT pg_catalog.pg_reload_conf()")?;
+        let pending: Vec<_> = settings
+            .iter()
+            .filter(|(name, _)| !not_applied.iter().any(|n| n.eq_ignore_ascii_case(name)))
+            .copied()
+            .collect();
+        not_applied.extend(self.wait_for_settings(&pending)?);
+        if not_applied.is_empty() {
+            Ok(())
+        } else {
+            not_applied.sort();
+            Err(ClusterError::SettingsNotApplied(not_applied))
+        }
+    }
+
+    /// Which of the given settings, changed with `ALTER SYSTEM`, cannot be
+    /// applied without restarting the server. See
+    /// [`Self::alter_system_and_reload`].
+    fn settings_needing_restart(
+        &self,
+        settings: &[(&str, Option<&str>)],
+    ) -> Result<Vec<String>, ClusterError> {
+        let names: Vec<String> = settings
+            .iter()
+            .map(|(name, _)| util::quote_literal(&name.to_lowercase()))
+            .collect();
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+        // The last entry for each setting is the one that counts.
         let statement = format!(
-            "CREATE DATABASE {}",
-            postgres_protocol::escape::escape_identifier(database)
+            "SELECT name FROM (
+                SELECT DISTINCT ON (lower(name)) name, applied
+                  FROM pg_catalog.pg_file_settings
+                 WHERE lower(name) IN ({})
+                 ORDER BY lower(name), seqno DESC
+             ) f WHERE NOT applied",
+            names.join(", "),
         );
-        self.connect("template1")?
-            .execute(statement.as_str(), &[])?;
+        Ok(self
+            .query("template1", &statement)?
+            .into_iter()
+            .filter_map(|row| row.into_iter().next())
+            .collect())
+    }
+
+    /// Wait for settings changed with `ALTER SYSTEM` to take effect after a
+    /// reload, which the server does asynchronously. Returns the names of those
+    /// that did not take effect in time. See [`Self::alter_system_and_reload`].
+    fn wait_for_settings(
+        &self,
+        settings: &[(&str, Option<&str>)],
+    ) -> Result<Vec<String>, ClusterError> {
+        const TIMEOUT: Duration = Duration::from_secs(10);
+        if settings.is_empty() {
+            return Ok(Vec::new());
+        }
+        // A setting is in effect when it comes from `postgresql.auto.conf`,
+        // where `ALTER SYSTEM` writes, or not from there when reset.
+        let conditions: Vec<String> = settings
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "(lower(name) = lower({}) AND \
+                      coalesce(sourcefile, '') {} '%postgresql.auto.conf')",
+                    util::quote_literal(name),
+                    if value.is_some() { "NOT LIKE" } else { "LIKE" },
+                )
+            })
+            .collect();
+        let statement = format!(
+            "SELECT name FROM pg_catalog.pg_settings WHERE {} ORDER BY name",
+            conditions.join(" OR "),
+        );
+        let started = Instant::now();
+        loop {
+            let names: Vec<String> = self
+                .query("template1", &statement)?
+                .into_iter()
+                .filter_map(|row| row.into_iter().next())
+                .collect();
+            if names.is_empty() || started.elapsed() > TIMEOUT {
+                return Ok(names);
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Change the cluster's [`Durability`] in its configuration, and reload
+    /// the configuration. This is sticky: the cluster continues to run with
+    /// this durability after it's restarted. The cluster must be running.
+    pub fn set_durability(&self, durability: Durability) -> Result<(), ClusterError> {
+        let settings: Vec<_> = match durability {
+            Durability::Default => durability
+                .settings()
+                .iter()
+                .map(|&(name, _)| (name, None))
+                .collect(),
+            Durability::Fast => durability
+                .settings()
+                .iter()
+                .map(|&(name, value)| (name, Some(value)))
+                .collect(),
+        };
+        self.alter_system_and_reload(&settings)
+    }
+
+    /// Make the named database faster for tests, at the expense of durability.
+    ///
+    /// Sessions in the database do not wait for commits to be flushed, i.e.
+    /// `synchronous_commit` is off. With `unlogged_tables`, an event trigger
+    /// also makes tables created in the database `UNLOGGED`; tables that cannot
+    /// be made unlogged, e.g. temporary tables, are left alone. Use this in
+    /// `template1` to have new databases inherit the event trigger, though not
+    /// the `synchronous_commit` setting.
+    ///
+    /// DANGER: unlogged tables are emptied if the server crashes. Also, since a
+    /// permanent table cannot reference an unlogged table, a table created with
+    /// a foreign key to an earlier table fails; create it with `UNLOGGED`. The
+    /// event trigger needs PostgreSQL 9.5 or later.
+    pub fn speed_up_for_tests(
+        &self,
+        database: &str,
+        unlogged_tables: bool,
+    ) -> Result<(), ClusterError> {
+        self.check_writable("alter database")?;
+        let mut statement = format!(
+            "ALTER DATABASE {} SET synchronous_commit = off;",
+            util::quote_identifier(database),
+        );
+        if unlogged_tables {
+            statement.push_str(UNLOGGED_TABLES_SQL);
+        }
+        self.query(database, &statement)?;
+        Ok(())
+    }
+
+    /// Create the named database.
+    pub fn createdb(&self, database: &str) -> Result<(), ClusterError> {
+        self.check_writable("create database")?;
+        let statement = format!("CREATE DATABASE {}", util::quote_identifier(database));
+        self.query("template1", &statement)?;
         Ok(())
     }
 
     /// Drop the named database.
     pub fn dropdb(&self, database: &str) -> Result<(), ClusterError> {
-        let statement = format!(
-            "DROP DATABASE {}",
-            postgres_protocol::escape::escape_identifier(database)
-        );
-        self.connect("template1")?
-            .execute(statement.as_str(), &[])?;
+        self.check_writable("drop database")?;
+        let statement = format!("DROP DATABASE {}", util::quote_identifier(database));
+        self.query("template1", &statement)?;
         Ok(())
     }
 
+    /// Run the given SQL in the given database, returning the rows of the
+    /// last statement's result as text; `NULL` becomes an empty string. With
+    /// the `client` feature this uses a connection from [`Self::connect`].
+    #[cfg(feature = "client")]
+    fn query(&self, database: &str, sql: &str) -> Result<Vec<Vec<String>>, ClusterError> {
+        let messages = self.connect(database)?.simple_query(sql)?;
+        Ok(messages
+            .iter()
+            .filter_map(|message| match message {
+                postgres::SimpleQueryMessage::Row(row) => Some(
+                    (0..row.len())
+                        .map(|index| row.get(index).unwrap_or_default().to_owned())
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Run the given SQL in the given database, returning the rows of the
+    /// last statement's result as text; `NULL` becomes an empty string.
+    /// Without the `client` feature this runs the runtime's `psql`.
+    #[cfg(not(feature = "client"))]
+    fn query(&self, database: &str, sql: &str) -> Result<Vec<Vec<String>>, ClusterError> {
+        // psql options:
+        //  -X -- do not read `~/.psqlrc`.
+        //  -A -t -- unaligned output, rows only.
+        //  -F <sep> -- separate fields with the ASCII unit separator.
+        //  -0 -- terminate rows with a zero byte.
+        let mut command = self.client("psql", database)?;
+        command
+            .args(["-X", "-q", "-A", "-t", "-F", "\x1f", "-0"])
+            .args(["-v", "ON_ERROR_STOP=1", "-c", sql]);
+        let output = run(&mut command)?;
+        if !output.status.success() {
+            return Err(ClusterError::Other(output));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout
+            .strip_suffix('\0')
+            .map(|rows| {
+                rows.split('\0')
+                    .map(|row| row.split('\x1f').map(str::to_owned).collect())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Dump the given database to a file using `pg_dump`, in its custom
+    /// archive format. This can be restored with [`Self::restore`]. The
+    /// cluster must be running.
+    pub fn dump<P: AsRef<Path>>(&self, database: &str, path: P) -> Result<(), ClusterError> {
+        let mut command = self.client("pg_dump", database)?;
+        command
+            .arg("--format=custom")
+            .arg("--file")
+            .arg(path.as_ref());
+        check(run(&mut command)?)
+    }
+
+    /// Restore a dump made by [`Self::dump`], or by `pg_dump` in one of its
+    /// archive formats, into the given database using `pg_restore`. The
+    /// database must already exist, and the cluster must be running. This
+    /// stops at the first error.
+    pub fn restore<P: AsRef<Path>>(&self, database: &str, path: P) -> Result<(), ClusterError> {
+        self.check_writable("restore")?;
+        let mut command = self.client("pg_restore", database)?;
+        command
+            .arg("--exit-on-error")
+            .arg("--dbname")
+            .arg(database)
+            .arg(path.as_ref());
+        check(run(&mut command)?)
+    }
+
+    /// A command for running one of the runtime's client programs, e.g.
+    /// `pg_dump`, against the given database in this cluster.
+    fn client(&self, program: &str, database: &str) -> Result<Command, ClusterError> {
+        let runtime = self.runtime()?;
+        runtime.validate_programs(&[program])?;
+        let mut command = runtime.execute(program);
+        command.env("PGHOST", &self.datadir);
+        command.env("PGDATABASE", database);
+        command.env("PGUSER", user());
+        if let Some(port) = self.port()? {
+            command.env("PGPORT", port.to_string());
+        }
+        Ok(command)
+    }
+
     /// Stop the cluster if it's running.
+    #[tracing::instrument(skip(self), fields(datadir = %self.datadir.display()))]
     pub fn stop(&self) -> Result<State, ClusterError> {
         match self._stop() {
             Err(ClusterError::UnixError(Errno::EAGAIN)) if !self.running()? => Ok(Unmodified),
@@ -341,27 +1381,135 @@ impl Cluster {
         if !self.running()? {
             return Ok(Unmodified);
         }
+        let started = Instant::now();
+        run(&mut self.stop_command()?)?;
+        self.record_timing(Phase::Stop, started.elapsed());
+        Ok(Modified)
+    }
+
+    /// A [`Command`] that will stop the cluster with `pg_ctl`.
+    fn stop_command(&self) -> Result<Command, ClusterError> {
         // pg_ctl options:
         //  -w -- wait for shutdown to complete.
         //  -m <mode> -- shutdown mode.
-        self.ctl()?
+        let mut command = self.ctl()?;
+        command
             .arg("stop")
             .arg("-s")
             .arg("-w")
             .arg("-m")
-            .arg("fast")
-            .output()?;
-        Ok(Modified)
+            .arg("fast");
+        Ok(command)
     }
 
     /// Destroy the cluster if it exists, after stopping it.
+    #[tracing::instrument(skip(self), fields(datadir = %self.datadir.display()))]
     pub fn destroy(&self) -> Result<State, ClusterError> {
+        self.check_writable("destroy")?;
         match self._destroy() {
             Err(ClusterError::UnixError(Errno::EAGAIN)) => Err(ClusterError::InUse),
             other => other,
         }
     }
 
+    /// Remove what's left of this cluster after `initdb` was interrupted, e.g.
+    /// killed, so that it can be created again. This is what to do after
+    /// [`ClusterError::InitdbInterrupted`]. The data directory itself is kept.
+    ///
+    /// Returns [`Unmodified`] if there's nothing to repair, i.e. if the
+    /// cluster exists, or there's nothing in the data directory. Fails, as
+    /// [`check_datadir`] does, when the data directory holds something else.
+    ///
+    /// Make sure that no other process is running `initdb` for this cluster
+    /// at the same time, e.g. by holding an exclusive lock. The functions in
+    /// the [`coordinate`][`crate::coordinate`] module do this, and repair
+    /// clusters as needed.
+    #[tracing::instrument(skip(self), fields(datadir = %self.datadir.display()))]
+    pub fn repair(&self) -> Result<State, ClusterError> {
+        self.check_writable("repair")?;
+        match check_datadir(&self.datadir) {
+            Err(ClusterError::InitdbInterrupted(_)) => {
+                tracing::warn!("removing remains of interrupted initdb");
+                for entry in fs::read_dir(&self.datadir)? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_dir() {
+                        fs::remove_dir_all(entry.path())?;
+                    } else {
+                        fs::remove_file(entry.path())?;
+                    }
+                }
+                Ok(Modified)
+            }
+            Err(ClusterError::NotADataDirectory(_, _)) if exists(self) => Ok(Unmodified),
+            Err(err) => Err(err),
+            Ok(()) => Ok(Unmodified),
+        }
+    }
+
+    /// Describe what [`Self::create`], [`Self::start`], [`Self::stop`], or
+    /// [`Self::destroy`] would do now, without doing it, e.g. to debug
+    /// problems with the environment:
+    ///
+    /// ```rust
+    /// # use postgresfixture::prelude::*;
+    /// # use postgresfixture::cluster::Operation;
+    /// let data_dir = tempdir::TempDir::new("data")?;
+    /// let cluster = Cluster::new(&data_dir, runtime::strategy::default())?;
+    /// for step in cluster.plan(Operation::Start)? {
+    ///     println!("{step}");
+    /// }
+    /// # Ok::<(), ClusterError>(())
+    /// ```
+    ///
+    /// This looks at the cluster to see what needs doing, e.g. whether it
+    /// exists or is running, and that may run `pg_ctl status`, but nothing is
+    /// changed. As with [`Self::start`], a free port is chosen when listening
+    /// on TCP port 0, but it may not be free later.
+    pub fn plan(&self, operation: Operation) -> Result<Vec<Step>, ClusterError> {
+        let mut steps = Vec::new();
+        let exists = exists(self);
+        match operation {
+            Operation::Create => self.plan_create(&mut steps)?,
+            Operation::Start => {
+                self.plan_create(&mut steps)?;
+                if !(exists && self.running()?) {
+                    let command = self.start_command()?;
+                    if let (Some(rotation), None | Some(Log::File(_))) =
+                        (self.log_rotation, &self.log)
+                    {
+                        let logfile = self.logfile();
+                        if rotation_due(&logfile, rotation)? {
+                            steps.push(Step::RotateLog(logfile));
+                        }
+                    }
+                    steps.push(Step::Run(describe(&command)));
+                }
+            }
+            Operation::Stop | Operation::Destroy => {
+                if exists && self.running()? {
+                    steps.push(Step::Run(describe(&self.stop_command()?)));
+                }
+                if operation == Operation::Destroy && self.datadir.is_dir() {
+                    steps.push(Step::RemoveDir(self.datadir.clone()));
+                }
+            }
+        }
+        Ok(steps)
+    }
+
+    fn plan_create(&self, steps: &mut Vec<Step>) -> Result<(), ClusterError> {
+        if !exists(self) {
+            let runtime = self.runtime()?;
+            runtime.validate()?;
+            if !self.datadir.is_dir() {
+                steps.push(Step::CreateDir(self.datadir.clone()));
+            }
+            steps.push(Step::Run(describe(&init(&runtime, &self.datadir))));
+            steps.push(Step::WriteFile(self.datadir.join(OWNER_FILE)));
+        }
+        Ok(())
+    }
+
     fn _destroy(&self) -> Result<State, ClusterError> {
         if self._stop()? == Modified || self.datadir.is_dir() {
             fs::remove_dir_all(&self.datadir)?;
@@ -370,6 +1518,216 @@ impl Cluster {
             Ok(Unmodified)
         }
     }
+
+    /// Upgrade the cluster to the given runtime's version of PostgreSQL, after
+    /// stopping it.
+    ///
+    /// This uses `pg_upgrade`, which needs the runtime for the cluster's
+    /// current version too, i.e. [`Self::runtime`]. A new cluster is created
+    /// alongside this one, in a directory with an `.upgrade` suffix, and the
+    /// data are copied into it. Only once that has succeeded does it replace
+    /// this cluster. Settings made with `ALTER SYSTEM` are carried over.
+    ///
+    /// Returns [`Unmodified`] if the cluster already has the same major
+    /// version as `to`. To use the cluster after upgrading, this cluster's
+    /// runtime strategy must be able to find a runtime for the new version.
+    #[tracing::instrument(skip(self), fields(datadir = %self.datadir.display()))]
+    pub fn upgrade(&self, to: &runtime::Runtime) -> Result<State, ClusterError> {
+        self.check_writable("upgrade")?;
+        match self._upgrade(to, false) {
+            Err(ClusterError::UnixError(Errno::EAGAIN)) => Err(ClusterError::InUse),
+            other => other,
+        }
+    }
+
+    /// Check that the cluster can be upgraded to the given runtime's version
+    /// of PostgreSQL, after stopping it, without upgrading it. See
+    /// [`Self::upgrade`].
+    ///
+    /// This uses `pg_upgrade --check`. Returns [`Unmodified`] if the cluster
+    /// already has the same major version as `to`, i.e. there's nothing to do,
+    /// else [`Modified`].
+    #[tracing::instrument(skip(self), fields(datadir = %self.datadir.display()))]
+    pub fn upgrade_check(&self, to: &runtime::Runtime) -> Result<State, ClusterError> {
+        match self._upgrade(to, true) {
+            Err(ClusterError::UnixError(Errno::EAGAIN)) => Err(ClusterError::InUse),
+            other => other,
+        }
+    }
+
+    fn _upgrade(&self, to: &runtime::Runtime, check_only: bool) -> Result<State, ClusterError> {
+        let Some(version) = version(self)? else {
+            return Err(ClusterError::DataDirectoryNotFound(self.datadir.clone()));
+        };
+        if version.compatible(to.version) {
+            return Ok(Unmodified);
+        }
+        // Check that both runtimes are complete before making any changes.
+        let from = self.runtime()?;
+        from.validate()?;
+        to.validate()?;
+        to.validate_programs(&["pg_upgrade"])?;
+        self._stop()?;
+
+        // Create the new cluster. Remove any left behind by an earlier attempt.
+        let datadir = self.sibling(".upgrade");
+        if datadir.is_dir() {
+            fs::remove_dir_all(&datadir)?;
+        }
+        fs::create_dir_all(&datadir)?;
+        let pg_upgrade = || {
+            check(run(&mut init(to, &datadir))?)?;
+            let mut command = to.execute("pg_upgrade");
+            command
+                .arg("--old-bindir")
+                .arg(&from.bindir)
+                .arg("--new-bindir")
+                .arg(&to.bindir)
+                .arg("--old-datadir")
+                .arg(&self.datadir)
+                .arg("--new-datadir")
+                .arg(&datadir)
+                // pg_upgrade puts its sockets and logs in the current directory.
+                .current_dir(&datadir);
+            if check_only {
+                command.arg("--check");
+            }
+            check(run(&mut command)?)
+        };
+        let result = pg_upgrade();
+        if check_only || result.is_err() {
+            fs::remove_dir_all(&datadir)?;
+            return result.map(|()| Modified);
+        }
+
+        // `ALTER SYSTEM` settings are not copied by pg_upgrade.
+        let auto_conf = self.datadir.join("postgresql.auto.conf");
+        if auto_conf.is_file() {
+            fs::copy(&auto_conf, datadir.join("postgresql.auto.conf"))?;
+        }
+        self.replace_datadir(&datadir)?;
+        Ok(Modified)
+    }
+
+    /// Back up the cluster with `pg_basebackup` into the directory `path`,
+    /// which must not exist or be empty. The backup is in tar format, i.e.
+    /// `base.tar` and any tablespaces, and includes the WAL needed to start a
+    /// cluster from it. The cluster must be running.
+    pub fn backup<P: AsRef<Path>>(&self, path: P) -> Result<(), ClusterError> {
+        let mut command = self.client("pg_basebackup", "postgres")?;
+        command
+            .arg("--pgdata")
+            .arg(path.as_ref())
+            .arg("--format=tar")
+            .arg("--wal-method=fetch")
+            .arg("--checkpoint=fast");
+        check(run(&mut command)?)
+    }
+
+    /// Save a snapshot of the cluster, after stopping it, by copying its data
+    /// directory to `path`, which must not exist. Use
+    /// [`Self::restore_snapshot`] to put it back.
+    #[tracing::instrument(skip(self, path), fields(
+        datadir = %self.datadir.display(),
+        path = %path.as_ref().display(),
+    ))]
+    pub fn snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), ClusterError> {
+        if !exists(self) {
+            return Err(ClusterError::DataDirectoryNotFound(self.datadir.clone()));
+        }
+        self._stop()?;
+        let path = path.as_ref();
+        if let Err(err) = util::copy_dir(&self.datadir, path) {
+            // Don't leave a partial snapshot behind, unless it was already
+            // there, i.e. it's another snapshot.
+            if err.kind() != io::ErrorKind::AlreadyExists {
+                fs::remove_dir_all(path).ok();
+            }
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    /// Replace the cluster with a snapshot saved by [`Self::snapshot`], after
+    /// stopping it. The snapshot is copied, so it can be restored again.
+    #[tracing::instrument(skip(self, path), fields(
+        datadir = %self.datadir.display(),
+        path = %path.as_ref().display(),
+    ))]
+    pub fn restore_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), ClusterError> {
+        self.check_writable("restore snapshot")?;
+        let path = path.as_ref();
+        if !exists(path) {
+            return Err(ClusterError::DataDirectoryNotFound(path.to_owned()));
+        }
+        if exists(self) {
+            self._stop()?;
+        }
+        // Copy the snapshot alongside, then swap it into place.
+        let datadir = self.sibling(".restore");
+        if datadir.is_dir() {
+            fs::remove_dir_all(&datadir)?;
+        }
+        util::copy_dir(path, &datadir)?;
+        self.replace_datadir(&datadir)
+    }
+
+    /// A path alongside the data directory, with the given suffix, e.g.
+    /// `/path/to/cluster.upgrade` when the suffix is `.upgrade`.
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut path = self.datadir.clone().into_os_string();
+        path.push(suffix);
+        PathBuf::from(path)
+    }
+
+    /// Replace the data directory with `datadir`, which is moved into place.
+    fn replace_datadir(&self, datadir: &Path) -> Result<(), ClusterError> {
+        if self.datadir.exists() {
+            let previous = self.sibling(".previous");
+            fs::rename(&self.datadir, &previous)?;
+            fs::rename(datadir, &self.datadir)?;
+            fs::remove_dir_all(&previous)?;
+        } else {
+            fs::rename(datadir, &self.datadir)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The strategy is not `Debug`, and resolving the runtime from it may be
+        // slow or fail, so it's left out.
+        f.debug_struct("Cluster")
+            .field("datadir", &self.datadir)
+            .field("tcp", &self.tcp)
+            .field("settings", &self.settings)
+            .field("server_env", &self.server_env)
+            .field("start_timeout", &self.start_timeout)
+            .field("log", &self.log)
+            .field("log_rotation", &self.log_rotation)
+            .field("connect_retry", &self.connect_retry)
+            .field("read_only", &self.read_only)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Summarises the cluster for humans, e.g. in logs. This reads the cluster's
+/// `PG_VERSION` and PID files but, unlike [`Cluster::runtime`] and
+/// [`Cluster::running`], does not run any commands.
+impl fmt::Display for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.datadir.display())?;
+        match version(&self.datadir) {
+            Ok(Some(version)) => write!(f, " (PostgreSQL {version}")?,
+            Ok(None) => return write!(f, " (not created)"),
+            Err(_) => return write!(f, " (unknown version)"),
+        }
+        match self.port() {
+            Ok(Some(port)) => write!(f, ", port {port})"),
+            Ok(None) | Err(_) => write!(f, ")"),
+        }
+    }
 }
 
 impl AsRef<Path> for Cluster {
@@ -378,6 +1736,366 @@ impl AsRef<Path> for Cluster {
     }
 }
 
+/// A command that will create a cluster in `datadir` using `runtime`.
+fn init(runtime: &runtime::Runtime, datadir: &Path) -> Command {
+    let mut command = runtime.execute("pg_ctl");
+    #[allow(clippy::suspicious_command_arg_space)]
+    command
+        .env("PGDATA", datadir)
+        .arg("init")
+        .arg("-s")
+        .arg("-o")
+        // Passing multiple flags in a single `arg(...)` is intentional. These
+        // constitute the single value for the `-o` flag above.
+        .arg("-E utf8 --locale C -A trust")
+        .env("TZ", "UTC");
+    command
+}
+
+/// Describe the given command as a shell command line, with the environment
+/// variables it sets, e.g. `PGDATA=/some/where /path/to/pg_ctl status`. The
+/// variables that every runtime command sets, e.g. `PATH`, are omitted; see
+/// [`runtime::Runtime::execute`].
+fn describe(command: &Command) -> String {
+    // Quote for a POSIX shell, so that the line can be copied and run. This
+    // differs from `escape_into`, which quotes the server options in `-o`.
+    let quote = |s: &OsStr| {
+        let s = s.to_string_lossy();
+        let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+        if !s.is_empty() && s.chars().all(safe) {
+            s.into_owned()
+        } else {
+            format!("'{}'", s.replace('\'', r"'\''"))
+        }
+    };
+    let mut words = Vec::new();
+    for (name, value) in command.get_envs() {
+        if !matches!(
+            name.to_str(),
+            Some("PATH" | "LD_LIBRARY_PATH" | "DYLD_LIBRARY_PATH")
+        ) {
+            let value = value.map(quote).unwrap_or_default();
+            words.push(format!("{}={value}", name.to_string_lossy()));
+        }
+    }
+    words.push(quote(command.get_program()));
+    words.extend(command.get_args().map(quote));
+    words.join(" ")
+}
+
+/// Run the given command to completion, collecting its output. The command and
+/// how long it took are logged.
+fn run(command: &mut Command) -> Result<Output, io::Error> {
+    tracing::debug!(?command, "running command");
+    let started = Instant::now();
+    let output = command.output()?;
+    tracing::debug!(status = %output.status, elapsed = ?started.elapsed(), "command finished");
+    Ok(output)
+}
+
+/// Fail unless the command that produced the given output succeeded.
+fn check(output: Output) -> Result<(), ClusterError> {
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(ClusterError::Other(output))
+    }
+}
+
+/// The user as which to connect to a cluster. Clusters are created by the
+/// current user, who is the superuser.
+fn user() -> String {
+    env::var("USER").unwrap_or_else(|_| "USER-not-set".to_string())
+}
+
+/// A database in a cluster. See [`Cluster::databases_detailed`].
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct Database {
+    /// The name of the database.
+    pub name: String,
+    /// The name of the role that owns the database.
+    pub owner: String,
+    /// The disk space used by the database, in bytes.
+    pub size: u64,
+    /// The character set encoding of the database, e.g. `UTF8`.
+    pub encoding: String,
+}
+
+/// The disk space used by a cluster. See [`Cluster::disk_usage`].
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct DiskUsage {
+    /// The size of the data directory, in bytes.
+    pub total: u64,
+    /// The size, in bytes, of each database, by name.
+    pub databases: BTreeMap<String, u64>,
+}
+
+/// An event trigger that makes new tables `UNLOGGED`. See
+/// [`Cluster::speed_up_for_tests`].
+const UNLOGGED_TABLES_SQL: &str = "
+CREATE OR REPLACE FUNCTION postgresfixture_unlogged_tables()
+RETURNS event_trigger LANGUAGE plpgsql AS $$
+DECLARE
+  command record;
+BEGIN
+  FOR command IN
+    SELECT object_identity FROM pg_catalog.pg_event_trigger_ddl_commands()
+     WHERE object_type = 'table'
+  LOOP
+    BEGIN
+      EXECUTE format('ALTER TABLE %s SET UNLOGGED', command.object_identity);
+    EXCEPTION WHEN others THEN
+      -- e.g. a temporary table, or one that references a logged table.
+      NULL;
+    END;
+  END LOOP;
+END $$;
+DROP EVENT TRIGGER IF EXISTS postgresfixture_unlogged_tables;
+CREATE EVENT TRIGGER postgresfixture_unlogged_tables ON ddl_command_end
+  WHEN TAG IN ('CREATE TABLE', 'CREATE TABLE AS', 'SELECT INTO')
+  EXECUTE PROCEDURE postgresfixture_unlogged_tables();
+";
+
+/// How much care a cluster takes to keep its data safe. See
+/// [`Cluster::set_durability`] and [`Cluster::with_durability`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Durability {
+    /// PostgreSQL's defaults for `fsync`, `full_page_writes`, and
+    /// `synchronous_commit`.
+    Default,
+    /// Disable `fsync`, `full_page_writes`, and `synchronous_commit`. This
+    /// makes the cluster faster, but DANGER: it can lead to unrecoverable data
+    /// corruption in the event of a power failure or system crash.
+    Fast,
+}
+
+impl Durability {
+    /// The settings that make up this durability.
+    fn settings(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Durability::Default => &[
+                ("fsync", "on"),
+                ("full_page_writes", "on"),
+                ("synchronous_commit", "on"),
+            ],
+            Durability::Fast => &[
+                ("fsync", "off"),
+                ("full_page_writes", "off"),
+                ("synchronous_commit", "off"),
+            ],
+        }
+    }
+}
+
+/// Where a cluster's server writes its log. See [`Cluster::with_log`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Log {
+    /// Append the server's output to this file. This is passed to `pg_ctl` as
+    /// its `-l` option, so a relative path is relative to the current working
+    /// directory.
+    File(PathBuf),
+    /// Use PostgreSQL's logging collector, which writes into files in
+    /// `directory` and can rotate them. A relative `directory` is relative to
+    /// the data directory.
+    Collector {
+        directory: PathBuf,
+        /// Start a new log file after this long; rounded to the nearest
+        /// minute. Without this, PostgreSQL's default of 1 day applies.
+        rotation_age: Option<Duration>,
+        /// Start a new log file once the current one reaches this many bytes;
+        /// rounded up to the nearest kilobyte. Without this, PostgreSQL's
+        /// default of 10MB applies.
+        rotation_size: Option<u64>,
+    },
+    /// Let the server write to this process's stderr, e.g. when supervising
+    /// the server in the foreground.
+    Stderr,
+}
+
+impl Log {
+    /// The server settings needed for this log destination.
+    fn settings(&self) -> Vec<(&str, OsString)> {
+        match self {
+            Log::File(_) | Log::Stderr => vec![],
+            Log::Collector { directory, rotation_age, rotation_size } => {
+                let mut settings = vec![
+                    ("logging_collector", "on".into()),
+                    ("log_directory", directory.into()),
+                ];
+                if let Some(age) = rotation_age {
+                    let minutes = (age.as_secs() + 30) / 60;
+                    settings.push(("log_rotation_age", format!("{minutes}min").into()));
+                }
+                if let Some(size) = rotation_size {
+                    let kilobytes = size.div_ceil(1024);
+                    settings.push(("log_rotation_size", format!("{kilobytes}kB").into()));
+                }
+                settings
+            }
+        }
+    }
+}
+
+/// When to rotate a cluster's log file. See [`Cluster::with_log_rotation`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LogRotation {
+    /// Rotate the log file once it's at least this many bytes.
+    Size(u64),
+    /// Rotate the log file if it was last written on an earlier day, in UTC.
+    Daily,
+}
+
+/// How to retry connecting to a cluster that is not yet accepting connections.
+/// See [`Cluster::with_connect_retry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectRetry {
+    /// Stop retrying after this long, and fail with the last error.
+    pub timeout: Duration,
+    /// How long to wait after the first failure. This doubles after each
+    /// failure thereafter, up to `max_delay`.
+    pub initial_delay: Duration,
+    /// The longest to wait between attempts.
+    pub max_delay: Duration,
+}
+
+impl Default for ConnectRetry {
+    /// Retry for up to 10 seconds, waiting 10ms at first, and at most 500ms.
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Options for a connection made with [`Cluster::connect_with`].
+///
+/// This is only available with the `client` feature.
+#[cfg(feature = "client")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectOptions {
+    /// Set `application_name`, which shows in `pg_stat_activity` and in the
+    /// server's log, e.g. to tell which test a connection belongs to.
+    pub application_name: Option<String>,
+    /// Set `search_path` to these schemas, in order. They do not need to be
+    /// quoted.
+    pub search_path: Option<Vec<String>>,
+    /// Set `statement_timeout`, so that statements are cancelled by the server
+    /// if they take longer. This is rounded down to the nearest millisecond.
+    pub statement_timeout: Option<Duration>,
+    /// Give up connecting after this long. See also
+    /// [`Cluster::with_connect_retry`], which may try again.
+    pub connect_timeout: Option<Duration>,
+}
+
+#[cfg(feature = "client")]
+impl ConnectOptions {
+    /// Apply these options to `config`. Settings are sent as command-line
+    /// options for the server process, as if with `PGOPTIONS`.
+    fn configure(&self, config: &mut postgres::Config) {
+        if let Some(name) = &self.application_name {
+            config.application_name(name);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            config.connect_timeout(timeout);
+        }
+        let mut settings = Vec::new();
+        if let Some(schemas) = &self.search_path {
+            let schemas: Vec<String> = schemas.iter().map(|s| util::quote_identifier(s)).collect();
+            settings.push(format!("search_path={}", schemas.join(",")));
+        }
+        if let Some(timeout) = self.statement_timeout {
+            settings.push(format!("statement_timeout={}", timeout.as_millis()));
+        }
+        if !settings.is_empty() {
+            // Spaces and backslashes in options must be escaped with a
+            // backslash.
+            let options: Vec<String> = settings
+                .iter()
+                .map(|setting| {
+                    let setting = setting.replace('\\', r"\\").replace(' ', r"\ ");
+                    format!("-c {setting}")
+                })
+                .collect();
+            config.options(&options.join(" "));
+        }
+    }
+}
+
+/// What [`Cluster::health_check`] should check, beyond the server answering a
+/// trivial query.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HealthCheck {
+    /// The server must be running a version compatible with this one.
+    pub version: Option<version::PartialVersion>,
+    /// The server must not be in recovery, e.g. a standby, so it can be
+    /// written to.
+    pub primary: bool,
+}
+
+/// The state of a cluster, from [`Cluster::health_check`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Health {
+    /// The version of PostgreSQL the server is running.
+    pub version: version::Version,
+    /// Whether the server is in recovery, e.g. because it's a standby.
+    pub in_recovery: bool,
+}
+
+/// Is this error from connecting likely to go away by itself, i.e. because the
+/// server is not yet listening, or is still starting up?
+#[cfg(feature = "client")]
+fn connect_error_is_transient(err: &postgres::Error) -> bool {
+    use std::error::Error;
+    err.code() == Some(&postgres::error::SqlState::CANNOT_CONNECT_NOW)
+        || matches!(err.source(), Some(source) if source.is::<io::Error>())
+}
+
+/// How many rotated log files to keep; see [`Cluster::with_log_rotation`].
+const LOG_FILES_KEPT: u32 = 3;
+
+/// Is `logfile` due to be rotated? It is not if it does not exist.
+fn rotation_due(logfile: &Path, rotation: LogRotation) -> io::Result<bool> {
+    let metadata = match fs::metadata(logfile) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    let day = |time: SystemTime| {
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            / (24 * 60 * 60)
+    };
+    Ok(match rotation {
+        LogRotation::Size(size) => metadata.len() >= size,
+        LogRotation::Daily => day(metadata.modified()?) < day(SystemTime::now()),
+    })
+}
+
+/// Rotate `logfile`, if it exists, when `rotation` says it's due.
+fn rotate_log(logfile: &Path, rotation: LogRotation) -> io::Result<()> {
+    if rotation_due(logfile, rotation)? {
+        let rotated = |n: u32| {
+            let mut path = logfile.as_os_str().to_owned();
+            path.push(format!(".{n}"));
+            PathBuf::from(path)
+        };
+        for n in (1..LOG_FILES_KEPT).rev() {
+            match fs::rename(rotated(n), rotated(n + 1)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => (),
+            }
+        }
+        fs::rename(logfile, rotated(1))?;
+    }
+    Ok(())
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum State {
     /// The action we requested was performed from this process, e.g. we tried
@@ -391,15 +2109,337 @@ pub enum State {
 // For convenience.
 use State::{Modified, Unmodified};
 
+/// An operation to describe with [`Cluster::plan`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operation {
+    /// As [`Cluster::create`].
+    Create,
+    /// As [`Cluster::start`].
+    Start,
+    /// As [`Cluster::stop`].
+    Stop,
+    /// As [`Cluster::destroy`].
+    Destroy,
+}
+
+/// A step that an [`Operation`] would take; see [`Cluster::plan`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Step {
+    /// Run a command, described as a shell command line.
+    Run(String),
+    /// Create a directory, and its parents.
+    CreateDir(PathBuf),
+    /// Write a file.
+    WriteFile(PathBuf),
+    /// Rotate a log file; see [`LogRotation`].
+    RotateLog(PathBuf),
+    /// Remove a directory and everything in it.
+    RemoveDir(PathBuf),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Run(command) => write!(fmt, "run: {command}"),
+            Self::CreateDir(path) => write!(fmt, "create directory: {}", path.display()),
+            Self::WriteFile(path) => write!(fmt, "write file: {}", path.display()),
+            Self::RotateLog(path) => write!(fmt, "rotate log: {}", path.display()),
+            Self::RemoveDir(path) => write!(fmt, "remove directory: {}", path.display()),
+        }
+    }
+}
+
 /// A fairly simplistic but quick check: does the directory exist and does it
-/// look like a PostgreSQL cluster data directory, i.e. does it contain a file
-/// named `PG_VERSION`?
+/// look like a PostgreSQL cluster data directory, i.e. does it contain files
+/// named `PG_VERSION` and `global/pg_control`?
 ///
 /// [`version()`] provides a more reliable measure, plus yields the version of
-/// PostgreSQL required to use the cluster.
+/// PostgreSQL required to use the cluster. [`check_datadir`] is stricter.
 pub fn exists<P: AsRef<Path>>(datadir: P) -> bool {
     let datadir = datadir.as_ref();
-    datadir.is_dir() && datadir.join("PG_VERSION").is_file()
+    datadir.is_dir()
+        && datadir.join("PG_VERSION").is_file()
+        && datadir.join("global").join("pg_control").is_file()
+}
+
+/// Check that a cluster can be created or started in `datadir`. It must not
+/// exist, be an empty directory, or be a data directory – see [`exists`] –
+/// that is owned by this process's user and that others cannot access, as the
+/// server requires.
+///
+/// Fails with [`ClusterError::NotADataDirectory`] otherwise, e.g. when given
+/// the wrong directory by mistake, rather than leaving `initdb` or the server
+/// to fail with a less helpful message. When the directory holds only what
+/// `initdb` leaves behind when interrupted, this fails with
+/// [`ClusterError::InitdbInterrupted`] instead; see [`Cluster::repair`].
+pub fn check_datadir<P: AsRef<Path>>(datadir: P) -> Result<(), ClusterError> {
+    use std::os::unix::fs::MetadataExt;
+    let datadir = datadir.as_ref();
+    let unusable = |reason: String| Err(ClusterError::NotADataDirectory(datadir.into(), reason));
+    let metadata = match fs::metadata(datadir) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    if !metadata.is_dir() {
+        unusable("it is not a directory".into())
+    } else if !exists(datadir) {
+        let names = fs::read_dir(datadir)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if names.is_empty() {
+            Ok(())
+        } else if names.iter().all(|name| made_by_initdb(name)) {
+            Err(ClusterError::InitdbInterrupted(datadir.into()))
+        } else {
+            unusable("it is not empty but has no PG_VERSION or global/pg_control".into())
+        }
+    } else if metadata.uid() != nix::unistd::geteuid().as_raw() {
+        unusable(format!("it is owned by user ID {}", metadata.uid()))
+    } else if metadata.mode() & 0o027 != 0 {
+        unusable(format!(
+            "its permissions are {:04o}, but must be 0700 or 0750",
+            metadata.mode() & 0o7777
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Could `initdb` have created a file or directory with this name in a data
+/// directory? This is true of, e.g., `base`, `pg_wal`, and `postgresql.conf`.
+fn made_by_initdb(name: &OsStr) -> bool {
+    name.to_str().is_some_and(|name| {
+        matches!(name, "PG_VERSION" | "base" | "global")
+            || name.starts_with("pg_")
+            || name.starts_with("postgresql.")
+    })
+}
+
+/// The environment variable that names a RAM-backed directory; see
+/// [`ram_dir`].
+pub const RAM_DIR_VAR: &str = "POSTGRESFIXTURE_RAM_DIR";
+
+/// Directories on RAM-backed filesystems that are commonly available.
+#[cfg(target_os = "linux")]
+const RAM_DIRS: &[&str] = &["/dev/shm", "/run/shm"];
+#[cfg(not(target_os = "linux"))]
+const RAM_DIRS: &[&str] = &[];
+
+/// A directory on a RAM-backed filesystem in which to put data directories.
+/// Clusters there are much faster, which can speed up test suites a lot, but
+/// they use memory and are lost when the system restarts.
+///
+/// This is the directory named by `POSTGRESFIXTURE_RAM_DIR`, e.g. a RAM disk
+/// mounted on macOS, or otherwise `/dev/shm` on Linux. Returns [`None`] when
+/// there's no such directory, or it's not writable; [`ram_or_temp_dir`] falls
+/// back to the system's temporary directory instead.
+pub fn ram_dir() -> Option<PathBuf> {
+    find_ram_dir(env::var_os(RAM_DIR_VAR), RAM_DIRS)
+}
+
+/// The first writable directory of `var`, if set and not empty, and then
+/// `candidates`.
+fn find_ram_dir(var: Option<OsString>, candidates: &[&str]) -> Option<PathBuf> {
+    let writable = |dir: &Path| {
+        let flags = nix::unistd::AccessFlags::W_OK | nix::unistd::AccessFlags::X_OK;
+        dir.is_dir() && nix::unistd::access(dir, flags).is_ok()
+    };
+    var.filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(candidates.iter().map(PathBuf::from))
+        .find(|dir| writable(dir))
+}
+
+/// [`ram_dir`] if there is one, otherwise [`std::env::temp_dir`], e.g.
+///
+/// ```rust
+/// # use postgresfixture::prelude::*;
+/// let data_dir = tempdir::TempDir::new_in(cluster::ram_or_temp_dir(), "data")?;
+/// let cluster = Cluster::new(&data_dir, runtime::strategy::default())?;
+/// # Ok::<(), ClusterError>(())
+/// ```
+pub fn ram_or_temp_dir() -> PathBuf {
+    ram_dir().unwrap_or_else(env::temp_dir)
+}
+
+/// Did the cluster's server exit without cleaning up, e.g. because it crashed
+/// or was killed? If so, this returns the server's process ID.
+///
+/// This is the case when the data directory contains a PID file naming a
+/// process that no longer exists. PostgreSQL will recover when the cluster is
+/// next started, but it may be worth investigating why it happened, e.g. in the
+/// [log file][`Cluster::logfile`].
+pub fn stale_pid<P: AsRef<Path>>(datadir: P) -> Result<Option<u32>, ClusterError> {
+    let pid: i32 = match fs::read_to_string(datadir.as_ref().join("postmaster.pid")) {
+        // The process ID is on the first line.
+        Ok(content) => match content
+            .lines()
+            .next()
+            .and_then(|line| line.trim().parse().ok())
+        {
+            Some(pid) => pid,
+            None => return Ok(None),
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    // Signal 0 checks that the process exists without sending a signal.
+    match nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None) {
+        Err(Errno::ESRCH) => Ok(u32::try_from(pid).ok()),
+        Ok(()) | Err(Errno::EPERM) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The file, in a cluster's data directory, recording which process created
+/// the cluster; see [`owner`].
+pub const OWNER_FILE: &str = "postgresfixture.owner";
+
+/// The process that created a cluster.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Owner {
+    /// The process ID of the creator.
+    pub pid: u32,
+    /// The name of the creator's program, if known.
+    pub program: String,
+    /// When the cluster was created, to the nearest second.
+    pub created: SystemTime,
+}
+
+impl Owner {
+    /// Does the creating process still exist? A process with the same ID may
+    /// since have been started, so this can be wrong in that direction.
+    pub fn is_alive(&self) -> bool {
+        let Ok(pid) = i32::try_from(self.pid) else {
+            return false;
+        };
+        // Signal 0 checks that the process exists without sending a signal.
+        !matches!(
+            nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None),
+            Err(Errno::ESRCH)
+        )
+    }
+}
+
+/// Record the current process as the creator of the cluster in `datadir`.
+fn write_owner(datadir: &Path) -> io::Result<()> {
+    let program = env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            exe.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default()
+        .replace(char::is_control, "?");
+    let created = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    fs::write(
+        datadir.join(OWNER_FILE),
+        format!("{pid} {created} {program}\n", pid = std::process::id()),
+    )
+}
+
+/// The process that created the cluster in `datadir`, if it was created by
+/// this crate, as recorded in [`OWNER_FILE`]. Clusters created by an earlier
+/// version of this crate, or by other tools, have no recorded owner.
+pub fn owner<P: AsRef<Path>>(datadir: P) -> Result<Option<Owner>, ClusterError> {
+    let content = match fs::read_to_string(datadir.as_ref().join(OWNER_FILE)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut fields = content.trim_end_matches('\n').splitn(3, ' ');
+    let pid = fields.next().and_then(|pid| pid.parse().ok());
+    let created = fields.next().and_then(|created| created.parse().ok());
+    Ok(match (pid, created) {
+        (Some(pid), Some(created)) => Some(Owner {
+            pid,
+            program: fields.next().unwrap_or_default().to_owned(),
+            created: SystemTime::UNIX_EPOCH + Duration::from_secs(created),
+        }),
+        _ => None,
+    })
+}
+
+/// When was the cluster in `datadir` last used? This is when its data
+/// directory, control file, PID file, or server log were last modified.
+pub fn last_used<P: AsRef<Path>>(datadir: P) -> Result<SystemTime, ClusterError> {
+    let datadir = datadir.as_ref();
+    let mut last_used = fs::metadata(datadir)?.modified()?;
+    for path in ["global/pg_control", "postmaster.pid", "postmaster.log"] {
+        match fs::metadata(datadir.join(path)) {
+            Ok(metadata) => last_used = last_used.max(metadata.modified()?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(last_used)
+}
+
+/// Find orphaned clusters in `dir`, i.e. clusters created by this crate whose
+/// creating process has gone, and that have not been used for at least
+/// `unused_for`; see [`owner`] and [`last_used`].
+///
+/// This looks at the subdirectories of `dir`, and at their subdirectories, so
+/// that it finds clusters created in a temporary directory, e.g. with
+/// `TempDir::new_in(dir, …)`, or in a subdirectory thereof. Orphans may still
+/// be running, e.g. if their creator was killed. Use
+/// [`coordinate::destroy_orphans`][`crate::coordinate::destroy_orphans`] to
+/// clean them up.
+pub fn orphans<P: AsRef<Path>>(dir: P, unused_for: Duration) -> Result<Vec<PathBuf>, ClusterError> {
+    let cutoff = SystemTime::now()
+        .checked_sub(unused_for)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let subdirs = |dir: &Path| -> io::Result<Vec<PathBuf>> {
+        match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| match entry {
+                    // Don't follow symlinks, which may lead anywhere.
+                    Ok(entry) if entry.file_type().is_ok_and(|t| t.is_dir()) => {
+                        Some(Ok(entry.path()))
+                    }
+                    Ok(_) => None,
+                    Err(err) => Some(Err(err)),
+                })
+                .collect(),
+            // Other users' temporary directories may not be readable, and
+            // other processes may remove directories while we look.
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::PermissionDenied | io::ErrorKind::NotFound
+                ) =>
+            {
+                Ok(Vec::new())
+            }
+            Err(err) => Err(err),
+        }
+    };
+    let mut orphans = Vec::new();
+    for path in subdirs(dir.as_ref())? {
+        let datadirs = if exists(&path) {
+            vec![path]
+        } else {
+            subdirs(&path)?
+        };
+        for datadir in datadirs {
+            let orphaned = match owner(&datadir) {
+                Ok(Some(owner)) => !owner.is_alive(),
+                // Not ours, or not readable; leave it alone.
+                Ok(None) | Err(_) => false,
+            };
+            if orphaned && last_used(&datadir).is_ok_and(|last_used| last_used <= cutoff) {
+                orphans.push(datadir);
+            }
+        }
+    }
+    orphans.sort();
+    Ok(orphans)
 }
 
 /// Yields the version of PostgreSQL required to use a cluster.
@@ -1,91 +1,94 @@
+use std::io;
 use std::path::PathBuf;
 use std::process::Output;
-use std::{error, fmt, io};
+use std::time::Duration;
 
 use crate::runtime;
 use crate::version;
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum ClusterError {
+    #[error("path is not UTF-8")]
     PathEncodingError, // Path is not UTF-8.
-    IoError(io::Error),
-    UnixError(nix::Error),
+    #[error("input/output error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("UNIX error: {0}")]
+    UnixError(#[from] nix::Error),
+    #[error("PostgreSQL version not supported: {0}")]
     UnsupportedVersion(version::Version),
-    UnknownVersion(version::VersionError),
+    #[error("PostgreSQL version not known: {0}")]
+    UnknownVersion(#[from] version::VersionError),
+    #[error("PostgreSQL runtime not found for version {0}")]
     RuntimeNotFound(version::PartialVersion),
+    #[error("PostgreSQL runtime not found")]
     RuntimeDefaultNotFound,
+    #[error("PostgreSQL runtime configuration error: {0}")]
+    RuntimeConfigError(#[source] toml::de::Error),
+    #[error("PostgreSQL runtime pg_config error: {0}")]
+    RuntimePgConfigError(String),
+    #[error("PostgreSQL runtime in {} is missing programs: {}", .0.display(), .1.join(", "))]
+    RuntimeIncomplete(PathBuf, Vec<String>),
+    #[error("data directory not found in {}", .0.display())]
     DataDirectoryNotFound(PathBuf),
-    DatabaseError(postgres::error::Error),
+    /// The path exists but cannot be used as a cluster's data directory; this
+    /// says why. See [`super::check_datadir`].
+    #[error("cannot use {} as a data directory: {}", .0.display(), .1)]
+    NotADataDirectory(PathBuf, String),
+    /// The data directory holds an incomplete cluster, probably because
+    /// `initdb` was interrupted. See [`super::Cluster::repair`].
+    #[error(
+        "{} holds an incomplete cluster, probably from an interrupted initdb; destroy or repair it to start again",
+        .0.display()
+    )]
+    InitdbInterrupted(PathBuf),
+    /// The cluster is [read-only][`super::Cluster::with_read_only`], so this
+    /// operation is forbidden.
+    #[error("cluster is read-only; cannot {0}")]
+    ReadOnly(&'static str),
+    #[cfg(feature = "client")]
+    #[error("database error: {0}")]
+    DatabaseError(#[from] postgres::error::Error),
+    #[error("cluster in use; cannot lock exclusively")]
     InUse, // Cluster is already in use; cannot lock exclusively.
+    /// `initdb` failed to create the cluster; this has its output.
+    #[error("initdb failed: {}", String::from_utf8_lossy(&.0.stderr).trim())]
+    InitdbFailed(Output),
+    /// The server did not start within the given time.
+    #[error("server did not start within {} seconds", .0.as_secs())]
+    StartTimedOut(Duration),
+    /// The path to the cluster's Unix socket would be longer than the
+    /// platform allows.
+    #[error("Unix socket path too long: {}", .0.display())]
+    SocketPathTooLong(PathBuf),
+    /// The cluster's configuration files have errors, as reported by
+    /// `pg_file_settings`.
+    #[error("configuration has errors: {}", .0.join("; "))]
+    ConfigurationInvalid(Vec<String>),
+    /// These settings did not take effect, e.g. because they can only be
+    /// changed by restarting the server.
+    #[error("settings did not take effect: {}", .0.join(", "))]
+    SettingsNotApplied(Vec<String>),
+    /// The cluster failed a [health check][`super::Cluster::health_check`];
+    /// this says why.
+    #[error("cluster is unhealthy: {0}")]
+    Unhealthy(String),
+    #[error("external command failed: {0:?}")]
     Other(Output),
 }
 
-impl fmt::Display for ClusterError {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        use ClusterError::*;
-        match *self {
-            PathEncodingError => write!(fmt, "path is not UTF-8"),
-            IoError(ref e) => write!(fmt, "input/output error: {e}"),
-            UnixError(ref e) => write!(fmt, "UNIX error: {e}"),
-            UnsupportedVersion(ref e) => write!(fmt, "PostgreSQL version not supported: {e}"),
-            UnknownVersion(ref e) => write!(fmt, "PostgreSQL version not known: {e}"),
-            RuntimeNotFound(ref v) => write!(fmt, "PostgreSQL runtime not found for version {v}"),
-            RuntimeDefaultNotFound => write!(fmt, "PostgreSQL runtime not found"),
-            DataDirectoryNotFound(ref p) => write!(fmt, "data directory not found in {p:?}"),
-            DatabaseError(ref e) => write!(fmt, "database error: {e}"),
-            InUse => write!(fmt, "cluster in use; cannot lock exclusively"),
-            Other(ref e) => write!(fmt, "external command failed: {e:?}"),
-        }
-    }
-}
-
-impl error::Error for ClusterError {
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        match *self {
-            ClusterError::PathEncodingError => None,
-            ClusterError::IoError(ref error) => Some(error),
-            ClusterError::UnixError(ref error) => Some(error),
-            ClusterError::UnsupportedVersion(_) => None,
-            ClusterError::UnknownVersion(ref error) => Some(error),
-            ClusterError::RuntimeNotFound(_) => None,
-            ClusterError::RuntimeDefaultNotFound => None,
-            ClusterError::DataDirectoryNotFound(_) => None,
-            ClusterError::DatabaseError(ref error) => Some(error),
-            ClusterError::InUse => None,
-            ClusterError::Other(_) => None,
-        }
-    }
-}
-
-impl From<io::Error> for ClusterError {
-    fn from(error: io::Error) -> ClusterError {
-        ClusterError::IoError(error)
-    }
-}
-
-impl From<nix::Error> for ClusterError {
-    fn from(error: nix::Error) -> ClusterError {
-        ClusterError::UnixError(error)
-    }
-}
-
-impl From<version::VersionError> for ClusterError {
-    fn from(error: version::VersionError) -> ClusterError {
-        ClusterError::UnknownVersion(error)
-    }
-}
-
-impl From<postgres::error::Error> for ClusterError {
-    fn from(error: postgres::error::Error) -> ClusterError {
-        ClusterError::DatabaseError(error)
-    }
-}
-
 impl From<runtime::RuntimeError> for ClusterError {
     fn from(error: runtime::RuntimeError) -> ClusterError {
         match error {
             runtime::RuntimeError::IoError(error) => ClusterError::IoError(error),
             runtime::RuntimeError::VersionError(error) => ClusterError::UnknownVersion(error),
+            runtime::RuntimeError::ConfigError(error) => ClusterError::RuntimeConfigError(error),
+            runtime::RuntimeError::PgConfigError(error) => {
+                ClusterError::RuntimePgConfigError(error)
+            }
+            runtime::RuntimeError::MissingPrograms(bindir, programs) => {
+                ClusterError::RuntimeIncomplete(bindir, programs)
+            }
         }
     }
 }
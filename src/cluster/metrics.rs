@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Something that takes time when using a cluster, and whose duration is
+/// recorded in [`Metrics`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Phase {
+    /// Running `initdb` to create the cluster.
+    Initdb,
+    /// Running `pg_ctl start`, which waits until the server is ready.
+    Start,
+    /// Running `pg_ctl stop`, which waits until the server has shut down.
+    Stop,
+    /// Connecting to the cluster.
+    Connect,
+    /// Waiting for the cluster to accept connections; see
+    /// [`Cluster::wait_until_ready`][`super::Cluster::wait_until_ready`].
+    Ready,
+    /// Waiting for a lock held by another process; see the
+    /// [`coordinate`][`crate::coordinate`] module.
+    LockWait,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use Phase::*;
+        match self {
+            Initdb => write!(fmt, "initdb"),
+            Start => write!(fmt, "start"),
+            Stop => write!(fmt, "stop"),
+            Connect => write!(fmt, "connect"),
+            Ready => write!(fmt, "wait until ready"),
+            LockWait => write!(fmt, "lock wait"),
+        }
+    }
+}
+
+/// How many times a [`Phase`] has happened, and how long it took.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timing {
+    pub count: u32,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl Timing {
+    /// The mean duration, or zero if this has not happened.
+    pub fn mean(&self) -> Duration {
+        self.total.checked_div(self.count).unwrap_or_default()
+    }
+}
+
+/// Durations of the work done with a [`Cluster`][`super::Cluster`]; see
+/// [`Cluster::metrics`][`super::Cluster::metrics`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metrics(BTreeMap<Phase, Timing>);
+
+impl Metrics {
+    /// The timing for the given phase. This is zero if it has not happened.
+    pub fn get(&self, phase: Phase) -> Timing {
+        self.0.get(&phase).copied().unwrap_or_default()
+    }
+
+    /// The timings of phases that have happened, in the order of [`Phase`].
+    pub fn iter(&self) -> impl Iterator<Item = (Phase, Timing)> + '_ {
+        self.0.iter().map(|(phase, timing)| (*phase, *timing))
+    }
+
+    /// Record that the given phase took `duration`.
+    pub fn record(&mut self, phase: Phase, duration: Duration) {
+        let timing = self.0.entry(phase).or_default();
+        timing.count = timing.count.saturating_add(1);
+        timing.total += duration;
+        timing.max = timing.max.max(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Metrics, Phase, Timing};
+
+    #[test]
+    fn metrics_accumulate_timings_per_phase() {
+        let mut metrics = Metrics::default();
+        assert_eq!(metrics.get(Phase::Start), Timing::default());
+        assert_eq!(metrics.get(Phase::Start).mean(), Duration::ZERO);
+        metrics.record(Phase::Start, Duration::from_millis(300));
+        metrics.record(Phase::Start, Duration::from_millis(100));
+        metrics.record(Phase::Initdb, Duration::from_secs(1));
+        assert_eq!(
+            metrics.get(Phase::Start),
+            Timing {
+                count: 2,
+                total: Duration::from_millis(400),
+                max: Duration::from_millis(300),
+            }
+        );
+        assert_eq!(metrics.get(Phase::Start).mean(), Duration::from_millis(200));
+        assert_eq!(
+            metrics.iter().map(|(phase, _)| phase).collect::<Vec<_>>(),
+            vec![Phase::Initdb, Phase::Start]
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{exists, version, Cluster, ClusterError};
+use crate::runtime;
+use crate::version::PartialVersion;
+
+/// Clusters for several versions of PostgreSQL, one per major version, kept in
+/// subdirectories of a parent directory, e.g. `15` and `9.6`.
+///
+/// This is useful for running the same tests against several versions:
+///
+/// ```rust
+/// # use postgresfixture::prelude::*;
+/// # use postgresfixture::cluster::ClusterSet;
+/// let parent_dir = tempdir::TempDir::new("clusters")?;
+/// let clusters = ClusterSet::new(&parent_dir, runtime::strategy::default());
+/// for runtime in runtime::strategy::default().runtimes() {
+///     let cluster = clusters.cluster(&runtime.version.into())?;
+///     cluster.start()?;
+///     // …
+///     cluster.stop()?;
+/// }
+/// # Ok::<(), ClusterError>(())
+/// ```
+pub struct ClusterSet {
+    /// The directory in which to place, or find, the clusters.
+    dir: PathBuf,
+    /// How to select the PostgreSQL installation to use for each version.
+    strategy: Box<dyn runtime::Strategy>,
+}
+
+impl ClusterSet {
+    /// Represent a set of clusters in the given directory.
+    pub fn new<P: AsRef<Path>, S: runtime::Strategy>(dir: P, strategy: S) -> Self {
+        Self { dir: dir.as_ref().to_owned(), strategy: Box::new(strategy) }
+    }
+
+    /// The directory in which the clusters are kept.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The cluster for the given version's major version, e.g. 15 for 15.4,
+    /// or 9.6 for 9.6.17. An existing cluster is reused. The cluster is not
+    /// created or started; use [`Cluster::start`] or the functions in the
+    /// [`coordinate`][`crate::coordinate`] module for that.
+    ///
+    /// Fails with [`ClusterError::RuntimeNotFound`] when there is no runtime
+    /// for that version.
+    pub fn cluster(&self, version: &PartialVersion) -> Result<Cluster, ClusterError> {
+        let version = version.widened();
+        let runtime = self
+            .strategy
+            .select(&version)
+            .ok_or(ClusterError::RuntimeNotFound(version))?;
+        Cluster::new(self.dir.join(version.to_string()), runtime)
+    }
+
+    /// The clusters that already exist in this set, ordered by version.
+    /// Subdirectories that do not contain a cluster are ignored.
+    ///
+    /// Each cluster is paired with its version, and is resolved as by
+    /// [`Self::cluster`]; this fails, e.g. with
+    /// [`ClusterError::RuntimeNotFound`] when the runtime for that version is
+    /// no longer installed, without affecting other clusters in the set.
+    #[allow(clippy::type_complexity)]
+    pub fn existing(
+        &self,
+    ) -> Result<Vec<(PartialVersion, Result<Cluster, ClusterError>)>, ClusterError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut versions = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if exists(&path) {
+                if let Some(version) = version(&path)? {
+                    versions.push(version);
+                }
+            }
+        }
+        versions.sort_by_key(PartialVersion::sort_key);
+        Ok(versions
+            .into_iter()
+            .map(|version| (version, self.cluster(&version)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClusterSet;
+    use crate::cluster::ClusterError;
+    use crate::runtime::{self, Strategy};
+    use crate::version::PartialVersion;
+
+    type TestResult = Result<(), ClusterError>;
+
+    #[test]
+    fn cluster_set_keeps_one_cluster_per_major_version() -> TestResult {
+        let parent_dir = tempdir::TempDir::new("clusters")?;
+        let clusters = ClusterSet::new(&parent_dir, runtime::strategy::default());
+        assert!(clusters.existing()?.is_empty());
+        let mut expected = Vec::new();
+        for runtime in runtime::strategy::default().runtimes() {
+            let version = PartialVersion::from(runtime.version).widened();
+            let cluster = clusters.cluster(&runtime.version.into())?;
+            assert_eq!(
+                parent_dir.path().join(version.to_string()),
+                cluster.datadir()
+            );
+            cluster.create()?;
+            expected.push(version.to_string());
+        }
+        expected.sort();
+        expected.dedup();
+        let mut existing: Vec<String> = clusters
+            .existing()?
+            .into_iter()
+            .map(|(version, cluster)| {
+                let cluster = cluster.unwrap();
+                assert_eq!(
+                    Ok(Some(version)),
+                    super::version(cluster.datadir()).map_err(drop)
+                );
+                version.to_string()
+            })
+            .collect();
+        existing.sort();
+        assert_eq!(expected, existing);
+        Ok(())
+    }
+
+    #[test]
+    fn cluster_set_lists_clusters_without_runtimes() -> TestResult {
+        let parent_dir = tempdir::TempDir::new("clusters")?;
+        let clusters = ClusterSet::new(&parent_dir, runtime::strategy::default());
+        let runtime = runtime::strategy::default().runtimes().next().unwrap();
+        clusters.cluster(&runtime.version.into())?.create()?;
+        // A cluster for a version of PostgreSQL that is not installed.
+        let datadir = parent_dir.path().join("999");
+        std::fs::create_dir_all(datadir.join("global"))?;
+        std::fs::write(datadir.join("PG_VERSION"), "999\n")?;
+        std::fs::write(datadir.join("global").join("pg_control"), "")?;
+        let existing = clusters.existing()?;
+        assert_eq!(2, existing.len());
+        assert!(existing.iter().any(|(_, cluster)| cluster.is_ok()));
+        assert!(existing.iter().any(|(version, cluster)| matches!(
+            cluster,
+            Err(ClusterError::RuntimeNotFound(v)) if v == version
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn cluster_set_fails_when_there_is_no_runtime() -> TestResult {
+        let parent_dir = tempdir::TempDir::new("clusters")?;
+        let clusters = ClusterSet::new(&parent_dir, runtime::strategy::default());
+        let version = PartialVersion::Post10m(999);
+        assert!(matches!(
+            clusters.cluster(&version),
+            Err(ClusterError::RuntimeNotFound(v)) if v == version
+        ));
+        Ok(())
+    }
+}
@@ -1,4 +1,7 @@
-use super::{exists, version, Cluster, ClusterError, State::*};
+use super::{
+    check_datadir, exists, find_ram_dir, rotate_log, stale_pid, version, Cluster, ClusterError,
+    Durability, HealthCheck, Log, LogRotation, Operation, Phase, State::*, Step, OWNER_FILE,
+};
 use crate::runtime::{self, strategy::Strategy, Runtime};
 use crate::version::{PartialVersion, Version};
 
@@ -6,6 +9,8 @@ use std::collections::HashSet;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 type TestResult = Result<(), ClusterError>;
 
@@ -25,6 +30,268 @@ fn cluster_new() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn cluster_describes_itself() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime.clone())?;
+        let path = data_dir.path().display();
+        assert_eq!(data_dir.path(), cluster.datadir());
+        assert_eq!(format!("{path} (not created)"), cluster.to_string());
+        assert!(format!("{cluster:?}").starts_with("Cluster { datadir: "));
+        cluster.start()?;
+        let version = version(&cluster)?.unwrap();
+        let port = cluster.port()?.unwrap();
+        assert_eq!(
+            format!("{path} (PostgreSQL {version}, port {port})"),
+            cluster.to_string()
+        );
+        cluster.stop()?;
+        assert_eq!(
+            format!("{path} (PostgreSQL {version})"),
+            cluster.to_string()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_records_timings() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let phases = Arc::new(Mutex::new(Vec::new()));
+        let cluster = Cluster::new(&data_dir, runtime)?.with_metrics_callback({
+            let phases = Arc::clone(&phases);
+            move |phase, _| phases.lock().unwrap().push(phase)
+        });
+        cluster.start()?;
+        cluster.start()?; // Already running; nothing to time.
+        cluster.stop()?;
+        let metrics = cluster.metrics();
+        for phase in [Phase::Initdb, Phase::Start, Phase::Stop] {
+            let timing = metrics.get(phase);
+            assert_eq!(timing.count, 1, "{phase}");
+            assert!(timing.total > Duration::ZERO, "{phase}");
+        }
+        assert_eq!(
+            *phases.lock().unwrap(),
+            vec![Phase::Initdb, Phase::Start, Phase::Stop]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_plan_describes_without_doing() -> TestResult {
+    let is_run = |step: &Step, action: &str| matches!(step, Step::Run(command) if command.contains(&format!("pg_ctl {action} ")));
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let tempdir = tempdir::TempDir::new("data")?;
+        let datadir = tempdir.path().join("cluster");
+        let cluster = Cluster::new(&datadir, runtime.clone())?;
+        let steps = cluster.plan(Operation::Start)?;
+        assert_eq!(steps.len(), 4, "{steps:?}");
+        assert_eq!(steps[0], Step::CreateDir(datadir.clone()));
+        assert!(is_run(&steps[1], "init"), "{steps:?}");
+        assert!(matches!(&steps[1], Step::Run(command) if command.starts_with("PGDATA=")));
+        assert_eq!(steps[2], Step::WriteFile(datadir.join(OWNER_FILE)));
+        assert!(is_run(&steps[3], "start"), "{steps:?}");
+        assert!(cluster.plan(Operation::Stop)?.is_empty());
+        assert!(cluster.plan(Operation::Destroy)?.is_empty());
+        assert!(!datadir.exists());
+
+        cluster.start()?;
+        assert!(cluster.plan(Operation::Create)?.is_empty());
+        assert!(cluster.plan(Operation::Start)?.is_empty());
+        let steps = cluster.plan(Operation::Destroy)?;
+        assert_eq!(steps.len(), 2, "{steps:?}");
+        assert!(is_run(&steps[0], "stop"), "{steps:?}");
+        assert_eq!(steps[1], Step::RemoveDir(datadir.clone()));
+        assert!(steps[1].to_string().starts_with("remove directory: "));
+        assert!(cluster.running()?);
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_disk_usage() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        let usage = cluster.disk_usage()?;
+        assert_eq!(0, usage.total);
+        assert!(usage.databases.is_empty());
+        cluster.start()?;
+        cluster.createdb("foo")?;
+        let usage = cluster.disk_usage()?;
+        assert!(usage.total > 0);
+        let names: Vec<&str> = usage.databases.keys().map(String::as_str).collect();
+        assert_eq!(names, ["foo", "postgres", "template0", "template1"]);
+        assert!(usage.databases.values().all(|size| *size > 0));
+        assert!(usage.databases.values().sum::<u64>() < usage.total);
+        cluster.stop()?;
+        let stopped = cluster.disk_usage()?;
+        assert!(stopped.total > 0);
+        assert!(stopped.databases.is_empty());
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_setting() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        cluster.start()?;
+        assert_eq!(Some("UTC".into()), cluster.setting("TimeZone")?);
+        assert_eq!(Some("UTC".into()), cluster.setting("timezone")?);
+        assert_eq!(None, cluster.setting("no_such_setting")?);
+        assert_eq!(None, cluster.setting("it's")?);
+        let settings = cluster.settings()?;
+        assert_eq!(settings.get("fsync"), cluster.setting("fsync")?.as_ref());
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_alter_system_and_reload() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        cluster.start()?;
+        cluster.alter_system_and_reload(&[("fsync", Some("off")), ("work_mem", Some("8MB"))])?;
+        assert_eq!(Some("off".into()), cluster.setting("fsync")?);
+        assert_eq!(Some("8MB".into()), cluster.setting("work_mem")?);
+        cluster.alter_system_and_reload(&[("fsync", None), ("work_mem", None)])?;
+        assert_eq!(Some("on".into()), cluster.setting("fsync")?);
+        assert_eq!(Some("4MB".into()), cluster.setting("work_mem")?);
+        // Some settings need a restart.
+        assert!(matches!(
+            cluster.alter_system_and_reload(&[("shared_buffers", Some("32MB"))]),
+            Err(ClusterError::SettingsNotApplied(names)) if names == ["shared_buffers"]
+        ));
+        cluster.alter_system_and_reload(&[("shared_buffers", None)])?;
+        // Errors in the configuration files are reported before reloading.
+        let mut conf = File::options()
+            .append(true)
+            .open(data_dir.path().join("postgresql.conf"))?;
+        std::io::Write::write_all(&mut conf, b"work_mem = 'lots'\n")?;
+        assert!(matches!(
+            cluster.alter_system_and_reload(&[("fsync", Some("off"))]),
+            Err(ClusterError::ConfigurationInvalid(errors)) if errors.len() == 1
+        ));
+        assert_eq!(Some("on".into()), cluster.setting("fsync")?);
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_durability() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let durability = |cluster: &Cluster| -> Result<Vec<Option<String>>, ClusterError> {
+            ["fsync", "full_page_writes", "synchronous_commit"]
+                .into_iter()
+                .map(|name| cluster.setting(name))
+                .collect()
+        };
+        let (on, off) = (Some("on".to_owned()), Some("off".to_owned()));
+        // Until stopped.
+        let cluster = Cluster::new(&data_dir, runtime.clone())?.with_durability(Durability::Fast);
+        cluster.start()?;
+        assert_eq!(
+            durability(&cluster)?,
+            [off.clone(), off.clone(), off.clone()]
+        );
+        cluster.stop()?;
+        // Sticky.
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        cluster.start()?;
+        assert_eq!(durability(&cluster)?, [on.clone(), on.clone(), on.clone()]);
+        cluster.set_durability(Durability::Fast)?;
+        assert_eq!(
+            durability(&cluster)?,
+            [off.clone(), off.clone(), off.clone()]
+        );
+        cluster.stop()?;
+        cluster.start()?;
+        assert_eq!(durability(&cluster)?, [off.clone(), off.clone(), off]);
+        cluster.set_durability(Durability::Default)?;
+        assert_eq!(durability(&cluster)?, [on.clone(), on.clone(), on]);
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn find_ram_dir_prefers_var_then_candidates() -> TestResult {
+    let dir = tempdir::TempDir::new("ram")?;
+    let path = dir.path().to_str().unwrap();
+    let missing = dir.path().join("missing");
+    let missing = missing.to_str().unwrap();
+    assert_eq!(
+        Some(dir.path().into()),
+        find_ram_dir(Some(path.into()), &[])
+    );
+    assert_eq!(
+        Some(dir.path().into()),
+        find_ram_dir(None, &[missing, path])
+    );
+    assert_eq!(
+        Some(dir.path().into()),
+        find_ram_dir(Some(missing.into()), &[path])
+    );
+    assert_eq!(
+        Some(dir.path().into()),
+        find_ram_dir(Some("".into()), &[path])
+    );
+    assert_eq!(None, find_ram_dir(Some(missing.into()), &[missing]));
+    Ok(())
+}
+
+#[test]
+fn cluster_speed_up_for_tests() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        cluster.start()?;
+        cluster.speed_up_for_tests("template1", true)?;
+        cluster.speed_up_for_tests("postgres", false)?;
+        cluster.createdb("things")?;
+        let tables = "CREATE TABLE a (id int PRIMARY KEY);
+                      CREATE UNLOGGED TABLE b (a_id int REFERENCES a);
+                      CREATE TABLE c AS SELECT 1 AS n;
+                      CREATE TEMPORARY TABLE d (n int);
+                      SELECT relname, relpersistence FROM pg_class
+                       WHERE relname IN ('a', 'b', 'c', 'd') ORDER BY relname";
+        // New databases inherit the event trigger from `template1`.
+        let rows = cluster.query("things", tables)?;
+        assert_eq!(rows, [["a", "u"], ["b", "u"], ["c", "u"], ["d", "t"]]);
+        let rows = cluster.query("postgres", tables)?;
+        assert_eq!(rows, [["a", "p"], ["b", "u"], ["c", "p"], ["d", "t"]]);
+        // Only sessions in those databases have `synchronous_commit` off.
+        let rows = cluster.query("template1", "SHOW synchronous_commit")?;
+        assert_eq!(rows, [["off"]]);
+        let rows = cluster.query("postgres", "SHOW synchronous_commit")?;
+        assert_eq!(rows, [["off"]]);
+        let rows = cluster.query("things", "SHOW synchronous_commit")?;
+        assert_eq!(rows, [["on"]]);
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
 #[test]
 fn cluster_does_not_exist() -> TestResult {
     for runtime in runtimes() {
@@ -75,6 +342,20 @@ fn cluster_has_version_when_it_does_exist() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn cluster_stale_pid_finds_pid_of_exited_server() -> TestResult {
+    let data_dir = tempdir::TempDir::new("data")?;
+    assert_eq!(None, stale_pid(&data_dir)?);
+    let pidfile = data_dir.path().join("postmaster.pid");
+    std::fs::write(&pidfile, format!("{}\n", std::process::id()))?;
+    assert_eq!(None, stale_pid(&data_dir)?);
+    let mut child = std::process::Command::new("true").spawn()?;
+    child.wait()?;
+    std::fs::write(&pidfile, format!("{}\n", child.id()))?;
+    assert_eq!(Some(child.id()), stale_pid(&data_dir)?);
+    Ok(())
+}
+
 #[test]
 fn cluster_has_pid_file() -> TestResult {
     let data_dir = PathBuf::from("/some/where");
@@ -123,12 +404,7 @@ fn cluster_create_creates_cluster_with_neutral_locale_and_timezone() -> TestResu
         let data_dir = tempdir::TempDir::new("data")?;
         let cluster = Cluster::new(&data_dir, runtime.clone())?;
         cluster.start()?;
-        let mut conn = cluster.connect("postgres")?;
-        let result = conn.query("SHOW ALL", &[])?;
-        let params: std::collections::HashMap<String, String> = result
-            .into_iter()
-            .map(|row| (row.get::<usize, String>(0), row.get::<usize, String>(1)))
-            .collect();
+        let params = cluster.settings()?;
         // PostgreSQL 9.4.22's release notes reveal:
         //
         //   Etc/UCT is now a backward-compatibility link to Etc/UTC,
@@ -231,6 +507,7 @@ fn cluster_destroy_stops_and_removes_cluster() -> TestResult {
 }
 
 #[test]
+#[cfg(feature = "client")]
 fn cluster_connect_connects() -> TestResult {
     for runtime in runtimes() {
         println!("{runtime:?}");
@@ -243,6 +520,666 @@ fn cluster_connect_connects() -> TestResult {
     Ok(())
 }
 
+#[test]
+#[cfg(feature = "client")]
+fn cluster_connect_with_options_sets_them() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        cluster.start()?;
+        let options = super::ConnectOptions {
+            application_name: Some("some test".into()),
+            search_path: Some(vec!["My Schema".into(), "public".into()]),
+            statement_timeout: Some(Duration::from_millis(1500)),
+            connect_timeout: Some(Duration::from_secs(5)),
+        };
+        let mut client = cluster.connect_with("template1", &options)?;
+        let row = client.query_one(
+            "SELECT current_setting('application_name'), current_setting('search_path'), \
+             current_setting('statement_timeout')",
+            &[],
+        )?;
+        assert_eq!(row.get::<_, String>(0), "some test");
+        assert_eq!(row.get::<_, String>(1), r#""My Schema","public""#);
+        assert_eq!(row.get::<_, String>(2), "1500ms");
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn cluster_connect_retries_until_server_starts() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster =
+            Cluster::new(&data_dir, runtime.clone())?.with_connect_retry(super::ConnectRetry {
+                timeout: Duration::from_secs(60),
+                ..super::ConnectRetry::default()
+            });
+        cluster.create()?;
+        // Start the cluster from elsewhere, a little later.
+        let background = std::thread::spawn({
+            let path = data_dir.path().to_owned();
+            move || {
+                std::thread::sleep(Duration::from_millis(200));
+                Cluster::new(path, runtime)?.start()
+            }
+        });
+        cluster.connect("template1")?;
+        background.join().unwrap()?;
+        // Errors other than the server not accepting connections are not
+        // retried.
+        let started = std::time::Instant::now();
+        assert!(cluster.connect("no-such-database").is_err());
+        assert!(started.elapsed() < Duration::from_secs(10));
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn cluster_connect_retry_gives_up_after_timeout() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let timeout = Duration::from_millis(300);
+        let cluster = Cluster::new(&data_dir, runtime)?
+            .with_connect_retry(super::ConnectRetry { timeout, ..super::ConnectRetry::default() });
+        cluster.create()?;
+        let started = std::time::Instant::now();
+        assert!(matches!(
+            cluster.connect("template1"),
+            Err(ClusterError::DatabaseError(_))
+        ));
+        assert!(started.elapsed() >= timeout);
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn cluster_url_connects() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        cluster.start()?;
+        let url = cluster.url("template1");
+        assert!(url.starts_with("postgresql://"));
+        let mut client = postgres::Client::connect(&url, postgres::NoTls)?;
+        let row = client.query_one("SELECT current_database()", &[])?;
+        assert_eq!("template1", row.get::<_, String>(0));
+        drop(client);
+        // By default the cluster only listens on its Unix socket.
+        assert_eq!(None, cluster.url_tcp("template1")?);
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn cluster_with_tcp_listens_on_tcp() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?.with_tcp("localhost", 0);
+        assert_eq!(None, cluster.port()?);
+        cluster.start()?;
+        let port = cluster.port()?.expect("port not found");
+        assert_ne!(0, port);
+        // Connecting over the Unix socket uses the same port.
+        cluster.connect("template1")?;
+        let url = cluster.url_tcp("template1")?.expect("not listening on TCP");
+        assert!(url.contains(&format!("@localhost:{port}/")));
+        postgres::Client::connect(&url, postgres::NoTls)?;
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_wait_until_ready_waits_for_server_to_start() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime.clone())?;
+        cluster.create()?;
+        // Not running, so not ready.
+        let deadline = std::time::Instant::now() + Duration::from_millis(100);
+        assert!(matches!(
+            cluster.wait_until_ready(deadline),
+            Err(ClusterError::StartTimedOut(_))
+        ));
+        // Start the cluster from elsewhere, a little later.
+        let background = std::thread::spawn({
+            let path = data_dir.path().to_owned();
+            move || {
+                std::thread::sleep(Duration::from_millis(200));
+                Cluster::new(path, runtime)?.start()
+            }
+        });
+        cluster.wait_until_ready(std::time::Instant::now() + Duration::from_secs(60))?;
+        background.join().unwrap()?;
+        assert!(cluster.running()?);
+        assert_eq!(cluster.metrics().get(Phase::Ready).count, 1);
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_health_check_checks_version() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime.clone())?;
+        cluster.start()?;
+        let check = HealthCheck { version: Some(runtime.version.into()), primary: true };
+        let health = cluster.health_check(&check)?;
+        assert_eq!(health.version, runtime.version);
+        assert!(!health.in_recovery);
+        let check = HealthCheck {
+            version: Some(PartialVersion::Post10m(999)),
+            ..HealthCheck::default()
+        };
+        assert!(matches!(
+            cluster.health_check(&check),
+            Err(ClusterError::Unhealthy(reason)) if reason.contains("not 999")
+        ));
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_with_setting_applies_until_stopped() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime.clone())?.with_setting("fsync", "off");
+        cluster.start()?;
+        assert_eq!(Some("off".into()), cluster.setting("fsync")?);
+        cluster.stop()?;
+        // The cluster's configuration is untouched.
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        cluster.start()?;
+        assert_eq!(Some("on".into()), cluster.setting("fsync")?);
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn cluster_with_server_env_sets_environment_of_server() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?
+            .with_server_env("POSTGRESFIXTURE_TEST", "some value")
+            .with_server_env("PGDATA", "/not/the/data/dir");
+        cluster.start()?;
+        // The first line of `postmaster.pid` is the server's PID.
+        let pidfile = std::fs::read_to_string(data_dir.path().join("postmaster.pid"))?;
+        let pid = pidfile.lines().next().unwrap_or_default();
+        let environ = std::fs::read(format!("/proc/{pid}/environ"))?;
+        let environ: Vec<&[u8]> = environ.split(|b| *b == 0).collect();
+        assert!(environ.contains(&&b"POSTGRESFIXTURE_TEST=some value"[..]));
+        assert!(!environ.contains(&&b"PGDATA=/not/the/data/dir"[..]));
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_create_refuses_directory_that_is_not_a_cluster() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        // Something else is in the directory.
+        File::create(data_dir.path().join("squatter"))?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        assert!(matches!(
+            cluster.create(),
+            Err(ClusterError::NotADataDirectory(path, _)) if path == data_dir.path()
+        ));
+        assert!(data_dir.path().join("squatter").is_file());
+        assert!(!data_dir.path().join("PG_VERSION").exists());
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_repair_removes_remains_of_interrupted_initdb() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        // What initdb might leave behind when killed.
+        std::fs::create_dir_all(data_dir.path().join("base").join("1"))?;
+        std::fs::create_dir(data_dir.path().join("pg_wal"))?;
+        std::fs::write(data_dir.path().join("PG_VERSION"), "16\n")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        assert!(!exists(&cluster));
+        assert!(matches!(
+            cluster.start(),
+            Err(ClusterError::InitdbInterrupted(path)) if path == data_dir.path()
+        ));
+        assert_eq!(cluster.repair()?, Modified);
+        assert_eq!(std::fs::read_dir(&data_dir)?.count(), 0);
+        assert_eq!(cluster.repair()?, Unmodified);
+        cluster.start()?;
+        assert_eq!(cluster.repair()?, Unmodified);
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_with_read_only_forbids_changes() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime.clone())?.with_read_only(true);
+        assert!(matches!(cluster.create(), Err(ClusterError::ReadOnly(_))));
+        assert!(matches!(cluster.start(), Err(ClusterError::ReadOnly(_))));
+        assert!(!exists(&cluster));
+        Cluster::new(&data_dir, runtime)?.create()?;
+        // An existing cluster can be started, inspected, and stopped.
+        assert_eq!(cluster.start()?, Modified);
+        assert!(cluster.databases()?.contains(&"template1".to_owned()));
+        assert!(matches!(
+            cluster.createdb("foo"),
+            Err(ClusterError::ReadOnly(_))
+        ));
+        assert!(matches!(
+            cluster.dropdb("template1"),
+            Err(ClusterError::ReadOnly(_))
+        ));
+        assert!(matches!(
+            cluster.set_durability(Durability::Fast),
+            Err(ClusterError::ReadOnly(_))
+        ));
+        assert!(matches!(cluster.destroy(), Err(ClusterError::ReadOnly(_))));
+        assert_eq!(cluster.stop()?, Modified);
+        assert!(exists(&cluster));
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_new_refuses_a_file() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let path = data_dir.path().join("file");
+        File::create(&path)?;
+        assert!(matches!(
+            Cluster::new(&path, runtime),
+            Err(ClusterError::NotADataDirectory(_, reason)) if reason.contains("not a directory")
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_start_refuses_data_directory_with_loose_permissions() -> TestResult {
+    use std::os::unix::fs::PermissionsExt;
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        cluster.create()?;
+        check_datadir(&data_dir)?;
+        std::fs::set_permissions(&data_dir, std::fs::Permissions::from_mode(0o777))?;
+        assert!(matches!(
+            cluster.start(),
+            Err(ClusterError::NotADataDirectory(_, reason)) if reason.contains("0777")
+        ));
+        std::fs::set_permissions(&data_dir, std::fs::Permissions::from_mode(0o700))?;
+        cluster.start()?;
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_start_fails_when_socket_path_is_too_long() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let data_dir = data_dir.path().join("d".repeat(100));
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        assert!(matches!(
+            cluster.start(),
+            Err(ClusterError::SocketPathTooLong(_))
+        ));
+        assert!(!cluster.running()?);
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_with_log_file_writes_log_there() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let log_dir = tempdir::TempDir::new("log")?;
+        let log_file = log_dir.path().join("server.log");
+        let cluster = Cluster::new(&data_dir, runtime)?.with_log(Log::File(log_file.clone()));
+        assert_eq!(log_file, cluster.logfile());
+        cluster.start()?;
+        assert!(log_file.is_file());
+        assert!(!data_dir.path().join("postmaster.log").exists());
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_with_log_collector_writes_log_into_directory() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?.with_log(Log::Collector {
+            directory: "log".into(),
+            rotation_age: Some(Duration::from_secs(60 * 60)),
+            rotation_size: Some(1 << 20),
+        });
+        cluster.start()?;
+        let settings = cluster.settings()?;
+        assert_eq!(settings["logging_collector"], "on");
+        assert_eq!(settings["log_rotation_age"], "1h");
+        assert_eq!(settings["log_rotation_size"], "1MB");
+        assert!(data_dir.path().join("log").read_dir()?.next().is_some());
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_with_log_rotation_rotates_log_when_starting() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?.with_log_rotation(LogRotation::Daily);
+        cluster.create()?;
+        let logfile = cluster.logfile();
+        let rotated = data_dir.path().join("postmaster.log.1");
+        // A log file written today is not rotated.
+        std::fs::write(&logfile, "today\n")?;
+        cluster.start()?;
+        cluster.stop()?;
+        assert!(!rotated.exists());
+        // A log file last written yesterday is rotated.
+        let yesterday = std::time::SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+        File::options()
+            .append(true)
+            .open(&logfile)?
+            .set_modified(yesterday)?;
+        cluster.start()?;
+        assert!(std::fs::read_to_string(&rotated)?.starts_with("today\n"));
+        assert!(!std::fs::read_to_string(&logfile)?.contains("today"));
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn rotate_log_keeps_a_limited_number_of_files() -> TestResult {
+    let log_dir = tempdir::TempDir::new("log")?;
+    let logfile = log_dir.path().join("server.log");
+    for n in 1..=5 {
+        std::fs::write(&logfile, n.to_string())?;
+        rotate_log(&logfile, LogRotation::Size(1))?;
+        assert!(!logfile.exists());
+    }
+    let mut names = std::fs::read_dir(&log_dir)?
+        .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+        .collect::<Result<Vec<_>, ClusterError>>()?;
+    names.sort();
+    assert_eq!(names, ["server.log.1", "server.log.2", "server.log.3"]);
+    assert_eq!(
+        std::fs::read_to_string(log_dir.path().join("server.log.1"))?,
+        "5"
+    );
+    assert_eq!(
+        std::fs::read_to_string(log_dir.path().join("server.log.3"))?,
+        "3"
+    );
+    // Not due: the file is smaller than the size limit.
+    std::fs::write(&logfile, "6")?;
+    rotate_log(&logfile, LogRotation::Size(2))?;
+    assert!(logfile.exists());
+    Ok(())
+}
+
+#[test]
+fn cluster_start_fails_when_server_does_not_start() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?
+            .with_start_timeout(Duration::from_secs(5))
+            .with_setting("shared_buffers", "nonsense");
+        assert!(matches!(cluster.start(), Err(ClusterError::Other(_))));
+        assert!(!cluster.running()?);
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_run_until_ready_times_out() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        let timeout = Duration::from_millis(100);
+        // A command that fails after the timeout has timed out, whatever it says.
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "sleep 0.2; exit 1"]);
+        assert!(matches!(
+            cluster.run_until_ready(&mut command, timeout),
+            Err(ClusterError::StartTimedOut(t)) if t == timeout
+        ));
+        // A command that outlives the timeout (and grace period) is abandoned.
+        let mut command = std::process::Command::new("sleep");
+        command.arg("10");
+        assert!(matches!(
+            cluster.run_until_ready(&mut command, timeout),
+            Err(ClusterError::StartTimedOut(_))
+        ));
+        // A command that fails before the timeout has not timed out.
+        let mut command = std::process::Command::new("false");
+        assert!(matches!(
+            cluster.run_until_ready(&mut command, Duration::from_secs(10)),
+            Ok(Some(output)) if !output.status.success()
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_shell_with_args_passes_args_to_psql() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        if runtime.validate_programs(&["psql"]).is_err() {
+            continue; // Some installations have no client programs.
+        }
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        cluster.start()?;
+        let status = cluster.shell_with_args("template1", &["-c", "SELECT 1"])?;
+        assert!(status.success());
+        let status = cluster.shell_with_args("template1", &["-c", "SELECT syntax error"])?;
+        assert!(!status.success());
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn cluster_dump_and_restore_round_trip() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        if runtime
+            .validate_programs(&["pg_dump", "pg_restore"])
+            .is_err()
+        {
+            continue; // Some installations have no client programs.
+        }
+        let data_dir = tempdir::TempDir::new("data")?;
+        let dump = data_dir.path().join("dump");
+        let cluster = Cluster::new(data_dir.path().join("cluster"), runtime)?;
+        cluster.start()?;
+        cluster.createdb("source")?;
+        cluster.createdb("target")?;
+        let mut conn = cluster.connect("source")?;
+        conn.batch_execute("CREATE TABLE things (name text); INSERT INTO things VALUES ('foo')")?;
+        cluster.dump("source", &dump)?;
+        cluster.restore("target", &dump)?;
+        let mut conn = cluster.connect("target")?;
+        let name: String = conn.query_one("SELECT name FROM things", &[])?.get(0);
+        assert_eq!("foo", name);
+        // Restoring again fails because the table already exists.
+        assert!(matches!(
+            cluster.restore("target", &dump),
+            Err(ClusterError::Other(_))
+        ));
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_upgrade_does_nothing_when_already_at_version() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime.clone())?;
+        cluster.start()?;
+        assert_eq!(Unmodified, cluster.upgrade_check(&runtime)?);
+        assert_eq!(Unmodified, cluster.upgrade(&runtime)?);
+        assert!(cluster.running()?);
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn cluster_upgrade_upgrades_to_newer_major_version() -> TestResult {
+    let runtimes: Vec<_> = runtimes().collect();
+    for from in &runtimes {
+        for to in &runtimes {
+            if to.version.major() <= from.version.major()
+                || to.validate_programs(&["pg_upgrade"]).is_err()
+            {
+                continue;
+            }
+            println!("{from:?} -> {to:?}");
+            let data_dir = tempdir::TempDir::new("data")?;
+            let (from_clone, to_clone) = (from.clone(), to.clone());
+            let strategy =
+                runtime::strategy::from_fn(move || [from_clone.clone(), to_clone.clone()]);
+            let cluster = Cluster::new(data_dir.path().join("cluster"), strategy)?;
+            cluster.start()?;
+            cluster.createdb("things")?;
+            cluster
+                .connect("template1")?
+                .execute("ALTER SYSTEM SET fsync = 'off'", &[])?;
+            assert_eq!(Modified, cluster.upgrade_check(to)?);
+            assert_eq!(Some(from.version.into()), version(&cluster)?);
+            assert_eq!(Modified, cluster.upgrade(to)?);
+            assert_eq!(to.version.major(), cluster.runtime()?.version.major());
+            cluster.start()?;
+            assert!(cluster.databases()?.contains(&"things".to_owned()));
+            assert_eq!(Some("off".into()), cluster.setting("fsync")?);
+            cluster.destroy()?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_snapshot_and_restore_snapshot() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let snapshot = data_dir.path().join("snapshot");
+        let cluster = Cluster::new(data_dir.path().join("cluster"), runtime)?;
+        cluster.start()?;
+        cluster.createdb("before")?;
+        cluster.snapshot(&snapshot)?;
+        assert!(!cluster.running()?);
+        assert!(exists(&snapshot));
+        // A snapshot is not overwritten.
+        assert!(cluster.snapshot(&snapshot).is_err());
+        assert!(exists(&snapshot));
+        cluster.start()?;
+        cluster.createdb("after")?;
+        cluster.restore_snapshot(&snapshot)?;
+        assert!(!cluster.running()?);
+        cluster.start()?;
+        let databases = cluster.databases()?;
+        assert!(databases.contains(&"before".to_owned()));
+        assert!(!databases.contains(&"after".to_owned()));
+        cluster.destroy()?;
+        // A snapshot can be restored in place of a missing cluster.
+        cluster.restore_snapshot(&snapshot)?;
+        assert!(exists(&cluster));
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_backup_writes_tar_archive() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        if runtime.validate_programs(&["pg_basebackup"]).is_err() {
+            continue; // Some installations have no client programs.
+        }
+        let data_dir = tempdir::TempDir::new("data")?;
+        let backup = data_dir.path().join("backup");
+        let cluster = Cluster::new(data_dir.path().join("cluster"), runtime)?;
+        cluster.start()?;
+        cluster.backup(&backup)?;
+        assert!(backup.join("base.tar").is_file());
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn cluster_exec_sets_connection_environment() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        cluster.start()?;
+        // The child connects using only `DATABASE_URL`.
+        let status = cluster.exec(
+            "template1",
+            "sh",
+            &[
+                "-c",
+                "test -n \"$PGUSER\" && env -u PGHOST -u PGDATABASE -u PGUSER \
+                 psql \"$DATABASE_URL\" -Atc 'SELECT 1'",
+            ],
+        )?;
+        assert!(status.success());
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
 #[test]
 fn cluster_databases_returns_vec_of_database_names() -> TestResult {
     for runtime in runtimes() {
@@ -263,6 +1200,29 @@ fn cluster_databases_returns_vec_of_database_names() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn cluster_databases_detailed_describes_databases() -> TestResult {
+    for runtime in runtimes() {
+        println!("{runtime:?}");
+        let data_dir = tempdir::TempDir::new("data")?;
+        let cluster = Cluster::new(&data_dir, runtime)?;
+        cluster.start()?;
+
+        let databases = cluster.databases_detailed()?;
+        let names: Vec<&str> = databases.iter().map(|db| db.name.as_str()).collect();
+        assert_eq!(vec!["postgres", "template0", "template1"], names);
+        for database in &databases {
+            assert!(!database.owner.is_empty());
+            assert!(database.size > 0);
+            // The cluster is created with `-E utf8`.
+            assert_eq!("UTF8", database.encoding);
+        }
+
+        cluster.destroy()?;
+    }
+    Ok(())
+}
+
 #[test]
 fn cluster_databases_with_non_plain_names_can_be_created_and_dropped() -> TestResult {
     // PostgreSQL identifiers containing hyphens, for example, or where we
@@ -16,13 +16,17 @@
 //! # Ok::<(), ClusterError>(())
 //! ```
 
-use std::time::Duration;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{error, fmt, fs, panic};
 
 use either::Either::{Left, Right};
-use rand::RngCore;
+use rand::Rng;
 
-use crate::cluster::{Cluster, ClusterError, State};
+use crate::cluster::{self, Cluster, ClusterError, State};
 use crate::lock;
+use crate::runtime::{self, Runtime};
 
 /// Perform `action` in `cluster`.
 ///
@@ -31,17 +35,18 @@ use crate::lock;
 /// (maybe) stops the cluster again, and finally returns the result of `action`.
 /// If there are other users of the cluster – i.e. if an exclusive lock cannot
 /// be acquired during the shutdown phase – then the cluster is left running.
-pub fn run_and_stop<'a, F, T>(
+pub fn run_and_stop<'a, F, T, B>(
     cluster: &'a Cluster,
-    lock: lock::UnlockedFile,
+    lock: lock::Unlocked<B>,
     action: F,
 ) -> Result<T, ClusterError>
 where
     F: std::panic::UnwindSafe + FnOnce(&'a Cluster) -> T,
+    B: lock::LockBackend,
 {
     let lock = startup(cluster, lock)?;
     let action_res = std::panic::catch_unwind(|| action(cluster));
-    let _: Option<State> = shutdown(cluster, lock, Cluster::stop)?;
+    let _: Option<State> = shutdown(cluster, lock, Cluster::stop, false)?;
     match action_res {
         Ok(result) => Ok(result),
         Err(err) => std::panic::resume_unwind(err),
@@ -54,55 +59,471 @@ where
 /// – i.e. stop the cluster and completely delete its data directory – before
 /// returning. If there are other users of the cluster – i.e. if an exclusive
 /// lock cannot be acquired during the shutdown phase – then the cluster is left
-/// running and is **not** destroyed.
-pub fn run_and_destroy<'a, F, T>(
+/// running and is **not** destroyed. Otherwise, the lock file is removed too.
+pub fn run_and_destroy<'a, F, T, B>(
     cluster: &'a Cluster,
-    lock: lock::UnlockedFile,
+    lock: lock::Unlocked<B>,
     action: F,
 ) -> Result<T, ClusterError>
 where
     F: std::panic::UnwindSafe + FnOnce(&'a Cluster) -> T,
+    B: lock::LockBackend,
 {
     let lock = startup(cluster, lock)?;
     let action_res = std::panic::catch_unwind(|| action(cluster));
-    let shutdown_res = shutdown(cluster, lock, Cluster::destroy);
+    let shutdown_res = shutdown(cluster, lock, Cluster::destroy, true);
     match action_res {
         Ok(result) => shutdown_res.map(|_| result),
         Err(err) => std::panic::resume_unwind(err),
     }
 }
 
-fn startup(
+/// Perform `action` in `cluster`, leaving the cluster running afterwards.
+///
+/// Similar to [`run_and_stop`] except this never stops the cluster. Note that
+/// the lock is released before returning, so the cluster is no longer in use
+/// as far as other processes are concerned: another process using
+/// [`run_and_stop`] may stop it when that process is done.
+pub fn run_and_leave_running<'a, F, T, B>(
+    cluster: &'a Cluster,
+    lock: lock::Unlocked<B>,
+    action: F,
+) -> Result<T, ClusterError>
+where
+    F: std::panic::UnwindSafe + FnOnce(&'a Cluster) -> T,
+    B: lock::LockBackend,
+{
+    let lock = startup(cluster, lock)?;
+    let action_res = std::panic::catch_unwind(|| action(cluster));
+    lock.unlock()?;
+    match action_res {
+        Ok(result) => Ok(result),
+        Err(err) => std::panic::resume_unwind(err),
+    }
+}
+
+/// Wrap `action` so that, if it fails or panics, the lines written to the
+/// cluster's server log while it ran are attached to its error or panic.
+///
+/// This helps to diagnose failures, e.g. of tests in CI, from their output
+/// alone. Use it with [`run_and_stop`] and friends:
+///
+/// ```rust
+/// # use postgresfixture::prelude::*;
+/// # let cluster_dir = tempdir::TempDir::new("cluster")?;
+/// # let cluster = Cluster::new(cluster_dir.path().join("data"), runtime::strategy::default())?;
+/// # let lock = lock::UnlockedFile::try_from(cluster_dir.path().join("lock").as_path())?;
+/// let result = coordinate::run_and_destroy(
+///     &cluster,
+///     lock,
+///     coordinate::with_log_excerpt(|cluster| cluster.createdb("template1")),
+/// )?;
+/// let err = result.unwrap_err();
+/// assert!(err.log.contains("already exists"));
+/// # Ok::<(), ClusterError>(())
+/// ```
+///
+/// A panic's message is extended with the log lines, but only when it's a
+/// string, as it is from [`panic!`] and friends.
+pub fn with_log_excerpt<'a, F, T, E>(
+    action: F,
+) -> impl FnOnce(&'a Cluster) -> Result<T, ActionError<E>> + panic::UnwindSafe
+where
+    F: panic::UnwindSafe + FnOnce(&'a Cluster) -> Result<T, E>,
+{
+    move |cluster| {
+        let logfile = cluster.logfile();
+        let offset = fs::metadata(&logfile).map_or(0, |metadata| metadata.len());
+        match panic::catch_unwind(|| action(cluster)) {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(error)) => Err(ActionError { error, log: read_log_from(&logfile, offset) }),
+            Err(payload) => {
+                let message = match payload.downcast_ref::<&str>() {
+                    Some(message) => Some((*message).to_owned()),
+                    None => payload.downcast_ref::<String>().cloned(),
+                };
+                match message {
+                    Some(message) => {
+                        let log = read_log_from(&logfile, offset);
+                        panic::resume_unwind(Box::new(format!(
+                            "{message}\n\nServer log while running action:\n{log}"
+                        )))
+                    }
+                    None => panic::resume_unwind(payload),
+                }
+            }
+        }
+    }
+}
+
+/// The error from an action wrapped by [`with_log_excerpt`].
+#[derive(Debug)]
+pub struct ActionError<E> {
+    /// The error returned by the action.
+    pub error: E,
+    /// The lines written to the cluster's server log while the action ran.
+    pub log: String,
+}
+
+impl<E: fmt::Display> fmt::Display for ActionError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if !self.log.is_empty() {
+            write!(f, "\n\nServer log while running action:\n{}", self.log)?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for ActionError<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Read the log from `offset` to its end. If the log is now shorter than
+/// `offset`, e.g. it was truncated, read all of it. Errors are ignored: the
+/// log is only to help with diagnosis.
+fn read_log_from(logfile: &Path, offset: u64) -> String {
+    let mut log = Vec::new();
+    if let Ok(mut file) = fs::File::open(logfile) {
+        let len = file.metadata().map_or(0, |metadata| metadata.len());
+        let offset = if len < offset { 0 } else { offset };
+        if file.seek(SeekFrom::Start(offset)).is_ok() {
+            let _ = file.read_to_end(&mut log);
+        }
+    }
+    String::from_utf8_lossy(&log).into_owned()
+}
+
+/// Create `cluster` if it does not exist, without starting it.
+///
+/// Using the given lock for synchronisation, this creates the cluster only
+/// while holding an exclusive lock. If the cluster is in use it must already
+/// exist, so this waits only until any process holding an exclusive lock, e.g.
+/// one that is itself creating the cluster, is done.
+pub fn create<B: lock::LockBackend>(
     cluster: &Cluster,
-    mut lock: lock::UnlockedFile,
-) -> Result<lock::LockedFileShared, ClusterError> {
+    mut lock: lock::Unlocked<B>,
+) -> Result<State, ClusterError> {
+    let mut backoff = Backoff::new();
+    loop {
+        lock = match lock.try_lock_exclusive()? {
+            Left(lock) => {
+                // Wait for a shared lock, i.e. for any exclusive holder to
+                // finish, then check if the cluster was created meanwhile.
+                let lock = lock_shared(cluster, lock)?;
+                if lock.is_unlinked() {
+                    lock.unlock()?.reopen()?
+                } else if cluster::exists(cluster) {
+                    lock.unlock()?;
+                    return Ok(State::Unmodified);
+                } else {
+                    // Back off; see `startup`.
+                    let lock = lock.unlock()?;
+                    std::thread::sleep(backoff.next());
+                    lock
+                }
+            }
+            Right(lock) if lock.is_unlinked() => lock.unlock()?.reopen()?,
+            Right(lock) => {
+                let state = repairing(cluster, Cluster::create)?;
+                lock.unlock()?;
+                return Ok(state);
+            }
+        }
+    }
+}
+
+/// Stop `cluster` if it is not in use.
+///
+/// Using the given lock for synchronisation, this stops the cluster only if an
+/// exclusive lock can be acquired, i.e. if no other process is using the
+/// cluster, e.g. via [`run_and_stop`]. Returns [`None`] if the cluster is in
+/// use, otherwise the result of [`Cluster::stop`]. Use [`Cluster::stop`]
+/// directly to stop the cluster regardless.
+pub fn stop<B: lock::LockBackend>(
+    cluster: &Cluster,
+    lock: lock::Unlocked<B>,
+) -> Result<Option<State>, ClusterError> {
+    match lock.try_lock_exclusive()? {
+        // The cluster is in use by someone/something else.
+        Left(_) => Ok(None),
+        Right(lock) => {
+            let state = cluster.stop()?;
+            lock.unlock()?;
+            Ok(Some(state))
+        }
+    }
+}
+
+/// Destroy `cluster` if it is not in use.
+///
+/// Similar to [`stop`] except this destroys the cluster – i.e. stops the
+/// cluster and completely deletes its data directory – and removes the lock
+/// file. Returns [`None`] if the cluster is in use, otherwise the result of
+/// [`Cluster::destroy`].
+///
+/// A cluster may also be locked with a file beside its data directory – see
+/// [`UnlockedFile::try_beside_datadir`][`lock::UnlockedFile::try_beside_datadir`]
+/// – by processes that do not share a lock directory. If that file exists,
+/// and is not `lock` itself, it is locked exclusively too, and removed along
+/// with the cluster.
+pub fn destroy<B: lock::LockBackend>(
+    cluster: &Cluster,
+    lock: lock::Unlocked<B>,
+) -> Result<Option<State>, ClusterError> {
+    match lock.try_lock_exclusive()? {
+        // The cluster is in use by someone/something else.
+        Left(_) => Ok(None),
+        Right(lock) => match lock_beside_datadir(cluster.datadir(), lock.backend().path())? {
+            BesideDatadir::InUse => {
+                lock.unlock()?;
+                Ok(None)
+            }
+            beside => {
+                let state = cluster.destroy()?;
+                lock.remove()?;
+                if let BesideDatadir::Locked(beside) = beside {
+                    beside.remove()?;
+                }
+                Ok(Some(state))
+            }
+        },
+    }
+}
+
+/// The lock file beside a cluster's data directory, as found by
+/// [`lock_beside_datadir`].
+enum BesideDatadir {
+    /// There's no such lock file, or it's the lock already held.
+    Absent,
+    /// The lock file is held by someone/something else.
+    InUse,
+    /// The lock file is now locked exclusively.
+    Locked(lock::LockedFileExclusive),
+}
+
+/// Take the lock file beside `datadir` exclusively, if it exists and is not the
+/// file at `held`, i.e. a lock file already held by the caller.
+fn lock_beside_datadir(datadir: &Path, held: Option<&Path>) -> io::Result<BesideDatadir> {
+    let Some(path) = lock::path_beside_datadir(datadir).filter(|path| path.exists()) else {
+        return Ok(BesideDatadir::Absent);
+    };
+    if held.is_some_and(|held| same_file(held, &path)) {
+        return Ok(BesideDatadir::Absent);
+    }
+    Ok(
+        match lock::UnlockedFile::try_from(&path)?.try_lock_exclusive()? {
+            Left(_) => BesideDatadir::InUse,
+            Right(lock) => BesideDatadir::Locked(lock),
+        },
+    )
+}
+
+/// Do `a` and `b` refer to the same file?
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// What [`destroy_orphans`] did with an orphaned cluster.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Orphan {
+    /// The cluster was destroyed.
+    Destroyed,
+    /// The cluster is in use, so it was left alone.
+    InUse,
+    /// The cluster could not be destroyed.
+    Failed(ClusterError),
+}
+
+/// Destroy orphaned clusters in `dir`, i.e. those found by
+/// [`cluster::orphans`], unless they are in use.
+///
+/// This is meant for test harnesses, e.g. to clean up clusters left behind by
+/// earlier runs that were killed. Each cluster is locked using the lock file
+/// for its data directory in `lock_dir`; see
+/// [`UnlockedFile::try_for_datadir_in`][`lock::UnlockedFile::try_for_datadir_in`].
+/// It's also locked with the file beside its data directory, if there is one;
+/// see [`destroy`]. Orphans that are still running are stopped first.
+///
+/// Returns the data directory of each orphan with what became of it. A failure
+/// to destroy one orphan does not stop the others from being destroyed; an
+/// error is returned only if the orphans could not be found.
+pub fn destroy_orphans<P: AsRef<Path>, L: AsRef<Path>>(
+    dir: P,
+    lock_dir: L,
+    unused_for: Duration,
+) -> Result<Vec<(PathBuf, Orphan)>, ClusterError> {
+    Ok(cluster::orphans(dir, unused_for)?
+        .into_iter()
+        .map(|datadir| {
+            let datadir = datadir.canonicalize().unwrap_or(datadir);
+            let orphan = match destroy_orphan(&datadir, lock_dir.as_ref()) {
+                Ok(Some(_)) => Orphan::Destroyed,
+                Ok(None) => Orphan::InUse,
+                Err(err) => Orphan::Failed(err),
+            };
+            (datadir, orphan)
+        })
+        .collect())
+}
+
+/// Destroy the orphaned cluster in `datadir` unless it is in use. See
+/// [`destroy_orphans`].
+fn destroy_orphan(datadir: &Path, lock_dir: &Path) -> Result<Option<State>, ClusterError> {
+    let lock = lock::UnlockedFile::try_for_datadir_in(lock_dir, datadir)?;
+    let cluster = Cluster::new(datadir, runtime::strategy::default())?;
+    destroy(&cluster, lock)
+}
+
+/// Upgrade `cluster` to the version of PostgreSQL in `to` if it is not in use.
+///
+/// Similar to [`stop`] except this stops and then upgrades the cluster. Returns
+/// [`None`] if the cluster is in use, otherwise the result of
+/// [`Cluster::upgrade`], or of [`Cluster::upgrade_check`] when `check_only` is
+/// true.
+pub fn upgrade<B: lock::LockBackend>(
+    cluster: &Cluster,
+    lock: lock::Unlocked<B>,
+    to: &Runtime,
+    check_only: bool,
+) -> Result<Option<State>, ClusterError> {
+    match lock.try_lock_exclusive()? {
+        // The cluster is in use by someone/something else.
+        Left(_) => Ok(None),
+        Right(lock) => {
+            let state = if check_only {
+                cluster.upgrade_check(to)?
+            } else {
+                cluster.upgrade(to)?
+            };
+            lock.unlock()?;
+            Ok(Some(state))
+        }
+    }
+}
+
+/// Save a snapshot of `cluster` to `path` if it is not in use.
+///
+/// Similar to [`stop`] except this stops the cluster and then saves a snapshot
+/// of it. Returns [`None`] if the cluster is in use. See [`Cluster::snapshot`].
+pub fn snapshot<B: lock::LockBackend, P: AsRef<Path>>(
+    cluster: &Cluster,
+    lock: lock::Unlocked<B>,
+    path: P,
+) -> Result<Option<()>, ClusterError> {
+    match lock.try_lock_exclusive()? {
+        // The cluster is in use by someone/something else.
+        Left(_) => Ok(None),
+        Right(lock) => {
+            cluster.snapshot(path)?;
+            lock.unlock()?;
+            Ok(Some(()))
+        }
+    }
+}
+
+/// Replace `cluster` with the snapshot in `path` if it is not in use.
+///
+/// Similar to [`stop`] except this stops the cluster and then replaces it with
+/// the snapshot. Returns [`None`] if the cluster is in use. See
+/// [`Cluster::restore_snapshot`].
+pub fn restore_snapshot<B: lock::LockBackend, P: AsRef<Path>>(
+    cluster: &Cluster,
+    lock: lock::Unlocked<B>,
+    path: P,
+) -> Result<Option<()>, ClusterError> {
+    match lock.try_lock_exclusive()? {
+        // The cluster is in use by someone/something else.
+        Left(_) => Ok(None),
+        Right(lock) => {
+            cluster.restore_snapshot(path)?;
+            lock.unlock()?;
+            Ok(Some(()))
+        }
+    }
+}
+
+/// Perform `action`, e.g. [`Cluster::start`], while holding an exclusive lock.
+/// If `initdb` was interrupted earlier, e.g. because a test run was killed,
+/// [repair][`Cluster::repair`] the cluster and perform `action` again. With
+/// the lock held, no other process can be running `initdb` meanwhile.
+fn repairing<F, T>(cluster: &Cluster, action: F) -> Result<T, ClusterError>
+where
+    F: Fn(&Cluster) -> Result<T, ClusterError>,
+{
+    match action(cluster) {
+        Err(ClusterError::InitdbInterrupted(_)) => {
+            cluster.repair()?;
+            action(cluster)
+        }
+        result => result,
+    }
+}
+
+/// Wait for a shared lock, recording how long that took in the cluster's
+/// [metrics][`Cluster::metrics`].
+fn lock_shared<B: lock::LockBackend>(
+    cluster: &Cluster,
+    lock: lock::Unlocked<B>,
+) -> Result<lock::LockedShared<B>, ClusterError> {
+    tracing::debug!("cluster locked exclusively elsewhere; waiting for shared lock");
+    let started = Instant::now();
+    let lock = lock.lock_shared()?;
+    cluster.record_timing(cluster::Phase::LockWait, started.elapsed());
+    Ok(lock)
+}
+
+#[tracing::instrument(skip_all)]
+fn startup<B: lock::LockBackend>(
+    cluster: &Cluster,
+    mut lock: lock::Unlocked<B>,
+) -> Result<lock::LockedShared<B>, ClusterError> {
+    let mut backoff = Backoff::new();
     loop {
         lock = match lock.try_lock_exclusive() {
             Ok(Left(lock)) => {
                 // The cluster is locked exclusively by someone/something else.
                 // Switch to a shared lock optimistically. This blocks until we
                 // get the shared lock.
-                let lock = lock.lock_shared()?;
-                // The cluster may have been started while that exclusive lock
-                // was held, so we must check if the cluster is running now –
-                // otherwise we loop back to the top again.
-                if cluster.running()? {
-                    return Ok(lock);
+                let lock = lock_shared(cluster, lock)?;
+                // The lock file may have been removed while we waited, e.g.
+                // because the cluster was destroyed. If so, start again with a
+                // fresh lock file.
+                if lock.is_unlinked() {
+                    lock.unlock()?.reopen()?
+                } else {
+                    // The cluster may have been started while that exclusive
+                    // lock was held, so we must check if the cluster is running
+                    // now – otherwise we loop back to the top again.
+                    if cluster.running()? {
+                        return Ok(lock);
+                    }
+                    // Release all locks then back off for a random time in an
+                    // attempt to make sure that when there are many competing
+                    // processes one of them rapidly acquires an exclusive lock
+                    // and is able to create and start the cluster. The first
+                    // delays are short, so a lone process is not held up, but
+                    // they grow while processes keep colliding.
+                    let lock = lock.unlock()?;
+                    let delay = backoff.next();
+                    tracing::debug!(?delay, "cluster not running; backing off");
+                    std::thread::sleep(delay);
+                    lock
                 }
-                // Release all locks then sleep for a random time between 200ms
-                // and 1000ms in an attempt to make sure that when there are
-                // many competing processes one of them rapidly acquires an
-                // exclusive lock and is able to create and start the cluster.
-                let lock = lock.unlock()?;
-                let delay = rand::thread_rng().next_u32();
-                let delay = 200 + (delay % 800);
-                let delay = Duration::from_millis(u64::from(delay));
-                std::thread::sleep(delay);
-                lock
+            }
+            Ok(Right(lock)) if lock.is_unlinked() => {
+                // The lock file was removed while we waited for it; see above.
+                lock.unlock()?.reopen()?
             }
             Ok(Right(lock)) => {
                 // We have an exclusive lock, so try to start the cluster.
-                cluster.start()?;
+                repairing(cluster, Cluster::start)?;
                 // Once started, downgrade to a shared log.
                 return Ok(lock.lock_shared()?);
             }
@@ -111,10 +532,34 @@ fn startup(
     }
 }
 
-fn shutdown<F, T>(
+/// Capped exponential backoff with jitter, for processes competing for a lock.
+struct Backoff {
+    ceiling: Duration,
+}
+
+impl Backoff {
+    const INITIAL: Duration = Duration::from_millis(10);
+    const MAX: Duration = Duration::from_secs(1);
+
+    fn new() -> Self {
+        Self { ceiling: Self::INITIAL }
+    }
+
+    /// A random delay between half and all of the current ceiling, which then
+    /// doubles, up to [`Self::MAX`].
+    fn next(&mut self) -> Duration {
+        let delay = rand::thread_rng().gen_range(self.ceiling / 2..=self.ceiling);
+        self.ceiling = (self.ceiling * 2).min(Self::MAX);
+        delay
+    }
+}
+
+#[tracing::instrument(skip_all)]
+fn shutdown<F, T, B: lock::LockBackend>(
     cluster: &Cluster,
-    lock: lock::LockedFileShared,
+    lock: lock::LockedShared<B>,
     action: F,
+    remove_lock: bool,
 ) -> Result<Option<T>, ClusterError>
 where
     F: FnOnce(&Cluster) -> Result<T, ClusterError>,
@@ -129,6 +574,10 @@ where
         Ok(Right(lock)) => {
             // We have an exclusive lock, so we can mutate the cluster.
             match action(cluster) {
+                Ok(result) if remove_lock => {
+                    lock.remove()?;
+                    Ok(Some(result))
+                }
                 Ok(result) => {
                     lock.unlock()?;
                     Ok(Some(result))
@@ -142,13 +591,19 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use std::time::Duration;
+
     use crate::{
-        cluster::{Cluster, ClusterError},
-        lock::UnlockedFile,
+        cluster::{self, Cluster, ClusterError, State},
+        lock::{MemoryLock, Unlocked, UnlockedFile},
         runtime::{self, Runtime, Strategy},
     };
 
-    use super::{run_and_destroy, run_and_stop};
+    use super::{
+        create, destroy, destroy_orphans, run_and_destroy, run_and_leave_running, run_and_stop,
+        stop, with_log_excerpt, Backoff, Orphan,
+    };
 
     type TestResult = Result<(), ClusterError>;
 
@@ -157,6 +612,21 @@ mod tests {
         Box::new(runtimes.into_iter())
     }
 
+    #[test]
+    fn backoff_grows_with_jitter_up_to_a_cap() {
+        let mut backoff = Backoff::new();
+        let mut ceiling = Backoff::INITIAL;
+        for _ in 0..20 {
+            let delay = backoff.next();
+            assert!(
+                delay >= ceiling / 2 && delay <= ceiling,
+                "{delay:?} {ceiling:?}"
+            );
+            ceiling = (ceiling * 2).min(Backoff::MAX);
+        }
+        assert_eq!(Backoff::MAX, ceiling);
+    }
+
     #[test]
     fn run_and_stop_leaves_the_cluster_in_place() -> TestResult {
         for runtime in runtimes() {
@@ -187,6 +657,270 @@ mod tests {
             assert!(!databases.is_empty());
             assert!(!cluster.running()?);
             assert!(!datadir.exists());
+            assert!(!lockpath.exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn run_and_stop_works_with_other_lock_backends() -> TestResult {
+        for runtime in runtimes() {
+            println!("{runtime:?}");
+            let tempdir = tempdir::TempDir::new("somewhere")?;
+            let datadir = tempdir.path().join("data");
+            let cluster = Cluster::new(&datadir, runtime)?;
+            let lock = MemoryLock::new();
+            let other = Unlocked::new(lock.handle());
+            let databases = run_and_stop(&cluster, Unlocked::new(lock), Cluster::databases)??;
+            assert!(!databases.is_empty());
+            assert!(!cluster.running()?);
+            // The lock has been released.
+            assert!(other.try_lock_exclusive()?.is_right());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn run_and_leave_running_leaves_the_cluster_running() -> TestResult {
+        for runtime in runtimes() {
+            println!("{runtime:?}");
+            let tempdir = tempdir::TempDir::new("somewhere")?;
+            let datadir = tempdir.path().join("data");
+            let cluster = Cluster::new(&datadir, runtime)?;
+            let lock = MemoryLock::new();
+            let other = Unlocked::new(lock.handle());
+            let databases =
+                run_and_leave_running(&cluster, Unlocked::new(lock), Cluster::databases)??;
+            assert!(!databases.is_empty());
+            assert!(cluster.running()?);
+            // The lock has been released.
+            assert!(other.try_lock_exclusive()?.is_right());
+            cluster.destroy()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn with_log_excerpt_attaches_log_to_errors() -> TestResult {
+        for runtime in runtimes() {
+            println!("{runtime:?}");
+            let tempdir = tempdir::TempDir::new("somewhere")?;
+            let datadir = tempdir.path().join("data");
+            let cluster = Cluster::new(&datadir, runtime)?;
+            let lock = MemoryLock::new();
+            let action = with_log_excerpt(|cluster| cluster.createdb("template1"));
+            let err = run_and_destroy(&cluster, Unlocked::new(lock), action)?.unwrap_err();
+            assert!(err.log.contains(r#"database "template1" already exists"#));
+            // Only lines written while the action ran are included.
+            assert!(!err.log.contains("database system is ready"));
+            assert!(err.to_string().contains("Server log while running action:"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn with_log_excerpt_attaches_log_to_panics() -> TestResult {
+        for runtime in runtimes() {
+            println!("{runtime:?}");
+            let tempdir = tempdir::TempDir::new("somewhere")?;
+            let datadir = tempdir.path().join("data");
+            let cluster = Cluster::new(&datadir, runtime)?;
+            let lock = MemoryLock::new();
+            let action = with_log_excerpt(|cluster| -> Result<(), ClusterError> {
+                cluster.createdb("template1").expect("createdb failed");
+                Ok(())
+            });
+            let payload =
+                std::panic::catch_unwind(|| run_and_destroy(&cluster, Unlocked::new(lock), action))
+                    .unwrap_err();
+            let message = payload.downcast_ref::<String>().unwrap();
+            assert!(message.starts_with("createdb failed"));
+            assert!(message.contains(r#"database "template1" already exists"#));
+            cluster.destroy()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn run_and_stop_repairs_after_interrupted_initdb() -> TestResult {
+        for runtime in runtimes() {
+            println!("{runtime:?}");
+            let tempdir = tempdir::TempDir::new("somewhere")?;
+            let datadir = tempdir.path().join("data");
+            fs::create_dir_all(datadir.join("global"))?;
+            fs::write(datadir.join("postgresql.conf"), "")?;
+            let cluster = Cluster::new(&datadir, runtime)?;
+            let lock = MemoryLock::new();
+            assert!(run_and_stop(
+                &cluster,
+                Unlocked::new(lock),
+                cluster::exists
+            )?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn create_creates_the_cluster_without_starting_it() -> TestResult {
+        for runtime in runtimes() {
+            println!("{runtime:?}");
+            let tempdir = tempdir::TempDir::new("somewhere")?;
+            let datadir = tempdir.path().join("data");
+            let cluster = Cluster::new(&datadir, runtime)?;
+            let lock = MemoryLock::new();
+            let other = Unlocked::new(lock.handle());
+            assert_eq!(
+                State::Modified,
+                create(&cluster, Unlocked::new(lock.handle()))?
+            );
+            assert!(!cluster.running()?);
+            assert_eq!(State::Unmodified, create(&cluster, Unlocked::new(lock))?);
+            // The lock has been released.
+            assert!(other.try_lock_exclusive()?.is_right());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn stop_leaves_the_cluster_running_when_in_use() -> TestResult {
+        for runtime in runtimes() {
+            println!("{runtime:?}");
+            let tempdir = tempdir::TempDir::new("somewhere")?;
+            let datadir = tempdir.path().join("data");
+            let cluster = Cluster::new(&datadir, runtime)?;
+            let lock = MemoryLock::new();
+            cluster.start()?;
+            // Another user of the cluster holds a shared lock.
+            let other = Unlocked::new(lock.handle()).lock_shared()?;
+            assert_eq!(None, stop(&cluster, Unlocked::new(lock.handle()))?);
+            assert!(cluster.running()?);
+            // Once that's released, the cluster can be stopped.
+            other.unlock()?;
+            assert_eq!(
+                Some(State::Modified),
+                stop(&cluster, Unlocked::new(lock.handle()))?
+            );
+            assert!(!cluster.running()?);
+            cluster.destroy()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn destroy_orphans_destroys_clusters_whose_creator_has_gone() -> TestResult {
+        let mut child = std::process::Command::new("true").spawn()?;
+        let gone = child.id();
+        child.wait()?;
+        for runtime in runtimes() {
+            println!("{runtime:?}");
+            let tempdir = tempdir::TempDir::new("somewhere")?;
+            let lock_dir = tempdir::TempDir::new("locks")?;
+            let orphan = tempdir.path().join("orphan");
+            let nested = tempdir.path().join("nested").join("data");
+            let owned = tempdir.path().join("owned");
+            for datadir in [&orphan, &nested, &owned] {
+                Cluster::new(datadir, runtime.clone())?.create()?;
+            }
+            Cluster::new(&nested, runtime.clone())?.start()?;
+            for datadir in [&orphan, &nested] {
+                fs::write(
+                    datadir.join(cluster::OWNER_FILE),
+                    format!("{gone} 0 test\n"),
+                )?;
+            }
+            // Only clusters unused for long enough are orphans.
+            assert!(cluster::orphans(&tempdir, Duration::from_secs(60 * 60))?.is_empty());
+            let expected = vec![nested.canonicalize()?, orphan.canonicalize()?];
+            let destroyed = destroy_orphans(&tempdir, &lock_dir, Duration::ZERO)?;
+            assert!(destroyed
+                .iter()
+                .all(|(_, orphan)| matches!(orphan, Orphan::Destroyed)));
+            assert_eq!(
+                destroyed
+                    .into_iter()
+                    .map(|(datadir, _)| datadir)
+                    .collect::<Vec<_>>(),
+                expected
+            );
+            assert!(!orphan.exists());
+            assert!(!nested.exists());
+            assert!(cluster::exists(&owned));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn destroy_orphans_skips_clusters_locked_beside_datadir() -> TestResult {
+        let mut child = std::process::Command::new("true").spawn()?;
+        let gone = child.id();
+        child.wait()?;
+        for runtime in runtimes() {
+            println!("{runtime:?}");
+            let tempdir = tempdir::TempDir::new("somewhere")?;
+            let lock_dir = tempdir::TempDir::new("locks")?;
+            let datadir = tempdir.path().join("orphan");
+            Cluster::new(&datadir, runtime.clone())?.create()?;
+            fs::write(
+                datadir.join(cluster::OWNER_FILE),
+                format!("{gone} 0 test\n"),
+            )?;
+            // Another process uses the cluster with a lock beside its data
+            // directory, e.g. `--lock-beside-datadir`.
+            let beside = UnlockedFile::try_beside_datadir(&datadir)?.lock_shared()?;
+            let orphans = destroy_orphans(&tempdir, &lock_dir, Duration::ZERO)?;
+            assert!(matches!(
+                orphans.as_slice(),
+                [(path, Orphan::InUse)] if path == &datadir.canonicalize()?
+            ));
+            assert!(cluster::exists(&datadir));
+            // Once that's released, the orphan is destroyed, along with the
+            // lock file beside it.
+            beside.unlock()?;
+            let orphans = destroy_orphans(&tempdir, &lock_dir, Duration::ZERO)?;
+            assert!(matches!(orphans.as_slice(), [(_, Orphan::Destroyed)]));
+            assert!(!datadir.exists());
+            assert!(!tempdir.path().join("orphan.lock").exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn destroy_with_lock_beside_datadir() -> TestResult {
+        for runtime in runtimes() {
+            println!("{runtime:?}");
+            let tempdir = tempdir::TempDir::new("somewhere")?;
+            let datadir = tempdir.path().join("data");
+            let cluster = Cluster::new(&datadir, runtime)?;
+            cluster.create()?;
+            let lock = UnlockedFile::try_beside_datadir(&datadir)?;
+            assert!(destroy(&cluster, lock)?.is_some());
+            assert!(!datadir.exists());
+            assert!(!tempdir.path().join("data.lock").exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn destroy_leaves_the_cluster_in_place_when_in_use() -> TestResult {
+        for runtime in runtimes() {
+            println!("{runtime:?}");
+            let tempdir = tempdir::TempDir::new("somewhere")?;
+            let datadir = tempdir.path().join("data");
+            let cluster = Cluster::new(&datadir, runtime)?;
+            let lockpath = tempdir.path().join("lock");
+            cluster.start()?;
+            // Another user of the cluster holds a shared lock.
+            let other = UnlockedFile::try_from(&lockpath)?.lock_shared()?;
+            assert_eq!(None, destroy(&cluster, UnlockedFile::try_from(&lockpath)?)?);
+            assert!(cluster.running()?);
+            // Once that's released, the cluster can be destroyed.
+            other.unlock()?;
+            assert_eq!(
+                Some(State::Modified),
+                destroy(&cluster, UnlockedFile::try_from(&lockpath)?)?
+            );
+            assert!(!datadir.exists());
+            assert!(!lockpath.exists());
         }
         Ok(())
     }
@@ -19,10 +19,12 @@
 //!   let cluster = Cluster::new(&data_dir, runtime)?;
 //!   cluster.start()?;
 //!   assert_eq!(cluster.databases()?, vec!["postgres", "template0", "template1"]);
+//!   # #[cfg(feature = "client")] {
 //!   let mut conn = cluster.connect("template1")?;
 //!   let rows = conn.query("SELECT 1234 -- …", &[])?;
 //!   let collations: Vec<i32> = rows.iter().map(|row| row.get(0)).collect();
 //!   assert_eq!(collations, vec![1234]);
+//!   # }
 //!   cluster.stop()?;
 //! }
 //! # Ok::<(), ClusterError>(())
@@ -46,6 +48,7 @@ pub mod cluster;
 pub mod coordinate;
 pub mod lock;
 pub mod prelude;
+pub mod registry;
 pub mod runtime;
 pub mod version;
 
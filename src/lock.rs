@@ -1,4 +1,4 @@
-//! File-based locking using [`flock(2)`](https://linux.die.net/man/2/flock).
+//! Locking, by default using [`flock(2)`](https://linux.die.net/man/2/flock).
 //!
 //! You must start with an [`UnlockedFile`].
 //!
@@ -13,158 +13,259 @@
 //! ```
 //!
 //! Dropping a [`LockedFileShared`] or [`LockedFileExclusive`] drops their locks
-//! too, by virtue of dropping the [`File`] they each wrap, so there's no need
-//! to call `unlock` unless you prefer to be explicit.
+//! too, by virtue of dropping the [`File`][`std::fs::File`] they each wrap, so
+//! there's no need to call `unlock` unless you prefer to be explicit.
+//!
+//! Each time a lock is taken, a line describing the holder – process ID,
+//! program name, timestamp, and lock mode – is appended to the lock file. Taking
+//! an exclusive lock truncates the file first, so it describes only the most
+//! recent holders. Use [`holders`] to read these records back, e.g. when trying
+//! to figure out which process is keeping a cluster in use.
+//!
+//! ```rust
+//! # let lock_dir = tempdir::TempDir::new("locks")?;
+//! # let lock_path = lock_dir.path().join("foo");
+//! # use postgresfixture::lock::{self, UnlockedFile};
+//! let lock = UnlockedFile::try_from(lock_path.as_path())?.lock_exclusive()?;
+//! let holders = lock::holders(&lock_path)?;
+//! assert_eq!(holders[0].pid, std::process::id());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! The lock states – [`Unlocked`], [`LockedShared`], and [`LockedExclusive`] –
+//! are generic over a [`LockBackend`]. [`UnlockedFile`] and friends are aliases
+//! for these states using the [`FileLock`] backend. [`MemoryLock`] is an
+//! alternative backend that works only within a single process, e.g. for tests.
+//! Implement [`LockBackend`] to integrate with some other locking mechanism.
+
+mod file;
+mod memory;
 
-use std::fs::File;
-use std::os::unix::io::AsRawFd;
+use std::io;
+use std::path::Path;
 
 use either::{Either, Left, Right};
-use nix::errno::Errno;
-use nix::fcntl::{flock, FlockArg};
-use nix::Result;
-use uuid::Uuid;
 
-#[derive(Debug)]
-pub struct UnlockedFile(File);
-#[derive(Debug)]
-pub struct LockedFileShared(File);
-#[derive(Debug)]
-pub struct LockedFileExclusive(File);
+pub use file::{default_dir, gc, holders, path_beside_datadir, subjects, FileLock, Holder};
+pub use memory::MemoryLock;
+
+/// A mechanism for taking shared and exclusive locks.
+///
+/// A backend tracks the lock held by a single owner, e.g. an open file. It's
+/// not normally used directly; wrap it in [`Unlocked`] instead, which ensures
+/// that lock transitions are well ordered.
+pub trait LockBackend: Sized {
+    /// Take a lock in the given mode, blocking until it is available. If a lock
+    /// is already held in another mode it is converted, which may not be
+    /// atomic.
+    fn lock(&mut self, mode: Mode) -> io::Result<()>;
+
+    /// Take a lock in the given mode if it is available without blocking.
+    /// Returns `false` if the lock is not available.
+    fn try_lock(&mut self, mode: Mode) -> io::Result<bool>;
+
+    /// Release the lock, blocking if necessary.
+    fn unlock(&mut self) -> io::Result<()>;
+
+    /// Release the lock if that can be done without blocking. Returns `false`
+    /// if the lock could not be released.
+    ///
+    /// The default implementation calls [`Self::unlock`].
+    fn try_unlock(&mut self) -> io::Result<bool> {
+        self.unlock().map(|()| true)
+    }
 
-impl From<File> for UnlockedFile {
-    fn from(file: File) -> Self {
-        Self(file)
+    /// Has the lock been [removed][`Self::remove`] by another owner? If so, a
+    /// lock held by this owner no longer excludes others, and this owner should
+    /// [reopen][`Self::reopen`] the lock.
+    ///
+    /// The default implementation returns `false`.
+    fn is_unlinked(&self) -> bool {
+        false
     }
-}
 
-impl TryFrom<&std::path::Path> for UnlockedFile {
-    type Error = std::io::Error;
+    /// Obtain a fresh, unlocked, backend for the same lock.
+    ///
+    /// The default implementation returns `self`.
+    fn reopen(self) -> io::Result<Self> {
+        Ok(self)
+    }
 
-    fn try_from(path: &std::path::Path) -> std::io::Result<Self> {
-        std::fs::OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(path)
-            .map(UnlockedFile)
+    /// Remove the lock entirely, e.g. delete a lock file. This is called only
+    /// while holding an exclusive lock.
+    ///
+    /// The default implementation does nothing.
+    fn remove(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+    /// The path of the file that backs this lock, if there is one.
+    ///
+    /// The default implementation returns [`None`].
+    fn path(&self) -> Option<&Path> {
+        None
     }
 }
 
-impl TryFrom<&std::path::PathBuf> for UnlockedFile {
-    type Error = std::io::Error;
-
-    fn try_from(path: &std::path::PathBuf) -> std::io::Result<Self> {
-        Self::try_from(path.as_path())
-    }
+/// The mode in which a lock was taken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Shared,
+    Exclusive,
 }
 
-impl TryFrom<&Uuid> for UnlockedFile {
-    type Error = std::io::Error;
+#[derive(Debug)]
+pub struct Unlocked<B: LockBackend = FileLock>(B);
+#[derive(Debug)]
+pub struct LockedShared<B: LockBackend = FileLock>(B);
+#[derive(Debug)]
+pub struct LockedExclusive<B: LockBackend = FileLock>(B);
 
-    fn try_from(uuid: &Uuid) -> std::io::Result<Self> {
-        let mut buffer = Uuid::encode_buffer();
-        let uuid = uuid.simple().encode_lower(&mut buffer);
-        let filename = ".postgresfixture.".to_owned() + uuid;
-        let path = std::env::temp_dir().join(filename);
-        UnlockedFile::try_from(&*path)
-    }
-}
+pub type UnlockedFile = Unlocked<FileLock>;
+pub type LockedFileShared = LockedShared<FileLock>;
+pub type LockedFileExclusive = LockedExclusive<FileLock>;
 
 #[allow(unused)]
-impl UnlockedFile {
-    pub fn try_lock_shared(self) -> Result<Either<Self, LockedFileShared>> {
-        match flock(self.0.as_raw_fd(), FlockArg::LockSharedNonblock) {
-            Ok(()) => Ok(Right(LockedFileShared(self.0))),
-            Err(Errno::EAGAIN) => Ok(Left(self)),
-            Err(err) => Err(err),
+impl<B: LockBackend> Unlocked<B> {
+    pub fn new(backend: B) -> Self {
+        Self(backend)
+    }
+
+    pub fn try_lock_shared(mut self) -> io::Result<Either<Self, LockedShared<B>>> {
+        if self.0.try_lock(Mode::Shared)? {
+            Ok(Right(LockedShared(self.0)))
+        } else {
+            Ok(Left(self))
         }
     }
 
-    pub fn lock_shared(self) -> Result<LockedFileShared> {
-        flock(self.0.as_raw_fd(), FlockArg::LockShared)?;
-        Ok(LockedFileShared(self.0))
+    pub fn lock_shared(mut self) -> io::Result<LockedShared<B>> {
+        self.0.lock(Mode::Shared)?;
+        Ok(LockedShared(self.0))
     }
 
-    pub fn try_lock_exclusive(self) -> Result<Either<Self, LockedFileExclusive>> {
-        match flock(self.0.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
-            Ok(()) => Ok(Right(LockedFileExclusive(self.0))),
-            Err(Errno::EAGAIN) => Ok(Left(self)),
-            Err(err) => Err(err),
+    pub fn try_lock_exclusive(mut self) -> io::Result<Either<Self, LockedExclusive<B>>> {
+        if self.0.try_lock(Mode::Exclusive)? {
+            Ok(Right(LockedExclusive(self.0)))
+        } else {
+            Ok(Left(self))
         }
     }
 
-    pub fn lock_exclusive(self) -> Result<LockedFileExclusive> {
-        flock(self.0.as_raw_fd(), FlockArg::LockExclusive)?;
-        Ok(LockedFileExclusive(self.0))
+    pub fn lock_exclusive(mut self) -> io::Result<LockedExclusive<B>> {
+        self.0.lock(Mode::Exclusive)?;
+        Ok(LockedExclusive(self.0))
+    }
+
+    /// Obtain a fresh lock, e.g. after it was removed by another process while
+    /// we waited for it. See [`LockBackend::reopen`].
+    pub fn reopen(self) -> io::Result<Self> {
+        self.0.reopen().map(Self)
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.0
     }
 }
 
 #[allow(unused)]
-impl LockedFileShared {
-    pub fn try_lock_exclusive(self) -> Result<Either<Self, LockedFileExclusive>> {
-        match flock(self.0.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
-            Ok(()) => Ok(Right(LockedFileExclusive(self.0))),
-            Err(Errno::EAGAIN) => Ok(Left(self)),
-            Err(err) => Err(err),
+impl<B: LockBackend> LockedShared<B> {
+    pub fn try_lock_exclusive(mut self) -> io::Result<Either<Self, LockedExclusive<B>>> {
+        if self.0.try_lock(Mode::Exclusive)? {
+            Ok(Right(LockedExclusive(self.0)))
+        } else {
+            Ok(Left(self))
         }
     }
 
-    pub fn lock_exclusive(self) -> Result<LockedFileExclusive> {
-        flock(self.0.as_raw_fd(), FlockArg::LockExclusive)?;
-        Ok(LockedFileExclusive(self.0))
+    pub fn lock_exclusive(mut self) -> io::Result<LockedExclusive<B>> {
+        self.0.lock(Mode::Exclusive)?;
+        Ok(LockedExclusive(self.0))
     }
 
-    pub fn try_unlock(self) -> Result<Either<Self, UnlockedFile>> {
-        match flock(self.0.as_raw_fd(), FlockArg::UnlockNonblock) {
-            Ok(()) => Ok(Right(UnlockedFile(self.0))),
-            Err(Errno::EAGAIN) => Ok(Left(self)),
-            Err(err) => Err(err),
+    pub fn try_unlock(mut self) -> io::Result<Either<Self, Unlocked<B>>> {
+        if self.0.try_unlock()? {
+            Ok(Right(Unlocked(self.0)))
+        } else {
+            Ok(Left(self))
         }
     }
 
-    pub fn unlock(self) -> Result<UnlockedFile> {
-        flock(self.0.as_raw_fd(), FlockArg::Unlock)?;
-        Ok(UnlockedFile(self.0))
+    pub fn unlock(mut self) -> io::Result<Unlocked<B>> {
+        self.0.unlock()?;
+        Ok(Unlocked(self.0))
+    }
+
+    /// Has this lock been removed by another owner? If so, this lock no longer
+    /// excludes others. See [`LockBackend::is_unlinked`].
+    pub fn is_unlinked(&self) -> bool {
+        self.0.is_unlinked()
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.0
     }
 }
 
 #[allow(unused)]
-impl LockedFileExclusive {
-    pub fn try_lock_shared(self) -> Result<Either<Self, LockedFileShared>> {
-        match flock(self.0.as_raw_fd(), FlockArg::LockSharedNonblock) {
-            Ok(()) => Ok(Right(LockedFileShared(self.0))),
-            Err(Errno::EAGAIN) => Ok(Left(self)),
-            Err(err) => Err(err),
+impl<B: LockBackend> LockedExclusive<B> {
+    pub fn try_lock_shared(mut self) -> io::Result<Either<Self, LockedShared<B>>> {
+        if self.0.try_lock(Mode::Shared)? {
+            Ok(Right(LockedShared(self.0)))
+        } else {
+            Ok(Left(self))
         }
     }
 
-    pub fn lock_shared(self) -> Result<LockedFileShared> {
-        flock(self.0.as_raw_fd(), FlockArg::LockShared)?;
-        Ok(LockedFileShared(self.0))
+    pub fn lock_shared(mut self) -> io::Result<LockedShared<B>> {
+        self.0.lock(Mode::Shared)?;
+        Ok(LockedShared(self.0))
     }
 
-    pub fn try_unlock(self) -> Result<Either<Self, UnlockedFile>> {
-        match flock(self.0.as_raw_fd(), FlockArg::UnlockNonblock) {
-            Ok(()) => Ok(Right(UnlockedFile(self.0))),
-            Err(Errno::EAGAIN) => Ok(Left(self)),
-            Err(err) => Err(err),
+    pub fn try_unlock(mut self) -> io::Result<Either<Self, Unlocked<B>>> {
+        if self.0.try_unlock()? {
+            Ok(Right(Unlocked(self.0)))
+        } else {
+            Ok(Left(self))
         }
     }
 
-    pub fn unlock(self) -> Result<UnlockedFile> {
-        flock(self.0.as_raw_fd(), FlockArg::Unlock)?;
-        Ok(UnlockedFile(self.0))
+    pub fn unlock(mut self) -> io::Result<Unlocked<B>> {
+        self.0.unlock()?;
+        Ok(Unlocked(self.0))
+    }
+
+    /// Has this lock been removed by another owner? If so, this lock no longer
+    /// excludes others. See [`LockBackend::is_unlinked`].
+    pub fn is_unlinked(&self) -> bool {
+        self.0.is_unlinked()
+    }
+
+    /// Remove the lock, e.g. delete the lock file, then release it.
+    ///
+    /// Removing the lock while holding it exclusively means that no other
+    /// process can be using it. Processes that opened the lock before it was
+    /// removed will find that it [has been unlinked][`Self::is_unlinked`] once
+    /// they obtain it, and must [reopen][`Unlocked::reopen`] it.
+    pub fn remove(mut self) -> io::Result<()> {
+        self.0.remove()?;
+        self.0.unlock()
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.0
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::UnlockedFile;
+    use super::{gc, holders, subjects, Mode, UnlockedFile};
+
+    use uuid::Uuid;
 
     use std::fs::OpenOptions;
     use std::io;
     use std::os::unix::io::AsRawFd;
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
 
     use either::Left;
     use nix::fcntl::{flock, FlockArg};
@@ -260,4 +361,160 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn file_lock_records_holders() -> io::Result<()> {
+        let lock_dir = tempdir::TempDir::new("locks")?;
+        let lock_filename = lock_dir.path().join("lock");
+
+        let lock = UnlockedFile::try_from(&lock_filename)?.lock_shared()?;
+        let lock = lock.unlock()?.lock_shared()?;
+        let records = lock.backend().holders()?;
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|holder| holder.mode == Mode::Shared));
+        assert!(records
+            .iter()
+            .all(|holder| holder.pid == std::process::id()));
+
+        // Taking an exclusive lock forgets earlier holders.
+        let lock = lock.lock_exclusive()?;
+        let records = holders(&lock_filename)?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].mode, Mode::Exclusive);
+
+        // Downgrading to a shared lock is recorded too.
+        let _lock = lock.lock_shared()?;
+        let records = holders(&lock_filename)?;
+        assert_eq!(
+            records.iter().map(|holder| holder.mode).collect::<Vec<_>>(),
+            vec![Mode::Exclusive, Mode::Shared]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn file_from_uuid_in_uses_given_directory() -> io::Result<()> {
+        let lock_dir = tempdir::TempDir::new("locks")?;
+        let uuid = Uuid::from_u128(0x1234);
+        let _lock = UnlockedFile::try_from_uuid_in(lock_dir.path(), &uuid)?.lock_exclusive()?;
+        let lock_filename = lock_dir
+            .path()
+            .join(".postgresfixture.00000000000000000000000000001234");
+        assert!(!can_lock_exclusive(lock_filename));
+        Ok(())
+    }
+
+    #[test]
+    fn file_beside_datadir() -> io::Result<()> {
+        let dir = tempdir::TempDir::new("data")?;
+        let cluster = dir.path().join("cluster");
+        let lock = UnlockedFile::try_beside_datadir(&cluster)?;
+        let lock_filename = dir.path().join("cluster.lock");
+        assert_eq!(Some(lock_filename.as_path()), lock.backend().path());
+        let lock = lock.lock_exclusive()?;
+        assert!(!can_lock_exclusive(&lock_filename));
+        // Not a lock file that `gc` looks after.
+        assert_eq!(Vec::<(PathBuf, PathBuf)>::new(), subjects(dir.path())?);
+        lock.remove()?;
+        assert!(!lock_filename.exists());
+        assert!(UnlockedFile::try_beside_datadir("/").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn gc_removes_unlocked_lock_files_for_missing_clusters() -> io::Result<()> {
+        let lock_dir = tempdir::TempDir::new("locks")?;
+        let data_dir = tempdir::TempDir::new("data")?;
+
+        // A cluster that does not exist.
+        let missing = data_dir.path().join("missing");
+        let missing_lock = UnlockedFile::try_for_datadir_in(lock_dir.path(), &missing)?;
+        let missing_path = missing_lock.backend().path().unwrap().to_owned();
+        drop(missing_lock);
+
+        // A cluster that does not exist, but the lock is held.
+        let held = data_dir.path().join("held");
+        let held_lock = UnlockedFile::try_for_datadir_in(lock_dir.path(), &held)?;
+        let held_lock = held_lock.lock_shared()?;
+
+        // A cluster that looks like it exists.
+        let present = data_dir.path().join("present");
+        std::fs::create_dir(&present)?;
+        std::fs::write(present.join("PG_VERSION"), "16\n")?;
+        std::fs::create_dir(present.join("global"))?;
+        std::fs::write(present.join("global").join("pg_control"), "")?;
+        let present_lock = UnlockedFile::try_for_datadir_in(lock_dir.path(), &present)?;
+        let present_path = present_lock.backend().path().unwrap().to_owned();
+        drop(present_lock);
+
+        let mut subjects = subjects(lock_dir.path())?;
+        subjects.sort();
+        let mut expected = vec![
+            (missing_path.clone(), missing),
+            (held_lock.backend().path().unwrap().to_owned(), held),
+            (present_path.clone(), present),
+        ];
+        expected.sort();
+        assert_eq!(expected, subjects);
+
+        assert_eq!(gc(lock_dir.path())?, vec![missing_path.clone()]);
+        assert!(!missing_path.exists());
+        assert!(held_lock.backend().path().unwrap().exists());
+        assert!(present_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn gc_skips_lock_files_that_cannot_be_opened() -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let lock_dir = tempdir::TempDir::new("locks")?;
+        let data_dir = tempdir::TempDir::new("data")?;
+
+        // A lock file that we may not open, e.g. one belonging to another
+        // user. When running as root this can be opened, but is empty.
+        let forbidden = lock_dir.path().join(".postgresfixture.forbidden");
+        std::fs::write(&forbidden, "")?;
+        std::fs::set_permissions(&forbidden, std::fs::Permissions::from_mode(0o000))?;
+
+        // A stale lock file.
+        let missing = data_dir.path().join("missing");
+        let missing_lock = UnlockedFile::try_for_datadir_in(lock_dir.path(), &missing)?;
+        let missing_path = missing_lock.backend().path().unwrap().to_owned();
+        drop(missing_lock);
+
+        assert_eq!(
+            subjects(lock_dir.path())?,
+            vec![(missing_path.clone(), missing)]
+        );
+        assert_eq!(gc(lock_dir.path())?, vec![missing_path]);
+        assert!(forbidden.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn removed_lock_file_is_seen_as_unlinked() -> io::Result<()> {
+        let lock_dir = tempdir::TempDir::new("locks")?;
+        let lock_filename = lock_dir.path().join("lock");
+        let lock = UnlockedFile::try_from(&lock_filename)?;
+        UnlockedFile::try_from(&lock_filename)?
+            .lock_exclusive()?
+            .remove()?;
+        let lock = lock.lock_exclusive()?;
+        assert!(lock.is_unlinked());
+        let lock = lock.unlock()?.reopen()?.lock_exclusive()?;
+        assert!(!lock.is_unlinked());
+        Ok(())
+    }
+
+    #[test]
+    fn file_try_from_creates_parent_directories() -> io::Result<()> {
+        let lock_dir = tempdir::TempDir::new("locks")?;
+        let lock_filename = lock_dir.path().join("foo").join("bar").join("lock");
+        let _lock = UnlockedFile::try_from(&lock_filename)?.lock_exclusive()?;
+        assert!(lock_filename.is_file());
+        Ok(())
+    }
 }
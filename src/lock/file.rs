@@ -0,0 +1,429 @@
+//! File-based locking using [`flock(2)`](https://linux.die.net/man/2/flock).
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use either::{Left, Right};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use uuid::Uuid;
+
+use super::{LockBackend, Mode, Unlocked, UnlockedFile};
+
+/// A lock backed by a file, using `flock(2)`.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    path: Option<PathBuf>,
+}
+
+impl FileLock {
+    /// The path to this lock file, if known.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Read the holder records from this lock file. See [`holders`].
+    pub fn holders(&self) -> io::Result<Vec<Holder>> {
+        read_holders(&self.file)
+    }
+
+    fn flock(&self, arg: FlockArg) -> io::Result<bool> {
+        match flock(self.file.as_raw_fd(), arg) {
+            Ok(()) => Ok(true),
+            Err(Errno::EAGAIN) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn record(&self, mode: Mode) {
+        if mode == Mode::Exclusive {
+            // We are the only holder now, so forget about earlier holders, but
+            // keep the subject of the lock.
+            let subject = read_subject(&self.file).ok().flatten();
+            let _ = self.file.set_len(0);
+            if let Some(subject) = subject {
+                write_subject(&self.file, &subject);
+            }
+        }
+        record_holder(&self.file, mode);
+    }
+}
+
+impl LockBackend for FileLock {
+    fn lock(&mut self, mode: Mode) -> io::Result<()> {
+        self.flock(match mode {
+            Mode::Shared => FlockArg::LockShared,
+            Mode::Exclusive => FlockArg::LockExclusive,
+        })?;
+        self.record(mode);
+        Ok(())
+    }
+
+    fn try_lock(&mut self, mode: Mode) -> io::Result<bool> {
+        let locked = self.flock(match mode {
+            Mode::Shared => FlockArg::LockSharedNonblock,
+            Mode::Exclusive => FlockArg::LockExclusiveNonblock,
+        })?;
+        if locked {
+            self.record(mode);
+        }
+        Ok(locked)
+    }
+
+    fn unlock(&mut self) -> io::Result<()> {
+        self.flock(FlockArg::Unlock).map(|_| ())
+    }
+
+    fn try_unlock(&mut self) -> io::Result<bool> {
+        self.flock(FlockArg::UnlockNonblock)
+    }
+
+    /// Has this lock file been removed from the filesystem?
+    fn is_unlinked(&self) -> bool {
+        self.file
+            .metadata()
+            .is_ok_and(|metadata| metadata.nlink() == 0)
+    }
+
+    /// Open the lock file afresh. Does nothing if this lock was created from a
+    /// [`File`], i.e. if its path is not known.
+    fn reopen(self) -> io::Result<Self> {
+        match self.path {
+            Some(ref path) => UnlockedFile::try_from(path).map(|lock| lock.0),
+            None => Ok(self),
+        }
+    }
+
+    /// Delete the lock file. Does nothing if this lock was created from a
+    /// [`File`], i.e. if its path is not known.
+    fn remove(&mut self) -> io::Result<()> {
+        match self.path {
+            Some(ref path) => match std::fs::remove_file(path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                other => other,
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+impl From<File> for UnlockedFile {
+    fn from(file: File) -> Self {
+        Unlocked(FileLock { file, path: None })
+    }
+}
+
+impl TryFrom<&std::path::Path> for UnlockedFile {
+    type Error = std::io::Error;
+
+    /// Open the lock file at `path`, creating it – and any missing parent
+    /// directories – if necessary.
+    fn try_from(path: &std::path::Path) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map(|file| Unlocked(FileLock { file, path: Some(path.to_owned()) }))
+    }
+}
+
+impl TryFrom<&std::path::PathBuf> for UnlockedFile {
+    type Error = std::io::Error;
+
+    fn try_from(path: &std::path::PathBuf) -> std::io::Result<Self> {
+        Self::try_from(path.as_path())
+    }
+}
+
+impl TryFrom<&Uuid> for UnlockedFile {
+    type Error = std::io::Error;
+
+    /// Open a lock file named for the given UUID in the [default
+    /// directory][`default_dir`].
+    fn try_from(uuid: &Uuid) -> std::io::Result<Self> {
+        UnlockedFile::try_from_uuid_in(default_dir(), uuid)
+    }
+}
+
+impl UnlockedFile {
+    /// Open a lock file named for the given UUID in the given directory.
+    ///
+    /// Use this when [`default_dir`] is not suitable, e.g. on multi-user
+    /// machines where `/tmp` is shared, or when `TMPDIR` differs between the
+    /// processes that need to coordinate. Good alternatives are the directory
+    /// containing the cluster's data directory, or `XDG_RUNTIME_DIR`.
+    pub fn try_from_uuid_in<P: AsRef<Path>>(dir: P, uuid: &Uuid) -> io::Result<Self> {
+        let mut buffer = Uuid::encode_buffer();
+        let uuid = uuid.simple().encode_lower(&mut buffer);
+        let filename = ".postgresfixture.".to_owned() + uuid;
+        UnlockedFile::try_from(dir.as_ref().join(filename).as_path())
+    }
+
+    /// Open a lock file for the cluster in the given data directory.
+    ///
+    /// The lock file is placed in `dir` and is named with a UUID derived from
+    /// `datadir`, so different processes will agree on the lock file to use as
+    /// long as they agree on the path to the data directory; it's best to
+    /// canonicalize `datadir` first. The data directory is also recorded in the
+    /// lock file so that [`gc`] can later figure out if it's still needed.
+    pub fn try_for_datadir_in<P: AsRef<Path>, D: AsRef<Path>>(
+        dir: P,
+        datadir: D,
+    ) -> io::Result<Self> {
+        let datadir = datadir.as_ref();
+        // Use the `Debug` form of `datadir`; it's what earlier versions used,
+        // so lock file names remain stable.
+        #[allow(clippy::unnecessary_debug_formatting)]
+        let uuid = Uuid::new_v5(&UUID_NS, format!("{datadir:?}").as_bytes());
+        let lock = UnlockedFile::try_from_uuid_in(dir, &uuid)?;
+        if lock.0.file.metadata()?.len() == 0 {
+            write_subject(&lock.0.file, datadir);
+        }
+        Ok(lock)
+    }
+
+    /// Open a lock file for the cluster in the given data directory, placed
+    /// beside it, e.g. `tmp/cluster.lock` for a cluster in `tmp/cluster`.
+    ///
+    /// Unlike [`Self::try_for_datadir_in`], processes need only agree on where
+    /// the cluster is, not also on a lock directory. The lock file is removed
+    /// with the cluster by, for example,
+    /// [`run_and_destroy`][`crate::coordinate::run_and_destroy`]. It's not
+    /// found by [`gc`] or [`subjects`].
+    pub fn try_beside_datadir<D: AsRef<Path>>(datadir: D) -> io::Result<Self> {
+        let datadir = datadir.as_ref();
+        let path = path_beside_datadir(datadir).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has no file name", datadir.display()),
+            )
+        })?;
+        let lock = UnlockedFile::try_from(path.as_path())?;
+        if lock.0.file.metadata()?.len() == 0 {
+            write_subject(&lock.0.file, datadir);
+        }
+        Ok(lock)
+    }
+}
+
+/// The path of the lock file beside the given data directory, as opened by
+/// [`UnlockedFile::try_beside_datadir`], e.g. `tmp/cluster.lock` for a cluster
+/// in `tmp/cluster`. Returns [`None`] if `datadir` has no file name.
+pub fn path_beside_datadir<D: AsRef<Path>>(datadir: D) -> Option<PathBuf> {
+    let datadir = datadir.as_ref();
+    let mut filename = datadir.file_name()?.to_owned();
+    filename.push(".lock");
+    Some(datadir.with_file_name(filename))
+}
+
+/// Namespace for UUIDs derived from data directory paths. See
+/// [`UnlockedFile::try_for_datadir_in`].
+const UUID_NS: Uuid = Uuid::from_u128(93_875_103_436_633_470_414_348_750_305_797_058_811);
+
+/// The directory in which UUID-named lock files are placed by default.
+///
+/// This is [`std::env::temp_dir`]. Use [`UnlockedFile::try_from_uuid_in`] to
+/// place lock files elsewhere.
+pub fn default_dir() -> std::path::PathBuf {
+    std::env::temp_dir()
+}
+
+/// Remove stale lock files from the given directory.
+///
+/// A lock file is stale if it was created by
+/// [`UnlockedFile::try_for_datadir_in`], the cluster whose data directory it
+/// records no longer exists, and it's not locked. Lock files that are locked,
+/// or that do not record a data directory, are left alone.
+///
+/// Returns the paths of the lock files that were removed.
+pub fn gc<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !is_lock_file(&path) {
+            continue;
+        }
+        // Lock files belonging to other users, e.g. in a shared `/tmp`, may
+        // not be ours to open or remove; skip them.
+        let stale = || -> io::Result<bool> {
+            let lock = UnlockedFile::try_from(path.as_path())?;
+            match read_subject(&lock.0.file)? {
+                Some(datadir) if !crate::cluster::exists(&datadir) => {
+                    match lock.try_lock_exclusive()? {
+                        Right(lock) => lock.remove().map(|()| true),
+                        Left(_) => Ok(false),
+                    }
+                }
+                _ => Ok(false),
+            }
+        };
+        match stale() {
+            Ok(true) => removed.push(path),
+            Ok(false) => (),
+            Err(err) => tracing::debug!(?path, %err, "skipping lock file"),
+        }
+    }
+    Ok(removed)
+}
+
+/// Find lock files created by [`UnlockedFile::try_for_datadir_in`] in the given
+/// directory, and the data directory that each is for.
+///
+/// This does not take a lock on the files, and the clusters may not exist.
+/// Lock files that cannot be read, e.g. those belonging to other users, are
+/// skipped.
+pub fn subjects<P: AsRef<Path>>(dir: P) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut subjects = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if is_lock_file(&path) {
+            match File::open(&path).and_then(|file| read_subject(&file)) {
+                Ok(Some(datadir)) => subjects.push((path, datadir)),
+                Ok(None) => (),
+                Err(err) => tracing::debug!(?path, %err, "skipping lock file"),
+            }
+        }
+    }
+    Ok(subjects)
+}
+
+/// Does the given path look like a lock file created by
+/// [`UnlockedFile::try_for_datadir_in`]?
+fn is_lock_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.as_bytes().starts_with(b".postgresfixture."))
+        && path.is_file()
+}
+
+/// Read the subject – the data directory the lock is for – from a lock file.
+fn read_subject(mut file: &File) -> io::Result<Option<PathBuf>> {
+    let mut content = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut content)?;
+    Ok(content.split(|&byte| byte == b'\n').find_map(|line| {
+        line.strip_prefix(b"subject\t")
+            .map(|subject| PathBuf::from(std::ffi::OsStr::from_bytes(subject)))
+    }))
+}
+
+/// Write the subject of a lock file. This is best-effort, like
+/// [`record_holder`]. Subjects containing newlines are not recorded.
+fn write_subject(mut file: &File, subject: &Path) {
+    let subject = subject.as_os_str().as_bytes();
+    if !subject.contains(&b'\n') {
+        let _ = file.write_all(&[b"subject\t", subject, b"\n"].concat());
+    }
+}
+
+/// A record of a process that took a lock.
+///
+/// The process may since have released the lock, or exited altogether.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Holder {
+    /// The process ID of the holder.
+    pub pid: u32,
+    /// The name of the holder's program, if known.
+    pub program: String,
+    /// When the lock was taken, to the nearest second.
+    pub timestamp: SystemTime,
+    /// The mode in which the lock was taken.
+    pub mode: Mode,
+}
+
+impl std::fmt::Display for Holder {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let secs = self
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let mode = match self.mode {
+            Mode::Shared => "shared",
+            Mode::Exclusive => "exclusive",
+        };
+        write!(fmt, "{}\t{mode}\t{secs}\t{}", self.pid, self.program)
+    }
+}
+
+impl std::str::FromStr for Holder {
+    type Err = io::Error;
+
+    fn from_str(line: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, line.to_owned());
+        let mut fields = line.splitn(4, '\t');
+        let pid = fields
+            .next()
+            .and_then(|pid| pid.parse().ok())
+            .ok_or_else(invalid)?;
+        let mode = match fields.next() {
+            Some("shared") => Mode::Shared,
+            Some("exclusive") => Mode::Exclusive,
+            _ => return Err(invalid()),
+        };
+        let secs = fields
+            .next()
+            .and_then(|secs| secs.parse().ok())
+            .ok_or_else(invalid)?;
+        let program = fields.next().ok_or_else(invalid)?.to_owned();
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        Ok(Self { pid, program, timestamp, mode })
+    }
+}
+
+/// Read the holder records from the lock file at `path`.
+///
+/// Records are in the order in which locks were taken, so the last record is
+/// the most recent. Taking an exclusive lock clears out older records, so this
+/// typically returns the most recent exclusive holder followed by any shared
+/// holders since then. Other lines, e.g. the subject line written by
+/// [`UnlockedFile::try_for_datadir_in`], are skipped.
+///
+/// This does not take a lock on the file.
+pub fn holders<P: AsRef<Path>>(path: P) -> io::Result<Vec<Holder>> {
+    read_holders(&File::open(path)?)
+}
+
+fn read_holders(mut file: &File) -> io::Result<Vec<Holder>> {
+    let mut content = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut content)?;
+    Ok(content
+        .lines()
+        .filter_map(|line| line.parse().ok())
+        .collect())
+}
+
+/// Append a holder record for this process to the given lock file.
+///
+/// This is best-effort: the lock has already been taken, and failing to record
+/// who holds it is not a good enough reason to give it up.
+fn record_holder(mut file: &File, mode: Mode) {
+    let program = std::env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            exe.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default()
+        .replace(char::is_control, "?");
+    let holder = Holder {
+        pid: std::process::id(),
+        program,
+        timestamp: SystemTime::now(),
+        mode,
+    };
+    let _ = writeln!(file, "{holder}");
+}
@@ -0,0 +1,156 @@
+//! In-process locking, e.g. for tests.
+
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+use super::{LockBackend, Mode};
+
+#[derive(Debug, Default)]
+struct State {
+    shared: usize,
+    exclusive: bool,
+}
+
+/// A lock that lives in memory and so works only within a single process.
+///
+/// Create a lock with [`MemoryLock::new`], then create further owners of the
+/// same lock with [`MemoryLock::handle`]. This is useful in tests, where it can
+/// stand in for a [`FileLock`][`super::FileLock`].
+///
+/// ```rust
+/// # use postgresfixture::lock::{MemoryLock, Unlocked};
+/// let lock = MemoryLock::new();
+/// let other = Unlocked::new(lock.handle());
+/// let _lock = Unlocked::new(lock).lock_exclusive()?;
+/// assert!(other.try_lock_shared()?.is_left());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryLock {
+    state: Arc<(Mutex<State>, Condvar)>,
+    held: Option<Mode>,
+}
+
+impl MemoryLock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Another owner of the same lock. The new owner does not hold the lock.
+    #[must_use]
+    pub fn handle(&self) -> Self {
+        Self { state: Arc::clone(&self.state), held: None }
+    }
+
+    /// Could this owner take the lock in the given mode?
+    fn available(&self, state: &State, mode: Mode) -> bool {
+        // Discount the lock this owner holds, if any.
+        let (shared, exclusive) = match self.held {
+            Some(Mode::Shared) => (state.shared - 1, state.exclusive),
+            Some(Mode::Exclusive) => (state.shared, false),
+            None => (state.shared, state.exclusive),
+        };
+        match mode {
+            Mode::Shared => !exclusive,
+            Mode::Exclusive => !exclusive && shared == 0,
+        }
+    }
+
+    fn release(&mut self, state: &mut State) {
+        match self.held.take() {
+            Some(Mode::Shared) => state.shared -= 1,
+            Some(Mode::Exclusive) => state.exclusive = false,
+            None => (),
+        }
+    }
+
+    fn take(&mut self, state: &mut State, mode: Mode) {
+        self.release(state);
+        match mode {
+            Mode::Shared => state.shared += 1,
+            Mode::Exclusive => state.exclusive = true,
+        }
+        self.held = Some(mode);
+    }
+}
+
+impl LockBackend for MemoryLock {
+    fn lock(&mut self, mode: Mode) -> io::Result<()> {
+        let state = Arc::clone(&self.state);
+        let mut guard = state.0.lock().unwrap_or_else(PoisonError::into_inner);
+        while !self.available(&guard, mode) {
+            guard = state.1.wait(guard).unwrap_or_else(PoisonError::into_inner);
+        }
+        self.take(&mut guard, mode);
+        state.1.notify_all();
+        Ok(())
+    }
+
+    fn try_lock(&mut self, mode: Mode) -> io::Result<bool> {
+        let state = Arc::clone(&self.state);
+        let mut guard = state.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.available(&guard, mode) {
+            self.take(&mut guard, mode);
+            state.1.notify_all();
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn unlock(&mut self) -> io::Result<()> {
+        let state = Arc::clone(&self.state);
+        let mut guard = state.0.lock().unwrap_or_else(PoisonError::into_inner);
+        self.release(&mut guard);
+        state.1.notify_all();
+        Ok(())
+    }
+}
+
+impl Drop for MemoryLock {
+    fn drop(&mut self) {
+        if self.held.is_some() {
+            let _ = self.unlock();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use either::{Left, Right};
+
+    use super::MemoryLock;
+    use crate::lock::Unlocked;
+
+    #[test]
+    fn memory_lock_shared_excludes_exclusive() -> io::Result<()> {
+        let lock = MemoryLock::new();
+        let other = Unlocked::new(lock.handle());
+        let lock = Unlocked::new(lock).lock_shared()?;
+        let other = match other.try_lock_exclusive()? {
+            Left(other) => other,
+            Right(_) => panic!("exclusive lock taken while shared lock held"),
+        };
+        let other = match other.try_lock_shared()? {
+            Left(_) => panic!("shared lock not taken"),
+            Right(other) => other,
+        };
+        drop(lock);
+        assert!(other.try_lock_exclusive()?.is_right());
+        Ok(())
+    }
+
+    #[test]
+    fn memory_lock_blocks_until_released() -> io::Result<()> {
+        let lock = MemoryLock::new();
+        let other = Unlocked::new(lock.handle());
+        let lock = Unlocked::new(lock).lock_exclusive()?;
+        let waiter = std::thread::spawn(move || other.lock_exclusive().map(|_| ()));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+        lock.unlock()?;
+        waiter.join().unwrap()
+    }
+}
@@ -1,72 +1,225 @@
 mod cli;
 
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io;
-use std::path::PathBuf;
-use std::process::{exit, ExitStatus};
+use std::io::{self, IsTerminal, Read, Seek, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-use clap::Parser;
-use color_eyre::eyre::{bail, Result, WrapErr};
+use clap::CommandFactory;
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
 use color_eyre::{Help, SectionExt};
+use nix::libc::{c_int, c_void, siginfo_t};
+use nix::sys::signal::{kill, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd::Pid;
 
 use postgresfixture::{
-    cluster, coordinate, lock,
+    cluster, coordinate, lock, registry,
     runtime::{self, strategy::Strategy},
+    version,
 };
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
-    let cli = cli::Cli::parse();
+    let cli = cli::Cli::parse_with_config()?;
+    init_logging(&cli.logging);
     let result = match cli.command {
-        cli::Commands::Shell { cluster, database, lifecycle } => run(
-            cluster.dir,
-            &database.name,
-            lifecycle.destroy,
-            initialise(cluster.mode),
+        cli::Commands::Shell { cluster, mode, listen, database, lifecycle, commands, args } => run(
+            &cluster,
+            listen.tcp(),
+            durability(mode.mode),
+            &database.names(),
+            Teardown::from(&lifecycle),
+            initialise(mode.mode),
             |cluster| {
-                check_exit(
+                run_child(
                     cluster
-                        .shell(&database.name)
+                        .shell_command(database.name(), &psql_args(&commands, args))
                         .wrap_err("Starting PostgreSQL shell in cluster failed")?,
                 )
             },
         ),
-        cli::Commands::Exec { cluster, database, command, args, lifecycle } => run(
-            cluster.dir,
-            &database.name,
-            lifecycle.destroy,
-            initialise(cluster.mode),
+        cli::Commands::Exec {
+            cluster,
+            mode,
+            listen,
+            database,
+            command,
+            args,
+            lifecycle,
+            detach,
+            workdir,
+        } => run(
+            &cluster,
+            listen.tcp(),
+            durability(mode.mode),
+            &database.names(),
+            if detach {
+                Teardown::LeaveRunning
+            } else {
+                Teardown::from(&lifecycle)
+            },
+            initialise(mode.mode),
             |cluster| {
-                check_exit(
-                    cluster
-                        .exec(&database.name, command, &args)
-                        .wrap_err("Executing command in cluster failed")?,
-                )
+                let mut command = cluster
+                    .exec_command(database.name(), command, &args)
+                    .wrap_err("Executing command in cluster failed")?;
+                if let Some(workdir) = workdir {
+                    command.current_dir(workdir);
+                }
+                run_child(command)
+            },
+        )
+        .and_then(|code| {
+            // Only leave the cluster running if the command succeeded.
+            if detach && code != 0 {
+                let (cluster, lock) = existing_cluster(&cluster)?;
+                coordinate::stop(&cluster, lock).wrap_err("Could not stop cluster")?;
+            }
+            Ok(code)
+        }),
+        cli::Commands::Runtimes { for_datadir, strategies } => {
+            runtimes(for_datadir.as_deref(), &strategies, cli.json)
+        }
+        cli::Commands::Start { cluster, listen, mode, dry_run: true, .. } => dry_run(
+            &cluster,
+            listen.tcp(),
+            durability(mode.mode),
+            cluster::Operation::Start,
+        ),
+        cli::Commands::Start {
+            cluster,
+            mode,
+            listen,
+            database,
+            lifecycle,
+            detach,
+            dry_run: _,
+        } => run(
+            &cluster,
+            listen.tcp(),
+            durability(mode.mode),
+            &database.names(),
+            if detach {
+                Teardown::LeaveRunning
+            } else {
+                Teardown::from(&lifecycle)
+            },
+            initialise(mode.mode),
+            |cluster| {
+                print_connection_info(cluster, database.name())
+                    .wrap_err("Could not print connection information")?;
+                if !detach {
+                    // Keep the cluster in use until interrupted.
+                    while !INTERRUPTED.load(Ordering::SeqCst) {
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                }
+                Ok(0)
             },
         ),
-        cli::Commands::Runtimes => {
-            let strategy = runtime::strategy::default();
-            let mut runtimes: Vec<_> = strategy.runtimes().collect();
-            let default = strategy.fallback();
-
-            // Sort by version. Higher versions will sort last.
-            runtimes.sort_by(|ra, rb| ra.version.cmp(&rb.version));
-
-            for runtime in runtimes {
-                let default = match default {
-                    Some(ref default) if default == &runtime => "=>",
-                    _ => "",
-                };
-                println!(
-                    "{default:2} {version:10} {bindir}",
-                    bindir = runtime.bindir.display(),
-                    version = runtime.version,
+        cli::Commands::List { cluster } => run(
+            &cluster,
+            None,
+            None,
+            &[],
+            Teardown::Stop,
+            initialise(None),
+            |cluster| {
+                let databases = cluster
+                    .databases_detailed()
+                    .wrap_err("Could not list databases")?;
+                if cli.json {
+                    println!("{}", serde_json::to_string_pretty(&databases)?);
+                } else {
+                    print_databases(&databases);
+                }
+                Ok(0)
+            },
+        ),
+        cli::Commands::Init { cluster, dry_run: true, .. } => {
+            dry_run(&cluster, None, None, cluster::Operation::Create)
+        }
+        cli::Commands::Init { cluster, mode, databases, dry_run: _ } => {
+            init(&cluster, mode.mode, &databases)
+        }
+        cli::Commands::Status { cluster } => status(&cluster, cli.json),
+        cli::Commands::Logs { cluster, follow, lines, since } => {
+            logs(&cluster, follow, lines, since.as_ref())
+        }
+        cli::Commands::Stop { cluster, dry_run: true, .. } => {
+            dry_run(&cluster, None, None, cluster::Operation::Stop)
+        }
+        cli::Commands::Stop { cluster, force, dry_run: _ } => stop(&cluster, force),
+        cli::Commands::Destroy { cluster, dry_run: true, .. } => {
+            dry_run(&cluster, None, None, cluster::Operation::Destroy)
+        }
+        cli::Commands::Destroy { cluster, yes, dry_run: _ } => destroy(&cluster, yes),
+        cli::Commands::Createdb { cluster, if_not_exists, databases } => {
+            createdb(&cluster, &databases, if_not_exists)
+        }
+        cli::Commands::Env { cluster, database, start } => {
+            if start {
+                run(
+                    &cluster,
+                    None,
+                    None,
+                    &[&database.name],
+                    Teardown::LeaveRunning,
+                    initialise(None),
+                    |cluster| {
+                        print_connection_info(cluster, &database.name)
+                            .wrap_err("Could not print connection information")?;
+                        Ok(0)
+                    },
                 )
+            } else {
+                let (cluster, _) = existing_cluster(&cluster)?;
+                print_connection_info(&cluster, &database.name)
+                    .wrap_err("Could not print connection information")?;
+                Ok(0)
             }
-
+        }
+        cli::Commands::Uri { cluster, database, tcp } => uri(&cluster, &database.name, tcp),
+        cli::Commands::Dump { cluster, database, output } => {
+            dump(&cluster, &database.name, &output)
+        }
+        cli::Commands::Restore { cluster, database, input } => {
+            restore(&cluster, &database.name, &input)
+        }
+        cli::Commands::Upgrade { cluster, to, dry_run } => upgrade(&cluster, &to, dry_run),
+        cli::Commands::Backup { cluster, output } => backup(&cluster, &output),
+        cli::Commands::Clusters => clusters(cli.json),
+        cli::Commands::Use { name, none: _ } => use_cluster(name.as_deref()),
+        cli::Commands::Snapshot { command } => match command {
+            cli::SnapshotCommands::Save { cluster, snapshot } => snapshot_save(&cluster, &snapshot),
+            cli::SnapshotCommands::Restore { cluster, snapshot } => {
+                snapshot_restore(&cluster, &snapshot)
+            }
+            cli::SnapshotCommands::List { cluster } => snapshot_list(&cluster, cli.json),
+        },
+        cli::Commands::Gc { lock, prune, older_than, orphans } => gc(
+            &lock.dir(),
+            prune.as_deref(),
+            older_than,
+            orphans.as_ref().map(Option::as_deref),
+        ),
+        #[cfg(feature = "download")]
+        cli::Commands::Install { version, list: _ } => install(version, cli.json),
+        cli::Commands::Completions { shell } => {
+            let mut command = cli::Cli::command();
+            let name = command.get_name().to_owned();
+            clap_complete::generate(shell, &mut command, name, &mut io::stdout());
             Ok(0)
         }
+        cli::Commands::Dropdb { cluster, if_exists, databases } => {
+            dropdb(&cluster, &databases, if_exists)
+        }
     };
 
     match result {
@@ -75,19 +228,323 @@ fn main() -> Result<()> {
     }
 }
 
-fn check_exit(status: ExitStatus) -> Result<i32> {
-    match status.code() {
-        Some(code) => Ok(code),
-        None => bail!("Command terminated: {status}"),
+/// Write log messages, e.g. from the library's instrumentation, to stderr.
+/// Timings are logged as spans close, e.g. how long it took to start the
+/// cluster.
+fn init_logging(logging: &cli::LoggingArgs) {
+    use tracing_subscriber::fmt::format::FmtSpan;
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(logging.level())
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(io::stderr);
+    match logging.log_format {
+        cli::LogFormat::Text => subscriber.init(),
+        cli::LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+/// List discovered runtimes, as a table or as JSON.
+fn runtimes(for_datadir: Option<&Path>, sources: &[cli::RuntimeSource], json: bool) -> Result<i32> {
+    // A runtimes configuration file that can't be loaded is otherwise only
+    // warned about, and its runtimes are left out, so say why here.
+    if sources.is_empty() || sources.contains(&cli::RuntimeSource::Config) {
+        let config = runtime::strategy::RuntimesFromConfig::Default;
+        if let Err(err) = config.config() {
+            return Err(err)
+                .wrap_err("Could not load runtimes configuration")
+                .with_section(|| {
+                    config
+                        .path()
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_default()
+                        .header("Configuration file:")
+                })
+                .suggestion("Fix or remove the configuration file");
+        }
+    }
+
+    let strategy: Box<dyn Strategy> = if sources.is_empty() {
+        Box::new(runtime::strategy::default())
+    } else {
+        Box::new(
+            sources
+                .iter()
+                .map(|source| runtime_source(*source))
+                .collect::<runtime::strategy::StrategySet>(),
+        )
+    };
+    let mut runtimes: Vec<_> = strategy.installations().collect();
+    let default = strategy.fallback();
+
+    // With a data directory, mark the runtime that will be used with the
+    // cluster therein, rather than the default.
+    let version = match for_datadir {
+        Some(datadir) => {
+            match cluster::version(datadir).wrap_err("Could not read cluster's version")? {
+                Some(version) => Some(version),
+                None => Err(eyre!("No cluster found"))
+                    .with_section(|| format!("{}", datadir.display()).header("Data directory:"))?,
+            }
+        }
+        None => None,
+    };
+    let selected = match version {
+        Some(version) => strategy.select(&version),
+        None => default.clone(),
+    };
+
+    // Sort by version. Higher versions will sort last. The same installation
+    // may be found more than once, e.g. on `PATH` and in a platform-specific
+    // location; list each `bindir` once.
+    runtimes.sort_by_key(|runtime| runtime.version);
+    let mut seen = std::collections::HashSet::new();
+    runtimes.retain(|runtime| seen.insert(runtime.bindir.clone()));
+
+    if json {
+        let sources = runtime_sources(sources);
+        let runtimes: Vec<_> = runtimes
+            .iter()
+            .map(|runtime| {
+                let source = sources
+                    .iter()
+                    .find(|(_, bindirs)| bindirs.contains(&runtime.bindir))
+                    .map(|(source, _)| source.to_string());
+                serde_json::json!({
+                    "version": runtime.version.to_string(),
+                    "bindir": runtime.bindir,
+                    "source": source,
+                    "default": default.as_ref() == Some(runtime),
+                    "selected": selected.as_ref() == Some(runtime),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&runtimes)?);
+    } else {
+        for runtime in runtimes {
+            let selected = match selected {
+                Some(ref selected) if selected == &runtime => "=>",
+                _ => "",
+            };
+            println!(
+                "{selected:2} {version:10} {bindir}",
+                bindir = runtime.bindir.display(),
+                version = runtime.version,
+            );
+        }
+    }
+
+    match (version, selected) {
+        (Some(version), None) => {
+            eprintln!(
+                "None of these runtimes can be used with the cluster, which needs \
+                 PostgreSQL {version}. Install PostgreSQL {version}, or use \
+                 `--runtime PATH` to name its `bindir`.",
+            );
+            Ok(1)
+        }
+        _ => Ok(0),
+    }
+}
+
+/// Install the runtime for the given version, downloading it if necessary, or
+/// list the versions that can be installed.
+#[cfg(feature = "download")]
+fn install(version: Option<version::PartialVersion>, json: bool) -> Result<i32> {
+    use version::{PartialVersion, Version};
+    let downloader = runtime::download::Downloader::new();
+    match version {
+        Some(version) => {
+            let runtime = match version {
+                PartialVersion::Pre10mm(a, b, c) => downloader.install(Version::Pre10(a, b, c)),
+                PartialVersion::Post10mm(a, b) => downloader.install(Version::Post10(a, b)),
+                _ => downloader.install_compatible(&version),
+            }
+            .wrap_err("Could not install runtime")
+            .with_section(|| version.to_string().header("Version:"))?;
+            eprintln!("Installed PostgreSQL {}", runtime.version);
+            println!("{}", runtime.bindir.display());
+        }
+        None => {
+            let installed = downloader.installed();
+            let available = downloader
+                .available()
+                .wrap_err("Could not list versions available to install")?;
+            let bindir = |version: &Version| {
+                installed
+                    .iter()
+                    .find(|runtime| runtime.version == *version)
+                    .map(|runtime| &runtime.bindir)
+            };
+            if json {
+                let versions: Vec<_> = available
+                    .iter()
+                    .map(|version| {
+                        serde_json::json!({
+                            "version": version.to_string(),
+                            "bindir": bindir(version),
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&versions)?);
+            } else {
+                for version in &available {
+                    match bindir(version) {
+                        Some(bindir) => println!("{version:10} {}", bindir.display()),
+                        None => println!("{version}"),
+                    }
+                }
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// The `bindir`s found by each of the given strategies – or, if none are
+/// given, each of the strategies that make up [`runtime::strategy::default`] –
+/// labelled, in order of precedence. This is how `runtimes --json` says where
+/// each runtime was found.
+fn runtime_sources(sources: &[cli::RuntimeSource]) -> Vec<(cli::RuntimeSource, Vec<PathBuf>)> {
+    let sources = if sources.is_empty() {
+        cli::RuntimeSource::ALL
+    } else {
+        sources
+    };
+    sources
+        .iter()
+        .map(|source| {
+            let bindirs = runtime_source(*source)
+                .installations()
+                .map(|runtime| runtime.bindir)
+                .collect();
+            (*source, bindirs)
+        })
+        .collect()
+}
+
+/// The strategy that finds runtimes from the given source.
+fn runtime_source(source: cli::RuntimeSource) -> Box<dyn Strategy> {
+    use cli::RuntimeSource;
+    use runtime::strategy::{
+        from_fn, OverriddenByEnv, RuntimesFromAsdf, RuntimesFromConfig, RuntimesFromPgenv,
+        RuntimesOnPath, RuntimesOnPlatform, StrategySet,
+    };
+    match source {
+        RuntimeSource::Env => Box::new(from_fn(|| {
+            OverriddenByEnv::new(StrategySet::new()).overridden()
+        })),
+        RuntimeSource::Config => Box::new(RuntimesFromConfig::Default),
+        RuntimeSource::Path => Box::new(RuntimesOnPath::Env),
+        RuntimeSource::Platform => Box::new(RuntimesOnPlatform::new()),
+        RuntimeSource::Pgenv => Box::new(RuntimesFromPgenv::Env),
+        RuntimeSource::Asdf => Box::new(RuntimesFromAsdf::Asdf),
+        RuntimeSource::Mise => Box::new(RuntimesFromAsdf::Mise),
+        #[cfg(feature = "download")]
+        RuntimeSource::Download => {
+            Box::new(from_fn(|| runtime::download::Downloader::new().installed()))
+        }
+    }
+}
+
+/// Print a warning if the given runtime's version of PostgreSQL is no longer
+/// supported upstream.
+fn warn_if_eol(runtime: &runtime::Runtime) {
+    if runtime.version.is_eol(version::Date::today()) {
+        let until = runtime
+            .version
+            .supported_until()
+            .map_or_else(String::new, |date| format!(" (support ended {date})"));
+        eprintln!(
+            "Warning: PostgreSQL {version} is end-of-life{until}; creating cluster with {bindir}",
+            version = runtime.version,
+            bindir = runtime.bindir.display(),
+        );
     }
 }
 
-const UUID_NS: uuid::Uuid = uuid::Uuid::from_u128(93875103436633470414348750305797058811);
+/// Run the given command to completion, forwarding signals to it, and return
+/// its exit code.
+fn run_child(mut command: Command) -> Result<i32> {
+    let mut child = command.spawn().wrap_err("Could not start command")?;
+    CHILD.store(i32::try_from(child.id()).unwrap_or(0), Ordering::SeqCst);
+    let status = child.wait();
+    CHILD.store(0, Ordering::SeqCst);
+    Ok(exit_code(status?))
+}
+
+/// The exit code for a child's exit status. When the child was terminated by
+/// a signal this is 128 plus the signal number, as in shells.
+fn exit_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
+}
+
+/// Set when this process receives SIGINT, TERM, or HUP. See [`run`].
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The PID of the child process started by [`run_child`], or 0 if none.
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// Handle SIGINT, TERM, and HUP. This sets [`INTERRUPTED`] and forwards the
+/// signal to the child process, if any. Signals from the terminal, e.g. on
+/// Ctrl-C, are not forwarded, since the child, in the same process group,
+/// receives those too.
+extern "C" fn on_signal(signum: c_int, info: *mut siginfo_t, _: *mut c_void) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    // SAFETY: with `SA_SIGINFO`, `info` points to a valid `siginfo_t`.
+    // Signals sent by another process, e.g. with kill(2), have `si_code <= 0`.
+    let from_process = unsafe { (*info).si_code } <= 0;
+    let child = CHILD.load(Ordering::SeqCst);
+    if from_process && child > 0 {
+        if let Ok(signal) = Signal::try_from(signum) {
+            let _ = kill(Pid::from_raw(child), signal);
+        }
+    }
+}
+
+/// Install [`on_signal`] as the handler for SIGINT, TERM, and HUP.
+fn handle_signals() -> nix::Result<()> {
+    let action = SigAction::new(
+        SigHandler::SigAction(on_signal),
+        SaFlags::SA_SIGINFO | SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP] {
+        // SAFETY: `on_signal` only does async-signal-safe things.
+        unsafe { sigaction(signal, &action) }?;
+    }
+    Ok(())
+}
+
+/// What to do with the cluster once the action in [`run`] is complete.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Teardown {
+    /// Stop the cluster, unless it's in use elsewhere.
+    Stop,
+    /// Destroy the cluster, unless it's in use elsewhere.
+    Destroy,
+    /// Leave the cluster running.
+    LeaveRunning,
+}
+
+impl From<&cli::LifecycleArgs> for Teardown {
+    fn from(lifecycle: &cli::LifecycleArgs) -> Self {
+        if lifecycle.destroy {
+            Teardown::Destroy
+        } else {
+            Teardown::Stop
+        }
+    }
+}
 
 fn run<INIT, ACTION>(
-    database_dir: PathBuf,
-    database_name: &str,
-    destroy: bool,
+    cluster_args: &cli::ClusterArgs,
+    tcp: Option<(&str, u16)>,
+    durability: Option<cluster::Durability>,
+    database_names: &[&str],
+    teardown: Teardown,
     initialise: INIT,
     action: ACTION,
 ) -> Result<i32>
@@ -95,8 +552,182 @@ where
     INIT: std::panic::UnwindSafe + FnOnce(&cluster::Cluster) -> Result<(), cluster::ClusterError>,
     ACTION: FnOnce(&cluster::Cluster) -> Result<i32> + std::panic::UnwindSafe,
 {
-    // Create the cluster directory first.
-    match fs::create_dir(&database_dir) {
+    let (cluster, lock) = prepare(cluster_args)?;
+    let cluster = match tcp {
+        Some((addresses, port)) => cluster.with_tcp(addresses, port),
+        None => cluster,
+    };
+    let cluster = match durability {
+        Some(durability) => cluster.with_durability(durability),
+        None => cluster,
+    };
+
+    let runner = match teardown {
+        Teardown::Stop => coordinate::run_and_stop,
+        Teardown::Destroy => coordinate::run_and_destroy,
+        Teardown::LeaveRunning => coordinate::run_and_leave_running,
+    };
+
+    runner(&cluster, lock, |cluster: &cluster::Cluster| {
+        initialise(cluster)?;
+
+        if tcp.is_some() {
+            match cluster.url_tcp("postgres") {
+                Ok(Some(url)) => eprintln!("Listening for TCP connections: {url}"),
+                Ok(None) => eprintln!(
+                    "Warning: cluster was already running without TCP; \
+                     restart it to listen for TCP connections"
+                ),
+                Err(err) => return Err(err).wrap_err("Could not find cluster's TCP address"),
+            }
+        }
+
+        let databases = cluster.databases().wrap_err("Could not list databases")?;
+        for &database_name in database_names {
+            if !databases.iter().any(|database| database == database_name) {
+                cluster
+                    .createdb(database_name)
+                    .wrap_err("Could not create database")
+                    .with_section(|| database_name.to_owned().header("Database:"))?;
+            }
+        }
+
+        // Catch SIGINT, TERM, and HUP. The child process, if any, will receive
+        // the signal, presumably terminate, then we'll tidy up. Actions without
+        // a child process can check for this with `INTERRUPTED`.
+        handle_signals().wrap_err("Could not set signal handler")?;
+
+        // Finally, run the given action.
+        action(cluster)
+    })
+    .with_section(|| log_excerpt(&cluster).header("Server log (last lines):"))?
+}
+
+/// The last few lines of the cluster's server log, e.g. to explain why the
+/// cluster did not start. This is empty if the log cannot be read.
+fn log_excerpt(cluster: &cluster::Cluster) -> String {
+    const LINES: usize = 20;
+    let log = fs::read(cluster.logfile()).unwrap_or_default();
+    let log = String::from_utf8_lossy(&log);
+    let lines: Vec<&str> = log.lines().collect();
+    lines[lines.len().saturating_sub(LINES)..].join("\n")
+}
+
+/// Arguments for `psql`: the given SQL commands, if any, each as a `-c`
+/// option, followed by `args`.
+fn psql_args(commands: &[String], args: Vec<OsString>) -> Vec<OsString> {
+    let mut psql_args: Vec<OsString> = Vec::new();
+    if !commands.is_empty() {
+        psql_args.push("--set=ON_ERROR_STOP=1".into());
+        for command in commands {
+            psql_args.push("-c".into());
+            psql_args.push(command.into());
+        }
+    }
+    psql_args.extend(args);
+    psql_args
+}
+
+/// Print a table of databases.
+fn print_databases(databases: &[cluster::Database]) {
+    let rows: Vec<[String; 4]> = databases
+        .iter()
+        .map(|database| {
+            [
+                database.name.clone(),
+                database.owner.clone(),
+                pretty_size(database.size),
+                database.encoding.clone(),
+            ]
+        })
+        .collect();
+    let header = ["Name", "Owner", "Size", "Encoding"].map(String::from);
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for [name, owner, size, encoding] in std::iter::once(&header).chain(&rows) {
+        println!(
+            "{name:w0$}  {owner:w1$}  {size:>w2$}  {encoding}",
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+    }
+}
+
+/// Format a size in bytes for humans, e.g. `7.3 MB`, using binary multiples
+/// as PostgreSQL's `pg_size_pretty` does.
+#[allow(clippy::cast_precision_loss)]
+fn pretty_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["bytes", "kB", "MB", "GB"] {
+        if size < 1024.0 {
+            return if unit == "bytes" {
+                format!("{bytes} {unit}")
+            } else {
+                format!("{size:.1} {unit}")
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.1} TB")
+}
+
+/// Print what `operation` would do to the cluster, without doing it. This does
+/// not take a lock, so another process may change the cluster meanwhile.
+fn dry_run(
+    cluster_args: &cli::ClusterArgs,
+    tcp: Option<(&str, u16)>,
+    durability: Option<cluster::Durability>,
+    operation: cluster::Operation,
+) -> Result<i32> {
+    let datadir = cluster_args.datadir()?;
+    let datadir = datadir.canonicalize().unwrap_or(datadir);
+    let cluster = new_cluster(&datadir, cluster_args.runtime.as_ref())?;
+    let cluster = match cluster_args.timeout {
+        Some(timeout) => cluster.with_start_timeout(Duration::from_secs(timeout)),
+        None => cluster,
+    };
+    let cluster = match cluster_args.log_rotate {
+        Some(rotation) => cluster.with_log_rotation(rotation),
+        None => cluster,
+    };
+    let cluster = match tcp {
+        Some((addresses, port)) => cluster.with_tcp(addresses, port),
+        None => cluster,
+    };
+    let cluster = match durability {
+        Some(durability) => cluster.with_durability(durability),
+        None => cluster,
+    };
+    let steps = cluster
+        .plan(operation)
+        .wrap_err("Could not work out what to do")
+        .with_section(|| format!("{}", datadir.display()).header("Data directory:"))?;
+    if steps.is_empty() {
+        eprintln!("Nothing to do");
+    }
+    for step in steps {
+        println!("{step}");
+    }
+    Ok(0)
+}
+
+/// Create the cluster directory, if necessary, and find the cluster in it and
+/// the lock file for it. The cluster itself may not yet exist.
+fn prepare(cluster_args: &cli::ClusterArgs) -> Result<(cluster::Cluster, lock::UnlockedFile)> {
+    let database_dir = &cluster_args.datadir()?;
+    // Create the cluster directory first. Named clusters may be in the
+    // registry's directory, which may not yet exist.
+    if let (Some(_), Some(parent)) = (&cluster_args.name, database_dir.parent()) {
+        fs::create_dir_all(parent)
+            .wrap_err("Could not create directory for database directory")
+            .with_section(|| format!("{}", parent.display()).header("Directory:"))?;
+    }
+    match fs::create_dir(database_dir) {
         Err(err) if err.kind() == io::ErrorKind::AlreadyExists => (),
         err @ Err(_) => err
             .wrap_err("Could not create database directory")
@@ -104,50 +735,902 @@ where
         _ => (),
     };
 
-    // Obtain a canonical path to the cluster directory.
-    let database_dir = database_dir
-        .canonicalize()
-        .wrap_err("Could not canonicalize database directory")
-        .with_section(|| format!("{}", database_dir.display()).header("Database directory:"))?;
+    let (database_dir, lock) = lock_for(database_dir, lock_dir(cluster_args).as_deref())?;
+    if let Some(name) = &cluster_args.name {
+        let path = registry_file()?;
+        registry::Registry::update(&path, |registry| registry.register(name, &database_dir))
+            .wrap_err("Could not register named cluster")
+            .with_section(|| format!("{}", path.display()).header("Registry:"))?;
+    }
+    let cluster = new_cluster(&database_dir, cluster_args.runtime.as_ref())?;
+    let cluster = match cluster_args.timeout {
+        Some(timeout) => cluster.with_start_timeout(Duration::from_secs(timeout)),
+        None => cluster,
+    };
+    let cluster = match cluster_args.log_rotate {
+        Some(rotation) => cluster.with_log_rotation(rotation),
+        None => cluster,
+    };
 
-    // Use the canonical path to construct the UUID with which we'll lock this
-    // cluster. Use the `Debug` form of `database_dir` for the lock file UUID.
-    let lock_uuid = uuid::Uuid::new_v5(&UUID_NS, format!("{:?}", &database_dir).as_bytes());
-    let lock = lock::UnlockedFile::try_from(&lock_uuid)
-        .wrap_err("Could not create UUID-based lock file")
-        .with_section(|| lock_uuid.to_string().header("UUID for lock file:"))?;
+    // Warn when creating a cluster with an end-of-life runtime. This is
+    // advisory, so don't fail if the runtime can't be determined here.
+    if !cluster::exists(&cluster) {
+        if let Ok(runtime) = cluster.runtime() {
+            warn_if_eol(&runtime);
+        }
+    }
 
-    let strategy = runtime::strategy::default();
-    let cluster = cluster::Cluster::new(&database_dir, strategy)?;
+    Ok((cluster, lock))
+}
 
-    let runner = if destroy {
-        coordinate::run_and_destroy
+/// Create the cluster, and the given databases. The cluster is started only if
+/// necessary, i.e. to create databases or set the mode.
+fn init(
+    cluster_args: &cli::ClusterArgs,
+    mode: Option<cli::Mode>,
+    databases: &[String],
+) -> Result<i32> {
+    // The cluster is not left running, so a non-sticky mode would be moot.
+    let mode = mode.filter(|mode| *mode != cli::Mode::FastUntilStopped);
+    if databases.is_empty() && mode.is_none() {
+        let (cluster, lock) = prepare(cluster_args)?;
+        coordinate::create(&cluster, lock).wrap_err("Could not create cluster")?;
+        Ok(0)
     } else {
-        coordinate::run_and_stop
-    };
+        let databases: Vec<&str> = databases.iter().map(String::as_str).collect();
+        run(
+            cluster_args,
+            None,
+            None,
+            &databases,
+            Teardown::Stop,
+            initialise(mode),
+            |_| Ok(0),
+        )
+    }
+}
 
-    runner(&cluster, lock, |cluster: &cluster::Cluster| {
-        initialise(cluster)?;
+/// Create the given databases in the cluster, creating and starting the cluster
+/// as necessary. Unless `if_not_exists` is given, this fails if any of the
+/// databases already exist.
+fn createdb(
+    cluster_args: &cli::ClusterArgs,
+    databases: &[String],
+    if_not_exists: bool,
+) -> Result<i32> {
+    run(
+        cluster_args,
+        None,
+        None,
+        &[],
+        Teardown::Stop,
+        initialise(None),
+        |cluster| {
+            let existing = cluster.databases().wrap_err("Could not list databases")?;
+            for database_name in databases {
+                if existing.contains(database_name) {
+                    if if_not_exists {
+                        continue;
+                    }
+                    return Err(eyre!("Database already exists"))
+                        .with_section(|| database_name.clone().header("Database:"));
+                }
+                cluster
+                    .createdb(database_name)
+                    .wrap_err("Could not create database")
+                    .with_section(|| database_name.clone().header("Database:"))?;
+            }
+            Ok(0)
+        },
+    )
+}
+
+/// Drop the given databases from the cluster, starting the cluster as
+/// necessary. Unless `if_exists` is given, this fails if any of the databases
+/// do not exist.
+fn dropdb(cluster_args: &cli::ClusterArgs, databases: &[String], if_exists: bool) -> Result<i32> {
+    // Don't create a cluster only to find that it has nothing to drop.
+    existing_cluster(cluster_args)?;
+    run(
+        cluster_args,
+        None,
+        None,
+        &[],
+        Teardown::Stop,
+        initialise(None),
+        |cluster| {
+            let existing = cluster.databases().wrap_err("Could not list databases")?;
+            for database_name in databases {
+                if !existing.contains(database_name) {
+                    if if_exists {
+                        continue;
+                    }
+                    return Err(eyre!("Database does not exist"))
+                        .with_section(|| database_name.clone().header("Database:"));
+                }
+                cluster
+                    .dropdb(database_name)
+                    .wrap_err("Could not drop database")
+                    .with_section(|| database_name.clone().header("Database:"))?;
+            }
+            Ok(0)
+        },
+    )
+}
 
+/// Dump the given database to `output`, starting the cluster as necessary.
+fn dump(cluster_args: &cli::ClusterArgs, database_name: &str, output: &Path) -> Result<i32> {
+    // Don't create a cluster only to find that it has nothing to dump.
+    existing_cluster(cluster_args)?;
+    run(
+        cluster_args,
+        None,
+        None,
+        &[],
+        Teardown::Stop,
+        initialise(None),
+        |cluster| {
+            cluster
+                .dump(database_name, output)
+                .wrap_err("Could not dump database")
+                .with_section(|| database_name.to_owned().header("Database:"))
+                .with_section(|| format!("{}", output.display()).header("Output:"))?;
+            Ok(0)
+        },
+    )
+}
+
+/// Restore the dump in `input` into the given database, creating and starting
+/// the cluster, and creating the database, as necessary.
+fn restore(cluster_args: &cli::ClusterArgs, database_name: &str, input: &Path) -> Result<i32> {
+    // Don't create a cluster only to find that there's nothing to restore.
+    if !input.is_file() {
+        return Err(eyre!("Dump not found"))
+            .with_section(|| format!("{}", input.display()).header("Input:"));
+    }
+    run(
+        cluster_args,
+        None,
+        None,
+        &[database_name],
+        Teardown::Stop,
+        initialise(None),
+        |cluster| {
+            cluster
+                .restore(database_name, input)
+                .wrap_err("Could not restore database")
+                .with_section(|| database_name.to_owned().header("Database:"))
+                .with_section(|| format!("{}", input.display()).header("Input:"))?;
+            Ok(0)
+        },
+    )
+}
+
+/// Print a connection URI for `database_name` in the cluster. With `tcp`, the
+/// URI connects over TCP, for which the cluster must be running.
+fn uri(cluster_args: &cli::ClusterArgs, database_name: &str, tcp: bool) -> Result<i32> {
+    let (cluster, _) = existing_cluster(cluster_args)?;
+    let uri = if tcp {
         if !cluster
-            .databases()
-            .wrap_err("Could not list databases")?
-            .contains(&database_name.to_string())
+            .running()
+            .wrap_err("Could not check if cluster is running")?
         {
+            bail!("Cluster is not running");
+        }
+        cluster
+            .url_tcp(database_name)
+            .wrap_err("Could not find cluster's TCP address")?
+            .ok_or_else(|| eyre!("Cluster is not listening on TCP"))?
+    } else {
+        cluster.url(database_name)
+    };
+    println!("{uri}");
+    Ok(0)
+}
+
+/// Print environment variables with which to connect to the cluster, in a form
+/// suitable for `eval` in a POSIX shell.
+fn print_connection_info(cluster: &cluster::Cluster, database_name: &str) -> io::Result<()> {
+    let datadir: &Path = cluster.as_ref();
+    let port = cluster
+        .port()
+        .map_err(io::Error::other)?
+        .map(|port| port.to_string());
+    let mut out = io::stdout().lock();
+    for (name, value) in [
+        ("PGDATA", datadir.as_os_str()),
+        ("PGHOST", datadir.as_os_str()),
+        ("PGDATABASE", OsStr::new(database_name)),
+        ("DATABASE_URL", OsStr::new(&cluster.url(database_name))),
+    ]
+    .into_iter()
+    .chain(port.as_deref().map(|port| ("PGPORT", OsStr::new(port))))
+    {
+        let mut line = format!("export {name}=").into_bytes();
+        shell_quote::sh::escape_into(value, &mut line);
+        line.push(b'\n');
+        out.write_all(&line)?;
+    }
+    out.flush()
+}
+
+/// Print the status of the cluster, as text or as JSON. This does not need the
+/// cluster to exist, nor does it create or start it.
+fn status(cluster_args: &cli::ClusterArgs, json: bool) -> Result<i32> {
+    let datadir = cluster_args.datadir()?;
+    let datadir = datadir.canonicalize().unwrap_or(datadir);
+    let cluster = new_cluster(&datadir, cluster_args.runtime.as_ref())?;
+    let exists = cluster::exists(&cluster);
+    let version = cluster::version(&cluster).wrap_err("Could not read cluster's version")?;
+    let running = server_running(&datadir)?;
+    let port = if running { cluster.port()? } else { None };
+    let stale_pid = cluster::stale_pid(&datadir)?;
+    let runtime = cluster.runtime().ok();
+    let disk_usage = if exists {
+        Some(
             cluster
-                .createdb(database_name)
-                .wrap_err("Could not create database")
-                .with_section(|| database_name.to_owned().header("Database:"))?;
+                .disk_usage()
+                .wrap_err("Could not find cluster's disk usage")?,
+        )
+    } else {
+        None
+    };
+
+    if json {
+        let status = serde_json::json!({
+            "datadir": datadir,
+            "exists": exists,
+            "version": version.map(|version| version.to_string()),
+            "running": running,
+            "port": port,
+            "url": running.then(|| cluster.url("postgres")),
+            "stale_pid": stale_pid,
+            "runtime": runtime.map(|runtime| serde_json::json!({
+                "version": runtime.version.to_string(),
+                "bindir": runtime.bindir,
+            })),
+            "disk_usage": disk_usage,
+        });
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    } else {
+        let state = match (exists, port, stale_pid) {
+            (false, _, _) => "not created".to_owned(),
+            (true, Some(port), _) => format!("running (port {port})"),
+            (true, None, Some(pid)) => {
+                format!("stopped, not cleanly (server PID {pid} has gone)")
+            }
+            (true, None, None) if running => "running".to_owned(),
+            (true, None, None) => "stopped".to_owned(),
+        };
+        println!("Data directory: {}", datadir.display());
+        println!("Status:         {state}");
+        if let Some(version) = version {
+            println!("Version:        {version}");
+        }
+        match runtime {
+            Some(runtime) => println!(
+                "Runtime:        {} ({})",
+                runtime.version,
+                runtime.bindir.display()
+            ),
+            None => println!("Runtime:        not found"),
+        }
+        if let Some(usage) = disk_usage {
+            println!("Disk usage:     {}", pretty_size(usage.total));
+            for (name, size) in usage.databases {
+                println!("  {name}: {}", pretty_size(size));
+            }
         }
+    }
+    Ok(0)
+}
 
-        // Ignore SIGINT, TERM, and HUP (with ctrlc feature "termination"). The
-        // child process will receive the signal, presumably terminate, then
-        // we'll tidy up.
-        ctrlc::set_handler(|| ()).wrap_err("Could not set signal handler")?;
+/// Print the server log for the cluster.
+///
+/// Lines are filtered by `since` and limited to the last `lines`. Lines logged
+/// by the server begin with a timestamp in UTC; lines that do not, e.g. the
+/// continuation of a multi-line statement, are printed if the line before them
+/// was printed. With `follow`, this then polls the log for new lines until
+/// interrupted.
+fn logs(
+    cluster_args: &cli::ClusterArgs,
+    follow: bool,
+    lines: Option<usize>,
+    since: Option<&cli::Since>,
+) -> Result<i32> {
+    let (cluster, _) = existing_cluster(cluster_args)?;
+    let logfile = cluster.logfile();
+    let since = since.map(|since| match since {
+        cli::Since::Ago(duration) => utc_timestamp(
+            SystemTime::now()
+                .checked_sub(*duration)
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+        ),
+        cli::Since::At(at) => at.clone(),
+    });
+    // Is a line in range? This is `None` when the line has no timestamp.
+    let in_range = |line: &str| {
+        let timestamp = line.get(..19).filter(|prefix| {
+            prefix.bytes().enumerate().all(|(i, b)| match i {
+                4 | 7 => b == b'-',
+                10 => b == b' ',
+                13 | 16 => b == b':',
+                _ => b.is_ascii_digit(),
+            })
+        })?;
+        Some(since.as_deref().map_or(true, |since| {
+            timestamp.get(..since.len()).unwrap_or(timestamp) >= since
+        }))
+    };
+    let mut printing = since.is_none();
+    let mut filter = |line: &str| {
+        printing = in_range(line).unwrap_or(printing);
+        printing
+    };
 
-        // Finally, run the given action.
-        action(cluster)
-    })?
+    let mut position = 0;
+    let mut partial = Vec::new();
+    let mut out = io::stdout().lock();
+    let mut first = true;
+    loop {
+        match fs::File::open(&logfile) {
+            Ok(mut file) => {
+                let length = file.metadata()?.len();
+                if length < position {
+                    // The log has been truncated or replaced; start again.
+                    position = 0;
+                    partial.clear();
+                }
+                file.seek(io::SeekFrom::Start(position))?;
+                let mut content = std::mem::take(&mut partial);
+                position += file.read_to_end(&mut content)? as u64;
+                // Hold back an incomplete last line until it is complete.
+                let complete = content
+                    .iter()
+                    .rposition(|&b| b == b'\n')
+                    .map_or(0, |n| n + 1);
+                partial = content.split_off(complete);
+                let content = String::from_utf8_lossy(&content);
+                let selected: Vec<&str> = content.lines().filter(|line| filter(line)).collect();
+                let skip = match lines {
+                    Some(lines) if first => selected.len().saturating_sub(lines),
+                    _ => 0,
+                };
+                for line in &selected[skip..] {
+                    writeln!(out, "{line}")?;
+                }
+                out.flush()?;
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound && follow => (),
+            Err(err) => {
+                return Err(err)
+                    .wrap_err("Could not read log file")
+                    .with_section(|| format!("{}", logfile.display()).header("Log file:"))
+            }
+        }
+        if !follow {
+            return Ok(0);
+        }
+        first = false;
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// Format the given time in UTC in the same way as the server's log, to the
+/// nearest second, e.g. `2024-06-01 13:45:07`.
+fn utc_timestamp(time: SystemTime) -> String {
+    let date = version::Date::from(time);
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() % 86400);
+    format!(
+        "{date} {:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Stop the cluster. Unless `force` is given, this fails if the cluster is in
+/// use.
+fn stop(cluster_args: &cli::ClusterArgs, force: bool) -> Result<i32> {
+    let (cluster, lock) = existing_cluster(cluster_args)?;
+    let lock_path = lock.backend().path().map(Path::to_owned);
+    let state = if force {
+        Some(cluster.stop().wrap_err("Could not stop cluster")?)
+    } else {
+        coordinate::stop(&cluster, lock).wrap_err("Could not stop cluster")?
+    };
+    match state {
+        Some(_) => Ok(0),
+        None => in_use(lock_path.as_deref()).suggestion("Use --force to stop the cluster anyway"),
+    }
+}
+
+/// Destroy the cluster. Unless `yes` is given, this asks for confirmation
+/// first. This fails if the cluster is in use.
+fn destroy(cluster_args: &cli::ClusterArgs, yes: bool) -> Result<i32> {
+    let (cluster, lock) = existing_cluster(cluster_args)?;
+    let datadir: &Path = cluster.as_ref();
+    if !yes && !confirm(&format!("Destroy cluster in {}?", datadir.display()))? {
+        bail!("Not confirmed; cluster left in place");
+    }
+    let lock_path = lock.backend().path().map(Path::to_owned);
+    match coordinate::destroy(&cluster, lock).wrap_err("Could not destroy cluster")? {
+        Some(_) => {
+            unregister(datadir)?;
+            Ok(0)
+        }
+        None => in_use(lock_path.as_deref()),
+    }
+}
+
+/// Forget the names of the cluster in `datadir`, if it has any.
+fn unregister(datadir: &Path) -> Result<()> {
+    match registry::file() {
+        Some(path) if path.exists() => registry::Registry::update(&path, |registry| {
+            let names: Vec<String> = registry
+                .clusters()
+                .filter(|(_, dir)| *dir == datadir)
+                .map(|(name, _)| name.to_owned())
+                .collect();
+            for name in names {
+                registry.unregister(&name);
+            }
+            Ok(())
+        })
+        .wrap_err("Could not unregister named cluster")
+        .with_section(|| format!("{}", path.display()).header("Registry:")),
+        _ => Ok(()),
+    }
+}
+
+/// The file in which the registry of named clusters is kept.
+fn registry_file() -> Result<PathBuf> {
+    registry::file()
+        .ok_or_else(|| eyre!("Could not find a directory for named clusters"))
+        .suggestion("Set XDG_DATA_HOME or HOME")
+}
+
+/// List named clusters, as a table or as JSON.
+fn clusters(json: bool) -> Result<i32> {
+    let path = registry_file()?;
+    let registry = registry::Registry::load(&path)
+        .wrap_err("Could not load registry of named clusters")
+        .with_section(|| format!("{}", path.display()).header("Registry:"))?;
+    let current = registry.current().map(|(name, _)| name);
+    if json {
+        let clusters: Vec<_> = registry
+            .clusters()
+            .map(|(name, datadir)| {
+                serde_json::json!({
+                    "name": name,
+                    "datadir": datadir,
+                    "exists": cluster::exists(datadir),
+                    "current": current == Some(name),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&clusters)?);
+    } else {
+        for (name, datadir) in registry.clusters() {
+            let selected = if current == Some(name) { "=>" } else { "" };
+            let missing = if cluster::exists(datadir) {
+                ""
+            } else {
+                " (not created)"
+            };
+            println!("{selected:2} {name:15} {}{missing}", datadir.display());
+        }
+    }
+    Ok(0)
+}
+
+/// Make the named cluster the default, or, with [`None`], make no named cluster
+/// the default.
+fn use_cluster(name: Option<&str>) -> Result<i32> {
+    let path = registry_file()?;
+    registry::Registry::update(&path, |registry| registry.set_current(name))
+        .wrap_err("Could not choose named cluster")
+        .with_section(|| format!("{}", path.display()).header("Registry:"))
+        .suggestion("Use `clusters` to list named clusters")?;
+    Ok(0)
+}
+
+/// Upgrade the cluster to the runtime chosen with `--to`. With `dry_run`, only
+/// check that the cluster can be upgraded. This fails if the cluster is in use.
+fn upgrade(cluster_args: &cli::ClusterArgs, to: &cli::RuntimeChoice, dry_run: bool) -> Result<i32> {
+    let (cluster, lock) = existing_cluster(cluster_args)?;
+    let from = cluster
+        .runtime()
+        .wrap_err("Could not find runtime for cluster's current version")
+        .suggestion("Use --runtime to give the path to a runtime for the current version")?;
+    let to = match to {
+        cli::RuntimeChoice::Matching(versions) => {
+            runtime::strategy::Constrained::new(runtime::strategy::default(), versions.clone())
+                .fallback()
+                .ok_or_else(|| eyre!("No runtime found matching {versions}"))?
+        }
+        cli::RuntimeChoice::Bindir(bindir) => runtime::Runtime::new(bindir)
+            .wrap_err("Could not use runtime")
+            .with_section(|| format!("{}", bindir.display()).header("Runtime:"))?,
+    };
+    let describe = |runtime: &runtime::Runtime| {
+        format!(
+            "PostgreSQL {version} ({bindir})",
+            version = runtime.version,
+            bindir = runtime.bindir.display(),
+        )
+    };
+    let lock_path = lock.backend().path().map(Path::to_owned);
+    let state = coordinate::upgrade(&cluster, lock, &to, dry_run)
+        .wrap_err(if dry_run {
+            "Cluster cannot be upgraded"
+        } else {
+            "Could not upgrade cluster"
+        })
+        .with_section(|| describe(&from).header("From:"))
+        .with_section(|| describe(&to).header("To:"))?;
+    match state {
+        None => return in_use(lock_path.as_deref()),
+        Some(cluster::State::Unmodified) => {
+            eprintln!("Cluster already uses {}; nothing to do", describe(&from));
+        }
+        Some(cluster::State::Modified) if dry_run => {
+            eprintln!(
+                "Cluster can be upgraded from {} to {}",
+                describe(&from),
+                describe(&to)
+            );
+        }
+        Some(cluster::State::Modified) => {
+            eprintln!(
+                "Cluster upgraded from {} to {}",
+                describe(&from),
+                describe(&to)
+            );
+        }
+    }
+    Ok(0)
+}
+
+/// Back up the cluster into `output`, starting the cluster as necessary.
+fn backup(cluster_args: &cli::ClusterArgs, output: &Path) -> Result<i32> {
+    // Don't create a cluster only to back it up.
+    existing_cluster(cluster_args)?;
+    run(
+        cluster_args,
+        None,
+        None,
+        &[],
+        Teardown::Stop,
+        initialise(None),
+        |cluster| {
+            cluster
+                .backup(output)
+                .wrap_err("Could not back up cluster")
+                .with_section(|| format!("{}", output.display()).header("Output:"))?;
+            Ok(0)
+        },
+    )
+}
+
+/// The directory in which snapshots of the cluster are kept, alongside the
+/// cluster's data directory.
+fn snapshots_dir(cluster: &cluster::Cluster) -> PathBuf {
+    let mut dir = cluster.as_ref().as_os_str().to_owned();
+    dir.push(".snapshots");
+    PathBuf::from(dir)
+}
+
+/// Save a snapshot of the cluster. This fails if the cluster is in use.
+fn snapshot_save(cluster_args: &cli::ClusterArgs, name: &str) -> Result<i32> {
+    let (cluster, lock) = existing_cluster(cluster_args)?;
+    let dir = snapshots_dir(&cluster);
+    let path = dir.join(name);
+    if path.exists() {
+        return Err(eyre!("Snapshot already exists"))
+            .with_section(|| format!("{}", path.display()).header("Snapshot:"));
+    }
+    fs::create_dir_all(&dir)
+        .wrap_err("Could not create snapshots directory")
+        .with_section(|| format!("{}", dir.display()).header("Directory:"))?;
+    let lock_path = lock.backend().path().map(Path::to_owned);
+    match coordinate::snapshot(&cluster, lock, &path)
+        .wrap_err("Could not save snapshot")
+        .with_section(|| format!("{}", path.display()).header("Snapshot:"))?
+    {
+        Some(()) => Ok(0),
+        None => in_use(lock_path.as_deref()),
+    }
+}
+
+/// Replace the cluster with a snapshot, creating the cluster directory as
+/// necessary. This fails if the cluster is in use.
+fn snapshot_restore(cluster_args: &cli::ClusterArgs, name: &str) -> Result<i32> {
+    let (cluster, lock) = prepare(cluster_args)?;
+    let path = snapshots_dir(&cluster).join(name);
+    if !cluster::exists(&path) {
+        return Err(eyre!("Snapshot not found"))
+            .with_section(|| format!("{}", path.display()).header("Snapshot:"));
+    }
+    let lock_path = lock.backend().path().map(Path::to_owned);
+    match coordinate::restore_snapshot(&cluster, lock, &path)
+        .wrap_err("Could not restore snapshot")
+        .with_section(|| format!("{}", path.display()).header("Snapshot:"))?
+    {
+        Some(()) => Ok(0),
+        None => in_use(lock_path.as_deref()),
+    }
+}
+
+/// List snapshots of the cluster, oldest first, with the time each was saved,
+/// as a table or as JSON.
+fn snapshot_list(cluster_args: &cli::ClusterArgs, json: bool) -> Result<i32> {
+    let (cluster, _) = existing_cluster(cluster_args)?;
+    let dir = snapshots_dir(&cluster);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => {
+            return Err(err)
+                .wrap_err("Could not read snapshots directory")
+                .with_section(|| format!("{}", dir.display()).header("Directory:"))
+        }
+    };
+    let mut snapshots = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if cluster::exists(&path) {
+            let saved = fs::metadata(&path)?.modified()?;
+            snapshots.push((saved, path));
+        }
+    }
+    snapshots.sort();
+    let snapshots: Vec<_> = snapshots
+        .into_iter()
+        .filter_map(|(saved, path)| {
+            let name = path.file_name()?.to_string_lossy().into_owned();
+            Some((name, utc_timestamp(saved), path))
+        })
+        .collect();
+    if json {
+        let snapshots: Vec<_> = snapshots
+            .iter()
+            .map(|(name, saved, path)| {
+                serde_json::json!({ "name": name, "saved": saved, "path": path })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&snapshots)?);
+    } else {
+        for (name, saved, _) in snapshots {
+            println!("{saved}  {name}");
+        }
+    }
+    Ok(0)
+}
+
+/// Remove stale lock files and report clusters that were not shut down
+/// cleanly. With `prune`, destroy clusters in that directory not used for
+/// `older_than`. Problems with individual clusters are reported but do not
+/// stop the others from being cleaned up; if there were any, this exits with 1.
+fn gc(
+    lock_dir: &Path,
+    prune: Option<&Path>,
+    older_than: Option<Duration>,
+    orphans: Option<Option<&Path>>,
+) -> Result<i32> {
+    let mut code = 0;
+    let removed = lock::gc(lock_dir)
+        .wrap_err("Could not remove stale lock files")
+        .with_section(|| format!("{}", lock_dir.display()).header("Lock directory:"))?;
+    for path in removed {
+        println!("Removed stale lock file {}", path.display());
+    }
+
+    let subjects = lock::subjects(lock_dir)
+        .wrap_err("Could not read lock files")
+        .with_section(|| format!("{}", lock_dir.display()).header("Lock directory:"))?;
+    for (_, datadir) in subjects {
+        match cluster::stale_pid(&datadir) {
+            Ok(None) => (),
+            Ok(Some(pid)) => println!(
+                "Cluster in {} was not shut down cleanly (server PID {pid} has gone)",
+                datadir.display()
+            ),
+            Err(err) => {
+                eprintln!("Warning: could not check {}: {err}", datadir.display());
+                code = 1;
+            }
+        }
+    }
+
+    if let (Some(dir), Some(older_than)) = (prune, older_than) {
+        let cutoff = SystemTime::now()
+            .checked_sub(older_than)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let entries = fs::read_dir(dir)
+            .wrap_err("Could not read directory to prune")
+            .with_section(|| format!("{}", dir.display()).header("Directory:"))?;
+        for entry in entries {
+            let datadir = entry?.path();
+            if !cluster::exists(&datadir) || cluster::last_used(&datadir)? > cutoff {
+                continue;
+            }
+            if let Err(report) = prune_cluster(&datadir, lock_dir) {
+                eprintln!(
+                    "Warning: could not destroy cluster in {}: {report}",
+                    datadir.display()
+                );
+                code = 1;
+            }
+        }
+    }
+
+    if let Some(dir) = orphans {
+        let dirs = match dir {
+            Some(dir) => vec![dir.to_owned()],
+            None => std::iter::once(std::env::temp_dir())
+                .chain(cluster::ram_dir())
+                .collect(),
+        };
+        for dir in dirs {
+            let orphans = coordinate::destroy_orphans(&dir, lock_dir, ORPHANS_UNUSED_FOR)
+                .wrap_err("Could not find orphaned clusters")
+                .with_section(|| format!("{}", dir.display()).header("Directory:"))?;
+            for (datadir, orphan) in orphans {
+                match orphan {
+                    coordinate::Orphan::Destroyed => {
+                        println!("Destroyed orphaned cluster in {}", datadir.display());
+                    }
+                    coordinate::Orphan::InUse => {
+                        println!("Skipped cluster in use in {}", datadir.display());
+                    }
+                    coordinate::Orphan::Failed(err) => {
+                        eprintln!(
+                            "Warning: could not destroy cluster in {}: {err}",
+                            datadir.display()
+                        );
+                        code = 1;
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    Ok(code)
+}
+
+/// How long an orphaned cluster must have been unused before `gc --orphans`
+/// destroys it.
+const ORPHANS_UNUSED_FOR: Duration = Duration::from_secs(60 * 60);
+
+/// Destroy the cluster in `datadir` unless it is in use or running.
+fn prune_cluster(datadir: &Path, lock_dir: &Path) -> Result<()> {
+    let (datadir, lock) = lock_for(datadir, Some(lock_dir))?;
+    if server_running(&datadir)? {
+        println!("Skipped running cluster in {}", datadir.display());
+        return Ok(());
+    }
+    let cluster = new_cluster(&datadir, None)?;
+    match coordinate::destroy(&cluster, lock)? {
+        Some(_) => println!("Destroyed cluster in {}", datadir.display()),
+        None => println!("Skipped cluster in use in {}", datadir.display()),
+    }
+    Ok(())
+}
+
+/// Is the server running for the cluster in `datadir`? This checks the PID
+/// file, so it does not need a runtime for the cluster, unlike
+/// [`cluster::Cluster::running`].
+fn server_running(datadir: &Path) -> Result<bool> {
+    Ok(cluster::exists(datadir)
+        && datadir.join("postmaster.pid").exists()
+        && cluster::stale_pid(datadir)?.is_none())
+}
+
+/// Ask the user a yes/no question on the terminal. Without a terminal, this
+/// fails rather than assume an answer.
+fn confirm(question: &str) -> Result<bool> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        bail!("Cannot ask for confirmation without a terminal; use --yes");
+    }
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    stdin.read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "YES" | "Yes"))
+}
+
+/// Find an existing cluster, and the lock file for it.
+fn existing_cluster(
+    cluster_args: &cli::ClusterArgs,
+) -> Result<(cluster::Cluster, lock::UnlockedFile)> {
+    let (database_dir, lock) =
+        lock_for(&cluster_args.datadir()?, lock_dir(cluster_args).as_deref())?;
+    let cluster = new_cluster(&database_dir, cluster_args.runtime.as_ref())?;
+    if cluster::exists(&cluster) {
+        Ok((cluster, lock))
+    } else {
+        Err(eyre!("No cluster found"))
+            .with_section(|| format!("{}", database_dir.display()).header("Database directory:"))
+    }
+}
+
+/// An error explaining that the cluster is in use, listing the processes that
+/// last took the lock at `lock_path`.
+fn in_use(lock_path: Option<&Path>) -> Result<i32> {
+    Err(eyre!("Cluster is in use")).with_section(|| {
+        lock_path
+            .and_then(|path| lock::holders(path).ok())
+            .unwrap_or_default()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .header("Lock holders (pid, mode, time, program):")
+    })
+}
+
+/// Represent the cluster in `database_dir`, using the runtime chosen with
+/// `--runtime`, if any, else the default runtime strategy.
+fn new_cluster(
+    database_dir: &Path,
+    runtime: Option<&cli::RuntimeChoice>,
+) -> Result<cluster::Cluster> {
+    let strategy = runtime::strategy::default();
+    Ok(match runtime {
+        None => cluster::Cluster::new(database_dir, strategy)?,
+        Some(cli::RuntimeChoice::Matching(versions)) => cluster::Cluster::new(
+            database_dir,
+            runtime::strategy::Constrained::new(strategy, versions.clone()),
+        )?,
+        Some(cli::RuntimeChoice::Bindir(bindir)) => {
+            let runtime = runtime::Runtime::new(bindir)
+                .wrap_err("Could not use runtime")
+                .with_section(|| format!("{}", bindir.display()).header("Runtime:"))?;
+            cluster::Cluster::new(database_dir, runtime)?
+        }
+    })
+}
+
+/// The directory in which to place the cluster's lock file, or [`None`] to
+/// place it beside the cluster's data directory.
+fn lock_dir(cluster_args: &cli::ClusterArgs) -> Option<PathBuf> {
+    (!cluster_args.lock_beside_datadir).then(|| cluster_args.lock.dir())
+}
+
+/// Obtain a canonical path to the cluster directory, and the lock file for
+/// that cluster in `lock_dir`, or beside the cluster directory if `lock_dir` is
+/// [`None`].
+fn lock_for(database_dir: &Path, lock_dir: Option<&Path>) -> Result<(PathBuf, lock::UnlockedFile)> {
+    let database_dir = database_dir
+        .canonicalize()
+        .wrap_err("Could not canonicalize database directory")
+        .with_section(|| format!("{}", database_dir.display()).header("Database directory:"))?;
+
+    // Use the canonical path to find the file with which we'll lock this
+    // cluster.
+    let lock = match lock_dir {
+        Some(lock_dir) => lock::UnlockedFile::try_for_datadir_in(lock_dir, &database_dir)
+            .wrap_err("Could not create UUID-based lock file")
+            .with_section(|| format!("{}", lock_dir.display()).header("Lock directory:"))?,
+        None => lock::UnlockedFile::try_beside_datadir(&database_dir)
+            .wrap_err("Could not create lock file beside database directory")
+            .with_section(|| format!("{}", database_dir.display()).header("Database directory:"))?,
+    };
+
+    Ok((database_dir, lock))
+}
+
+/// The durability to apply when starting the cluster, for modes that must not
+/// reconfigure the cluster.
+fn durability(mode: Option<cli::Mode>) -> Option<cluster::Durability> {
+    match mode {
+        Some(cli::Mode::FastUntilStopped) => Some(cluster::Durability::Fast),
+        Some(cli::Mode::Fast | cli::Mode::Slow) | None => None,
+    }
 }
 
 /// Create an initialisation function that will set appropriate PostgreSQL
@@ -157,27 +1640,11 @@ fn initialise(
 ) -> impl std::panic::UnwindSafe + FnOnce(&cluster::Cluster) -> Result<(), cluster::ClusterError> {
     match mode {
         Some(cli::Mode::Fast) => {
-            |cluster: &cluster::Cluster| {
-                let mut conn = cluster.connect("template1")?;
-                conn.execute("ALTER SYSTEM SET fsync = 'off'", &[])?;
-                conn.execute("ALTER SYSTEM SET full_page_writes = 'off'", &[])?;
-                conn.execute("ALTER SYSTEM SET synchronous_commit = 'off'", &[])?;
-                // TODO: Check `pg_file_settings` for errors before reloading.
-                conn.execute("SELECT pg_reload_conf()", &[])?;
-                Ok(())
-            }
+            |cluster: &cluster::Cluster| cluster.set_durability(cluster::Durability::Fast)
         }
         Some(cli::Mode::Slow) => {
-            |cluster: &cluster::Cluster| {
-                let mut conn = cluster.connect("template1")?;
-                conn.execute("ALTER SYSTEM RESET fsync", &[])?;
-                conn.execute("ALTER SYSTEM RESET full_page_writes", &[])?;
-                conn.execute("ALTER SYSTEM RESET synchronous_commit", &[])?;
-                // TODO: Check `pg_file_settings` for errors before reloading.
-                conn.execute("SELECT pg_reload_conf()", &[])?;
-                Ok(())
-            }
+            |cluster: &cluster::Cluster| cluster.set_durability(cluster::Durability::Default)
         }
-        None => |_: &cluster::Cluster| Ok(()),
+        Some(cli::Mode::FastUntilStopped) | None => |_: &cluster::Cluster| Ok(()),
     }
 }
@@ -0,0 +1,293 @@
+//! A registry of named clusters, so that a few long-lived clusters, e.g. for
+//! development, can be referred to by name rather than by path.
+//!
+//! The registry is kept in [`file()`], i.e. `postgresfixture/clusters.toml` in
+//! `$XDG_DATA_HOME` or `~/.local/share`. It maps names to data directories,
+//! and records which cluster is current, i.e. the one to use when no other is
+//! given.
+//!
+//! ```rust
+//! # use postgresfixture::registry::Registry;
+//! let dir = tempdir::TempDir::new("registry")?;
+//! let path = dir.path().join("clusters.toml");
+//! Registry::update(&path, |registry| {
+//!     registry.register("dev", "/var/tmp/dev")?;
+//!     registry.set_current(Some("dev"))
+//! })?;
+//! let registry = Registry::load(&path)?;
+//! assert_eq!(registry.current(), Some(("dev", "/var/tmp/dev".as_ref())));
+//! # Ok::<(), postgresfixture::registry::RegistryError>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::{env, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::lock::UnlockedFile;
+use crate::util;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum RegistryError {
+    #[error("input/output error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("could not parse registry: {0}")]
+    ParseError(#[from] toml::de::Error),
+    #[error("could not write registry: {0}")]
+    SerializeError(#[from] toml::ser::Error),
+    /// Names must be a single, ordinary path component; see [`check_name`].
+    #[error(
+        "invalid cluster name {0:?}; names must not be empty, start with `.`, or contain `{sep}`",
+        sep = std::path::MAIN_SEPARATOR
+    )]
+    InvalidName(String),
+    /// There is no cluster registered with this name.
+    #[error("no cluster named {0:?}")]
+    NotRegistered(String),
+}
+
+/// The directory in which the registry, and clusters created for it without
+/// an explicit data directory, are kept: `postgresfixture` in
+/// `$XDG_DATA_HOME`, else in `~/.local/share`.
+pub fn dir() -> Option<PathBuf> {
+    find_dir(env::var_os("XDG_DATA_HOME"), env::var_os("HOME"))
+}
+
+fn find_dir(data_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    data_home
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.map(|home| Path::new(&home).join(".local").join("share")))
+        .map(|dir| dir.join("postgresfixture"))
+}
+
+/// The file in which the registry is kept: `clusters.toml` in [`dir`].
+pub fn file() -> Option<PathBuf> {
+    dir().map(|dir| dir.join("clusters.toml"))
+}
+
+/// A default data directory for a cluster with the given name, in [`dir`].
+pub fn default_datadir(name: &str) -> Option<PathBuf> {
+    dir().map(|dir| dir.join("clusters").join(name))
+}
+
+/// Check that the given name can be used for a cluster. Names are used as
+/// directory names, e.g. by [`default_datadir`], so must be a single, ordinary
+/// path component.
+pub fn check_name(name: &str) -> Result<(), RegistryError> {
+    if name.is_empty() || name.starts_with('.') || name.contains(std::path::MAIN_SEPARATOR) {
+        Err(RegistryError::InvalidName(name.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Named clusters, and which of them is current.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Registry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    current: Option<String>,
+    #[serde(default)]
+    clusters: BTreeMap<String, PathBuf>,
+}
+
+impl Registry {
+    /// Load the registry from the given file. A missing file is an empty
+    /// registry.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RegistryError> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err)?,
+        }
+    }
+
+    /// Save the registry to the given file, creating its directory if
+    /// necessary. The file is written to a temporary file which is then renamed
+    /// into place, so that other processes never see a partial file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), RegistryError> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        let path_tmp = dir.join(util::temp_name(".clusters"));
+        fs::write(&path_tmp, toml::to_string(self)?)?;
+        fs::rename(&path_tmp, path)?;
+        Ok(())
+    }
+
+    /// Load the registry from the given file, change it with `update`, then
+    /// save it. This holds an exclusive lock on a neighbouring lock file
+    /// throughout, so that concurrent updates are not lost. Nothing is saved
+    /// if `update` fails.
+    pub fn update<P, F, T>(path: P, update: F) -> Result<T, RegistryError>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(&mut Self) -> Result<T, RegistryError>,
+    {
+        let path = path.as_ref();
+        let lock = UnlockedFile::try_from(&path.with_extension("lock"))?.lock_exclusive()?;
+        let mut registry = Self::load(path)?;
+        let result = update(&mut registry)?;
+        registry.save(path)?;
+        lock.unlock()?;
+        Ok(result)
+    }
+
+    /// The data directory of the cluster with the given name, if registered.
+    pub fn get(&self, name: &str) -> Option<&Path> {
+        self.clusters.get(name).map(PathBuf::as_path)
+    }
+
+    /// The registered clusters, ordered by name.
+    pub fn clusters(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.clusters
+            .iter()
+            .map(|(name, datadir)| (name.as_str(), datadir.as_path()))
+    }
+
+    /// Register a cluster with the given name, replacing any cluster already
+    /// registered with that name. The data directory should be absolute.
+    pub fn register<D: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        datadir: D,
+    ) -> Result<(), RegistryError> {
+        check_name(name)?;
+        self.clusters
+            .insert(name.to_owned(), datadir.as_ref().to_owned());
+        Ok(())
+    }
+
+    /// Forget the cluster with the given name, returning its data directory if
+    /// it was registered. The cluster itself is not touched. If it was current,
+    /// no cluster is current afterwards.
+    pub fn unregister(&mut self, name: &str) -> Option<PathBuf> {
+        if self.current.as_deref() == Some(name) {
+            self.current = None;
+        }
+        self.clusters.remove(name)
+    }
+
+    /// The current cluster's name and data directory, if there is one.
+    pub fn current(&self) -> Option<(&str, &Path)> {
+        let name = self.current.as_deref()?;
+        self.get(name).map(|datadir| (name, datadir))
+    }
+
+    /// Make the cluster with the given name current, or, with [`None`], make
+    /// no cluster current. Fails if there's no cluster with that name.
+    pub fn set_current(&mut self, name: Option<&str>) -> Result<(), RegistryError> {
+        match name {
+            Some(name) if !self.clusters.contains_key(name) => {
+                Err(RegistryError::NotRegistered(name.to_owned()))
+            }
+            _ => {
+                self.current = name.map(str::to_owned);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{find_dir, Registry, RegistryError};
+
+    #[test]
+    fn find_dir_prefers_data_home_then_home() {
+        assert_eq!(
+            find_dir(Some("/data".into()), Some("/home/me".into())),
+            Some(PathBuf::from("/data/postgresfixture"))
+        );
+        assert_eq!(
+            find_dir(Some("".into()), Some("/home/me".into())),
+            Some(PathBuf::from("/home/me/.local/share/postgresfixture"))
+        );
+        assert_eq!(find_dir(None, None), None);
+    }
+
+    #[test]
+    fn registry_round_trips_through_file() -> Result<(), RegistryError> {
+        let dir = tempdir::TempDir::new("registry")?;
+        let path = dir.path().join("clusters.toml");
+        assert_eq!(Registry::load(&path)?, Registry::default());
+        Registry::update(&path, |registry| {
+            registry.register("alice", "/tmp/alice")?;
+            registry.register("bob", "/tmp/bob")?;
+            registry.set_current(Some("bob"))
+        })?;
+        let registry = Registry::load(&path)?;
+        assert_eq!(
+            registry.clusters().collect::<Vec<_>>(),
+            vec![
+                ("alice", Path::new("/tmp/alice")),
+                ("bob", Path::new("/tmp/bob"))
+            ]
+        );
+        assert_eq!(registry.current(), Some(("bob", Path::new("/tmp/bob"))));
+        Ok(())
+    }
+
+    #[test]
+    fn registry_update_saves_nothing_on_error() -> Result<(), RegistryError> {
+        let dir = tempdir::TempDir::new("registry")?;
+        let path = dir.path().join("clusters.toml");
+        let result = Registry::update(&path, |registry| {
+            registry.register("alice", "/tmp/alice")?;
+            registry.set_current(Some("bob"))
+        });
+        assert!(matches!(result, Err(RegistryError::NotRegistered(name)) if name == "bob"));
+        assert_eq!(Registry::load(&path)?, Registry::default());
+        Ok(())
+    }
+
+    #[test]
+    fn registry_update_does_not_lose_concurrent_updates() -> Result<(), RegistryError> {
+        let dir = tempdir::TempDir::new("registry")?;
+        let path = dir.path().join("clusters.toml");
+        let threads: Vec<_> = (0..8)
+            .map(|n| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    Registry::update(&path, |registry| {
+                        registry.register(&format!("cluster{n}"), "/tmp/foo")
+                    })
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().expect("thread panicked")?;
+        }
+        assert_eq!(Registry::load(&path)?.clusters().count(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn registry_unregister_clears_current() -> Result<(), RegistryError> {
+        let mut registry = Registry::default();
+        registry.register("alice", "/tmp/alice")?;
+        registry.set_current(Some("alice"))?;
+        assert_eq!(registry.unregister("alice"), Some("/tmp/alice".into()));
+        assert_eq!(registry.current(), None);
+        assert_eq!(registry.unregister("alice"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn registry_rejects_invalid_names() {
+        let mut registry = Registry::default();
+        for name in ["", ".hidden", "a/b"] {
+            assert!(matches!(
+                registry.register(name, "/tmp/foo"),
+                Err(RegistryError::InvalidName(_))
+            ));
+        }
+        assert_eq!(registry.clusters().count(), 0);
+    }
+}
@@ -6,20 +6,34 @@
 //! represents one such runtime; the [`Strategy`] trait represents how to find
 //! and select a runtime.
 
-mod cache;
+pub mod cache;
+#[cfg(feature = "docker")]
+pub mod docker;
+#[cfg(feature = "download")]
+pub mod download;
 mod error;
+mod pg_config;
 pub mod strategy;
 
 use std::env;
 use std::ffi::OsStr;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use crate::util;
 use crate::version;
 pub use error::RuntimeError;
+pub use pg_config::PgConfig;
 pub use strategy::Strategy;
 
+/// The environment variable that adds to the dynamic linker's search path.
+#[cfg(target_os = "macos")]
+pub const LIBRARY_PATH_VAR: &str = "DYLD_FALLBACK_LIBRARY_PATH";
+/// The environment variable that adds to the dynamic linker's search path.
+#[cfg(not(target_os = "macos"))]
+pub const LIBRARY_PATH_VAR: &str = "LD_LIBRARY_PATH";
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Runtime {
     /// Path to the directory containing the `pg_ctl` executable and other
@@ -36,8 +50,141 @@ impl Runtime {
         Ok(Self { bindir: bindir.as_ref().to_owned(), version })
     }
 
+    /// The programs that [`Self::validate`] checks for, i.e. those needed to
+    /// create and run a cluster.
+    pub const REQUIRED_PROGRAMS: &'static [&'static str] = &["pg_ctl", "initdb", "postgres"];
+
+    /// Check that the programs needed to create and run a cluster –
+    /// [`Self::REQUIRED_PROGRAMS`] – exist in [`Self::bindir`] and are
+    /// executable.
+    ///
+    /// [`Self::new`] only checks `pg_ctl`, so a partial installation, e.g. one
+    /// with only client programs, would otherwise fail part-way through
+    /// creating a cluster.
+    pub fn validate(&self) -> Result<(), RuntimeError> {
+        self.validate_programs(Self::REQUIRED_PROGRAMS)
+    }
+
+    /// Check that the given programs exist in [`Self::bindir`] and are
+    /// executable, e.g. `["psql", "pg_dump"]`. The error names all programs
+    /// that are missing.
+    pub fn validate_programs<T: AsRef<str>>(&self, programs: &[T]) -> Result<(), RuntimeError> {
+        let missing: Vec<String> = programs
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|program| !is_executable(&self.bindir.join(program)))
+            .map(str::to_owned)
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(RuntimeError::MissingPrograms(self.bindir.clone(), missing))
+        }
+    }
+
+    /// The directory containing the real `pg_ctl` executable for this runtime,
+    /// i.e. [`Self::bindir`] with all symlinks resolved, including a symlinked
+    /// `pg_ctl`. Two runtimes with the same canonical `bindir` are the same
+    /// installation. If `pg_ctl` cannot be resolved, this returns
+    /// [`Self::bindir`].
+    pub fn canonical_bindir(&self) -> PathBuf {
+        self.bindir
+            .join("pg_ctl")
+            .canonicalize()
+            .ok()
+            .and_then(|pg_ctl| pg_ctl.parent().map(Path::to_owned))
+            .unwrap_or_else(|| self.bindir.clone())
+    }
+
+    /// Build information for this runtime, from its `pg_config`.
+    ///
+    /// This is cached in the same way as the runtime's version, so calling it
+    /// repeatedly is cheap. Not all installations include `pg_config`; some
+    /// packagers put it into a separate development package.
+    ///
+    /// ```rust,no_run
+    /// # use postgresfixture::runtime::{self, RuntimeError, Strategy};
+    /// # let runtime = runtime::strategy::default().fallback().unwrap();
+    /// let pkglibdir = runtime.pg_config()?.pkglibdir();
+    /// # Ok::<(), RuntimeError>(())
+    /// ```
+    pub fn pg_config(&self) -> Result<PgConfig, RuntimeError> {
+        cache::pg_config(self.bindir.join("pg_config"))
+    }
+
+    /// The names of extensions shipped with this runtime, sorted.
+    ///
+    /// These are found by looking for control files in the `extension`
+    /// subdirectory of the runtime's `SHAREDIR`, as reported by
+    /// [`Self::pg_config`]. An extension being available here does not mean
+    /// that it's installed into any database, only that `CREATE EXTENSION` can
+    /// be expected to work.
+    ///
+    /// ```rust,no_run
+    /// # use postgresfixture::runtime::{self, RuntimeError, Strategy};
+    /// # let runtime = runtime::strategy::default().fallback().unwrap();
+    /// if !runtime.available_extensions()?.iter().any(|name| name == "postgis") {
+    ///     return Ok(()); // Skip this test.
+    /// }
+    /// # Ok::<(), RuntimeError>(())
+    /// ```
+    pub fn available_extensions(&self) -> Result<Vec<String>, RuntimeError> {
+        let sharedir = self
+            .pg_config()?
+            .sharedir()
+            .ok_or_else(|| RuntimeError::PgConfigError("SHAREDIR not reported".into()))?;
+        let mut names = fs::read_dir(sharedir.join("extension"))?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                match path.extension() {
+                    Some(ext) if ext == "control" => path.file_stem()?.to_str().map(str::to_owned),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    /// The directory containing this runtime's shared libraries, if it has its
+    /// own: the `lib` directory next to [`Self::canonical_bindir`], when that
+    /// contains `libpq`. This is typical of relocated or hand-built
+    /// installations, e.g. `/opt/pg/15/{bin,lib}`, whose programs may not find
+    /// their libraries without help. Runtimes installed by a package manager
+    /// generally use system library directories, and this returns [`None`].
+    pub fn libdir(&self) -> Option<PathBuf> {
+        let libdir = self.canonical_bindir().parent()?.join("lib");
+        fs::read_dir(&libdir)
+            .ok()?
+            .filter_map(Result::ok)
+            .any(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with("libpq.") && !name.ends_with(".a")
+            })
+            .then_some(libdir)
+    }
+
+    /// Set up the environment of the given command to use this runtime: put
+    /// [`Self::bindir`] at the front of `PATH` and, if this runtime has its own
+    /// [`Self::libdir`], put that at the front of the dynamic linker's search
+    /// path, i.e. [`LIBRARY_PATH_VAR`].
+    fn prepare(&self, command: &mut Command) {
+        command.env(
+            "PATH",
+            util::prepend_to_path(&self.bindir, env::var_os("PATH")).unwrap(),
+        );
+        if let Some(libdir) = self.libdir() {
+            command.env(
+                LIBRARY_PATH_VAR,
+                util::prepend_to_path(&libdir, env::var_os(LIBRARY_PATH_VAR)).unwrap(),
+            );
+        }
+    }
+
     /// Return a [`Command`] prepped to run the given `program` in this
-    /// PostgreSQL runtime.
+    /// PostgreSQL runtime, with [`Self::bindir`] at the front of `PATH`, and
+    /// [`Self::libdir`] on the dynamic linker's search path.
     ///
     /// ```rust
     /// # use postgresfixture::runtime::{self, RuntimeError, Strategy};
@@ -52,15 +199,13 @@ impl Runtime {
     /// [`env::join_paths`].
     pub fn execute<T: AsRef<OsStr>>(&self, program: T) -> Command {
         let mut command = Command::new(self.bindir.join(program.as_ref()));
-        command.env(
-            "PATH",
-            util::prepend_to_path(&self.bindir, env::var_os("PATH")).unwrap(),
-        );
+        self.prepare(&mut command);
         command
     }
 
     /// Return a [`Command`] prepped to run the given `program` with this
-    /// PostgreSQL runtime at the front of `PATH`. This is very similar to
+    /// PostgreSQL runtime at the front of `PATH`, and its [`Self::libdir`] on
+    /// the dynamic linker's search path. This is very similar to
     /// [`Self::execute`] except it does not qualify the given program name with
     /// [`Self::bindir`].
     ///
@@ -77,29 +222,25 @@ impl Runtime {
     /// [`env::join_paths`].
     pub fn command<T: AsRef<OsStr>>(&self, program: T) -> Command {
         let mut command = Command::new(program);
-        command.env(
-            "PATH",
-            util::prepend_to_path(&self.bindir, env::var_os("PATH")).unwrap(),
-        );
+        self.prepare(&mut command);
         command
     }
 }
 
+/// Is the given path an executable file? Follows symlinks.
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
 #[cfg(test)]
 mod tests {
+    use super::strategy::find_bindir;
     use super::{Runtime, RuntimeError};
 
-    use std::env;
-    use std::path::PathBuf;
-
     type TestResult = Result<(), RuntimeError>;
 
-    fn find_bindir() -> PathBuf {
-        env::split_paths(&env::var_os("PATH").expect("PATH not set"))
-            .find(|path| path.join("pg_ctl").exists())
-            .expect("pg_ctl not on PATH")
-    }
-
     #[test]
     fn runtime_new() -> TestResult {
         let bindir = find_bindir();
@@ -107,4 +248,79 @@ mod tests {
         assert_eq!(bindir, pg.bindir);
         Ok(())
     }
+
+    #[test]
+    fn runtime_validate() -> TestResult {
+        let bindir = find_bindir();
+        Runtime::new(&bindir)?.validate()?;
+        // A directory with only `pg_ctl` is not a complete runtime.
+        let tempdir = tempdir::TempDir::new("bindir")?;
+        std::os::unix::fs::symlink(bindir.join("pg_ctl"), tempdir.path().join("pg_ctl"))?;
+        std::fs::write(tempdir.path().join("initdb"), "not executable")?;
+        let runtime = Runtime::new(tempdir.path())?;
+        match runtime.validate() {
+            Err(RuntimeError::MissingPrograms(bindir, programs)) => {
+                assert_eq!(tempdir.path(), bindir);
+                assert_eq!(vec!["initdb", "postgres"], programs);
+            }
+            result => panic!("unexpected result: {result:?}"),
+        }
+        runtime.validate_programs(&["pg_ctl"])?;
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_libdir() -> TestResult {
+        use std::os::unix::fs::PermissionsExt;
+
+        // A hand-built installation with its own `libpq`.
+        let prefix = tempdir::TempDir::new("prefix")?;
+        let bindir = prefix.path().join("bin");
+        let libdir = prefix.path().join("lib");
+        std::fs::create_dir(&bindir)?;
+        std::fs::create_dir(&libdir)?;
+        let pg_ctl = bindir.join("pg_ctl");
+        std::fs::write(&pg_ctl, "#!/bin/sh\necho 'pg_ctl (PostgreSQL) 15.4'\n")?;
+        std::fs::set_permissions(&pg_ctl, std::fs::Permissions::from_mode(0o755))?;
+        let runtime = Runtime::new(&bindir)?;
+        assert_eq!(None, runtime.libdir());
+        std::fs::write(libdir.join("libpq.so.5"), "")?;
+        assert_eq!(Some(libdir.canonicalize()?), runtime.libdir());
+        let output = runtime.command("env").output()?;
+        let expected = format!(
+            "{}={}",
+            super::LIBRARY_PATH_VAR,
+            libdir.canonicalize()?.display()
+        );
+        assert!(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line == expected || line.starts_with(&format!("{expected}:"))));
+        // A system installation does not have its own `libpq`.
+        assert_eq!(None, Runtime::new(find_bindir())?.libdir());
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_pg_config() -> TestResult {
+        // `pg_ctl` on `PATH` may be a symlink into the real `bindir`, which is
+        // where we'll find `pg_config`.
+        let pg_ctl = find_bindir().join("pg_ctl").canonicalize()?;
+        let pg = Runtime::new(pg_ctl.parent().unwrap())?;
+        let config = pg.pg_config()?;
+        assert!(config.sharedir().is_some_and(|dir| dir.is_dir()));
+        assert!(config.pkglibdir().is_some_and(|dir| dir.is_dir()));
+        // A second call is served from the cache.
+        assert_eq!(config, pg.pg_config()?);
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_available_extensions() -> TestResult {
+        let pg_ctl = find_bindir().join("pg_ctl").canonicalize()?;
+        let pg = Runtime::new(pg_ctl.parent().unwrap())?;
+        let extensions = pg.available_extensions()?;
+        assert!(extensions.iter().any(|name| name == "plpgsql"));
+        assert!(extensions.windows(2).all(|w| w[0] < w[1]));
+        Ok(())
+    }
 }
@@ -1,34 +1,75 @@
-//! Version cache for binaries.
+//! Caches for information gleaned from binaries.
+//!
+//! Versions are also persisted to disk, in [`file()`], so that discovering
+//! runtimes in a new process – e.g. each `cargo test` run – does not mean
+//! running every `pg_ctl` again.
+//!
+//! Test harnesses can [`prewarm`] the cache during setup rather than on first
+//! use, and long-running tools can [`clear`] it, e.g. after PostgreSQL has been
+//! upgraded.
 
 use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{self, File};
 use std::hash::Hasher;
-use std::io::Read;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{self, Command};
 use std::sync::RwLock;
 
-use super::RuntimeError;
+use nix::fcntl::{flock, FlockArg};
+
+use super::{PgConfig, RuntimeError, Strategy};
 use crate::version::{Version, VersionError};
 
-#[derive(Debug)]
-struct Entry {
+#[derive(Clone, Debug, PartialEq)]
+struct Entry<T> {
     size: u64,
+    /// Entries persisted by earlier versions of this crate have no stamp.
+    stamp: Option<Stamp>,
     hash: u64,
-    version: Version,
+    value: T,
+}
+
+/// A binary's inode number and modification time, in nanoseconds since the
+/// epoch. With its size, these are checked before hashing the binary: when
+/// all are unchanged, so is the binary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Stamp {
+    inode: u64,
+    mtime: i128,
 }
 
+impl From<&fs::Metadata> for Stamp {
+    fn from(metadata: &fs::Metadata) -> Self {
+        Self {
+            inode: metadata.ino(),
+            mtime: i128::from(metadata.mtime()) * 1_000_000_000 + i128::from(metadata.mtime_nsec()),
+        }
+    }
+}
+
+type Cache<T> = RwLock<HashMap<PathBuf, Entry<T>>>;
+
 lazy_static! {
-    static ref CACHE: RwLock<HashMap<PathBuf, Entry>> = HashMap::new().into();
+    static ref VERSIONS: Cache<Version> = file()
+        .and_then(|path| load(&path).ok())
+        .unwrap_or_default()
+        .into();
+    static ref PG_CONFIGS: Cache<PgConfig> = HashMap::new().into();
 }
 
 /// Get a cached version of PostgreSQL from a given PostgreSQL binary.
 ///
 /// If the binary referenced has changed, as measured by size and a hash
 /// calculated from its contents, this will run the binary again to determine
-/// the version. Even with hashing, a cache hit turns out to be ~10x faster than
-/// running `pg_ctl -version` (and adds 200-300µs to a cache miss).
+/// the version. The binary is hashed only when its size, inode, or
+/// modification time have changed since it was cached. Even with hashing, a
+/// cache hit turns out to be ~10x faster than running `pg_ctl -version` (and
+/// adds 200-300µs to a cache miss).
 ///
 /// The [PostgreSQL "Versioning Policy"][versioning] shows that version numbers
 /// are **not** SemVer compatible. The [`version`][`mod@crate::version`] module
@@ -37,43 +78,230 @@ lazy_static! {
 ///
 /// [versioning]: https://www.postgresql.org/support/versioning/
 pub fn version<P: AsRef<Path>>(binary: P) -> Result<Version, RuntimeError> {
-    let binary: PathBuf = binary.as_ref().canonicalize()?;
-    let (size, hash) = {
-        let mut file = File::open(&binary)?;
-        let size = file.metadata()?.len();
-        let hash = {
-            let mut hasher = DefaultHasher::new();
-            let mut buffer = [0u8; 16384]; // 16 kiB buffer.
-            loop {
-                let bytes_read = file.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break; // Reached end of file
-                }
-                hasher.write(&buffer[..bytes_read]);
+    cached(
+        &VERSIONS,
+        binary.as_ref(),
+        |binary| version_from_binary(binary),
+        |binary, entry| {
+            // Failing to persist the cache is not fatal; it's only a cache.
+            if let Some(path) = file() {
+                let _ = save(&path, binary, entry);
             }
-            hasher.finish()
+        },
+    )
+}
+
+/// Get cached build information from a given `pg_config` binary.
+///
+/// This is cached in the same way as [`version`].
+pub fn pg_config<P: AsRef<Path>>(binary: P) -> Result<PgConfig, RuntimeError> {
+    cached(
+        &PG_CONFIGS,
+        binary.as_ref(),
+        |binary| pg_config_from_binary(binary),
+        |_, _| (),
+    )
+}
+
+/// Find the runtimes known to the given strategy, caching their versions.
+///
+/// Returns the number of runtimes found.
+pub fn prewarm<S: Strategy + ?Sized>(strategy: &S) -> usize {
+    // Each runtime's version is cached as it is discovered.
+    strategy.runtimes().count()
+}
+
+/// Forget all cached versions and build information, including versions
+/// persisted to [`file()`].
+pub fn clear() -> io::Result<()> {
+    if let Ok(mut cache) = VERSIONS.write() {
+        cache.clear();
+    }
+    if let Ok(mut cache) = PG_CONFIGS.write() {
+        cache.clear();
+    }
+    match file() {
+        Some(path) => remove(&path),
+        None => Ok(()),
+    }
+}
+
+/// The versions cached in this process, keyed by the canonical path of each
+/// `pg_ctl` binary. This includes versions loaded from [`file()`].
+pub fn snapshot() -> BTreeMap<PathBuf, Version> {
+    VERSIONS
+        .read()
+        .map(|cache| {
+            cache
+                .iter()
+                .map(|(binary, entry)| (binary.clone(), entry.value))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Get a value from the given cache, or calculate it from the given binary. A
+/// newly calculated entry is passed to `store` too.
+fn cached<T, F, S>(
+    cache: &Cache<T>,
+    binary: &Path,
+    calculate: F,
+    store: S,
+) -> Result<T, RuntimeError>
+where
+    T: Clone,
+    F: FnOnce(&Path) -> Result<T, RuntimeError>,
+    S: FnOnce(&Path, &Entry<T>),
+{
+    let binary: PathBuf = binary.canonicalize()?;
+    let metadata = fs::metadata(&binary)?;
+    let size = metadata.len();
+    let stamp = Stamp::from(&metadata);
+    let known = |hash: Option<u64>| {
+        let cache = cache.read().ok()?;
+        let entry = cache.get(&binary)?;
+        let unchanged = match hash {
+            None => entry.stamp == Some(stamp),
+            Some(hash) => entry.hash == hash,
         };
-        (size, hash)
+        (entry.size == size && unchanged).then(|| entry.value.clone())
     };
 
-    // Try to check if we already know the version.
-    if let Ok(cache) = CACHE.read() {
-        if let Some(entry) = cache.get(&binary) {
-            if entry.size == size && entry.hash == hash {
-                return Ok(entry.version);
-            }
+    // If the binary's size, inode, and modification time are unchanged, we
+    // know the value without reading the binary.
+    if let Some(value) = known(None) {
+        return Ok(value);
+    }
+
+    // Otherwise hash the binary. If it's unchanged we still know the value,
+    // but the entry is stored again with the new stamp.
+    let hash = hash_file(&binary)?;
+    let value = match known(Some(hash)) {
+        Some(value) => value,
+        // Okay, we definitely need to run the binary.
+        None => calculate(&binary)?,
+    };
+
+    // Try to cache the value.
+    let entry = Entry { size, stamp: Some(stamp), hash, value: value.clone() };
+    store(&binary, &entry);
+    if let Ok(mut cache) = cache.write() {
+        cache.insert(binary, entry);
+    }
+
+    Ok(value)
+}
+
+/// Hash the contents of the given file.
+fn hash_file(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = [0u8; 16384]; // 16 kiB buffer.
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break; // Reached end of file
         }
+        hasher.write(&buffer[..bytes_read]);
     }
+    Ok(hasher.finish())
+}
 
-    // Okay, we definitely need to check the version.
-    let version = version_from_binary(&binary)?;
+/// The file in which versions are persisted: `postgresfixture/versions` in
+/// `$XDG_CACHE_HOME`, else in `~/.cache`.
+pub fn file() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .map(|dir| dir.join("postgresfixture").join("versions"))
+}
 
-    // Try to cache the version.
-    if let Ok(mut cache) = CACHE.write() {
-        cache.insert(binary, Entry { size, hash, version });
+/// Load persisted versions from the given file.
+///
+/// Each line of the file is `{size} {hash} {version} {inode} {mtime} {path}`,
+/// or `{size} {hash} {version} {path}` as written by earlier versions of this
+/// crate. Lines that cannot be parsed are ignored.
+fn load(path: &Path) -> io::Result<HashMap<PathBuf, Entry<Version>>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, ' ');
+            let size = fields.next()?.parse().ok()?;
+            let hash = fields.next()?.parse().ok()?;
+            let value = fields.next()?.parse().ok()?;
+            let rest = fields.next()?;
+            let mut fields = rest.splitn(3, ' ');
+            let stamp = match (fields.next(), fields.next(), fields.next()) {
+                (Some(inode), Some(mtime), Some(binary)) => match (inode.parse(), mtime.parse()) {
+                    (Ok(inode), Ok(mtime)) => Some((Stamp { inode, mtime }, binary)),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let (stamp, binary) = match stamp {
+                Some((stamp, binary)) => (Some(stamp), binary),
+                None => (None, rest),
+            };
+            Some((PathBuf::from(binary), Entry { size, stamp, hash, value }))
+        })
+        .collect())
+}
+
+/// Persist a version to the given file.
+///
+/// The file is rewritten – to a temporary file which is then renamed into
+/// place – while holding an exclusive lock on a neighbouring lock file, so
+/// that concurrent processes neither see partial files nor lose each other's
+/// entries. Entries for binaries that no longer exist are dropped.
+fn save(path: &Path, binary: &Path, entry: &Entry<Version>) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let lock = File::create(path.with_extension("lock"))?;
+    flock(lock.as_raw_fd(), FlockArg::LockExclusive)?;
+
+    let mut entries = match load(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+        Err(err) => return Err(err),
+    };
+    entries.retain(|binary, _| binary.exists());
+    entries.insert(binary.to_owned(), entry.clone());
+
+    let path_tmp = dir.join(format!(".versions.{}", process::id()));
+    let mut file = io::BufWriter::new(File::create(&path_tmp)?);
+    for (binary, Entry { size, stamp, hash, value }) in &entries {
+        // Paths that aren't UTF-8, or that contain newlines, are not persisted.
+        if let Some(binary) = binary.to_str().filter(|binary| !binary.contains('\n')) {
+            match stamp {
+                Some(Stamp { inode, mtime }) => {
+                    writeln!(file, "{size} {hash} {value} {inode} {mtime} {binary}")?;
+                }
+                None => writeln!(file, "{size} {hash} {value} {binary}")?,
+            }
+        }
     }
+    file.flush()?;
+    drop(file);
+    fs::rename(&path_tmp, path)
+    // The lock is released when `lock` is dropped.
+}
 
-    Ok(version)
+/// Remove persisted versions from the given file.
+///
+/// This holds the same lock as [`save`], so that a concurrent save is not
+/// half-undone.
+fn remove(path: &Path) -> io::Result<()> {
+    let lock = match File::create(path.with_extension("lock")) {
+        Ok(lock) => lock,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    flock(lock.as_raw_fd(), FlockArg::LockExclusive)?;
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
 }
 
 /// Get the version of PostgreSQL from a given PostgreSQL binary.
@@ -95,3 +323,131 @@ fn version_from_binary<P: AsRef<Path>>(binary: P) -> Result<Version, RuntimeErro
         Err(VersionError::Missing)?
     }
 }
+
+/// Get build information from a given `pg_config` binary.
+fn pg_config_from_binary<P: AsRef<Path>>(binary: P) -> Result<PgConfig, RuntimeError> {
+    let output = Command::new(binary.as_ref()).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).parse()?)
+    } else {
+        Err(RuntimeError::PgConfigError(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    use super::{cached, load, prewarm, remove, save, snapshot, Entry, Stamp};
+    use crate::runtime::strategy::{self, Strategy};
+    use crate::version::Version;
+
+    #[test]
+    fn save_and_load_versions() -> std::io::Result<()> {
+        let tempdir = tempdir::TempDir::new("cache")?;
+        let path = tempdir.path().join("cache/versions");
+        // Use binaries that exist, since entries for missing binaries are
+        // dropped when saving.
+        let binary_a = env::current_exe()?;
+        let binary_b = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let stamp = Stamp { inode: 5, mtime: 1_700_000_000_123_456_789 };
+        let entry_a = Entry {
+            size: 1,
+            stamp: Some(stamp),
+            hash: 2,
+            value: Version::Post10(15, 4),
+        };
+        let entry_b = Entry {
+            size: 3,
+            stamp: None,
+            hash: 4,
+            value: Version::Pre10(9, 6, 24),
+        };
+        save(&path, &binary_a, &entry_a)?;
+        save(&path, &binary_b, &entry_b)?;
+        save(&path, "/does/not/exist".as_ref(), &entry_b)?;
+        save(&path, &binary_b, &entry_b)?;
+        let entries = load(&path)?;
+        assert_eq!(2, entries.len());
+        assert_eq!(Some(&entry_a), entries.get(&binary_a));
+        assert_eq!(Some(&entry_b), entries.get(&binary_b));
+        Ok(())
+    }
+
+    #[test]
+    fn remove_versions() -> std::io::Result<()> {
+        let tempdir = tempdir::TempDir::new("cache")?;
+        let path = tempdir.path().join("cache/versions");
+        // Nothing to remove; not even the directory.
+        remove(&path)?;
+        let entry = Entry { size: 1, stamp: None, hash: 2, value: Version::Post10(15, 4) };
+        save(&path, &env::current_exe()?, &entry)?;
+        remove(&path)?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn prewarm_caches_versions_of_runtimes() {
+        let strategy = strategy::default();
+        assert!(prewarm(&strategy) > 0);
+        let versions = snapshot();
+        for runtime in strategy.runtimes() {
+            let pg_ctl = runtime.bindir.join("pg_ctl").canonicalize().unwrap();
+            assert_eq!(Some(&runtime.version), versions.get(&pg_ctl));
+        }
+    }
+
+    #[test]
+    fn load_versions_without_stamps() -> std::io::Result<()> {
+        let tempdir = tempdir::TempDir::new("cache")?;
+        let path = tempdir.path().join("versions");
+        fs::write(&path, "1 2 15.4 /some/where/with spaces/pg_ctl\n")?;
+        let entries = load(&path)?;
+        assert_eq!(
+            Some(&Entry { size: 1, stamp: None, hash: 2, value: Version::Post10(15, 4) }),
+            entries.get(&PathBuf::from("/some/where/with spaces/pg_ctl"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cached_recalculates_only_when_binary_changes() -> Result<(), crate::runtime::RuntimeError> {
+        let tempdir = tempdir::TempDir::new("cache")?;
+        let binary = tempdir.path().join("binary");
+        fs::write(&binary, "one")?;
+        let cache = HashMap::new().into();
+        let calculations = Cell::new(0);
+        let lookup = || {
+            cached(
+                &cache,
+                &binary,
+                |_| {
+                    calculations.set(calculations.get() + 1);
+                    Ok(fs::read_to_string(&binary)?)
+                },
+                |_, _| (),
+            )
+        };
+        assert_eq!("one", lookup()?);
+        assert_eq!("one", lookup()?);
+        assert_eq!(1, calculations.get());
+        // A new modification time alone means hashing, not recalculating.
+        let file = fs::File::options().write(true).open(&binary)?;
+        file.set_modified(SystemTime::now() - Duration::from_secs(60))?;
+        assert_eq!("one", lookup()?);
+        assert_eq!(1, calculations.get());
+        // Different contents of the same size mean recalculating.
+        fs::write(&binary, "two")?;
+        assert_eq!("two", lookup()?);
+        assert_eq!(2, calculations.get());
+        Ok(())
+    }
+}
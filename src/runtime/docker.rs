@@ -0,0 +1,412 @@
+//! Run PostgreSQL from Docker images.
+//!
+//! This is for machines that have no local PostgreSQL installation at all. A
+//! [`Runtime`] is created for a Docker image, e.g. one of the [official
+//! PostgreSQL images][official], by writing small shell scripts – shims – into
+//! a directory that then serves as the runtime's `bindir`. Each shim runs the
+//! corresponding program in a container:
+//!
+//! - When `pg_ctl start` is run for a data directory, a long-lived container is
+//!   started with the data directory mounted into it at the same path, and the
+//!   server runs in that container. The server listens on a socket in the data
+//!   directory – as with any other runtime – so clients on the host can connect
+//!   to it. `pg_ctl stop` removes the container again.
+//! - Other programs run in that container if it's running, otherwise they run
+//!   in a new container which is removed when the program exits.
+//!
+//! Containers run as the current user, with `/etc/passwd` mounted read-only so
+//! that PostgreSQL can look up the user's name. Sockets in bind-mounted
+//! directories are only usable from the host when Docker runs natively, i.e.
+//! on Linux. Elsewhere, e.g. with Docker Desktop, connect over TCP instead.
+//!
+//! When a cluster is started with [`Cluster::with_tcp`], the server's port is
+//! published on the host's loopback interface, `127.0.0.1`, and the server
+//! listens on all of the container's addresses so that the published port
+//! reaches it. The port is found in the options that [`Cluster`] passes to
+//! `pg_ctl start` with `-o`.
+//!
+//! This module is only available with the `docker` feature.
+//!
+//! [official]: https://hub.docker.com/_/postgres
+//! [`Cluster`]: crate::cluster::Cluster
+//! [`Cluster::with_tcp`]: crate::cluster::Cluster::with_tcp
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use super::{strategy::Runtimes, Runtime, RuntimeError, Strategy};
+use crate::util;
+use crate::version;
+
+/// The programs for which shims are written.
+pub const PROGRAMS: &[&str] = &[
+    "createdb",
+    "dropdb",
+    "initdb",
+    "pg_basebackup",
+    "pg_config",
+    "pg_ctl",
+    "pg_dump",
+    "pg_dumpall",
+    "pg_isready",
+    "pg_restore",
+    "pg_upgrade",
+    "postgres",
+    "psql",
+    "vacuumdb",
+];
+
+/// The shim template. `@IMAGE@` and `@PROGRAM@` are replaced with shell-quoted
+/// values when writing a shim.
+const SHIM: &str = r#"#!/bin/sh
+# Generated by postgresfixture: run @PROGRAM@ from the @IMAGE@ Docker image.
+image=@IMAGE@
+program=@PROGRAM@
+docker="${POSTGRESFIXTURE_DOCKER:-docker}"
+user="$(id -u):$(id -g)"
+tty=; [ -t 0 ] && [ -t 1 ] && tty=--tty
+action=
+if [ "$program" = pg_ctl ]; then
+  for arg; do
+    case "$arg" in start|stop) action="$arg";; esac
+  done
+fi
+# The TCP port in the given postgres options, if it listens on TCP at all.
+tcp_port() {
+  eval "set -- $1"
+  host= port=
+  while [ $# -gt 0 ]; do
+    case "$1" in
+      -h) host="${2-}"; [ $# -gt 1 ] && shift;;
+      -p) port="${2-}"; [ $# -gt 1 ] && shift;;
+    esac
+    shift
+  done
+  [ -n "$host" ] && printf '%s' "$port"
+}
+publish=
+if [ "$action" = start ]; then
+  prev=
+  for arg; do
+    if [ "$prev" = -o ]; then
+      port="$(tcp_port "$arg")"
+      [ -n "$port" ] && publish="--publish=127.0.0.1:$port:$port"
+    fi
+    prev="$arg"
+  done
+  # Listen on all of the container's addresses so the published port works.
+  [ -n "$publish" ] && set -- "$@" -o "-c listen_addresses='*'"
+fi
+running=
+if [ -n "$PGDATA" ]; then
+  name="postgresfixture-$(printf '%s' "$PGDATA" | cksum | cut -d ' ' -f 1)"
+  running="$("$docker" inspect -f '{{.State.Running}}' "$name" 2>/dev/null)"
+  if [ "$action" = start ] && [ "$running" != true ]; then
+    "$docker" rm -f "$name" >/dev/null 2>&1
+    "$docker" run --detach --init --name "$name" --user "$user" $publish \
+      --volume /etc/passwd:/etc/passwd:ro --volume "$PGDATA:$PGDATA" \
+      --entrypoint sleep "$image" infinity >/dev/null || exit
+    running=true
+  fi
+fi
+if [ "$running" = true ]; then
+  "$docker" exec --interactive $tty --user "$user" \
+    --env PGDATA --env PGHOST --env PGDATABASE --env PGUSER \
+    "$name" "$program" "$@"
+  status=$?
+  if [ "$action" = stop ] && [ $status -eq 0 ]; then
+    "$docker" rm -f "$name" >/dev/null 2>&1
+  fi
+  exit $status
+fi
+set -- --entrypoint "$program" "$image" "$@"
+if [ -n "$PGHOST" ] && [ "$PGHOST" != "$PGDATA" ] && [ -d "$PGHOST" ]; then
+  set -- --volume "$PGHOST:$PGHOST" "$@"
+fi
+if [ -n "$PGDATA" ]; then
+  set -- --volume "$PGDATA:$PGDATA" "$@"
+fi
+exec "$docker" run --rm --interactive $tty --user "$user" \
+  --volume /etc/passwd:/etc/passwd:ro \
+  --env PGDATA --env PGHOST --env PGDATABASE --env PGUSER "$@"
+"#;
+
+/// The default directory in which to write shims: `postgresfixture/docker` in
+/// `$XDG_CACHE_HOME`, else in `~/.cache`, else in the system's temporary
+/// directory.
+pub fn default_dir() -> PathBuf {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(env::temp_dir)
+        .join("postgresfixture")
+        .join("docker")
+}
+
+/// Create `dir`, accessible only to the current user, if it does not exist.
+/// Refuse to use it if it's owned by another user: the shims in it are run
+/// with the current user's privileges, so nobody else may be able to replace
+/// them.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    let owner = fs::metadata(dir)?.uid();
+    if owner == nix::unistd::geteuid().as_raw() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is owned by user ID {owner}", dir.display()),
+        ))
+    }
+}
+
+/// Write shims for the given Docker `image` into `bindir`.
+///
+/// Each shim is written to a temporary file and then renamed into place, so
+/// it's safe for concurrent processes, or threads, to write the same shims.
+pub fn write_shims<P: AsRef<Path>>(bindir: P, image: &str) -> io::Result<()> {
+    let bindir = bindir.as_ref();
+    fs::create_dir_all(bindir)?;
+    // Quote for POSIX shells; each single quote becomes '\''.
+    let image_quoted = format!("'{}'", image.replace('\'', r"'\''"));
+    for program in PROGRAMS {
+        let shim = SHIM
+            .replace("@IMAGE@", &image_quoted)
+            .replace("@PROGRAM@", program);
+        let path = bindir.join(program);
+        let path_tmp = bindir.join(util::temp_name(&format!(".{program}")));
+        let mut file = fs::File::create(&path_tmp)?;
+        file.write_all(shim.as_bytes())?;
+        file.set_permissions(fs::Permissions::from_mode(0o755))?;
+        drop(file);
+        fs::rename(&path_tmp, &path)?;
+    }
+    Ok(())
+}
+
+/// Find runtimes in Docker images.
+///
+/// By default this uses the official `postgres` images. Runtimes for the given
+/// tags are always available, subject to Docker being able to pull them. When
+/// selecting a runtime for a version that's not covered by those tags, this
+/// will try the image tagged with that version's major number, e.g.
+/// `postgres:15` for a cluster created by PostgreSQL 15.
+#[derive(Clone, Debug)]
+pub struct RuntimesFromDocker {
+    repository: String,
+    tags: Vec<String>,
+    dir: PathBuf,
+}
+
+impl RuntimesFromDocker {
+    /// Use images from the official `postgres` repository with the given tags,
+    /// in order of preference, e.g. `["16", "15"]`. The first tag is used as
+    /// the fallback runtime; if there are no tags, `latest` is used.
+    pub fn new<I, T>(tags: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        Self {
+            repository: "postgres".into(),
+            tags: tags.into_iter().map(Into::into).collect(),
+            dir: default_dir(),
+        }
+    }
+
+    /// Use images from the given repository rather than `postgres`.
+    #[must_use]
+    pub fn with_repository<R: Into<String>>(mut self, repository: R) -> Self {
+        self.repository = repository.into();
+        self
+    }
+
+    /// Write shims into subdirectories of the given directory rather than
+    /// [`default_dir`]. Either way, the directory is created, accessible only
+    /// to the current user, if it does not exist, and must be owned by the
+    /// current user.
+    #[must_use]
+    pub fn with_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    /// The full name of the image with the given tag.
+    pub fn image(&self, tag: &str) -> String {
+        format!("{}:{tag}", self.repository)
+    }
+
+    /// The directory in which shims for the given tag are written.
+    pub fn bindir(&self, tag: &str) -> PathBuf {
+        let name: String = self
+            .image(tag)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.dir.join(name).join("bin")
+    }
+
+    /// Create a runtime for the image with the given tag.
+    ///
+    /// This writes the shims, then runs `pg_ctl --version` in a container to
+    /// determine the version of the runtime – which may involve pulling the
+    /// image.
+    pub fn runtime(&self, tag: &str) -> Result<Runtime, RuntimeError> {
+        create_private_dir(&self.dir)?;
+        let bindir = self.bindir(tag);
+        write_shims(&bindir, &self.image(tag))?;
+        Runtime::new(bindir)
+    }
+}
+
+impl Strategy for RuntimesFromDocker {
+    fn runtimes(&self) -> Runtimes<'_> {
+        Box::new(
+            self.tags
+                .iter()
+                // Throw away runtimes that we can't determine the version for.
+                .filter_map(|tag| self.runtime(tag).ok()),
+        )
+    }
+
+    /// The highest compatible runtime from the configured tags, else the
+    /// runtime tagged with the given version's major number.
+    fn select(&self, version: &version::PartialVersion) -> Option<Runtime> {
+        self.runtimes()
+            .filter(|runtime| version.compatible(runtime.version))
+            .max_by(|ra, rb| ra.version.cmp(&rb.version))
+            .or_else(|| {
+                self.runtime(&version.widened().to_string())
+                    .ok()
+                    .filter(|runtime| version.compatible(runtime.version))
+            })
+    }
+
+    /// The runtime for the first tag, else for `latest`.
+    fn fallback(&self) -> Option<Runtime> {
+        self.runtime(self.tags.first().map_or("latest", String::as_str))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::{create_private_dir, write_shims, RuntimesFromDocker, PROGRAMS};
+
+    #[test]
+    fn write_shims_writes_executable_scripts() -> std::io::Result<()> {
+        let tempdir = tempdir::TempDir::new("docker")?;
+        let bindir = tempdir.path().join("bin");
+        write_shims(&bindir, "postgres:16 'quoted'")?;
+        for program in PROGRAMS {
+            let shim = bindir.join(program);
+            let mode = fs::metadata(&shim)?.permissions().mode();
+            assert_eq!(0o755, mode & 0o777);
+            let script = fs::read_to_string(&shim)?;
+            assert!(script.starts_with("#!/bin/sh\n"));
+            assert!(script.contains(&format!("\nprogram={program}\n")));
+            assert!(script.contains(r"image='postgres:16 '\''quoted'\'''"));
+        }
+        // Writing again, i.e. replacing the shims, is fine.
+        write_shims(&bindir, "postgres:16")?;
+        Ok(())
+    }
+
+    #[test]
+    fn create_private_dir_creates_dir_for_current_user_only() -> std::io::Result<()> {
+        let tempdir = tempdir::TempDir::new("docker")?;
+        let dir = tempdir.path().join("shims");
+        create_private_dir(&dir)?;
+        assert_eq!(0o700, fs::metadata(&dir)?.permissions().mode() & 0o777);
+        // An existing directory owned by the current user is fine.
+        create_private_dir(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn create_private_dir_refuses_dir_owned_by_another_user() {
+        // The root directory is owned by root; skip if that's us.
+        if nix::unistd::geteuid().is_root() {
+            return;
+        }
+        let err = create_private_dir("/".as_ref()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
+    /// Run the `pg_ctl` shim with a fake `docker` that records its arguments,
+    /// one invocation per line.
+    fn run_pg_ctl_shim(options: &str) -> std::io::Result<Vec<String>> {
+        let tempdir = tempdir::TempDir::new("docker")?;
+        let bindir = tempdir.path().join("bin");
+        write_shims(&bindir, "postgres:16")?;
+        let docker = tempdir.path().join("docker");
+        fs::write(
+            &docker,
+            "#!/bin/sh\n[ \"$1\" = inspect ] && exit 1\necho \"$*\" >> \"$LOG\"\n",
+        )?;
+        fs::set_permissions(&docker, fs::Permissions::from_mode(0o755))?;
+        let log = tempdir.path().join("log");
+        let status = std::process::Command::new(bindir.join("pg_ctl"))
+            .args(["start", "-w", "-o", options])
+            .env("POSTGRESFIXTURE_DOCKER", &docker)
+            .env("PGDATA", tempdir.path().join("data"))
+            .env("LOG", &log)
+            .status()?;
+        assert!(status.success());
+        Ok(fs::read_to_string(&log)?.lines().map(Into::into).collect())
+    }
+
+    #[test]
+    fn pg_ctl_shim_publishes_tcp_port() -> std::io::Result<()> {
+        let calls = run_pg_ctl_shim("-h 'localhost' -p 5433 -k '/some/where'")?;
+        assert_eq!(3, calls.len(), "{calls:?}");
+        assert!(calls[1].starts_with("run --detach"), "{calls:?}");
+        assert!(
+            calls[1].contains(" --publish=127.0.0.1:5433:5433 "),
+            "{calls:?}"
+        );
+        assert!(calls[2].starts_with("exec "), "{calls:?}");
+        assert!(
+            calls[2].ends_with(" -o -c listen_addresses='*'"),
+            "{calls:?}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pg_ctl_shim_does_not_publish_without_tcp() -> std::io::Result<()> {
+        let calls = run_pg_ctl_shim("-h '' -k '/some/where'")?;
+        assert_eq!(3, calls.len(), "{calls:?}");
+        assert!(!calls[1].contains("--publish"), "{calls:?}");
+        assert!(!calls[2].contains("listen_addresses"), "{calls:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn bindir_is_named_for_image() {
+        let strategy = RuntimesFromDocker::new(["16"])
+            .with_repository("example.com/pg")
+            .with_dir("/somewhere");
+        assert_eq!("example.com/pg:16", strategy.image("16"));
+        assert_eq!(
+            std::path::Path::new("/somewhere/example.com_pg_16/bin"),
+            strategy.bindir("16")
+        );
+    }
+}
@@ -0,0 +1,539 @@
+//! Download prebuilt PostgreSQL binaries.
+//!
+//! This fetches the archives published by the [embedded-postgres-binaries]
+//! project to Maven Central, verifies their checksums, and unpacks them into a
+//! cache directory, from where they can be used as any other [`Runtime`]. This
+//! means that, for example, CI can use this crate without any PostgreSQL
+//! installed on the system.
+//!
+//! Each archive is checked against the SHA-1 checksum published alongside it.
+//! This is **not** a security check: the checksum comes from the same place as
+//! the archive, so it catches a corrupt or truncated download but not one that
+//! has been tampered with. To guard against tampering, pin the SHA-256
+//! checksum of each version you use with [`Downloader::with_sha256`]; nothing
+//! is pinned by default.
+//!
+//! ```rust,no_run
+//! # use postgresfixture::runtime::{download::Downloader, Strategy};
+//! # use postgresfixture::version::Version;
+//! let downloader = Downloader::new();
+//! let runtime = downloader.install(Version::Post10(15, 4))?;
+//! // Or, as a strategy that downloads on demand:
+//! let runtime = downloader.select(&"14".parse()?);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! This module is only available with the `download` feature.
+//!
+//! [embedded-postgres-binaries]: https://github.com/zonkyio/embedded-postgres-binaries
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use sha1::Digest;
+
+use super::{strategy::Runtimes, Runtime, RuntimeError, Strategy};
+use crate::util;
+use crate::version::{PartialVersion, Version};
+
+/// The default base URL from which to download binaries.
+pub const MAVEN_CENTRAL: &str = "https://repo1.maven.org/maven2";
+
+/// The Maven group that binaries are published under, as a path.
+const GROUP: &str = "io/zonky/test/postgres";
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum DownloadError {
+    #[error("input/output error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("download error: {0}")]
+    HttpError(#[source] Box<ureq::Error>),
+    #[error("archive error: {0}")]
+    ArchiveError(#[from] zip::result::ZipError),
+    #[error("{0}")]
+    RuntimeError(#[from] RuntimeError),
+    /// The downloaded archive did not have the expected checksum.
+    #[error("checksum mismatch for {url}: expected {expected}, got {actual}")]
+    ChecksumMismatch { url: String, expected: String, actual: String },
+    /// There are no binaries for this platform.
+    #[error("no PostgreSQL binaries for this platform")]
+    UnsupportedPlatform,
+    /// The downloaded archive did not contain PostgreSQL binaries.
+    #[error("no PostgreSQL binaries found in {0}")]
+    BinariesNotFound(String),
+    /// No binaries are available for the requested version.
+    #[error("no PostgreSQL binaries for version {0}")]
+    VersionNotFound(PartialVersion),
+}
+
+impl From<ureq::Error> for DownloadError {
+    fn from(error: ureq::Error) -> DownloadError {
+        DownloadError::HttpError(Box::new(error))
+    }
+}
+
+/// The platform name used by embedded-postgres-binaries for the current
+/// platform, e.g. `linux-amd64`, if binaries are published for it.
+pub fn platform() -> Option<&'static str> {
+    Some(match (env::consts::OS, env::consts::ARCH) {
+        ("linux", "x86_64") if cfg!(target_env = "musl") => "alpine-amd64",
+        ("linux", "aarch64") if cfg!(target_env = "musl") => "alpine-arm64v8",
+        ("linux", "x86_64") => "linux-amd64",
+        ("linux", "x86") => "linux-i386",
+        ("linux", "aarch64") => "linux-arm64v8",
+        ("linux", "arm") => "linux-arm32v7",
+        ("linux", "powerpc64") => "linux-ppc64le",
+        ("macos", "x86_64") => "darwin-amd64",
+        ("macos", "aarch64") => "darwin-arm64v8",
+        ("windows", "x86_64") => "windows-amd64",
+        ("windows", "x86") => "windows-i386",
+        _ => return None,
+    })
+}
+
+/// The default directory into which binaries are unpacked:
+/// `postgresfixture/runtimes` in `$XDG_CACHE_HOME`, else in `~/.cache`, else in
+/// the system's temporary directory.
+pub fn default_dir() -> PathBuf {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(env::temp_dir)
+        .join("postgresfixture")
+        .join("runtimes")
+}
+
+/// The version string used by embedded-postgres-binaries, e.g. `15.4.0` for
+/// PostgreSQL 15.4, or `9.6.24` for PostgreSQL 9.6.24.
+fn artifact_version(version: Version) -> String {
+    match version {
+        Version::Pre10(a, b, c) => format!("{a}.{b}.{c}"),
+        Version::Post10(a, b) => format!("{a}.{b}.0"),
+        // Prereleases are not published, but this is at least unambiguous.
+        Version::Post10Pre(a, pre) => format!("{a}.0.0-{pre}"),
+    }
+}
+
+/// Parse a version string used by embedded-postgres-binaries.
+fn parse_artifact_version(version: &str) -> Option<Version> {
+    let mut parts = version.split('.').map(str::parse::<u32>);
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(a)), Some(Ok(b)), Some(Ok(c)), None) if a < 10 => Some(Version::Pre10(a, b, c)),
+        (Some(Ok(a)), Some(Ok(b)), Some(Ok(0)), None) if a >= 10 => Some(Version::Post10(a, b)),
+        _ => None,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+    bytes.iter().fold(String::new(), |mut out, byte| {
+        let _ = write!(out, "{byte:02x}");
+        out
+    })
+}
+
+/// Download PostgreSQL binaries and install them into a cache directory.
+///
+/// This is also a [`Strategy`]: it knows about runtimes that have already been
+/// installed and, when asked to select a runtime, or for a fallback runtime,
+/// it will download one if necessary.
+#[derive(Clone, Debug)]
+pub struct Downloader {
+    base_url: String,
+    dir: PathBuf,
+    platform: Option<String>,
+    sha256: HashMap<Version, String>,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Self {
+            base_url: MAVEN_CENTRAL.into(),
+            dir: default_dir(),
+            platform: platform().map(Into::into),
+            sha256: HashMap::new(),
+        }
+    }
+}
+
+impl Downloader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Download from a Maven repository at the given URL rather than from
+    /// [`MAVEN_CENTRAL`], e.g. a mirror. A `file://` URL refers to a repository
+    /// on the local filesystem.
+    #[must_use]
+    pub fn with_base_url<U: Into<String>>(mut self, base_url: U) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Install into the given directory rather than [`default_dir`].
+    #[must_use]
+    pub fn with_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    /// Download binaries for the given platform, e.g. `linux-amd64`, rather
+    /// than for the current platform.
+    #[must_use]
+    pub fn with_platform<P: Into<String>>(mut self, platform: P) -> Self {
+        self.platform = Some(platform.into());
+        self
+    }
+
+    /// Require that the archive for the given version has the given SHA-256
+    /// checksum (in hex). Archives are always checked against the SHA-1
+    /// checksum published alongside them, but that comes from the same origin
+    /// as the archive so it only catches corruption. This is the only check
+    /// against tampering.
+    #[must_use]
+    pub fn with_sha256<S: Into<String>>(mut self, version: Version, sha256: S) -> Self {
+        self.sha256.insert(version, sha256.into().to_lowercase());
+        self
+    }
+
+    fn platform(&self) -> Result<&str, DownloadError> {
+        self.platform
+            .as_deref()
+            .ok_or(DownloadError::UnsupportedPlatform)
+    }
+
+    /// The URL of the directory holding all versions for this platform.
+    fn artifact_url(&self) -> Result<String, DownloadError> {
+        Ok(format!(
+            "{}/{GROUP}/embedded-postgres-binaries-{}",
+            self.base_url.trim_end_matches('/'),
+            self.platform()?,
+        ))
+    }
+
+    /// The URL of the archive for the given version.
+    pub fn url(&self, version: Version) -> Result<String, DownloadError> {
+        let version = artifact_version(version);
+        Ok(format!(
+            "{}/{version}/embedded-postgres-binaries-{}-{version}.jar",
+            self.artifact_url()?,
+            self.platform()?,
+        ))
+    }
+
+    /// The directory into which the given version is installed.
+    pub fn install_dir(&self, version: Version) -> Result<PathBuf, DownloadError> {
+        Ok(self.dir.join(format!("{}-{version}", self.platform()?)))
+    }
+
+    fn fetch(url: &str) -> Result<Vec<u8>, DownloadError> {
+        if let Some(path) = url.strip_prefix("file://") {
+            Ok(fs::read(path)?)
+        } else {
+            let mut bytes = Vec::new();
+            ureq::get(url)
+                .call()?
+                .into_reader()
+                .read_to_end(&mut bytes)?;
+            Ok(bytes)
+        }
+    }
+
+    /// The versions for which binaries are available for this platform.
+    pub fn available(&self) -> Result<Vec<Version>, DownloadError> {
+        lazy_static! {
+            static ref RE: regex::Regex = regex::Regex::new(r"<version>\s*([^<\s]+)\s*</version>")
+                .expect("invalid regex (for matching versions in Maven metadata)");
+        }
+        let metadata = Self::fetch(&format!("{}/maven-metadata.xml", self.artifact_url()?))?;
+        let metadata = String::from_utf8_lossy(&metadata);
+        let mut versions: Vec<_> = RE
+            .captures_iter(&metadata)
+            .filter_map(|caps| parse_artifact_version(&caps[1]))
+            .collect();
+        versions.sort_unstable();
+        versions.dedup();
+        Ok(versions)
+    }
+
+    /// Runtimes that have already been installed.
+    pub fn installed(&self) -> Vec<Runtime> {
+        let Ok(platform) = self.platform() else {
+            return vec![];
+        };
+        let mut runtimes: Vec<_> = fs::read_dir(&self.dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .filter(|entry| {
+                        entry
+                            .file_name()
+                            .to_string_lossy()
+                            .starts_with(&format!("{platform}-"))
+                    })
+                    .filter_map(|entry| Runtime::new(entry.path().join("bin")).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        runtimes.sort_by_key(|runtime| runtime.version);
+        runtimes
+    }
+
+    /// Install the given version, if it is not already installed, and return
+    /// the installed runtime.
+    pub fn install(&self, version: Version) -> Result<Runtime, DownloadError> {
+        let dir = self.install_dir(version)?;
+        if !dir.join("bin").is_dir() {
+            let url = self.url(version)?;
+            let archive = Self::fetch(&url)?;
+            self.verify(&url, version, &archive)?;
+            self.unpack(&url, &archive, &dir)?;
+        }
+        Ok(Runtime::new(dir.join("bin"))?)
+    }
+
+    /// Install the highest available version that is compatible with the
+    /// given version.
+    pub fn install_compatible(&self, version: &PartialVersion) -> Result<Runtime, DownloadError> {
+        match self
+            .available()?
+            .into_iter()
+            .filter(|available| version.compatible(*available))
+            .max()
+        {
+            Some(available) => self.install(available),
+            None => Err(DownloadError::VersionNotFound(*version)),
+        }
+    }
+
+    fn verify(&self, url: &str, version: Version, archive: &[u8]) -> Result<(), DownloadError> {
+        let sha1 = Self::fetch(&format!("{url}.sha1"))?;
+        let expected = String::from_utf8_lossy(&sha1)
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let actual = hex(&sha1::Sha1::digest(archive));
+        if expected != actual {
+            return Err(DownloadError::ChecksumMismatch { url: url.into(), expected, actual });
+        }
+        if let Some(expected) = self.sha256.get(&version) {
+            let actual = hex(&sha2::Sha256::digest(archive));
+            if *expected != actual {
+                return Err(DownloadError::ChecksumMismatch {
+                    url: url.into(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Unpack the `.txz` inside the given archive into `dir`. This unpacks into
+    /// a temporary directory first, then renames it into place, so concurrent
+    /// installs of the same version, by other processes or by other threads in
+    /// this process, are safe.
+    fn unpack(&self, url: &str, archive: &[u8], dir: &Path) -> Result<(), DownloadError> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive))?;
+        let name = archive
+            .file_names()
+            .find(|name| {
+                Path::new(name)
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("txz"))
+            })
+            .map(ToOwned::to_owned)
+            .ok_or_else(|| DownloadError::BinariesNotFound(url.into()))?;
+        let txz = archive.by_name(&name)?;
+        fs::create_dir_all(&self.dir)?;
+        let dir_tmp = self.dir.join(util::temp_name(".install"));
+        fs::create_dir(&dir_tmp)?;
+        // We created `dir_tmp`, so it's ours to remove if anything goes wrong.
+        let unpacked = tar::Archive::new(xz2::read::XzDecoder::new(txz))
+            .unpack(&dir_tmp)
+            .and_then(|()| fs::rename(&dir_tmp, dir));
+        match unpacked {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                let _ = fs::remove_dir_all(&dir_tmp);
+                if dir.join("bin").is_dir() {
+                    // Another process or thread installed this version first.
+                    Ok(())
+                } else {
+                    Err(err)?
+                }
+            }
+        }
+    }
+}
+
+impl Strategy for Downloader {
+    /// Runtimes that have already been installed.
+    fn runtimes(&self) -> Runtimes<'_> {
+        Box::new(self.installed().into_iter())
+    }
+
+    /// The highest compatible runtime that has already been installed, else
+    /// the highest compatible version available for download, once
+    /// installed.
+    fn select(&self, version: &PartialVersion) -> Option<Runtime> {
+        self.installed()
+            .into_iter()
+            .filter(|runtime| version.compatible(runtime.version))
+            .max_by(|ra, rb| ra.version.cmp(&rb.version))
+            .or_else(|| self.install_compatible(version).ok())
+    }
+
+    /// The highest runtime that has already been installed, else the highest
+    /// version available for download, once installed.
+    fn fallback(&self) -> Option<Runtime> {
+        self.installed().pop().or_else(|| {
+            let latest = self.available().ok()?.pop()?;
+            self.install(latest).ok()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    use super::{artifact_version, hex, parse_artifact_version, DownloadError, Downloader};
+    use crate::runtime::Strategy;
+    use crate::version::Version;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn artifact_versions_round_trip() {
+        for version in [Version::Pre10(9, 6, 24), Version::Post10(15, 4)] {
+            let artifact = artifact_version(version);
+            assert_eq!(Some(version), parse_artifact_version(&artifact));
+        }
+        assert_eq!("15.4.0", artifact_version(Version::Post10(15, 4)));
+        assert_eq!(None, parse_artifact_version("15.4.1"));
+        assert_eq!(None, parse_artifact_version("15.4"));
+    }
+
+    /// Create a fake Maven repository containing a "PostgreSQL" whose `pg_ctl`
+    /// only knows how to report its version.
+    fn fake_repository(
+        base: &Path,
+        version: Version,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut tar = tar::Builder::new(xz2::write::XzEncoder::new(Vec::new(), 1));
+        let script = format!("#!/bin/sh\necho 'pg_ctl (PostgreSQL) {version}'\n");
+        let mut header = tar::Header::new_gnu();
+        header.set_size(script.len() as u64);
+        header.set_mode(0o755);
+        header.set_cksum();
+        tar.append_data(&mut header, "bin/pg_ctl", script.as_bytes())?;
+        let txz = tar.into_inner()?.finish()?;
+
+        let mut jar = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        jar.start_file("postgres-fake.txz", zip::write::FileOptions::default())?;
+        jar.write_all(&txz)?;
+        let jar = jar.finish()?.into_inner();
+
+        let artifact = artifact_version(version);
+        let dir = base.join(format!(
+            "io/zonky/test/postgres/embedded-postgres-binaries-fake/{artifact}"
+        ));
+        fs::create_dir_all(&dir)?;
+        let jar_path = dir.join(format!("embedded-postgres-binaries-fake-{artifact}.jar"));
+        fs::write(&jar_path, &jar)?;
+        fs::write(
+            jar_path.with_extension("jar.sha1"),
+            hex(&<sha1::Sha1 as sha1::Digest>::digest(&jar)),
+        )?;
+        fs::write(
+            dir.parent().unwrap().join("maven-metadata.xml"),
+            format!("<metadata><versioning><versions><version>{artifact}</version></versions></versioning></metadata>"),
+        )?;
+        Ok(hex(&<sha2::Sha256 as sha2::Digest>::digest(&jar)))
+    }
+
+    #[test]
+    fn downloader_installs_from_repository() -> TestResult {
+        let version = Version::Post10(15, 4);
+        let tempdir = tempdir::TempDir::new("download")?;
+        let repo = tempdir.path().join("repo");
+        let sha256 = fake_repository(&repo, version)?;
+        let downloader = Downloader::new()
+            .with_base_url(format!("file://{}", repo.display()))
+            .with_dir(tempdir.path().join("runtimes"))
+            .with_platform("fake")
+            .with_sha256(version, sha256);
+        assert_eq!(vec![version], downloader.available()?);
+        assert_eq!(0, downloader.runtimes().count());
+        let runtime = downloader
+            .select(&"15".parse()?)
+            .expect("runtime not installed");
+        assert_eq!(version, runtime.version);
+        assert_eq!(
+            tempdir.path().join("runtimes/fake-15.4/bin"),
+            runtime.bindir
+        );
+        let mode = fs::metadata(runtime.bindir.join("pg_ctl"))?
+            .permissions()
+            .mode();
+        assert_eq!(0o755, mode & 0o777);
+        assert_eq!(vec![runtime], downloader.runtimes().collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn downloader_installs_concurrently_from_threads() -> TestResult {
+        let version = Version::Post10(15, 4);
+        let tempdir = tempdir::TempDir::new("download")?;
+        let repo = tempdir.path().join("repo");
+        fake_repository(&repo, version)?;
+        let downloader = Downloader::new()
+            .with_base_url(format!("file://{}", repo.display()))
+            .with_dir(tempdir.path().join("runtimes"))
+            .with_platform("fake");
+        let runtimes = std::thread::scope(|scope| {
+            (0..8)
+                .map(|_| scope.spawn(|| downloader.install(version)))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().expect("install panicked"))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        assert!(runtimes.iter().all(|runtime| runtime.version == version));
+        // Nothing is left behind but the installed runtime.
+        let entries: Vec<_> = fs::read_dir(tempdir.path().join("runtimes"))?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(vec!["fake-15.4"], entries);
+        Ok(())
+    }
+
+    #[test]
+    fn downloader_verifies_checksums() -> TestResult {
+        let version = Version::Post10(15, 4);
+        let tempdir = tempdir::TempDir::new("download")?;
+        let repo = tempdir.path().join("repo");
+        fake_repository(&repo, version)?;
+        let downloader = Downloader::new()
+            .with_base_url(format!("file://{}", repo.display()))
+            .with_dir(tempdir.path().join("runtimes"))
+            .with_platform("fake")
+            .with_sha256(version, "abc123");
+        assert!(matches!(
+            downloader.install(version),
+            Err(DownloadError::ChecksumMismatch { .. })
+        ));
+        assert!(!downloader.install_dir(version)?.exists());
+        Ok(())
+    }
+}
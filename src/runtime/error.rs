@@ -1,40 +1,19 @@
-use std::{error, fmt, io};
+use std::io;
+use std::path::PathBuf;
 
 use crate::version;
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum RuntimeError {
-    IoError(io::Error),
-    VersionError(version::VersionError),
-}
-
-impl fmt::Display for RuntimeError {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        use RuntimeError::*;
-        match *self {
-            IoError(ref e) => write!(fmt, "input/output error: {e}"),
-            VersionError(ref e) => e.fmt(fmt),
-        }
-    }
-}
-
-impl error::Error for RuntimeError {
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        match *self {
-            RuntimeError::IoError(ref error) => Some(error),
-            RuntimeError::VersionError(ref error) => Some(error),
-        }
-    }
-}
-
-impl From<io::Error> for RuntimeError {
-    fn from(error: io::Error) -> RuntimeError {
-        RuntimeError::IoError(error)
-    }
-}
-
-impl From<version::VersionError> for RuntimeError {
-    fn from(error: version::VersionError) -> RuntimeError {
-        RuntimeError::VersionError(error)
-    }
+    #[error("input/output error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("{0}")]
+    VersionError(#[from] version::VersionError),
+    #[error("configuration error: {0}")]
+    ConfigError(#[from] toml::de::Error),
+    #[error("pg_config error: {0}")]
+    PgConfigError(String),
+    #[error("programs missing or not executable in {}: {}", .0.display(), .1.join(", "))]
+    MissingPrograms(PathBuf, Vec<String>),
 }
@@ -0,0 +1,172 @@
+//! Build information for a runtime, as reported by `pg_config`.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use super::RuntimeError;
+
+/// Build information for a runtime, as reported by `pg_config`.
+///
+/// This is useful when building or installing extensions against a runtime:
+/// it says where to find headers, where shared libraries go, and how the
+/// runtime was configured. Keys are those printed by `pg_config` when run
+/// without arguments, e.g. `SHAREDIR`, `PKGLIBDIR`, or `CONFIGURE`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PgConfig {
+    values: BTreeMap<String, String>,
+}
+
+impl PgConfig {
+    /// The raw value for the given key, e.g. `CFLAGS`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// All keys and their raw values.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        self.get(key)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// Location of user executables, i.e. the runtime's `bindir`.
+    pub fn bindir(&self) -> Option<PathBuf> {
+        self.path("BINDIR")
+    }
+
+    /// Location of C header files for client interfaces.
+    pub fn includedir(&self) -> Option<PathBuf> {
+        self.path("INCLUDEDIR")
+    }
+
+    /// Location of C header files for the server, i.e. for extensions.
+    pub fn includedir_server(&self) -> Option<PathBuf> {
+        self.path("INCLUDEDIR-SERVER")
+    }
+
+    /// Location of object code libraries.
+    pub fn libdir(&self) -> Option<PathBuf> {
+        self.path("LIBDIR")
+    }
+
+    /// Location of dynamically loadable modules, i.e. where the shared
+    /// libraries for extensions are installed.
+    pub fn pkglibdir(&self) -> Option<PathBuf> {
+        self.path("PKGLIBDIR")
+    }
+
+    /// Location of architecture-independent support files, e.g. extension
+    /// control and script files, which are in its `extension` subdirectory.
+    pub fn sharedir(&self) -> Option<PathBuf> {
+        self.path("SHAREDIR")
+    }
+
+    /// Location of the extension makefile, `pgxs.mk`.
+    pub fn pgxs(&self) -> Option<PathBuf> {
+        self.path("PGXS")
+    }
+
+    /// The options given to `configure` when PostgreSQL was built, e.g.
+    /// `["--prefix=/usr", "--with-openssl"]`.
+    pub fn configure(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        let mut flag = String::new();
+        let mut quoted = false;
+        for c in self.get("CONFIGURE").unwrap_or_default().chars() {
+            match c {
+                '\'' => quoted = !quoted,
+                c if c.is_whitespace() && !quoted => {
+                    if !flag.is_empty() {
+                        flags.push(std::mem::take(&mut flag));
+                    }
+                }
+                c => flag.push(c),
+            }
+        }
+        if !flag.is_empty() {
+            flags.push(flag);
+        }
+        flags
+    }
+}
+
+impl FromStr for PgConfig {
+    type Err = RuntimeError;
+
+    /// Parse the output of `pg_config` run without arguments, i.e. lines of
+    /// the form `KEY = value`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| match line.split_once('=') {
+                Some((key, value)) => Ok((key.trim().to_owned(), value.trim().to_owned())),
+                None => Err(RuntimeError::PgConfigError(format!(
+                    "unexpected output: {line:?}"
+                ))),
+            })
+            .collect::<Result<BTreeMap<_, _>, _>>()?;
+        Ok(Self { values })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{PgConfig, RuntimeError};
+
+    const OUTPUT: &str = "\
+BINDIR = /usr/lib/postgresql/15/bin
+INCLUDEDIR = /usr/include/postgresql
+INCLUDEDIR-SERVER = /usr/include/postgresql/15/server
+PKGLIBDIR = /usr/lib/postgresql/15/lib
+SHAREDIR = /usr/share/postgresql/15
+CONFIGURE =  '--prefix=/usr' '--with-extra-version= (Debian 15.4-1)' '--with-openssl' 'CFLAGS=-g -O2'
+LDFLAGS_EX =
+VERSION = PostgreSQL 15.4
+";
+
+    #[test]
+    fn parse_pg_config_output() -> Result<(), RuntimeError> {
+        let config: PgConfig = OUTPUT.parse()?;
+        assert_eq!(
+            Some(PathBuf::from("/usr/share/postgresql/15")),
+            config.sharedir()
+        );
+        assert_eq!(
+            Some(PathBuf::from("/usr/lib/postgresql/15/lib")),
+            config.pkglibdir()
+        );
+        assert_eq!(
+            Some(PathBuf::from("/usr/include/postgresql/15/server")),
+            config.includedir_server()
+        );
+        assert_eq!(None, config.libdir());
+        assert_eq!(Some(""), config.get("LDFLAGS_EX"));
+        assert_eq!(Some("PostgreSQL 15.4"), config.get("VERSION"));
+        assert_eq!(
+            vec![
+                "--prefix=/usr",
+                "--with-extra-version= (Debian 15.4-1)",
+                "--with-openssl",
+                "CFLAGS=-g -O2",
+            ],
+            config.configure()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_pg_config_garbage() {
+        assert!(matches!(
+            "what is this".parse::<PgConfig>(),
+            Err(RuntimeError::PgConfigError(_))
+        ));
+    }
+}
@@ -1,10 +1,19 @@
+mod asdf;
+mod config;
+mod pgenv;
+
+use std::collections::HashMap;
 use std::env;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use crate::version;
 
-use super::Runtime;
+use super::{Runtime, RuntimeError};
+pub use asdf::RuntimesFromAsdf;
+pub use config::{Config, ConfigRuntime, RuntimesFromConfig};
+pub use pgenv::RuntimesFromPgenv;
 
 pub type Runtimes<'a> = Box<dyn Iterator<Item = Runtime> + 'a>;
 
@@ -22,7 +31,16 @@ pub type Runtimes<'a> = Box<dyn Iterator<Item = Runtime> + 'a>;
 /// A good place to start is [`default()`]. It might do what you need.
 pub trait Strategy: std::panic::RefUnwindSafe + 'static {
     /// Find all runtimes that this strategy knows about.
-    fn runtimes(&self) -> Runtimes;
+    fn runtimes(&self) -> Runtimes<'_>;
+
+    /// Find all installations that this strategy knows about, including those
+    /// that [`Self::runtimes`] leaves out as duplicates. This is intended for
+    /// diagnostics, e.g. to see every installation that was discovered.
+    ///
+    /// The default implementation returns [`Self::runtimes`].
+    fn installations(&self) -> Runtimes<'_> {
+        self.runtimes()
+    }
 
     /// Determine the most appropriate runtime known to this strategy for the
     /// given version constraint.
@@ -36,6 +54,19 @@ pub trait Strategy: std::panic::RefUnwindSafe + 'static {
             .max_by(|ra, rb| ra.version.cmp(&rb.version))
     }
 
+    /// Determine the most appropriate runtime known to this strategy for the
+    /// given [`VersionConstraint`][`version::VersionConstraint`], e.g. the
+    /// range of versions that an application supports.
+    ///
+    /// The default implementation narrows the list of runtimes to those that
+    /// match the given constraint, then chooses the one with the highest
+    /// version number. It might return [`None`].
+    fn select_matching(&self, constraint: &version::VersionConstraint) -> Option<Runtime> {
+        self.runtimes()
+            .filter(|runtime| constraint.matches(runtime.version))
+            .max_by(|ra, rb| ra.version.cmp(&rb.version))
+    }
+
     /// The runtime to use when there are no version constraints, e.g. when
     /// creating a new cluster.
     ///
@@ -49,7 +80,9 @@ pub trait Strategy: std::panic::RefUnwindSafe + 'static {
 /// Find runtimes on a given path, or on `PATH` (from the environment).
 ///
 /// Parses input according to platform conventions for the `PATH` environment
-/// variable. See [`env::split_paths`] for details.
+/// variable. See [`env::split_paths`] for details. Directories that contain
+/// `pg_ctl` but not the server programs, e.g. from a client-only package, are
+/// ignored.
 #[derive(Clone, Debug)]
 pub enum RuntimesOnPath {
     /// Find runtimes on the given path.
@@ -61,7 +94,7 @@ pub enum RuntimesOnPath {
 impl RuntimesOnPath {
     fn find_on_path<T: AsRef<OsStr> + ?Sized>(path: &T) -> Vec<PathBuf> {
         env::split_paths(path)
-            .filter(|bindir| bindir.join("pg_ctl").exists())
+            .filter(|bindir| has_server(bindir))
             .collect()
     }
 
@@ -74,143 +107,617 @@ impl RuntimesOnPath {
 }
 
 impl Strategy for RuntimesOnPath {
-    fn runtimes(&self) -> Runtimes {
+    fn runtimes(&self) -> Runtimes<'_> {
         Box::new(
-            match self {
+            probe(match self {
                 RuntimesOnPath::Custom(path) => Self::find_on_path(path),
                 RuntimesOnPath::Env => Self::find_on_env_path(),
-            }
-            .into_iter()
-            // Throw away runtimes that we can't determine the version for.
-            .filter_map(|bindir| Runtime::new(bindir).ok()),
+            })
+            .into_iter(),
         )
     }
 }
 
+/// Probe the given `bin` directories for runtimes.
+///
+/// Each directory is probed on its own thread, since determining a runtime's
+/// version may mean running `pg_ctl`. Runtimes are returned in the same order
+/// as the given directories. Directories for which we can't determine the
+/// version are thrown away.
+fn probe(bindirs: Vec<PathBuf>) -> Vec<Runtime> {
+    if bindirs.len() < 2 {
+        return bindirs
+            .into_iter()
+            .filter_map(|bindir| Runtime::new(bindir).ok())
+            .collect();
+    }
+    std::thread::scope(|scope| {
+        bindirs
+            .into_iter()
+            .map(|bindir| scope.spawn(|| Runtime::new(bindir).ok()))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .filter_map(|handle| handle.join().ok().flatten())
+            .collect()
+    })
+}
+
+/// Does the given directory contain the server programs, i.e.
+/// [`Runtime::REQUIRED_PROGRAMS`]? Some client-only packages ship `pg_ctl`
+/// without the server, and those directories are not useful as runtimes.
+fn has_server(bindir: &Path) -> bool {
+    Runtime::REQUIRED_PROGRAMS.iter().all(|program| {
+        bindir
+            .join(format!("{program}{}", env::consts::EXE_SUFFIX))
+            .is_file()
+    })
+}
+
+/// Find the directories containing files matching the given glob pattern,
+/// e.g. `/usr/lib/postgresql/*/bin/pg_ctl` to find PostgreSQL `bin`
+/// directories. Directories without the server programs are ignored; see
+/// [`has_server`].
+fn find_bindirs(pattern: &str) -> Vec<PathBuf> {
+    glob::glob(pattern)
+        .ok()
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|path| path.is_file())
+                .filter_map(|path| path.parent().map(Path::to_owned))
+                .filter(|bindir| has_server(bindir))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 /// Find runtimes using platform-specific knowledge.
 ///
 /// For example:
 /// - on Debian and Ubuntu, check subdirectories of `/usr/lib/postgresql`.
-/// - on macOS, check Homebrew.
+/// - on Red Hat and Fedora, check `/usr/pgsql-*` and `/usr/lib64/pgsql`.
+/// - on macOS, check Homebrew (including the unversioned `postgresql`
+///   formula) and MacPorts.
+/// - on the BSDs, check `/usr/local`.
+/// - on Windows, check EnterpriseDB installations.
 ///
 /// More platform-specific knowledge may be added to this strategy in the
 /// future.
-#[derive(Clone, Debug)]
-pub struct RuntimesOnPlatform;
+///
+/// Additional glob patterns matching `bin` directories, e.g.
+/// `/opt/postgres/*/bin`, can be given with [`RuntimesOnPlatform::with_globs`]
+/// or in the `POSTGRESFIXTURE_RUNTIME_GLOBS` environment variable, separated
+/// in the same way as `PATH`. Runtimes matching these patterns are found
+/// before those found using platform-specific knowledge.
+#[derive(Clone, Debug, Default)]
+pub struct RuntimesOnPlatform {
+    globs: Vec<String>,
+}
 
 impl RuntimesOnPlatform {
+    /// The environment variable from which additional glob patterns are read.
+    pub const GLOBS_VAR: &'static str = "POSTGRESFIXTURE_RUNTIME_GLOBS";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also find runtimes in `bin` directories matching the given glob
+    /// patterns, e.g. `/opt/postgres/*/bin`.
+    #[must_use]
+    pub fn with_globs<I, T>(mut self, globs: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.globs.extend(globs.into_iter().map(Into::into));
+        self
+    }
+
+    /// The additional glob patterns to search: those given to
+    /// [`Self::with_globs`], then those in [`Self::GLOBS_VAR`].
+    pub fn globs(&self) -> Vec<String> {
+        let mut globs = self.globs.clone();
+        if let Some(value) = env::var_os(Self::GLOBS_VAR) {
+            globs.extend(
+                env::split_paths(&value)
+                    .filter(|glob| !glob.as_os_str().is_empty())
+                    .map(|glob| glob.to_string_lossy().into_owned()),
+            );
+        }
+        globs
+    }
+
+    /// Find the `bin` directories matching [`Self::globs`], then those found
+    /// using platform-specific knowledge, without duplicates.
+    pub fn find_all(&self) -> Vec<PathBuf> {
+        let mut bindirs: Vec<PathBuf> = Vec::new();
+        let found = self
+            .globs()
+            .into_iter()
+            .flat_map(|glob| find_bindirs(&Path::new(&glob).join("pg_ctl").to_string_lossy()))
+            .chain(Self::find());
+        for bindir in found {
+            if !bindirs.contains(&bindir) {
+                bindirs.push(bindir);
+            }
+        }
+        bindirs
+    }
+
     /// Find runtimes using platform-specific knowledge (Linux).
     ///
-    /// For example: on Debian and Ubuntu, check `/usr/lib/postgresql`.
+    /// For example:
+    /// - on Debian and Ubuntu, check `/usr/lib/postgresql`.
+    /// - on Red Hat and derivatives using PGDG packages, check `/usr/pgsql-*`.
+    /// - on Fedora, check `/usr/lib64/pgsql` (e.g. `postgresql-upgrade`).
     #[cfg(any(doc, target_os = "linux"))]
     pub fn find() -> Vec<PathBuf> {
-        glob::glob("/usr/lib/postgresql/*/bin/pg_ctl")
-            .ok()
-            .map(|entries| {
-                entries
-                    .filter_map(Result::ok)
-                    .filter(|path| path.is_file())
-                    .filter_map(|path| path.parent().map(Path::to_owned))
-                    .collect()
-            })
-            .unwrap_or_default()
+        [
+            "/usr/lib/postgresql/*/bin/pg_ctl",
+            "/usr/pgsql-*/bin/pg_ctl",
+            "/usr/lib64/pgsql/*/bin/pg_ctl",
+        ]
+        .into_iter()
+        .flat_map(find_bindirs)
+        .collect()
     }
 
     /// Find runtimes using platform-specific knowledge (macOS).
     ///
-    /// For example: check Homebrew.
+    /// For example: check Homebrew, then MacPorts.
     #[cfg(any(doc, target_os = "macos"))]
     pub fn find() -> Vec<PathBuf> {
+        let mut bindirs = Self::find_homebrew();
+        bindirs.extend(find_bindirs("/opt/local/lib/postgresql*/bin/pg_ctl"));
+        bindirs
+    }
+
+    /// Find runtimes installed by Homebrew (macOS).
+    ///
+    /// The Homebrew prefix is taken from `HOMEBREW_PREFIX` if it's set, else
+    /// from `brew --prefix`. Both versioned formulae, e.g. `postgresql@15`, and
+    /// the unversioned `postgresql` formula are found. The stable symlinks in
+    /// `opt`, e.g. `opt/postgresql@15/bin`, are preferred since they survive
+    /// upgrades; installations in `Cellar` are found only when there's no such
+    /// symlink to them.
+    #[cfg(any(doc, target_os = "macos"))]
+    fn find_homebrew() -> Vec<PathBuf> {
         use std::ffi::OsString;
         use std::os::unix::ffi::OsStringExt;
 
-        std::process::Command::new("brew")
-            .arg("--prefix")
+        let brew_prefix = env::var_os("HOMEBREW_PREFIX")
+            .filter(|prefix| !prefix.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::process::Command::new("brew")
+                    .arg("--prefix")
+                    .output()
+                    .ok()
+                    .filter(|output| output.status.success())
+                    .map(|output| OsString::from_vec(output.stdout))
+                    .map(|prefix| PathBuf::from(prefix.to_string_lossy().trim_end()))
+            });
+        let Some(brew_prefix) = brew_prefix else {
+            return vec![];
+        };
+
+        let brew_prefix = glob::Pattern::escape(&brew_prefix.to_string_lossy());
+        let mut bindirs: Vec<PathBuf> = [
+            format!("{brew_prefix}/opt/postgresql@*/bin/pg_ctl"),
+            format!("{brew_prefix}/opt/postgresql/bin/pg_ctl"),
+        ]
+        .iter()
+        .flat_map(|pattern| find_bindirs(pattern))
+        .collect();
+        let canonical: Vec<PathBuf> = bindirs
+            .iter()
+            .filter_map(|bindir| bindir.canonicalize().ok())
+            .collect();
+        bindirs.extend(
+            [
+                format!("{brew_prefix}/Cellar/postgresql@*/*/bin/pg_ctl"),
+                format!("{brew_prefix}/Cellar/postgresql/*/bin/pg_ctl"),
+            ]
+            .iter()
+            .flat_map(|pattern| find_bindirs(pattern))
+            .filter(|bindir| {
+                bindir
+                    .canonicalize()
+                    .map_or(true, |bindir| !canonical.contains(&bindir))
+            }),
+        );
+        bindirs
+    }
+
+    /// Find runtimes using platform-specific knowledge (BSDs).
+    ///
+    /// For example: check `/usr/local/bin`, where packages install the current
+    /// version, then `/usr/local/libexec/postgresql*`, where other versions may
+    /// be installed, e.g. by packages that support `pg_upgrade`.
+    #[cfg(any(
+        doc,
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+    ))]
+    pub fn find() -> Vec<PathBuf> {
+        [
+            "/usr/local/bin/pg_ctl",
+            "/usr/local/libexec/postgresql*/pg_ctl",
+            "/usr/local/libexec/postgresql*/bin/pg_ctl",
+        ]
+        .into_iter()
+        .flat_map(find_bindirs)
+        .collect()
+    }
+
+    /// Find runtimes using platform-specific knowledge (other platforms).
+    ///
+    /// There is no platform-specific knowledge for this platform, so no
+    /// runtimes are found.
+    #[cfg(not(any(
+        doc,
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        windows,
+    )))]
+    pub fn find() -> Vec<PathBuf> {
+        vec![]
+    }
+
+    /// Find runtimes using platform-specific knowledge (Windows).
+    ///
+    /// For example: check the registry keys written by the EnterpriseDB
+    /// installer, then `%ProgramFiles%\PostgreSQL\<version>\bin`.
+    #[cfg(any(doc, windows))]
+    pub fn find() -> Vec<PathBuf> {
+        let program_files = env::var_os("ProgramFiles")
+            .map_or_else(|| PathBuf::from(r"C:\Program Files"), PathBuf::from);
+        let mut bindirs = Self::find_in_registry();
+        for bindir in find_bindirs(&Self::program_files_pattern(&program_files)) {
+            if !bindirs.contains(&bindir) {
+                bindirs.push(bindir);
+            }
+        }
+        bindirs
+    }
+
+    /// Find the `bin` directories of installations recorded in the registry by
+    /// the EnterpriseDB installer, by asking `reg` for the "Base Directory" of
+    /// each installation under `HKLM\SOFTWARE\PostgreSQL\Installations`.
+    #[cfg(any(doc, windows))]
+    fn find_in_registry() -> Vec<PathBuf> {
+        std::process::Command::new("reg")
+            .arg("query")
+            .arg(r"HKLM\SOFTWARE\PostgreSQL\Installations")
+            .arg("/s")
+            .arg("/v")
+            .arg("Base Directory")
             .output()
             .ok()
-            .and_then(|output| {
-                if output.status.success() {
-                    Some(OsString::from_vec(output.stdout))
-                } else {
-                    None
-                }
-            })
-            .and_then(|brew_prefix| {
-                glob::glob(&format!(
-                    "{}/Cellar/postgresql@*/*/bin/pg_ctl",
-                    brew_prefix.to_string_lossy().trim_end()
-                ))
-                .ok()
-            })
-            .map(|entries| {
-                entries
-                    .filter_map(Result::ok)
-                    .filter(|path| path.is_file())
-                    .filter_map(|path| path.parent().map(Path::to_owned))
+            .filter(|output| output.status.success())
+            .map(|output| {
+                Self::parse_base_directories(&String::from_utf8_lossy(&output.stdout))
+                    .into_iter()
+                    .filter(|bindir| has_server(bindir))
                     .collect()
             })
             .unwrap_or_default()
     }
+
+    /// The `bin` directories of the installations named in the output of `reg
+    /// query` for "Base Directory" values (Windows).
+    #[cfg(any(doc, test, windows))]
+    fn parse_base_directories(output: &str) -> Vec<PathBuf> {
+        // Lines look like: `    Base Directory    REG_SZ    C:\...`.
+        output
+            .lines()
+            .filter_map(|line| line.split_once("REG_SZ"))
+            .map(|(_, base)| Path::new(base.trim()).join("bin"))
+            .collect()
+    }
+
+    /// A glob pattern matching `pg_ctl.exe` in installations made by the
+    /// EnterpriseDB installer in the given `Program Files` directory (Windows).
+    #[cfg(any(doc, test, windows))]
+    fn program_files_pattern(program_files: &Path) -> String {
+        format!(
+            r"{}\PostgreSQL\*\bin\pg_ctl.exe",
+            glob::Pattern::escape(&program_files.to_string_lossy())
+        )
+    }
 }
 
 impl Strategy for RuntimesOnPlatform {
-    fn runtimes(&self) -> Runtimes {
-        Box::new(
-            Self::find()
-                .into_iter()
-                // Throw away runtimes that we can't determine the version for.
-                .filter_map(|bindir| Runtime::new(bindir).ok()),
-        )
+    fn runtimes(&self) -> Runtimes<'_> {
+        Box::new(probe(self.find_all()).into_iter())
     }
 }
 
+/// How a [`StrategySet`] chooses between runtimes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Preference {
+    /// Ask each strategy in turn; the first to answer wins. Most strategies
+    /// prefer the highest compatible version they know of.
+    #[default]
+    First,
+    /// Prefer the highest version known to any strategy. When several runtimes
+    /// have the same version, the one from the earliest strategy wins.
+    Highest,
+    /// Prefer the lowest version known to any strategy, e.g. to test against
+    /// the oldest supported version. When several runtimes have the same
+    /// version, the one from the earliest strategy wins.
+    Lowest,
+}
+
 /// Combine multiple runtime strategies, in order of preference.
-pub struct StrategySet(Vec<Box<dyn Strategy>>);
+///
+/// ```rust
+/// # use postgresfixture::runtime::strategy::{
+/// #     Preference, RuntimesOnPath, RuntimesOnPlatform, StrategySet,
+/// # };
+/// // Prefer platform installations over those on `PATH`, and prefer the
+/// // lowest compatible version.
+/// let mut strategy = StrategySet::new()
+///     .with_preference(Preference::Lowest);
+/// strategy.push_back(RuntimesOnPath::Env);
+/// strategy.push_front(RuntimesOnPlatform::new());
+/// ```
+pub struct StrategySet {
+    strategies: Vec<Box<dyn Strategy>>,
+    preference: Preference,
+}
+
+impl StrategySet {
+    /// An empty set of strategies, using [`Preference::First`]. Compare with
+    /// [`StrategySet::default`], which is populated.
+    pub fn new() -> Self {
+        Self { strategies: vec![], preference: Preference::First }
+    }
+
+    /// Choose between runtimes according to the given preference.
+    #[must_use]
+    pub fn with_preference(mut self, preference: Preference) -> Self {
+        self.preference = preference;
+        self
+    }
+
+    /// Add a strategy with the highest priority, i.e. before all others.
+    pub fn push_front<S: Strategy>(&mut self, strategy: S) {
+        self.strategies.insert(0, Box::new(strategy));
+    }
+
+    /// Add a strategy with the lowest priority, i.e. after all others.
+    pub fn push_back<S: Strategy>(&mut self, strategy: S) {
+        self.strategies.push(Box::new(strategy));
+    }
+
+    /// The preference used to choose between runtimes.
+    pub fn preference(&self) -> Preference {
+        self.preference
+    }
+
+    /// The number of strategies in this set.
+    pub fn len(&self) -> usize {
+        self.strategies.len()
+    }
+
+    /// Whether this set has no strategies.
+    pub fn is_empty(&self) -> bool {
+        self.strategies.is_empty()
+    }
+
+    /// Choose from the given runtimes according to [`Self::preference`]. This
+    /// is not normally used with [`Preference::First`], but treats it as
+    /// [`Preference::Highest`].
+    fn choose<I: Iterator<Item = Runtime>>(&self, runtimes: I) -> Option<Runtime> {
+        match self.preference {
+            // `min_by_key` returns the first of equal elements.
+            Preference::First | Preference::Highest => {
+                runtimes.min_by_key(|runtime| std::cmp::Reverse(runtime.version))
+            }
+            Preference::Lowest => runtimes.min_by_key(|runtime| runtime.version),
+        }
+    }
+}
+
+impl FromIterator<Box<dyn Strategy>> for StrategySet {
+    /// Collect strategies in order of preference, using [`Preference::First`].
+    fn from_iter<I: IntoIterator<Item = Box<dyn Strategy>>>(iter: I) -> Self {
+        Self {
+            strategies: iter.into_iter().collect(),
+            preference: Preference::First,
+        }
+    }
+}
 
 impl Strategy for StrategySet {
     /// Runtimes known to all strategies, in the same order as each strategy
     /// returns them.
     ///
-    /// Note that runtimes are deduplicated by version number, i.e. if a runtime
-    /// with the same version number appears in multiple strategies, it will
-    /// only be returned the first time it is seen.
-    fn runtimes(&self) -> Runtimes {
+    /// Note that runtimes are deduplicated by [canonical
+    /// `bindir`][`Runtime::canonical_bindir`], i.e. if the same installation
+    /// is found by multiple strategies, or via a symlink, it will only be
+    /// returned the first time it is seen. Different installations of the same
+    /// version are all returned.
+    fn runtimes(&self) -> Runtimes<'_> {
         let mut seen = std::collections::HashSet::new();
         Box::new(
-            self.0
+            self.strategies
                 .iter()
                 .flat_map(|strategy| strategy.runtimes())
-                .filter(move |runtime| seen.insert(runtime.version)),
+                .filter(move |runtime| seen.insert(runtime.canonical_bindir())),
         )
     }
 
-    /// Asks each strategy in turn to select a runtime. The first non-[`None`]
-    /// answer is selected.
+    /// Installations known to all strategies, in the same order as each
+    /// strategy returns them, without deduplication.
+    fn installations(&self) -> Runtimes<'_> {
+        Box::new(
+            self.strategies
+                .iter()
+                .flat_map(|strategy| strategy.installations()),
+        )
+    }
+
+    /// With [`Preference::First`], asks each strategy in turn to select a
+    /// runtime; the first non-[`None`] answer is selected. Otherwise chooses
+    /// from all compatible runtimes.
     fn select(&self, version: &version::PartialVersion) -> Option<Runtime> {
-        self.0.iter().find_map(|strategy| strategy.select(version))
+        match self.preference {
+            Preference::First => self
+                .strategies
+                .iter()
+                .find_map(|strategy| strategy.select(version)),
+            _ => self.choose(
+                self.runtimes()
+                    .filter(|runtime| version.compatible(runtime.version)),
+            ),
+        }
+    }
+
+    /// With [`Preference::First`], asks each strategy in turn to select a
+    /// runtime matching the given constraint; the first non-[`None`] answer is
+    /// selected. Otherwise chooses from all matching runtimes.
+    fn select_matching(&self, constraint: &version::VersionConstraint) -> Option<Runtime> {
+        match self.preference {
+            Preference::First => self
+                .strategies
+                .iter()
+                .find_map(|strategy| strategy.select_matching(constraint)),
+            _ => self.choose(
+                self.runtimes()
+                    .filter(|runtime| constraint.matches(runtime.version)),
+            ),
+        }
     }
 
-    /// Asks each strategy in turn for a fallback runtime. The first
-    /// non-[`None`] answer is selected.
+    /// With [`Preference::First`], asks each strategy in turn for a fallback
+    /// runtime; the first non-[`None`] answer is selected. Otherwise chooses
+    /// from all runtimes.
     fn fallback(&self) -> Option<Runtime> {
-        self.0.iter().find_map(|strategy| strategy.fallback())
+        match self.preference {
+            Preference::First => self
+                .strategies
+                .iter()
+                .find_map(|strategy| strategy.fallback()),
+            _ => self.choose(self.runtimes()),
+        }
     }
 }
 
-/// Select runtimes from on `PATH` followed by platform-specific runtimes.
+/// Select runtimes from configuration files, then from on `PATH`, followed by
+/// platform-specific runtimes, then runtimes installed by pgenv, asdf, or mise.
+/// With the `download` feature, runtimes that have already been downloaded
+/// come last; nothing is downloaded on demand.
 impl Default for StrategySet {
     fn default() -> Self {
-        Self(vec![
+        #[cfg_attr(not(feature = "download"), allow(unused_mut))]
+        let mut strategies: Vec<Box<dyn Strategy>> = vec![
+            Box::new(RuntimesFromConfig::Default),
             Box::new(RuntimesOnPath::Env),
-            Box::new(RuntimesOnPlatform),
-        ])
+            Box::new(RuntimesOnPlatform::new()),
+            Box::new(RuntimesFromPgenv::Env),
+            Box::new(RuntimesFromAsdf::Asdf),
+            Box::new(RuntimesFromAsdf::Mise),
+        ];
+        #[cfg(feature = "download")]
+        strategies.push(Box::new(from_fn(|| {
+            super::download::Downloader::new().installed()
+        })));
+        Self { strategies, preference: Preference::First }
+    }
+}
+
+/// Something that can be turned into a [`Runtime`], for use with [`from_fn`].
+pub trait IntoRuntime {
+    /// Convert into a runtime, or [`None`] if that's not possible.
+    fn into_runtime(self) -> Option<Runtime>;
+}
+
+impl IntoRuntime for Runtime {
+    fn into_runtime(self) -> Option<Runtime> {
+        Some(self)
+    }
+}
+
+/// A path is a runtime's `bindir`. It is thrown away if we can't determine the
+/// version of the runtime.
+impl IntoRuntime for PathBuf {
+    fn into_runtime(self) -> Option<Runtime> {
+        Runtime::new(self).ok()
+    }
+}
+
+/// A path is a runtime's `bindir`. It is thrown away if we can't determine the
+/// version of the runtime.
+impl IntoRuntime for &Path {
+    fn into_runtime(self) -> Option<Runtime> {
+        Runtime::new(self).ok()
+    }
+}
+
+/// Errors are thrown away.
+impl<E> IntoRuntime for Result<Runtime, E> {
+    fn into_runtime(self) -> Option<Runtime> {
+        self.ok()
+    }
+}
+
+/// A strategy that finds runtimes by calling a function. See [`from_fn`].
+#[derive(Clone)]
+pub struct FromFn<F>(F);
+
+/// Create a strategy from a function that returns runtimes, or the `bindir`s
+/// of runtimes – anything that implements [`IntoRuntime`].
+///
+/// This lets an application feed in its own discovery logic, e.g. from its
+/// build system, without implementing [`Strategy`] itself. The function is
+/// called every time runtimes are needed. The default implementations of
+/// [`Strategy::select`] and [`Strategy::fallback`] are used.
+///
+/// ```rust
+/// # use std::path::PathBuf;
+/// # use postgresfixture::runtime::strategy::{self, Strategy};
+/// let strategy = strategy::from_fn(|| {
+///     std::env::var_os("MY_PG_BINDIR").map(PathBuf::from)
+/// });
+/// let runtime = strategy.fallback();
+/// ```
+pub fn from_fn<F, I>(f: F) -> FromFn<F>
+where
+    F: Fn() -> I + std::panic::RefUnwindSafe + 'static,
+    I: IntoIterator,
+    I::Item: IntoRuntime,
+{
+    FromFn(f)
+}
+
+impl<F, I> Strategy for FromFn<F>
+where
+    F: Fn() -> I + std::panic::RefUnwindSafe + 'static,
+    I: IntoIterator,
+    I::Item: IntoRuntime,
+{
+    fn runtimes(&self) -> Runtimes<'_> {
+        let runtimes: Vec<_> = (self.0)()
+            .into_iter()
+            .filter_map(IntoRuntime::into_runtime)
+            .collect();
+        Box::new(runtimes.into_iter())
     }
 }
 
 /// Use a single runtime as a strategy.
 impl Strategy for Runtime {
     /// This runtime itself is the only runtime known to this strategy.
-    fn runtimes(&self) -> Runtimes {
+    fn runtimes(&self) -> Runtimes<'_> {
         Box::new(std::iter::once(self.clone()))
     }
 
@@ -223,24 +730,553 @@ impl Strategy for Runtime {
         }
     }
 
+    /// Return this runtime if it matches the given version constraint.
+    fn select_matching(&self, constraint: &version::VersionConstraint) -> Option<Runtime> {
+        if constraint.matches(self.version) {
+            Some(self.clone())
+        } else {
+            None
+        }
+    }
+
     /// Always return this runtime.
     fn fallback(&self) -> Option<Runtime> {
         Some(self.clone())
     }
 }
 
+/// A request for a runtime, as made through [`Strategy`].
+#[derive(Clone, Copy)]
+enum Selection<'a> {
+    Version(&'a version::PartialVersion),
+    Matching(&'a version::VersionConstraint),
+    Fallback,
+}
+
+impl Selection<'_> {
+    /// Ask the given strategy for a runtime.
+    fn select<S: Strategy>(self, strategy: &S) -> Option<Runtime> {
+        match self {
+            Selection::Version(version) => strategy.select(version),
+            Selection::Matching(constraint) => strategy.select_matching(constraint),
+            Selection::Fallback => strategy.fallback(),
+        }
+    }
+
+    /// Does the given runtime satisfy this request?
+    fn wants(self, runtime: &Runtime) -> bool {
+        match self {
+            Selection::Version(version) => version.compatible(runtime.version),
+            Selection::Matching(constraint) => constraint.matches(runtime.version),
+            Selection::Fallback => true,
+        }
+    }
+}
+
+/// Selection for strategies that filter another strategy's runtimes: prefer
+/// the inner strategy's choice if it passes `keep`, otherwise the runtime with
+/// the highest version number that passes `keep` and satisfies `selection`.
+fn select_filtered<S, F>(inner: &S, selection: Selection, keep: F) -> Option<Runtime>
+where
+    S: Strategy,
+    F: Fn(&Runtime) -> bool,
+{
+    selection
+        .select(inner)
+        .filter(|runtime| keep(runtime))
+        .or_else(|| {
+            inner
+                .runtimes()
+                .filter(|runtime| keep(runtime) && selection.wants(runtime))
+                .max_by(|ra, rb| ra.version.cmp(&rb.version))
+        })
+}
+
+/// Constrain another strategy to runtimes compatible with a PostgreSQL version
+/// named in the environment.
+///
+/// By default the version is read from `POSTGRESFIXTURE_VERSION` or, if that is
+/// not set, `PGVERSION`, e.g. `PGVERSION=15` or `PGVERSION=9.6`. This allows a
+/// CI matrix, for example, to pin the version of PostgreSQL without changing
+/// any code.
+///
+/// When none of the variables are set (or they are empty) the inner strategy is
+/// used unchanged. When a variable is set but cannot be parsed as a
+/// [`PartialVersion`][`version::PartialVersion`], no runtimes match.
+///
+/// The environment is consulted every time a runtime is requested rather than
+/// once when this strategy is created.
+#[derive(Clone, Debug)]
+pub struct PinnedByEnv<S> {
+    inner: S,
+    vars: &'static [&'static str],
+}
+
+impl<S: Strategy> PinnedByEnv<S> {
+    /// The environment variables consulted by [`PinnedByEnv::new`], in order of
+    /// preference.
+    pub const VARS: &'static [&'static str] = &["POSTGRESFIXTURE_VERSION", "PGVERSION"];
+
+    /// Constrain `inner` using the version named in [`Self::VARS`].
+    pub fn new(inner: S) -> Self {
+        Self::with_vars(inner, Self::VARS)
+    }
+
+    /// Constrain `inner` using the version named in the given environment
+    /// variables, in order of preference.
+    pub fn with_vars(inner: S, vars: &'static [&'static str]) -> Self {
+        Self { inner, vars }
+    }
+
+    /// The inner strategy.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// The version constraint named in the environment, if any.
+    pub fn pinned(&self) -> Option<Result<version::PartialVersion, version::VersionError>> {
+        self.vars
+            .iter()
+            .filter_map(env::var_os)
+            .find(|value| !value.is_empty())
+            .map(|value| value.to_string_lossy().parse())
+    }
+
+    /// Filter the given runtimes by the pinned version, if there is one.
+    fn filter<'a>(&self, runtimes: Runtimes<'a>) -> Runtimes<'a> {
+        match self.pinned() {
+            None => runtimes,
+            Some(Ok(pinned)) => {
+                Box::new(runtimes.filter(move |runtime| pinned.compatible(runtime.version)))
+            }
+            Some(Err(_)) => Box::new(std::iter::empty()),
+        }
+    }
+
+    fn select_pinned(&self, selection: Selection) -> Option<Runtime> {
+        match self.pinned() {
+            None => selection.select(&self.inner),
+            Some(Ok(pinned)) => select_filtered(&self.inner, selection, |runtime| {
+                pinned.compatible(runtime.version)
+            }),
+            Some(Err(_)) => None,
+        }
+    }
+}
+
+impl<S: Strategy> Strategy for PinnedByEnv<S> {
+    /// Runtimes known to the inner strategy that are compatible with the pinned
+    /// version, if there is one.
+    fn runtimes(&self) -> Runtimes<'_> {
+        self.filter(self.inner.runtimes())
+    }
+
+    /// Installations known to the inner strategy that are compatible with the
+    /// pinned version, if there is one.
+    fn installations(&self) -> Runtimes<'_> {
+        self.filter(self.inner.installations())
+    }
+
+    /// Prefer the inner strategy's selection if it's compatible with the pinned
+    /// version, otherwise select the compatible runtime with the highest
+    /// version number.
+    fn select(&self, version: &version::PartialVersion) -> Option<Runtime> {
+        self.select_pinned(Selection::Version(version))
+    }
+
+    /// Prefer the inner strategy's selection if it's compatible with the pinned
+    /// version, otherwise select the matching runtime with the highest version
+    /// number.
+    fn select_matching(&self, constraint: &version::VersionConstraint) -> Option<Runtime> {
+        self.select_pinned(Selection::Matching(constraint))
+    }
+
+    /// Prefer the inner strategy's fallback if it's compatible with the pinned
+    /// version, otherwise select the compatible runtime with the highest
+    /// version number.
+    fn fallback(&self) -> Option<Runtime> {
+        self.select_pinned(Selection::Fallback)
+    }
+}
+
+/// Constrain another strategy to runtimes in a
+/// [`VersionSet`][`version::VersionSet`], e.g. to never use anything older than
+/// PostgreSQL 13, or to avoid point releases with a known bug.
+///
+/// ```rust
+/// # use postgresfixture::runtime::strategy::{self, Constrained, Strategy};
+/// let strategy = Constrained::new(strategy::default(), ">=13".parse()?);
+/// let strategy = Constrained::new(strategy::default(), ">=13 except 14.0".parse()?);
+/// # Ok::<(), postgresfixture::version::VersionError>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Constrained<S> {
+    inner: S,
+    constraint: version::VersionSet,
+}
+
+impl<S: Strategy> Constrained<S> {
+    /// Constrain `inner` to runtimes in `constraint`. A
+    /// [`VersionConstraint`][`version::VersionConstraint`] can be converted
+    /// into a set with [`Into::into`].
+    pub fn new(inner: S, constraint: version::VersionSet) -> Self {
+        Self { inner, constraint }
+    }
+
+    /// The set of versions to which the inner strategy is constrained.
+    pub fn constraint(&self) -> &version::VersionSet {
+        &self.constraint
+    }
+
+    fn select_constrained(&self, selection: Selection) -> Option<Runtime> {
+        select_filtered(&self.inner, selection, |runtime| {
+            self.constraint.matches(runtime.version)
+        })
+    }
+}
+
+impl<S: Strategy> Strategy for Constrained<S> {
+    /// Runtimes known to the inner strategy that match the constraint.
+    fn runtimes(&self) -> Runtimes<'_> {
+        Box::new(
+            self.inner
+                .runtimes()
+                .filter(|runtime| self.constraint.matches(runtime.version)),
+        )
+    }
+
+    /// Installations known to the inner strategy that match the constraint.
+    fn installations(&self) -> Runtimes<'_> {
+        Box::new(
+            self.inner
+                .installations()
+                .filter(|runtime| self.constraint.matches(runtime.version)),
+        )
+    }
+
+    /// Prefer the inner strategy's selection if it matches the constraint,
+    /// otherwise select the compatible matching runtime with the highest
+    /// version number.
+    fn select(&self, version: &version::PartialVersion) -> Option<Runtime> {
+        self.select_constrained(Selection::Version(version))
+    }
+
+    /// When this strategy's constraint is a single
+    /// [`VersionConstraint`][`version::VersionConstraint`], asks the inner
+    /// strategy to select a runtime matching both that and the given
+    /// constraint. Otherwise, prefer the inner strategy's selection if it is in
+    /// this strategy's set, else select the matching runtime with the highest
+    /// version number.
+    fn select_matching(&self, constraint: &version::VersionConstraint) -> Option<Runtime> {
+        match self.constraint.as_constraint() {
+            Some(own) => {
+                let constraint = own
+                    .comparators()
+                    .iter()
+                    .chain(constraint.comparators())
+                    .copied()
+                    .collect();
+                self.inner.select_matching(&constraint)
+            }
+            None => self.select_constrained(Selection::Matching(constraint)),
+        }
+    }
+
+    /// Prefer the inner strategy's fallback if it matches the constraint,
+    /// otherwise the matching runtime with the highest version number.
+    fn fallback(&self) -> Option<Runtime> {
+        self.select_constrained(Selection::Fallback)
+    }
+}
+
+/// Constrain another strategy to runtimes that pass [`Runtime::validate`],
+/// i.e. complete installations.
+///
+/// This means checking each runtime's programs when it's discovered, which is
+/// why it's optional. Without it, a partial installation can be selected and
+/// then fail part-way through creating a cluster.
+#[derive(Clone, Debug)]
+pub struct Validated<S>(pub S);
+
+impl<S> Validated<S> {
+    fn keep(runtime: &Runtime) -> bool {
+        runtime.validate().is_ok()
+    }
+}
+
+impl<S: Strategy> Strategy for Validated<S> {
+    /// Runtimes known to the inner strategy that are complete.
+    fn runtimes(&self) -> Runtimes<'_> {
+        Box::new(self.0.runtimes().filter(Self::keep))
+    }
+
+    /// All installations known to the inner strategy, complete or not.
+    fn installations(&self) -> Runtimes<'_> {
+        self.0.installations()
+    }
+
+    /// Prefer the inner strategy's selection if it's complete, otherwise select
+    /// the compatible complete runtime with the highest version number.
+    fn select(&self, version: &version::PartialVersion) -> Option<Runtime> {
+        select_filtered(&self.0, Selection::Version(version), Self::keep)
+    }
+
+    /// Prefer the inner strategy's selection if it's complete, otherwise select
+    /// the matching complete runtime with the highest version number.
+    fn select_matching(&self, constraint: &version::VersionConstraint) -> Option<Runtime> {
+        select_filtered(&self.0, Selection::Matching(constraint), Self::keep)
+    }
+
+    /// Prefer the inner strategy's fallback if it's complete, otherwise the
+    /// complete runtime with the highest version number.
+    fn fallback(&self) -> Option<Runtime> {
+        select_filtered(&self.0, Selection::Fallback, Self::keep)
+    }
+}
+
+/// Override another strategy with a runtime named in the environment.
+///
+/// By default the runtime's `bindir` is read from `POSTGRESFIXTURE_BINDIR`,
+/// e.g. `POSTGRESFIXTURE_BINDIR=/usr/lib/postgresql/15/bin`. When it's set,
+/// that runtime is the sole runtime known to this strategy, so a single
+/// environment variable can force a specific installation to be used. When
+/// it's set but there's no runtime there, no runtimes are known at all.
+///
+/// When the variable is not set (or is empty) the inner strategy is used
+/// unchanged. The environment is consulted every time a runtime is requested.
+#[derive(Clone, Debug)]
+pub struct OverriddenByEnv<S> {
+    inner: S,
+    var: &'static str,
+}
+
+impl<S: Strategy> OverriddenByEnv<S> {
+    /// The environment variable consulted by [`OverriddenByEnv::new`].
+    pub const VAR: &'static str = "POSTGRESFIXTURE_BINDIR";
+
+    /// Override `inner` with the runtime named in [`Self::VAR`].
+    pub fn new(inner: S) -> Self {
+        Self::with_var(inner, Self::VAR)
+    }
+
+    /// Override `inner` with the runtime named in the given environment
+    /// variable.
+    pub fn with_var(inner: S, var: &'static str) -> Self {
+        Self { inner, var }
+    }
+
+    /// The inner strategy.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// The runtime named in the environment, if any.
+    pub fn overridden(&self) -> Option<Result<Runtime, RuntimeError>> {
+        env::var_os(self.var)
+            .filter(|bindir| !bindir.is_empty())
+            .map(Runtime::new)
+    }
+}
+
+impl<S: Strategy> Strategy for OverriddenByEnv<S> {
+    fn runtimes(&self) -> Runtimes<'_> {
+        match self.overridden() {
+            None => self.inner.runtimes(),
+            Some(runtime) => Box::new(runtime.into_iter()),
+        }
+    }
+
+    fn installations(&self) -> Runtimes<'_> {
+        match self.overridden() {
+            None => self.inner.installations(),
+            Some(runtime) => Box::new(runtime.into_iter()),
+        }
+    }
+
+    fn select(&self, version: &version::PartialVersion) -> Option<Runtime> {
+        match self.overridden() {
+            None => self.inner.select(version),
+            Some(runtime) => runtime.ok()?.select(version),
+        }
+    }
+
+    fn select_matching(&self, constraint: &version::VersionConstraint) -> Option<Runtime> {
+        match self.overridden() {
+            None => self.inner.select_matching(constraint),
+            Some(runtime) => runtime.ok()?.select_matching(constraint),
+        }
+    }
+
+    fn fallback(&self) -> Option<Runtime> {
+        match self.overridden() {
+            None => self.inner.fallback(),
+            Some(runtime) => runtime.ok(),
+        }
+    }
+}
+
+/// Memoized answers from a strategy. See [`Cached`].
+#[derive(Debug, Default)]
+struct Memo {
+    runtimes: Option<Vec<Runtime>>,
+    installations: Option<Vec<Runtime>>,
+    select: HashMap<String, Option<Runtime>>,
+    select_matching: HashMap<String, Option<Runtime>>,
+    /// The outer [`None`] means "not yet asked".
+    #[allow(clippy::option_option)]
+    fallback: Option<Option<Runtime>>,
+}
+
+/// Memoize the answers of another strategy.
+///
+/// Most strategies scan the filesystem every time they're asked for runtimes,
+/// and may run `pg_ctl` too. This wrapper asks the inner strategy once for
+/// each question – e.g. once for [`Strategy::runtimes`], once for each version
+/// passed to [`Strategy::select`] – and remembers the answer until
+/// [`Cached::invalidate`] is called.
+#[derive(Debug)]
+pub struct Cached<S> {
+    inner: S,
+    memo: Mutex<Memo>,
+}
+
+impl<S: Strategy> Cached<S> {
+    /// Memoize the answers of `inner`.
+    pub fn new(inner: S) -> Self {
+        Self { inner, memo: Mutex::default() }
+    }
+
+    /// The inner strategy.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Forget all memoized answers, e.g. after installing a new runtime.
+    pub fn invalidate(&self) {
+        if let Ok(mut memo) = self.memo.lock() {
+            *memo = Memo::default();
+        }
+    }
+
+    /// Get a memoized answer, or calculate and memoize it. The lock is not held
+    /// while calculating, so concurrent callers may both calculate an answer.
+    fn memoize<T, G, P, F>(&self, get: G, put: P, calculate: F) -> T
+    where
+        T: Clone,
+        G: FnOnce(&Memo) -> Option<T>,
+        P: FnOnce(&mut Memo, T),
+        F: FnOnce() -> T,
+    {
+        if let Some(answer) = self.memo.lock().ok().and_then(|memo| get(&memo)) {
+            return answer;
+        }
+        let answer = calculate();
+        if let Ok(mut memo) = self.memo.lock() {
+            put(&mut memo, answer.clone());
+        }
+        answer
+    }
+}
+
+impl<S: Strategy> Strategy for Cached<S> {
+    fn runtimes(&self) -> Runtimes<'_> {
+        Box::new(
+            self.memoize(
+                |memo| memo.runtimes.clone(),
+                |memo, runtimes| memo.runtimes = Some(runtimes),
+                || self.inner.runtimes().collect(),
+            )
+            .into_iter(),
+        )
+    }
+
+    fn installations(&self) -> Runtimes<'_> {
+        Box::new(
+            self.memoize(
+                |memo| memo.installations.clone(),
+                |memo, installations| memo.installations = Some(installations),
+                || self.inner.installations().collect(),
+            )
+            .into_iter(),
+        )
+    }
+
+    fn select(&self, version: &version::PartialVersion) -> Option<Runtime> {
+        let key = version.to_string();
+        self.memoize(
+            |memo| memo.select.get(&key).cloned(),
+            |memo, runtime| drop(memo.select.insert(key.clone(), runtime)),
+            || self.inner.select(version),
+        )
+    }
+
+    fn select_matching(&self, constraint: &version::VersionConstraint) -> Option<Runtime> {
+        let key = constraint.to_string();
+        self.memoize(
+            |memo| memo.select_matching.get(&key).cloned(),
+            |memo, runtime| drop(memo.select_matching.insert(key.clone(), runtime)),
+            || self.inner.select_matching(constraint),
+        )
+    }
+
+    fn fallback(&self) -> Option<Runtime> {
+        self.memoize(
+            |memo| memo.fallback.clone(),
+            |memo, runtime| memo.fallback = Some(runtime),
+            || self.inner.fallback(),
+        )
+    }
+}
+
+/// The `bindir` of the first runtime on `PATH`, for use in tests.
+///
+/// # Panics
+///
+/// If there is no `pg_ctl` on `PATH`.
+#[cfg(test)]
+pub fn find_bindir() -> PathBuf {
+    env::split_paths(&env::var_os("PATH").expect("PATH not set"))
+        .find(|path| path.join("pg_ctl").exists())
+        .expect("pg_ctl not on PATH")
+}
+
+/// The type of the [default runtime strategy][`default()`].
+pub type DefaultStrategy = PinnedByEnv<OverriddenByEnv<Cached<StrategySet>>>;
+
+impl DefaultStrategy {
+    /// Forget all memoized answers, e.g. after installing a new runtime. See
+    /// [`Cached::invalidate`].
+    pub fn invalidate(&self) {
+        self.inner().inner().invalidate();
+    }
+}
+
 /// The default runtime strategy.
 ///
-/// At present this returns the default [`StrategySet`].
-pub fn default() -> impl Strategy {
-    StrategySet::default()
+/// At present this returns the default [`StrategySet`], memoized with
+/// [`Cached`], overridden by any runtime named in the environment – see
+/// [`OverriddenByEnv`] – and constrained by any version pinned in the
+/// environment – see [`PinnedByEnv`].
+///
+/// Each call returns a new strategy with nothing memoized, so hold on to the
+/// strategy rather than calling this repeatedly, and call
+/// [`DefaultStrategy::invalidate`] when the installed runtimes change.
+pub fn default() -> DefaultStrategy {
+    PinnedByEnv::new(OverriddenByEnv::new(Cached::new(StrategySet::default())))
 }
 
 #[cfg(test)]
 mod tests {
     use std::env;
+    use std::path::Path;
+
+    use crate::runtime::Runtime;
+    use crate::version::{PartialVersion, Version, VersionConstraint};
 
-    use super::{RuntimesOnPath, RuntimesOnPlatform, Strategy, StrategySet};
+    use super::{
+        find_bindir, from_fn, probe, Cached, Constrained, OverriddenByEnv, PinnedByEnv, Preference,
+        RuntimesOnPath, RuntimesOnPlatform, Strategy, StrategySet, Validated,
+    };
 
     /// This will fail if there are no PostgreSQL runtimes installed.
     #[test]
@@ -251,6 +1287,39 @@ mod tests {
         assert_ne!(0, runtimes.count());
     }
 
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtime_probe_preserves_order() {
+        let bindir = find_bindir();
+        let bindirs = vec![
+            "/does/not/exist".into(),
+            bindir.join("."),
+            bindir.clone(),
+            "/does/not/exist/either".into(),
+            bindir.join("../bin"),
+        ];
+        let runtimes = probe(bindirs);
+        let found: Vec<_> = runtimes.iter().map(|runtime| &runtime.bindir).collect();
+        assert_eq!(
+            vec![&bindir.join("."), &bindir, &bindir.join("../bin")],
+            found
+        );
+    }
+
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtime_find_custom_path_requires_server() -> std::io::Result<()> {
+        let bindir = find_bindir();
+        // A client-only installation that has `pg_ctl` but not the server.
+        let tempdir = tempdir::TempDir::new("strategy")?;
+        std::os::unix::fs::symlink(bindir.join("pg_ctl"), tempdir.path().join("pg_ctl"))?;
+        let path = env::join_paths([tempdir.path(), &bindir]).unwrap();
+        let strategy = RuntimesOnPath::Custom(path.into());
+        let runtimes: Vec<_> = strategy.runtimes().map(|runtime| runtime.bindir).collect();
+        assert_eq!(vec![bindir], runtimes);
+        Ok(())
+    }
+
     /// This will fail if there are no PostgreSQL runtimes installed.
     #[test]
     fn runtime_find_env_path() {
@@ -262,8 +1331,8 @@ mod tests {
     #[test]
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     fn runtime_find_on_platform() {
-        let runtimes = RuntimesOnPlatform.runtimes();
-        assert_ne!(0, runtimes.count());
+        let strategy = RuntimesOnPlatform::new();
+        assert_ne!(0, strategy.runtimes().count());
     }
 
     /// This will fail if there are no PostgreSQL runtimes installed. It's also
@@ -278,4 +1347,334 @@ mod tests {
         // There is always a fallback.
         assert!(strategy.fallback().is_some());
     }
+
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtime_pinned_by_env() {
+        const VARS: &[&str] = &["POSTGRESFIXTURE_TEST_PINNED_BY_ENV"];
+        let runtime = RuntimesOnPath::Env.runtimes().next().unwrap();
+        let strategy = PinnedByEnv::with_vars(runtime.clone(), VARS);
+        // Nothing pinned.
+        env::remove_var(VARS[0]);
+        assert_eq!(strategy.fallback(), Some(runtime.clone()));
+        // Pinned to a compatible version.
+        let version = PartialVersion::from(runtime.version).widened();
+        env::set_var(VARS[0], version.to_string());
+        assert_eq!(strategy.fallback(), Some(runtime.clone()));
+        assert_eq!(strategy.select(&version), Some(runtime.clone()));
+        // Pinned to an incompatible version.
+        env::set_var(VARS[0], "8.0");
+        assert_eq!(strategy.fallback(), None);
+        assert_eq!(strategy.runtimes().count(), 0);
+        // Pinned to garbage.
+        env::set_var(VARS[0], "garbage");
+        assert_eq!(strategy.fallback(), None);
+        env::remove_var(VARS[0]);
+    }
+
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtime_overridden_by_env() {
+        const VAR: &str = "POSTGRESFIXTURE_TEST_OVERRIDDEN_BY_ENV";
+        let runtime = RuntimesOnPath::Env.runtimes().next().unwrap();
+        let other = Runtime { bindir: "/other".into(), version: Version::Post10(99, 0) };
+        let strategy = OverriddenByEnv::with_var(other.clone(), VAR);
+        // Nothing overridden.
+        env::remove_var(VAR);
+        assert_eq!(strategy.fallback(), Some(other.clone()));
+        // Overridden with a real runtime.
+        env::set_var(VAR, &runtime.bindir);
+        assert_eq!(strategy.fallback(), Some(runtime.clone()));
+        assert_eq!(
+            strategy.runtimes().collect::<Vec<_>>(),
+            vec![runtime.clone()]
+        );
+        let version = PartialVersion::from(runtime.version).widened();
+        assert_eq!(strategy.select(&version), Some(runtime.clone()));
+        assert_eq!(strategy.select(&"99".parse().unwrap()), None);
+        // Overridden with something that is not a runtime.
+        env::set_var(VAR, "/does/not/exist");
+        assert_eq!(strategy.fallback(), None);
+        assert_eq!(strategy.runtimes().count(), 0);
+        env::remove_var(VAR);
+    }
+
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtime_strategy_set_select_matching() {
+        let strategy = StrategySet::default();
+        let fallback = strategy.fallback().unwrap();
+        let constraint: VersionConstraint = format!(">={}", fallback.version).parse().unwrap();
+        assert_eq!(
+            strategy.select_matching(&constraint),
+            Some(fallback.clone())
+        );
+        let constraint: VersionConstraint = format!(">{}", fallback.version).parse().unwrap();
+        assert_eq!(strategy.select_matching(&constraint), None);
+    }
+
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtime_strategy_set_dedupes_by_canonical_bindir() -> std::io::Result<()> {
+        let bindir = find_bindir();
+        let tempdir = tempdir::TempDir::new("strategy")?;
+        let linkdir = tempdir.path().join("bin");
+        std::os::unix::fs::symlink(&bindir, &linkdir)?;
+        let mut strategy = StrategySet::new();
+        strategy.push_back(RuntimesOnPath::Custom(linkdir.clone()));
+        strategy.push_front(RuntimesOnPath::Custom(bindir.clone()));
+        let runtimes: Vec<_> = strategy.runtimes().map(|runtime| runtime.bindir).collect();
+        assert_eq!(vec![bindir.clone()], runtimes);
+        let installations: Vec<_> = strategy
+            .installations()
+            .map(|runtime| runtime.bindir)
+            .collect();
+        assert_eq!(vec![bindir, linkdir], installations);
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_strategy_set_preference() {
+        let runtime = |bindir: &str, version| Runtime { bindir: bindir.into(), version };
+        let strategies = || -> Vec<Box<dyn Strategy>> {
+            vec![
+                Box::new(runtime("/a", Version::Post10(14, 9))),
+                Box::new(runtime("/b", Version::Post10(16, 1))),
+                Box::new(runtime("/c", Version::Post10(13, 2))),
+                Box::new(runtime("/d", Version::Post10(16, 1))),
+            ]
+        };
+        let version: PartialVersion = "16".parse().unwrap();
+        let constraint: VersionConstraint = ">=14".parse().unwrap();
+
+        let strategy: StrategySet = strategies().into_iter().collect();
+        assert_eq!(Preference::First, strategy.preference());
+        assert_eq!(
+            Some(runtime("/a", Version::Post10(14, 9))),
+            strategy.fallback()
+        );
+        assert_eq!(
+            Some(runtime("/b", Version::Post10(16, 1))),
+            strategy.select(&version)
+        );
+        assert_eq!(
+            Some(runtime("/a", Version::Post10(14, 9))),
+            strategy.select_matching(&constraint)
+        );
+
+        let strategy = strategy.with_preference(Preference::Highest);
+        assert_eq!(
+            Some(runtime("/b", Version::Post10(16, 1))),
+            strategy.fallback()
+        );
+        assert_eq!(
+            Some(runtime("/b", Version::Post10(16, 1))),
+            strategy.select(&version)
+        );
+        assert_eq!(
+            Some(runtime("/b", Version::Post10(16, 1))),
+            strategy.select_matching(&constraint)
+        );
+
+        let mut strategy = strategy.with_preference(Preference::Lowest);
+        assert_eq!(
+            Some(runtime("/c", Version::Post10(13, 2))),
+            strategy.fallback()
+        );
+        assert_eq!(
+            Some(runtime("/b", Version::Post10(16, 1))),
+            strategy.select(&version)
+        );
+        assert_eq!(
+            Some(runtime("/a", Version::Post10(14, 9))),
+            strategy.select_matching(&constraint)
+        );
+
+        strategy.push_front(runtime("/e", Version::Post10(16, 1)));
+        assert_eq!(5, strategy.len());
+        assert_eq!(
+            Some(runtime("/e", Version::Post10(16, 1))),
+            strategy.select(&version)
+        );
+    }
+
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtime_strategy_from_fn() {
+        let bindir = find_bindir();
+        let strategy = from_fn(move || vec![bindir.clone(), "/does/not/exist".into()]);
+        assert_eq!(1, strategy.runtimes().count());
+        assert!(strategy.fallback().is_some());
+
+        let runtime = Runtime { bindir: "/a".into(), version: Version::Post10(16, 1) };
+        let strategy = from_fn(move || Some(runtime.clone()));
+        let version: PartialVersion = "16".parse().unwrap();
+        assert_eq!(
+            Some(Version::Post10(16, 1)),
+            strategy.select(&version).map(|r| r.version)
+        );
+        let version: PartialVersion = "15".parse().unwrap();
+        assert_eq!(None, strategy.select(&version));
+    }
+
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtime_strategy_validated() -> std::io::Result<()> {
+        let bindir = find_bindir();
+        let tempdir = tempdir::TempDir::new("strategy")?;
+        std::os::unix::fs::symlink(bindir.join("pg_ctl"), tempdir.path().join("pg_ctl"))?;
+        let bindirs = vec![tempdir.path().to_owned(), bindir.clone()];
+        let strategy = from_fn(move || bindirs.clone());
+        assert_eq!(2, strategy.runtimes().count());
+        let strategy = Validated(strategy);
+        let runtimes: Vec<_> = strategy.runtimes().map(|runtime| runtime.bindir).collect();
+        assert_eq!(vec![bindir.clone()], runtimes);
+        assert_eq!(2, strategy.installations().count());
+        assert_eq!(
+            Some(bindir),
+            strategy.fallback().map(|runtime| runtime.bindir)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_strategy_constrained() {
+        let runtime = |bindir: &str, version| Runtime { bindir: bindir.into(), version };
+        let strategy: StrategySet = vec![
+            Box::new(runtime("/a", Version::Post10(12, 9))) as Box<dyn Strategy>,
+            Box::new(runtime("/b", Version::Post10(16, 1))),
+            Box::new(runtime("/c", Version::Post10(14, 2))),
+        ]
+        .into_iter()
+        .collect();
+        let strategy = Constrained::new(strategy, ">=13, <16".parse().unwrap());
+        let versions: Vec<_> = strategy.runtimes().map(|runtime| runtime.version).collect();
+        assert_eq!(vec![Version::Post10(14, 2)], versions);
+        // The inner fallback, /a, is too old.
+        assert_eq!(
+            Some(runtime("/c", Version::Post10(14, 2))),
+            strategy.fallback()
+        );
+        assert_eq!(None, strategy.select(&"12".parse().unwrap()));
+        assert_eq!(
+            Some(runtime("/c", Version::Post10(14, 2))),
+            strategy.select(&"14".parse().unwrap())
+        );
+        assert_eq!(None, strategy.select_matching(&">=15".parse().unwrap()));
+        assert_eq!(
+            Some(runtime("/c", Version::Post10(14, 2))),
+            strategy.select_matching(&"*".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn runtime_strategy_constrained_to_set() {
+        let runtime = |bindir: &str, version| Runtime { bindir: bindir.into(), version };
+        let strategy: StrategySet = vec![
+            Box::new(runtime("/a", Version::Post10(12, 9))) as Box<dyn Strategy>,
+            Box::new(runtime("/b", Version::Post10(16, 1))),
+            Box::new(runtime("/c", Version::Post10(14, 2))),
+        ]
+        .into_iter()
+        .collect();
+        let strategy = Constrained::new(strategy, ">=13 except >=16.0, <16.2".parse().unwrap());
+        let versions: Vec<_> = strategy.runtimes().map(|runtime| runtime.version).collect();
+        assert_eq!(vec![Version::Post10(14, 2)], versions);
+        assert_eq!(None, strategy.select_matching(&">=15".parse().unwrap()));
+        assert_eq!(
+            Some(runtime("/c", Version::Post10(14, 2))),
+            strategy.select_matching(&"*".parse().unwrap())
+        );
+    }
+
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtime_find_on_platform_with_globs() -> std::io::Result<()> {
+        let bindir = find_bindir();
+        let tempdir = tempdir::TempDir::new("strategy")?;
+        std::fs::create_dir(tempdir.path().join("pg-x.y"))?;
+        std::os::unix::fs::symlink(&bindir, tempdir.path().join("pg-x.y/bin"))?;
+        let glob = format!("{}/pg-*/bin", tempdir.path().display());
+        let strategy = RuntimesOnPlatform::new().with_globs([glob.as_str(), glob.as_str()]);
+        let bindirs = strategy.find_all();
+        assert_eq!(Some(&tempdir.path().join("pg-x.y/bin")), bindirs.first());
+        assert_eq!(
+            1,
+            bindirs
+                .iter()
+                .filter(|b| b.starts_with(tempdir.path()))
+                .count()
+        );
+        assert_eq!(
+            Some(tempdir.path().join("pg-x.y/bin")),
+            strategy.runtimes().next().map(|runtime| runtime.bindir)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_find_on_windows_parses_registry_and_program_files() {
+        let output = concat!(
+            "\r\n",
+            "HKEY_LOCAL_MACHINE\\SOFTWARE\\PostgreSQL\\Installations\\postgresql-x64-15\r\n",
+            "    Base Directory    REG_SZ    C:\\Program Files\\PostgreSQL\\15\r\n",
+            "\r\n",
+            "HKEY_LOCAL_MACHINE\\SOFTWARE\\PostgreSQL\\Installations\\postgresql-x64-16\r\n",
+            "    Base Directory    REG_SZ    D:\\PostgreSQL\\16 \r\n",
+            "\r\n",
+            "End of search: 2 match(es) found.\r\n",
+        );
+        assert_eq!(
+            RuntimesOnPlatform::parse_base_directories(output),
+            vec![
+                Path::new(r"C:\Program Files\PostgreSQL\15").join("bin"),
+                Path::new(r"D:\PostgreSQL\16").join("bin"),
+            ]
+        );
+        assert_eq!(
+            RuntimesOnPlatform::program_files_pattern(Path::new(r"C:\Program Files (x86)")),
+            r"C:\Program Files (x86)\PostgreSQL\*\bin\pg_ctl.exe"
+        );
+        assert_eq!(
+            RuntimesOnPlatform::program_files_pattern(Path::new(r"C:\[odd]")),
+            r"C:\[[]odd[]]\PostgreSQL\*\bin\pg_ctl.exe"
+        );
+    }
+
+    #[test]
+    fn runtime_strategy_default_can_be_invalidated() {
+        let strategy = super::default();
+        let cached = strategy.inner().inner();
+        // Ask the cached strategy directly, bypassing the environment.
+        cached.runtimes().for_each(drop);
+        assert!(cached.memo.lock().unwrap().runtimes.is_some());
+        strategy.invalidate();
+        assert!(cached.memo.lock().unwrap().runtimes.is_none());
+    }
+
+    #[test]
+    fn runtime_strategy_cached() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let strategy = Cached::new(from_fn({
+            let calls = Arc::clone(&calls);
+            move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Some(Runtime { bindir: "/a".into(), version: Version::Post10(16, 1) })
+            }
+        }));
+        let version: PartialVersion = "16".parse().unwrap();
+        for _ in 0..3 {
+            assert_eq!(1, strategy.runtimes().count());
+            assert!(strategy.select(&version).is_some());
+            assert!(strategy.fallback().is_some());
+        }
+        // Once each for `runtimes`, `select`, and `fallback`.
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+        strategy.invalidate();
+        assert_eq!(1, strategy.runtimes().count());
+        assert_eq!(4, calls.load(Ordering::SeqCst));
+    }
 }
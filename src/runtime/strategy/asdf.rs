@@ -0,0 +1,95 @@
+//! Find runtimes installed by [asdf](https://asdf-vm.com/) or
+//! [mise](https://mise.jdx.dev/).
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use super::{find_bindirs, probe, Runtimes, Strategy};
+
+/// Find runtimes installed by [asdf](https://asdf-vm.com/) or
+/// [mise](https://mise.jdx.dev/) using the `postgres` plugin.
+///
+/// Both tools install each version into its own directory under
+/// `installs/postgres` in their data directory, e.g.
+/// `~/.asdf/installs/postgres/15.4/bin`.
+#[derive(Clone, Debug)]
+pub enum RuntimesFromAsdf {
+    /// Find runtimes installed under the given asdf/mise data directory.
+    Custom(PathBuf),
+    /// Find runtimes installed by asdf, under `ASDF_DATA_DIR` (environment
+    /// variable), else under `~/.asdf`.
+    Asdf,
+    /// Find runtimes installed by mise, under `MISE_DATA_DIR` (environment
+    /// variable), else under `$XDG_DATA_HOME/mise`, else under
+    /// `~/.local/share/mise`.
+    Mise,
+}
+
+/// Get a non-empty environment variable as a path.
+fn env_path(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+impl RuntimesFromAsdf {
+    /// The asdf/mise data directory, if it can be determined.
+    pub fn data_dir(&self) -> Option<PathBuf> {
+        match self {
+            RuntimesFromAsdf::Custom(dir) => Some(dir.clone()),
+            RuntimesFromAsdf::Asdf => env_path("ASDF_DATA_DIR")
+                .or_else(|| env_path("HOME").map(|home| home.join(".asdf"))),
+            RuntimesFromAsdf::Mise => env_path("MISE_DATA_DIR")
+                .or_else(|| env_path("XDG_DATA_HOME").map(|data| data.join("mise")))
+                .or_else(|| env_path("HOME").map(|home| home.join(".local/share/mise"))),
+        }
+    }
+
+    /// Find the `bin` directories of runtimes installed by asdf/mise.
+    pub fn find(&self) -> Vec<PathBuf> {
+        match self.data_dir() {
+            Some(dir) => Self::find_in(&dir),
+            None => vec![],
+        }
+    }
+
+    fn find_in(dir: &Path) -> Vec<PathBuf> {
+        find_bindirs(&format!(
+            "{}/installs/postgres/*/bin/pg_ctl",
+            glob::Pattern::escape(&dir.to_string_lossy())
+        ))
+    }
+}
+
+impl Strategy for RuntimesFromAsdf {
+    fn runtimes(&self) -> Runtimes<'_> {
+        Box::new(probe(self.find()).into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    use super::{RuntimesFromAsdf, Strategy};
+    use crate::runtime::strategy::find_bindir;
+
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtimes_from_asdf() -> std::io::Result<()> {
+        let bindir = find_bindir();
+        let data = tempdir::TempDir::new("asdf")?;
+        let installs = data.path().join("installs/postgres");
+        fs::create_dir_all(installs.join("x.y"))?;
+        symlink(&bindir, installs.join("x.y/bin"))?;
+        // Other plugins and broken installations are ignored.
+        fs::create_dir_all(data.path().join("installs/python/3.12/bin"))?;
+        fs::create_dir_all(installs.join("broken/bin"))?;
+        let strategy = RuntimesFromAsdf::Custom(data.path().to_owned());
+        let runtimes: Vec<_> = strategy.runtimes().collect();
+        assert_eq!(1, runtimes.len());
+        assert_eq!(installs.join("x.y/bin"), runtimes[0].bindir);
+        Ok(())
+    }
+}
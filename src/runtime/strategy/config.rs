@@ -0,0 +1,284 @@
+//! Find runtimes listed in a configuration file.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::Deserialize;
+
+use super::{probe, Runtimes, Strategy};
+use crate::runtime::{Runtime, RuntimeError};
+use crate::version;
+
+/// The contents of a runtimes configuration file.
+///
+/// For example:
+///
+/// ```toml
+/// # The runtime to use when there are no version constraints.
+/// default = "pg15"
+///
+/// # Runtimes, in order of preference.
+/// [[runtime]]
+/// name = "pg15"
+/// bindir = "/usr/lib/postgresql/15/bin"
+///
+/// [[runtime]]
+/// name = "pg14"
+/// bindir = "../postgres/14/bin"  # Relative to this file.
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The name of the runtime to use when there are no version constraints.
+    pub default: Option<String>,
+    /// Named runtimes, in order of preference.
+    #[serde(default, rename = "runtime")]
+    pub runtimes: Vec<ConfigRuntime>,
+}
+
+/// A named runtime in a [`Config`].
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigRuntime {
+    pub name: String,
+    /// Path to the directory containing `pg_ctl`. A relative path is relative
+    /// to the directory containing the configuration file.
+    pub bindir: PathBuf,
+}
+
+impl Config {
+    /// Load configuration from the given file. Relative paths to runtimes are
+    /// resolved relative to the directory containing the file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RuntimeError> {
+        let path = path.as_ref();
+        let mut config: Self = toml::from_str(&fs::read_to_string(path)?)?;
+        if let Some(dir) = path.parent() {
+            for runtime in &mut config.runtimes {
+                runtime.bindir = dir.join(&runtime.bindir);
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Find runtimes listed in a configuration file.
+///
+/// This lets a team standardise on the PostgreSQL installations that should be
+/// used, either per-project or per-user. See [`Config`] for the file format.
+///
+/// Runtimes are preferred in the order in which they're listed. When selecting
+/// a runtime for a given version, the first compatible runtime is chosen, even
+/// if there is a later runtime with a higher version number. The fallback
+/// runtime is the one named by `default`, else the first runtime listed.
+///
+/// Runtimes whose version cannot be determined are skipped. A configuration
+/// file that does not exist or cannot be parsed yields no runtimes at all. A
+/// warning is logged, once per file, when it cannot be loaded; use
+/// [`RuntimesFromConfig::config`] to see why.
+#[derive(Clone, Debug)]
+pub enum RuntimesFromConfig {
+    /// Read the given configuration file.
+    Custom(PathBuf),
+    /// Read the first of:
+    /// - `.postgresfixture/runtimes.toml` in the current directory or any of
+    ///   its ancestors;
+    /// - `postgresfixture/runtimes.toml` in `$XDG_CONFIG_HOME`, else in
+    ///   `~/.config`.
+    Default,
+}
+
+impl RuntimesFromConfig {
+    /// The configuration file this strategy reads, if there is one.
+    pub fn path(&self) -> Option<PathBuf> {
+        match self {
+            RuntimesFromConfig::Custom(path) => Some(path.clone()),
+            RuntimesFromConfig::Default => Self::find_in_project().or_else(Self::find_in_user),
+        }
+    }
+
+    fn find_in_project() -> Option<PathBuf> {
+        env::current_dir().ok().and_then(|cwd| {
+            cwd.ancestors()
+                .map(|dir| dir.join(".postgresfixture").join("runtimes.toml"))
+                .find(|path| path.is_file())
+        })
+    }
+
+    fn find_in_user() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("postgresfixture").join("runtimes.toml"))
+            .filter(|path| path.is_file())
+    }
+
+    /// Load the configuration for this strategy.
+    ///
+    /// When there is no configuration file, this returns an empty [`Config`].
+    /// A file that is named explicitly with [`RuntimesFromConfig::Custom`] must
+    /// exist, however.
+    pub fn config(&self) -> Result<Config, RuntimeError> {
+        match (self, self.path()) {
+            (RuntimesFromConfig::Custom(_), Some(path)) => Config::load(path),
+            (RuntimesFromConfig::Default, Some(path)) => match Config::load(path) {
+                Err(RuntimeError::IoError(err)) if err.kind() == io::ErrorKind::NotFound => {
+                    Ok(Config::default())
+                }
+                result => result,
+            },
+            (_, None) => Ok(Config::default()),
+        }
+    }
+
+    /// Load the configuration for this strategy, or log a warning and return
+    /// [`None`] if that fails. Each file is warned about only once.
+    fn config_or_warn(&self) -> Option<Config> {
+        lazy_static! {
+            static ref WARNED: Mutex<HashSet<Option<PathBuf>>> = Mutex::default();
+        }
+        match self.config() {
+            Ok(config) => Some(config),
+            Err(err) => {
+                let path = self.path();
+                if WARNED
+                    .lock()
+                    .is_ok_and(|mut warned| warned.insert(path.clone()))
+                {
+                    tracing::warn!(?path, %err, "could not load runtimes configuration; ignoring it");
+                }
+                None
+            }
+        }
+    }
+
+    /// Find the runtime with the given name.
+    pub fn named(&self, name: &str) -> Option<Runtime> {
+        self.config_or_warn()?
+            .runtimes
+            .into_iter()
+            .find(|runtime| runtime.name == name)
+            .and_then(|runtime| Runtime::new(runtime.bindir).ok())
+    }
+}
+
+impl Strategy for RuntimesFromConfig {
+    fn runtimes(&self) -> Runtimes<'_> {
+        let bindirs = self
+            .config_or_warn()
+            .map(|config| config.runtimes)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|runtime| runtime.bindir)
+            .collect();
+        Box::new(probe(bindirs).into_iter())
+    }
+
+    /// The first compatible runtime, in order of preference.
+    fn select(&self, version: &version::PartialVersion) -> Option<Runtime> {
+        self.runtimes()
+            .find(|runtime| version.compatible(runtime.version))
+    }
+
+    /// The first runtime matching the given constraint, in order of
+    /// preference.
+    fn select_matching(&self, constraint: &version::VersionConstraint) -> Option<Runtime> {
+        self.runtimes()
+            .find(|runtime| constraint.matches(runtime.version))
+    }
+
+    /// The runtime named by `default`, else the first runtime.
+    fn fallback(&self) -> Option<Runtime> {
+        match self.config_or_warn()?.default {
+            Some(name) => self.named(&name),
+            None => self.runtimes().next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{Config, ConfigRuntime, RuntimesFromConfig, Strategy};
+    use crate::runtime::strategy::find_bindir;
+    use crate::runtime::RuntimeError;
+
+    type TestResult = Result<(), RuntimeError>;
+
+    #[test]
+    fn config_load_resolves_relative_paths() -> TestResult {
+        let tempdir = tempdir::TempDir::new("config")?;
+        let path = tempdir.path().join("runtimes.toml");
+        fs::write(
+            &path,
+            "default = \"b\"\n\
+             [[runtime]]\nname = \"a\"\nbindir = \"/abs/bin\"\n\
+             [[runtime]]\nname = \"b\"\nbindir = \"rel/bin\"\n",
+        )?;
+        let config = Config::load(&path)?;
+        assert_eq!(
+            config,
+            Config {
+                default: Some("b".into()),
+                runtimes: vec![
+                    ConfigRuntime { name: "a".into(), bindir: "/abs/bin".into() },
+                    ConfigRuntime { name: "b".into(), bindir: tempdir.path().join("rel/bin") },
+                ],
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn config_load_rejects_unknown_fields() -> TestResult {
+        let tempdir = tempdir::TempDir::new("config")?;
+        let path = tempdir.path().join("runtimes.toml");
+        fs::write(&path, "defualt = \"a\"\n")?;
+        assert!(matches!(
+            Config::load(&path),
+            Err(RuntimeError::ConfigError(_))
+        ));
+        // The strategy warns, then ignores the file.
+        let strategy = RuntimesFromConfig::Custom(path);
+        assert_eq!(0, strategy.runtimes().count());
+        assert_eq!(None, strategy.fallback());
+        Ok(())
+    }
+
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtimes_from_config() -> TestResult {
+        let bindir = find_bindir();
+        let tempdir = tempdir::TempDir::new("config")?;
+        let path = tempdir.path().join("runtimes.toml");
+        fs::write(
+            &path,
+            format!(
+                "default = \"good\"\n\
+                 [[runtime]]\nname = \"bad\"\nbindir = \"does/not/exist\"\n\
+                 [[runtime]]\nname = \"good\"\nbindir = {:?}\n",
+                bindir.to_str().unwrap(),
+            ),
+        )?;
+        let strategy = RuntimesFromConfig::Custom(path);
+        let runtimes: Vec<_> = strategy.runtimes().collect();
+        assert_eq!(1, runtimes.len());
+        assert_eq!(bindir, runtimes[0].bindir);
+        assert_eq!(Some(&runtimes[0]), strategy.fallback().as_ref());
+        assert_eq!(None, strategy.named("bad"));
+        Ok(())
+    }
+
+    #[test]
+    fn runtimes_from_missing_config() {
+        let strategy = RuntimesFromConfig::Custom("/does/not/exist.toml".into());
+        assert!(matches!(strategy.config(), Err(RuntimeError::IoError(_))));
+        assert_eq!(0, strategy.runtimes().count());
+        assert_eq!(None, strategy.fallback());
+    }
+}
@@ -0,0 +1,75 @@
+//! Find runtimes installed by [pgenv](https://github.com/theory/pgenv).
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use super::{find_bindirs, probe, Runtimes, Strategy};
+
+/// Find runtimes installed by [pgenv](https://github.com/theory/pgenv).
+///
+/// pgenv builds each version of PostgreSQL into its own `pgsql-*` directory,
+/// e.g. `~/.pgenv/pgsql-15.4/bin`.
+#[derive(Clone, Debug)]
+pub enum RuntimesFromPgenv {
+    /// Find runtimes installed under the given pgenv root directory.
+    Custom(PathBuf),
+    /// Find runtimes installed under `PGENV_ROOT` (environment variable), else
+    /// under `~/.pgenv`.
+    Env,
+}
+
+impl RuntimesFromPgenv {
+    /// The pgenv root directory, if it can be determined.
+    pub fn root(&self) -> Option<PathBuf> {
+        match self {
+            RuntimesFromPgenv::Custom(root) => Some(root.clone()),
+            RuntimesFromPgenv::Env => env::var_os("PGENV_ROOT")
+                .filter(|root| !root.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".pgenv"))),
+        }
+    }
+
+    /// Find the `bin` directories of runtimes installed by pgenv.
+    pub fn find(&self) -> Vec<PathBuf> {
+        match self.root() {
+            Some(root) => find_bindirs(&format!(
+                "{}/pgsql-*/bin/pg_ctl",
+                glob::Pattern::escape(&root.to_string_lossy())
+            )),
+            None => vec![],
+        }
+    }
+}
+
+impl Strategy for RuntimesFromPgenv {
+    fn runtimes(&self) -> Runtimes<'_> {
+        Box::new(probe(self.find()).into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    use super::{RuntimesFromPgenv, Strategy};
+    use crate::runtime::strategy::find_bindir;
+
+    /// This will fail if there are no PostgreSQL runtimes installed.
+    #[test]
+    fn runtimes_from_pgenv() -> std::io::Result<()> {
+        let bindir = find_bindir();
+        let root = tempdir::TempDir::new("pgenv")?;
+        fs::create_dir(root.path().join("pgsql-x.y"))?;
+        symlink(&bindir, root.path().join("pgsql-x.y/bin"))?;
+        // Things that are not pgenv installations are ignored.
+        fs::create_dir_all(root.path().join("src/bin"))?;
+        fs::create_dir_all(root.path().join("pgsql-empty/bin"))?;
+        let strategy = RuntimesFromPgenv::Custom(root.path().to_owned());
+        let runtimes: Vec<_> = strategy.runtimes().collect();
+        assert_eq!(1, runtimes.len());
+        assert_eq!(root.path().join("pgsql-x.y/bin"), runtimes[0].bindir);
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
-use std::env;
 use std::ffi::OsString;
 use std::path::Path;
+use std::{env, fs, io};
 
 type PrependedPath = Result<OsString, env::JoinPathsError>;
 
@@ -19,6 +19,89 @@ pub fn prepend_to_path(dir: &Path, path: Option<OsString>) -> PrependedPath {
     })
 }
 
+/// Percent-encode the given bytes for use in a URI, e.g. in a path segment or
+/// query parameter. Only unreserved characters (RFC 3986) are left as-is.
+pub fn percent_encode(bytes: &[u8]) -> String {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut encoded = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push('%');
+            encoded.push(HEX[usize::from(byte >> 4)] as char);
+            encoded.push(HEX[usize::from(byte & 0xF)] as char);
+        }
+    }
+    encoded
+}
+
+/// Quote the given name as an SQL identifier, e.g. for a database name in a
+/// `CREATE DATABASE` statement.
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Quote the given string as an SQL literal, e.g. for a setting's name in a
+/// query. This assumes `standard_conforming_strings`, the default since
+/// PostgreSQL 9.1.
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Copy the directory `from` and everything in it to `to`, which must not yet
+/// exist. Symbolic links are copied as links; they are not followed.
+pub fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir(to)?;
+    fs::set_permissions(to, fs::metadata(from)?.permissions())?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let (from, to) = (entry.path(), to.join(entry.file_name()));
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir(&from, &to)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(&from)?, &to)?;
+        } else {
+            fs::copy(&from, &to)?;
+        }
+    }
+    Ok(())
+}
+
+/// The total size, in bytes, of the files in the directory `dir` and its
+/// subdirectories. Symbolic links are not followed. Files that disappear while
+/// this is running, e.g. because a server is using the directory, are skipped.
+pub fn dir_size(dir: &Path) -> io::Result<u64> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut size = 0;
+    for entry in entries {
+        let entry = entry?;
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => size += dir_size(&entry.path())?,
+            Ok(metadata) => size += metadata.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(size)
+}
+
+/// A name for a temporary file or directory, e.g. `.install.1234.0`, that is
+/// unique among callers in this process and in other processes: it includes
+/// the process ID and a counter. This is for things that are written and then
+/// renamed into place.
+pub fn temp_name(prefix: &str) -> String {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{prefix}.{}.{count}", std::process::id())
+}
+
 #[cfg(test)]
 mod tests {
     use std::env;
@@ -68,4 +151,58 @@ mod tests {
         assert_eq!(expected, observed);
         Ok(())
     }
+
+    #[test]
+    fn test_percent_encode_leaves_unreserved_characters_alone() {
+        assert_eq!("aZ09-._~", super::percent_encode(b"aZ09-._~"));
+        assert_eq!(
+            "%2Ftmp%2Fa%20b%3F%26%C3%A9",
+            super::percent_encode("/tmp/a b?&é".as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_quote_identifier_doubles_quotes() {
+        assert_eq!(r#""foo""#, super::quote_identifier("foo"));
+        assert_eq!(r#""a ""b"" c""#, super::quote_identifier(r#"a "b" c"#));
+    }
+
+    #[test]
+    fn test_quote_literal_doubles_quotes() {
+        assert_eq!("'foo'", super::quote_literal("foo"));
+        assert_eq!("'a ''b'' c'", super::quote_literal("a 'b' c"));
+    }
+
+    #[test]
+    fn test_copy_dir_copies_files_directories_and_links() -> TestResult {
+        let tempdir = tempdir::TempDir::new("copy")?;
+        let from = tempdir.path().join("from");
+        std::fs::create_dir_all(from.join("a/b"))?;
+        std::fs::write(from.join("a/b/file"), "content")?;
+        std::os::unix::fs::symlink("a/b/file", from.join("link"))?;
+        let to = tempdir.path().join("to");
+        super::copy_dir(&from, &to)?;
+        assert_eq!("content", std::fs::read_to_string(to.join("a/b/file"))?);
+        assert_eq!(
+            std::path::Path::new("a/b/file"),
+            std::fs::read_link(to.join("link"))?
+        );
+        // The destination must not exist.
+        assert!(super::copy_dir(&from, &to).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_size_adds_up_files_in_subdirectories() -> TestResult {
+        let tempdir = tempdir::TempDir::new("size")?;
+        std::fs::create_dir_all(tempdir.path().join("a/b"))?;
+        std::fs::write(tempdir.path().join("a/file"), "12345")?;
+        std::fs::write(tempdir.path().join("a/b/file"), "123")?;
+        std::os::unix::fs::symlink("a/file", tempdir.path().join("link"))?;
+        let link_size = std::fs::symlink_metadata(tempdir.path().join("link"))?.len();
+        assert_eq!(8 + link_size, super::dir_size(tempdir.path())?);
+        // A directory that does not exist is empty.
+        assert_eq!(0, super::dir_size(&tempdir.path().join("nowhere"))?);
+        Ok(())
+    }
 }
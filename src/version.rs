@@ -11,10 +11,16 @@
 //!
 //! [versioning]: https://www.postgresql.org/support/versioning/
 
+mod constraint;
 mod current;
+mod eol;
 mod error;
 mod partial;
+mod set;
 
-pub use current::Version;
+pub use constraint::{Comparator, Op, VersionConstraint};
+pub use current::{Prerelease, Version};
+pub use eol::Date;
 pub use error::VersionError;
-pub use partial::PartialVersion;
+pub use partial::{OrderedPartialVersion, PartialVersion};
+pub use set::VersionSet;
@@ -0,0 +1,267 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::{PartialVersion, Version, VersionError};
+
+/// A comparison operator in a [`VersionConstraint`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// `=`, or no operator at all: the version must match in every part that
+    /// is given, e.g. `=15` matches 15.0 and 15.4, but `=15.4` matches only
+    /// 15.4.
+    Eq,
+    /// `>`: the version must be greater in the parts that are given, e.g. `>15`
+    /// matches 16.0 but not 15.4.
+    Gt,
+    /// `>=`: the version must be greater or equal in the parts that are given,
+    /// e.g. `>=15` matches 15.0 and 16.0.
+    Ge,
+    /// `<`: the version must be less in the parts that are given, e.g. `<16`
+    /// matches 15.4 but not 16.0.
+    Lt,
+    /// `<=`: the version must be less or equal in the parts that are given,
+    /// e.g. `<=15` matches 15.4 but not 16.0.
+    Le,
+    /// `~`: the version must be [compatible][`PartialVersion::compatible`],
+    /// e.g. `~15` matches any 15.x, and `~15.4` matches 15.4 and 15.5 but not
+    /// 15.3.
+    Tilde,
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Tilde => "~",
+        }
+    }
+}
+
+/// One part of a [`VersionConstraint`], e.g. `>=13`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Comparator {
+    pub op: Op,
+    pub version: PartialVersion,
+}
+
+impl Comparator {
+    /// Does the given [`Version`] satisfy this comparator?
+    pub fn matches(&self, version: Version) -> bool {
+        let ordering = PartialVersion::from(version).partial_cmp(&self.version);
+        match self.op {
+            Op::Eq => ordering.is_some_and(std::cmp::Ordering::is_eq),
+            Op::Gt => ordering.is_some_and(std::cmp::Ordering::is_gt),
+            Op::Ge => ordering.is_some_and(std::cmp::Ordering::is_ge),
+            Op::Lt => ordering.is_some_and(std::cmp::Ordering::is_lt),
+            Op::Le => ordering.is_some_and(std::cmp::Ordering::is_le),
+            Op::Tilde => self.version.compatible(version),
+        }
+    }
+}
+
+impl fmt::Display for Comparator {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}{}", self.op.symbol(), self.version)
+    }
+}
+
+impl FromStr for Comparator {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (op, rest) = [
+            // Two-character operators must come first.
+            (">=", Op::Ge),
+            ("<=", Op::Le),
+            (">", Op::Gt),
+            ("<", Op::Lt),
+            ("=", Op::Eq),
+            ("~", Op::Tilde),
+        ]
+        .into_iter()
+        .find_map(|(symbol, op)| s.strip_prefix(symbol).map(|rest| (op, rest.trim_start())))
+        .unwrap_or((Op::Eq, s));
+        if rest.is_empty() {
+            Err(VersionError::Missing)
+        } else if rest.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            Ok(Self { op, version: rest.parse()? })
+        } else {
+            Err(VersionError::BadlyFormed)
+        }
+    }
+}
+
+/// A set of requirements on PostgreSQL versions, e.g. `>=13, <16`.
+///
+/// This is parsed from a comma-separated list of comparators, each of which is
+/// an optional operator followed by a [`PartialVersion`]. A version matches the
+/// constraint when it satisfies every comparator. See [`Op`] for the operators
+/// that are supported.
+///
+/// ```rust
+/// # use postgresfixture::version::{Version, VersionConstraint};
+/// let constraint: VersionConstraint = ">=13, <16".parse()?;
+/// assert!(constraint.matches(Version::Post10(15, 4)));
+/// assert!(!constraint.matches(Version::Post10(16, 0)));
+/// let constraint: VersionConstraint = "~15".parse()?;
+/// assert!(constraint.matches(Version::Post10(15, 4)));
+/// let constraint: VersionConstraint = "15.4".parse()?;
+/// assert!(!constraint.matches(Version::Post10(15, 5)));
+/// # Ok::<(), postgresfixture::version::VersionError>(())
+/// ```
+///
+/// An empty constraint, or `*`, matches any version.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VersionConstraint(Vec<Comparator>);
+
+impl VersionConstraint {
+    /// Does the given [`Version`] satisfy this constraint?
+    pub fn matches(&self, version: Version) -> bool {
+        self.0.iter().all(|comparator| comparator.matches(version))
+    }
+
+    /// The comparators that make up this constraint.
+    pub fn comparators(&self) -> &[Comparator] {
+        &self.0
+    }
+}
+
+/// A constraint that matches versions [compatible][`PartialVersion::compatible`]
+/// with the given [`PartialVersion`].
+impl From<PartialVersion> for VersionConstraint {
+    fn from(version: PartialVersion) -> Self {
+        Self(vec![Comparator { op: Op::Tilde, version }])
+    }
+}
+
+impl FromIterator<Comparator> for VersionConstraint {
+    fn from_iter<T: IntoIterator<Item = Comparator>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl fmt::Display for VersionConstraint {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return fmt.pad("*");
+        }
+        let comparators: Vec<_> = self.0.iter().map(ToString::to_string).collect();
+        fmt.pad(&comparators.join(", "))
+    }
+}
+
+impl FromStr for VersionConstraint {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" | "*" => Ok(Self::default()),
+            s => s.split(',').map(str::parse).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{PartialVersion::*, Version::*, VersionError::*};
+    use super::{Comparator, Op, VersionConstraint};
+
+    #[test]
+    fn parses_comparators() {
+        let parse = |s: &str| s.parse::<Comparator>();
+        assert_eq!(
+            Ok(Comparator { op: Op::Eq, version: Post10mm(15, 4) }),
+            parse("15.4")
+        );
+        assert_eq!(
+            Ok(Comparator { op: Op::Eq, version: Post10m(15) }),
+            parse("= 15")
+        );
+        assert_eq!(
+            Ok(Comparator { op: Op::Ge, version: Post10m(13) }),
+            parse(">=13")
+        );
+        assert_eq!(
+            Ok(Comparator { op: Op::Gt, version: Pre10m(9, 6) }),
+            parse(">9.6")
+        );
+        assert_eq!(
+            Ok(Comparator { op: Op::Le, version: Post10m(16) }),
+            parse("<=16")
+        );
+        assert_eq!(
+            Ok(Comparator { op: Op::Lt, version: Post10m(16) }),
+            parse(" < 16 ")
+        );
+        assert_eq!(
+            Ok(Comparator { op: Op::Tilde, version: Post10m(15) }),
+            parse("~15")
+        );
+    }
+
+    #[test]
+    fn parse_returns_error_when_comparator_is_invalid() {
+        assert_eq!(Err(Missing), ">=".parse::<Comparator>());
+        assert_eq!(Err(BadlyFormed), "=>15".parse::<Comparator>());
+        assert_eq!(Err(BadlyFormed), "15 or 16".parse::<Comparator>());
+        assert_eq!(Err(BadlyFormed), "9".parse::<Comparator>());
+        assert_eq!(Err(Missing), ">=13,".parse::<VersionConstraint>());
+    }
+
+    #[test]
+    fn displays_constraint() {
+        let constraint: VersionConstraint = " >= 13 ,<16,9.6".parse().unwrap();
+        assert_eq!(">=13, <16, =9.6", constraint.to_string());
+        assert_eq!("*", VersionConstraint::default().to_string());
+        assert_eq!(
+            "~12.2",
+            VersionConstraint::from(Post10mm(12, 2)).to_string()
+        );
+    }
+
+    #[test]
+    fn empty_constraint_matches_everything() {
+        for constraint in ["", "*", " * "] {
+            let constraint: VersionConstraint = constraint.parse().unwrap();
+            assert!(constraint.matches(Pre10(9, 6, 17)));
+            assert!(constraint.matches(Post10(16, 1)));
+        }
+    }
+
+    #[test]
+    fn range_matches() {
+        let constraint: VersionConstraint = ">=13, <16".parse().unwrap();
+        assert!(!constraint.matches(Post10(12, 9)));
+        assert!(constraint.matches(Post10(13, 0)));
+        assert!(constraint.matches(Post10(15, 4)));
+        assert!(!constraint.matches(Post10(16, 0)));
+        assert!(!constraint.matches(Pre10(9, 6, 17)));
+    }
+
+    #[test]
+    fn partial_comparisons_consider_only_given_parts() {
+        let matches = |c: &str, v| c.parse::<VersionConstraint>().unwrap().matches(v);
+        assert!(matches("<=15", Post10(15, 4)));
+        assert!(!matches(">15", Post10(15, 4)));
+        assert!(matches("=15", Post10(15, 4)));
+        assert!(!matches("15.4", Post10(15, 5)));
+        assert!(matches("9.6", Pre10(9, 6, 17)));
+        assert!(!matches("9.6.16", Pre10(9, 6, 17)));
+    }
+
+    #[test]
+    fn tilde_matches_compatible_versions() {
+        let matches = |c: &str, v| c.parse::<VersionConstraint>().unwrap().matches(v);
+        assert!(matches("~15", Post10(15, 0)));
+        assert!(matches("~15", Post10(15, 4)));
+        assert!(!matches("~15", Post10(16, 0)));
+        assert!(matches("~15.4", Post10(15, 5)));
+        assert!(!matches("~15.4", Post10(15, 3)));
+        assert!(matches("~9.6.16", Pre10(9, 6, 17)));
+    }
+}
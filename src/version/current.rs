@@ -15,6 +15,7 @@
 // 12.7, 90624 for 9.6.24. See https://pgpedia.info/s/server_version_num.html
 // and https://www.postgresql.org/docs/16/runtime-config-preset.html.
 
+use std::cmp::Ordering;
 use std::fmt;
 use std::str::FromStr;
 
@@ -29,7 +30,7 @@ use super::VersionError;
 /// page][versioning] is what this models.
 ///
 /// [versioning]: https://www.postgresql.org/support/versioning/
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Version {
     /// Pre-PostgreSQL 10, with major, point, and minor version numbers, e.g.
     /// 9.6.17. It is an error to create this variant with a major number >= 10.
@@ -37,6 +38,76 @@ pub enum Version {
     /// PostgreSQL 10+, with major and minor version number, e.g. 10.3. It is an
     /// error to create this variant with a major number < 10.
     Post10(u32, u32),
+    /// A prerelease of PostgreSQL 10+, with major version number, e.g. 17beta1
+    /// or 18devel. This sorts before all releases with the same major number.
+    /// It is an error to create this variant with a major number < 10.
+    /// Prereleases before PostgreSQL 10 are not supported.
+    Post10Pre(u32, Prerelease),
+}
+
+/// The stage of a prerelease, e.g. the `beta1` in 17beta1.
+///
+/// Stages are ordered: `devel` < `alpha` < `beta` < `rc`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Prerelease {
+    /// A development snapshot, e.g. 18devel.
+    Devel,
+    /// An alpha release, e.g. 17alpha1.
+    Alpha(u32),
+    /// A beta release, e.g. 17beta1.
+    Beta(u32),
+    /// A release candidate, e.g. 17rc1.
+    Rc(u32),
+}
+
+impl Version {
+    /// The major version number, e.g. 9 for 9.6.17, or 15 for 15.4.
+    pub fn major(&self) -> u32 {
+        match *self {
+            Version::Pre10(a, ..) | Version::Post10(a, ..) | Version::Post10Pre(a, ..) => a,
+        }
+    }
+
+    /// The point version number, e.g. 6 for 9.6.17. Only versions of
+    /// PostgreSQL before 10 have one.
+    pub fn point(&self) -> Option<u32> {
+        match *self {
+            Version::Pre10(_, b, _) => Some(b),
+            Version::Post10(..) | Version::Post10Pre(..) => None,
+        }
+    }
+
+    /// The minor version number, e.g. 17 for 9.6.17, or 4 for 15.4.
+    /// Prereleases do not have one.
+    pub fn minor(&self) -> Option<u32> {
+        match *self {
+            Version::Pre10(_, _, c) => Some(c),
+            Version::Post10(_, b) => Some(b),
+            Version::Post10Pre(..) => None,
+        }
+    }
+
+    /// Provide a key by which versions are ordered. A prerelease sorts after
+    /// all earlier major versions and before all releases of its own.
+    fn sort_key(&self) -> (u32, u32, bool, Option<Prerelease>, u32) {
+        match *self {
+            Version::Pre10(a, b, c) => (a, b, true, None, c),
+            Version::Post10(a, b) => (a, 0, true, None, b),
+            Version::Post10Pre(a, pre) => (a, 0, false, Some(pre), 0),
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl fmt::Display for Version {
@@ -44,6 +115,18 @@ impl fmt::Display for Version {
         match self {
             Version::Pre10(a, b, c) => fmt.pad(&format!("{a}.{b}.{c}")),
             Version::Post10(a, b) => fmt.pad(&format!("{a}.{b}")),
+            Version::Post10Pre(a, pre) => fmt.pad(&format!("{a}{pre}")),
+        }
+    }
+}
+
+impl fmt::Display for Prerelease {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Prerelease::Devel => fmt.pad("devel"),
+            Prerelease::Alpha(n) => fmt.pad(&format!("alpha{n}")),
+            Prerelease::Beta(n) => fmt.pad(&format!("beta{n}")),
+            Prerelease::Rc(n) => fmt.pad(&format!("rc{n}")),
         }
     }
 }
@@ -53,29 +136,50 @@ impl FromStr for Version {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"(?x) \b (\d+) [.] (\d+) (?: [.] (\d+) )? \b")
-                .expect("invalid regex (for matching PostgreSQL versions)");
+            static ref RE: Regex = Regex::new(
+                r"(?x) \b (\d+) (?:
+                    [.] (\d+) (?: [.] (\d+) )? |
+                    (devel) | (alpha|beta|rc) (\d+)
+                ) \b"
+            )
+            .expect("invalid regex (for matching PostgreSQL versions)");
         }
         match RE.captures(s) {
             Some(caps) => {
                 let a = caps[1].parse::<u32>()?;
-                let b = caps[2].parse::<u32>()?;
-                match caps.get(3) {
-                    Some(m) => {
-                        let c = m.as_str().parse::<u32>()?;
-                        if a >= 10 {
-                            Err(VersionError::BadlyFormed)
-                        } else {
-                            Ok(Version::Pre10(a, b, c))
+                if let Some(b) = caps.get(2) {
+                    let b = b.as_str().parse::<u32>()?;
+                    match caps.get(3) {
+                        Some(m) => {
+                            let c = m.as_str().parse::<u32>()?;
+                            if a >= 10 {
+                                Err(VersionError::BadlyFormed)
+                            } else {
+                                Ok(Version::Pre10(a, b, c))
+                            }
                         }
-                    }
-                    None => {
-                        if a < 10 {
-                            Err(VersionError::BadlyFormed)
-                        } else {
-                            Ok(Version::Post10(a, b))
+                        None => {
+                            if a < 10 {
+                                Err(VersionError::BadlyFormed)
+                            } else {
+                                Ok(Version::Post10(a, b))
+                            }
                         }
                     }
+                } else if a < 10 {
+                    Err(VersionError::BadlyFormed)
+                } else if caps.get(4).is_some() {
+                    Ok(Version::Post10Pre(a, Prerelease::Devel))
+                } else {
+                    let n = caps[6].parse::<u32>()?;
+                    Ok(Version::Post10Pre(
+                        a,
+                        match &caps[5] {
+                            "alpha" => Prerelease::Alpha(n),
+                            "beta" => Prerelease::Beta(n),
+                            _ => Prerelease::Rc(n),
+                        },
+                    ))
                 }
             }
             None => Err(VersionError::Missing),
@@ -85,8 +189,8 @@ impl FromStr for Version {
 
 #[cfg(test)]
 mod tests {
-    use super::Version::{Post10, Pre10};
-    use super::{Version, VersionError::*};
+    use super::Version::{Post10, Post10Pre, Pre10};
+    use super::{Prerelease, Version, VersionError::*};
 
     use std::cmp::Ordering;
 
@@ -151,4 +255,62 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn parses_prerelease_versions() {
+        assert_eq!(Ok(Post10Pre(17, Prerelease::Beta(1))), "17beta1".parse());
+        assert_eq!(Ok(Post10Pre(18, Prerelease::Devel)), "18devel".parse());
+        assert_eq!(
+            Ok(Post10Pre(16, Prerelease::Rc(2))),
+            "pg_ctl (PostgreSQL) 16rc2".parse()
+        );
+        assert_eq!(Ok(Post10Pre(15, Prerelease::Alpha(3))), "15alpha3".parse());
+        // Prereleases before 10 are not supported.
+        assert_eq!(Err(BadlyFormed), "9beta1".parse::<Version>());
+        // A prerelease stage must be numbered, except for devel.
+        assert_eq!(Err(Missing), "17beta".parse::<Version>());
+    }
+
+    #[test]
+    fn displays_prerelease_versions() {
+        for version in ["17beta1", "18devel", "16rc2", "15alpha3"] {
+            assert_eq!(version, version.parse::<Version>().unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn prereleases_sort_before_releases() {
+        let mut versions = vec![
+            Post10(17, 0),
+            Post10Pre(17, Prerelease::Rc(1)),
+            Post10Pre(17, Prerelease::Beta(2)),
+            Post10(16, 4),
+            Post10Pre(17, Prerelease::Beta(1)),
+            Post10Pre(18, Prerelease::Devel),
+            Post10Pre(17, Prerelease::Devel),
+            Pre10(9, 6, 24),
+        ];
+        versions.sort();
+        assert_eq!(
+            versions,
+            vec![
+                Pre10(9, 6, 24),
+                Post10(16, 4),
+                Post10Pre(17, Prerelease::Devel),
+                Post10Pre(17, Prerelease::Beta(1)),
+                Post10Pre(17, Prerelease::Beta(2)),
+                Post10Pre(17, Prerelease::Rc(1)),
+                Post10(17, 0),
+                Post10Pre(18, Prerelease::Devel),
+            ]
+        );
+    }
+
+    #[test]
+    fn accessors() {
+        let parts = |v: Version| (v.major(), v.point(), v.minor());
+        assert_eq!((9, Some(6), Some(17)), parts(Pre10(9, 6, 17)));
+        assert_eq!((15, None, Some(4)), parts(Post10(15, 4)));
+        assert_eq!((17, None, None), parts(Post10Pre(17, Prerelease::Beta(1))));
+    }
 }
@@ -0,0 +1,180 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::Version;
+
+/// A calendar date, used to say when support for a PostgreSQL version ends.
+///
+/// This is deliberately minimal: it only needs to be ordered, displayed, and
+/// obtained for today (in UTC).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    /// A date from its year, month (1-12), and day of the month (1-31).
+    pub const fn new(year: u32, month: u32, day: u32) -> Self {
+        Self { year, month, day }
+    }
+
+    /// Today's date in UTC, according to the system clock.
+    pub fn today() -> Self {
+        SystemTime::now().into()
+    }
+
+    /// Convert a count of days since 1970-01-01 into a date. This is Howard
+    /// Hinnant's `civil_from_days` algorithm, restricted to dates after the
+    /// epoch.
+    #[allow(clippy::cast_possible_truncation)]
+    fn from_days_since_epoch(days: u64) -> Self {
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + u64::from(month <= 2);
+        Self::new(year as u32, month as u32, day as u32)
+    }
+}
+
+/// The date in UTC at the given time. Times before the epoch are treated as
+/// the epoch.
+impl From<SystemTime> for Date {
+    fn from(time: SystemTime) -> Self {
+        let days = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() / 86400);
+        Self::from_days_since_epoch(days)
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.pad(&format!(
+            "{:04}-{:02}-{:02}",
+            self.year, self.month, self.day
+        ))
+    }
+}
+
+/// The final day of support for each major version of PostgreSQL, from the
+/// [PostgreSQL "Versioning Policy" page][versioning]. Older versions come
+/// first. Versions before 10 are keyed by their major and point numbers.
+///
+/// [versioning]: https://www.postgresql.org/support/versioning/
+const SUPPORTED_UNTIL: &[((u32, u32), Date)] = &[
+    ((9, 0), Date::new(2015, 10, 8)),
+    ((9, 1), Date::new(2016, 10, 27)),
+    ((9, 2), Date::new(2017, 11, 9)),
+    ((9, 3), Date::new(2018, 11, 8)),
+    ((9, 4), Date::new(2020, 2, 13)),
+    ((9, 5), Date::new(2021, 2, 11)),
+    ((9, 6), Date::new(2021, 11, 11)),
+    ((10, 0), Date::new(2022, 11, 10)),
+    ((11, 0), Date::new(2023, 11, 9)),
+    ((12, 0), Date::new(2024, 11, 21)),
+    ((13, 0), Date::new(2025, 11, 13)),
+    ((14, 0), Date::new(2026, 11, 12)),
+    ((15, 0), Date::new(2027, 11, 11)),
+    ((16, 0), Date::new(2028, 11, 9)),
+    ((17, 0), Date::new(2029, 11, 8)),
+    ((18, 0), Date::new(2030, 11, 14)),
+];
+
+impl Version {
+    /// The major version, keyed as in [`SUPPORTED_UNTIL`].
+    fn release_series(&self) -> (u32, u32) {
+        (self.major(), self.point().unwrap_or(0))
+    }
+
+    /// The last day on which this version's release series is supported
+    /// upstream, according to the support matrix embedded in this crate.
+    ///
+    /// Returns [`None`] when the release series is not known, e.g. it's newer
+    /// than this crate, or it's older than PostgreSQL 9.0.
+    pub fn supported_until(&self) -> Option<Date> {
+        let series = self.release_series();
+        SUPPORTED_UNTIL
+            .iter()
+            .find(|(s, _)| *s == series)
+            .map(|(_, date)| *date)
+    }
+
+    /// Is this version's release series end-of-life as of the given date?
+    ///
+    /// Release series older than those in the embedded support matrix are
+    /// considered end-of-life; newer ones are not.
+    ///
+    /// ```rust
+    /// # use postgresfixture::version::{Date, Version};
+    /// assert!(Version::Pre10(9, 6, 24).is_eol(Date::new(2022, 1, 1)));
+    /// assert!(!Version::Post10(16, 4).is_eol(Date::new(2025, 1, 1)));
+    /// ```
+    pub fn is_eol(&self, as_of: Date) -> bool {
+        match self.supported_until() {
+            Some(until) => as_of > until,
+            None => SUPPORTED_UNTIL
+                .first()
+                .is_some_and(|(oldest, _)| self.release_series() < *oldest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Prerelease, Version::*};
+    use super::{Date, SUPPORTED_UNTIL};
+
+    #[test]
+    fn support_matrix_is_ordered() {
+        assert!(SUPPORTED_UNTIL.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn supported_until() {
+        assert_eq!(
+            Some(Date::new(2021, 11, 11)),
+            Pre10(9, 6, 24).supported_until()
+        );
+        assert_eq!(
+            Some(Date::new(2027, 11, 11)),
+            Post10(15, 4).supported_until()
+        );
+        assert_eq!(
+            Some(Date::new(2029, 11, 8)),
+            Post10Pre(17, Prerelease::Beta(1)).supported_until()
+        );
+        assert_eq!(None, Pre10(8, 4, 22).supported_until());
+        assert_eq!(None, Post10(99, 0).supported_until());
+    }
+
+    #[test]
+    fn is_eol() {
+        let as_of = Date::new(2025, 11, 13);
+        assert!(Pre10(8, 4, 22).is_eol(as_of));
+        assert!(Post10(12, 22).is_eol(as_of));
+        // Support ends at the end of the given day.
+        assert!(!Post10(13, 22).is_eol(as_of));
+        assert!(Post10(13, 22).is_eol(Date::new(2025, 11, 14)));
+        assert!(!Post10(99, 0).is_eol(as_of));
+    }
+
+    #[test]
+    fn date_from_days_since_epoch() {
+        assert_eq!(Date::new(1970, 1, 1), Date::from_days_since_epoch(0));
+        assert_eq!(Date::new(2000, 2, 29), Date::from_days_since_epoch(11_016));
+        assert_eq!(Date::new(2024, 12, 31), Date::from_days_since_epoch(20_088));
+        assert_eq!(
+            "2024-12-31",
+            Date::from_days_since_epoch(20_088).to_string()
+        );
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(20_088 * 86400 + 86399);
+        assert_eq!(Date::new(2024, 12, 31), Date::from(time));
+    }
+}
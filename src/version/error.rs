@@ -1,27 +1,15 @@
-use std::{error, fmt, num};
+use std::num;
 
 /// Error parsing a PostgreSQL version number.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[non_exhaustive]
 pub enum VersionError {
+    #[error("badly formed")]
     BadlyFormed,
+    #[error("not found")]
     Missing,
 }
 
-impl fmt::Display for VersionError {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            VersionError::BadlyFormed => write!(fmt, "badly formed"),
-            VersionError::Missing => write!(fmt, "not found"),
-        }
-    }
-}
-
-impl error::Error for VersionError {
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        None
-    }
-}
-
 impl From<num::ParseIntError> for VersionError {
     fn from(_error: num::ParseIntError) -> VersionError {
         VersionError::BadlyFormed
@@ -66,6 +66,8 @@ impl From<&Version> for PartialVersion {
         match *version {
             Pre10(a, b, c) => PartialVersion::Pre10mm(a, b, c),
             Post10(a, b) => PartialVersion::Post10mm(a, b),
+            // A prerelease has no minor number.
+            Post10Pre(a, _) => PartialVersion::Post10m(a),
         }
     }
 }
@@ -114,19 +116,49 @@ impl PartialVersion {
     /// given version's major number must match exactly, and the minor number
     /// must be greater than or equal to this `PartialVersion`'s minor number.
     /// When this `PartialVersion` has no minor number, the given version is
-    /// assumed to be compatible.
+    /// assumed to be compatible; this includes prereleases, e.g. 17beta1 is
+    /// compatible with 17, but not with 17.0.
     #[allow(dead_code)]
     pub fn compatible(&self, version: Version) -> bool {
         use PartialVersion::*;
         match (*self, version) {
             (Pre10m(a, b), Version::Pre10(x, y, _)) => a == x && b == y,
             (Pre10mm(a, b, c), Version::Pre10(x, y, z)) => a == x && b == y && c <= z,
-            (Post10m(a), Version::Post10(x, _)) => a == x,
+            (Post10m(a), Version::Post10(x, _) | Version::Post10Pre(x, _)) => a == x,
             (Post10mm(a, b), Version::Post10(x, y)) => a == x && b <= y,
             _ => false,
         }
     }
 
+    /// The major version number, e.g. 9 for 9.6, or 15 for 15.
+    pub fn major(&self) -> u32 {
+        use PartialVersion::*;
+        match *self {
+            Pre10m(a, ..) | Pre10mm(a, ..) | Post10m(a) | Post10mm(a, ..) => a,
+        }
+    }
+
+    /// The point version number, e.g. 6 for 9.6. Only versions of PostgreSQL
+    /// before 10 have one.
+    pub fn point(&self) -> Option<u32> {
+        use PartialVersion::*;
+        match *self {
+            Pre10m(_, b) | Pre10mm(_, b, _) => Some(b),
+            Post10m(_) | Post10mm(..) => None,
+        }
+    }
+
+    /// The minor version number, if present, e.g. 17 for 9.6.17, or 4 for
+    /// 15.4.
+    pub fn minor(&self) -> Option<u32> {
+        use PartialVersion::*;
+        match *self {
+            Pre10mm(_, _, c) => Some(c),
+            Post10mm(_, b) => Some(b),
+            Pre10m(..) | Post10m(_) => None,
+        }
+    }
+
     /// Remove minor number.
     #[must_use]
     pub fn widened(&self) -> PartialVersion {
@@ -143,8 +175,8 @@ impl PartialVersion {
     /// `PartialVersion` does not implement [`Eq`] or [`Ord`] because they would
     /// disagree with its [`PartialEq`] and [`PartialOrd`] implementations, so
     /// this function provides a sort key that implements [`Ord`] and can be
-    /// used with sorting functions, e.g. [`slice::sort_by_key`].
-    #[allow(dead_code)]
+    /// used with sorting functions, e.g. [`slice::sort_by_key`]. See
+    /// [`OrderedPartialVersion`] for a wrapper that orders by this key.
     pub fn sort_key(&self) -> (u32, Option<u32>, Option<u32>) {
         use PartialVersion::*;
         match *self {
@@ -189,6 +221,73 @@ impl PartialOrd for PartialVersion {
     }
 }
 
+/// A [`PartialVersion`] that implements [`Eq`], [`Ord`], and [`Hash`], so it
+/// can be sorted, or used as a key in a [`BTreeMap`][std::collections::BTreeMap]
+/// or [`HashSet`][std::collections::HashSet].
+///
+/// This orders by [`PartialVersion::sort_key`]. It agrees with the ordering of
+/// `PartialVersion` except where that finds versions equal because a part is
+/// missing from one of them; here the less specific version sorts first, e.g.
+/// 9.6 < 9.6.0 < 9.6.1, and 15 < 15.0 < 15.1. Only versions with exactly the
+/// same parts are equal.
+///
+/// ```rust
+/// # use postgresfixture::version::{OrderedPartialVersion, PartialVersion};
+/// let mut versions: Vec<OrderedPartialVersion> = ["15.1", "9.6", "15", "9.6.0"]
+///     .iter()
+///     .map(|v| v.parse::<PartialVersion>().unwrap().into())
+///     .collect();
+/// versions.sort();
+/// let versions: Vec<String> = versions.iter().map(ToString::to_string).collect();
+/// assert_eq!(versions, ["9.6", "9.6.0", "15", "15.1"]);
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct OrderedPartialVersion(pub PartialVersion);
+
+impl From<PartialVersion> for OrderedPartialVersion {
+    fn from(version: PartialVersion) -> Self {
+        Self(version)
+    }
+}
+
+impl From<OrderedPartialVersion> for PartialVersion {
+    fn from(version: OrderedPartialVersion) -> Self {
+        version.0
+    }
+}
+
+impl PartialEq for OrderedPartialVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.sort_key() == other.0.sort_key()
+    }
+}
+
+impl Eq for OrderedPartialVersion {}
+
+impl PartialOrd for OrderedPartialVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedPartialVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.sort_key().cmp(&other.0.sort_key())
+    }
+}
+
+impl std::hash::Hash for OrderedPartialVersion {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.sort_key().hash(state);
+    }
+}
+
+impl fmt::Display for OrderedPartialVersion {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(fmt)
+    }
+}
+
 impl fmt::Display for PartialVersion {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -229,7 +328,7 @@ impl FromStr for PartialVersion {
 #[cfg(test)]
 mod tests {
     use super::super::{Version, VersionError::*};
-    use super::{PartialVersion, PartialVersion::*};
+    use super::{OrderedPartialVersion, PartialVersion, PartialVersion::*};
 
     use rand::seq::SliceRandom;
     use rand::thread_rng;
@@ -412,4 +511,29 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn accessors() {
+        let parts = |v: PartialVersion| (v.major(), v.point(), v.minor());
+        assert_eq!((9, Some(6), None), parts(Pre10m(9, 6)));
+        assert_eq!((9, Some(6), Some(17)), parts(Pre10mm(9, 6, 17)));
+        assert_eq!((15, None, None), parts(Post10m(15)));
+        assert_eq!((15, None, Some(4)), parts(Post10mm(15, 4)));
+    }
+
+    #[test]
+    fn ordered_partial_version_is_total() {
+        let ordered = |v: PartialVersion| OrderedPartialVersion(v);
+        // Unlike PartialVersion, a missing part breaks the tie.
+        assert_eq!(Post10m(15), Post10mm(15, 0));
+        assert!(ordered(Post10m(15)) < ordered(Post10mm(15, 0)));
+        assert!(ordered(Pre10m(9, 6)) < ordered(Pre10mm(9, 6, 0)));
+        assert!(ordered(Pre10mm(9, 6, 24)) < ordered(Post10m(10)));
+        assert_eq!(ordered(Post10mm(15, 4)), ordered(Post10mm(15, 4)));
+        let set: std::collections::BTreeSet<_> = [Post10m(15), Post10mm(15, 0), Post10m(15)]
+            .into_iter()
+            .map(OrderedPartialVersion)
+            .collect();
+        assert_eq!(2, set.len());
+    }
 }
@@ -0,0 +1,332 @@
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+
+use super::{Version, VersionConstraint, VersionError};
+
+/// A set of PostgreSQL versions, built from [`VersionConstraint`]s with union,
+/// intersection, and difference.
+///
+/// This is useful for describing the versions an application supports when a
+/// single range is not enough, e.g. to accept PostgreSQL 13 through 16 except
+/// for some point releases with a known bug.
+///
+/// It is parsed from [`VersionConstraint`]s joined with operators. From lowest
+/// to highest precedence these are:
+///
+/// - `||`: union, i.e. versions in either set.
+/// - `except`: difference, i.e. versions in the left set but not in the right.
+/// - `&&`: intersection, i.e. versions in both sets. Note that commas within a
+///   [`VersionConstraint`] already intersect its comparators.
+///
+/// Parentheses can be used for grouping.
+///
+/// ```rust
+/// # use postgresfixture::version::{Version, VersionSet};
+/// let set: VersionSet = ">=13, <=16 except >=14.0, <=14.2".parse()?;
+/// assert!(set.matches(Version::Post10(13, 1)));
+/// assert!(!set.matches(Version::Post10(14, 1)));
+/// assert!(set.matches(Version::Post10(14, 3)));
+/// let set: VersionSet = "~9.6 || (>=14 except 15.0)".parse()?;
+/// assert!(set.matches(Version::Pre10(9, 6, 24)));
+/// assert!(!set.matches(Version::Post10(15, 0)));
+/// assert!(set.matches(Version::Post10(16, 2)));
+/// # Ok::<(), postgresfixture::version::VersionError>(())
+/// ```
+///
+/// An empty set expression, or `*`, matches any version.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VersionSet {
+    /// Versions matching a constraint.
+    Matching(VersionConstraint),
+    /// Versions in any of the given sets. An empty union matches nothing.
+    Union(Vec<VersionSet>),
+    /// Versions in all of the given sets. An empty intersection matches
+    /// everything.
+    Intersection(Vec<VersionSet>),
+    /// Versions in the first set but not in the second.
+    Difference(Box<VersionSet>, Box<VersionSet>),
+}
+
+impl Default for VersionSet {
+    /// The set of all versions.
+    fn default() -> Self {
+        Self::Matching(VersionConstraint::default())
+    }
+}
+
+impl VersionSet {
+    /// Is the given [`Version`] in this set?
+    pub fn matches(&self, version: Version) -> bool {
+        match self {
+            Self::Matching(constraint) => constraint.matches(version),
+            Self::Union(sets) => sets.iter().any(|set| set.matches(version)),
+            Self::Intersection(sets) => sets.iter().all(|set| set.matches(version)),
+            Self::Difference(set, except) => set.matches(version) && !except.matches(version),
+        }
+    }
+
+    /// Versions in this set or in `other`.
+    #[must_use]
+    pub fn union(self, other: VersionSet) -> Self {
+        match self {
+            Self::Union(mut sets) => {
+                sets.push(other);
+                Self::Union(sets)
+            }
+            set => Self::Union(vec![set, other]),
+        }
+    }
+
+    /// Versions in both this set and `other`.
+    #[must_use]
+    pub fn intersection(self, other: VersionSet) -> Self {
+        match self {
+            Self::Intersection(mut sets) => {
+                sets.push(other);
+                Self::Intersection(sets)
+            }
+            set => Self::Intersection(vec![set, other]),
+        }
+    }
+
+    /// Versions in this set but not in `other`.
+    #[must_use]
+    pub fn difference(self, other: VersionSet) -> Self {
+        Self::Difference(Box::new(self), Box::new(other))
+    }
+
+    /// The constraint this set is equivalent to, if it is a single
+    /// [`VersionConstraint`].
+    pub fn as_constraint(&self) -> Option<&VersionConstraint> {
+        match self {
+            Self::Matching(constraint) => Some(constraint),
+            _ => None,
+        }
+    }
+}
+
+impl From<VersionConstraint> for VersionSet {
+    fn from(constraint: VersionConstraint) -> Self {
+        Self::Matching(constraint)
+    }
+}
+
+impl fmt::Display for VersionSet {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        /// Parenthesise anything that's not a plain constraint.
+        fn operand(set: &VersionSet) -> String {
+            match set {
+                VersionSet::Matching(constraint) => constraint.to_string(),
+                set => format!("({set})"),
+            }
+        }
+        let join = |sets: &[VersionSet], op| {
+            let sets: Vec<_> = sets.iter().map(operand).collect();
+            sets.join(op)
+        };
+        match self {
+            Self::Matching(constraint) => fmt.pad(&constraint.to_string()),
+            // An empty union is not expressible with the operators above, but
+            // it's also not useful, so it's displayed as an impossible version.
+            Self::Union(sets) if sets.is_empty() => fmt.pad("* except *"),
+            Self::Union(sets) => fmt.pad(&join(sets, " || ")),
+            Self::Intersection(sets) if sets.is_empty() => fmt.pad("*"),
+            Self::Intersection(sets) => fmt.pad(&join(sets, " && ")),
+            Self::Difference(set, except) => {
+                fmt.pad(&format!("{} except {}", operand(set), operand(except)))
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Open,
+    Close,
+    Or,
+    And,
+    Except,
+    Constraint(&'a str),
+}
+
+fn tokenize(s: &str) -> Vec<Token<'_>> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"\(|\)|\|\||&&|\bexcept\b")
+            .expect("invalid regex (for matching version set operators)");
+    }
+    fn constraint<'a>(tokens: &mut Vec<Token<'a>>, text: &'a str) {
+        if !text.trim().is_empty() {
+            tokens.push(Token::Constraint(text));
+        }
+    }
+    let mut tokens = Vec::new();
+    let mut last = 0;
+    for m in RE.find_iter(s) {
+        constraint(&mut tokens, &s[last..m.start()]);
+        tokens.push(match m.as_str() {
+            "(" => Token::Open,
+            ")" => Token::Close,
+            "||" => Token::Or,
+            "&&" => Token::And,
+            _ => Token::Except,
+        });
+        last = m.end();
+    }
+    constraint(&mut tokens, &s[last..]);
+    tokens
+}
+
+/// A recursive-descent parser for version set expressions.
+struct Parser<'a> {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token<'a>>>,
+}
+
+impl Parser<'_> {
+    fn union(&mut self) -> Result<VersionSet, VersionError> {
+        let mut set = self.difference()?;
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            set = set.union(self.difference()?);
+        }
+        Ok(set)
+    }
+
+    fn difference(&mut self) -> Result<VersionSet, VersionError> {
+        let mut set = self.intersection()?;
+        while self.tokens.next_if_eq(&Token::Except).is_some() {
+            set = set.difference(self.intersection()?);
+        }
+        Ok(set)
+    }
+
+    fn intersection(&mut self) -> Result<VersionSet, VersionError> {
+        let mut set = self.operand()?;
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            set = set.intersection(self.operand()?);
+        }
+        Ok(set)
+    }
+
+    fn operand(&mut self) -> Result<VersionSet, VersionError> {
+        match self.tokens.next() {
+            Some(Token::Open) => {
+                let set = self.union()?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(set),
+                    _ => Err(VersionError::BadlyFormed),
+                }
+            }
+            Some(Token::Constraint(constraint)) => Ok(VersionSet::Matching(constraint.parse()?)),
+            _ => Err(VersionError::BadlyFormed),
+        }
+    }
+}
+
+impl FromStr for VersionSet {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s);
+        if tokens.is_empty() {
+            return Ok(Self::default());
+        }
+        let mut parser = Parser { tokens: tokens.into_iter().peekable() };
+        let set = parser.union()?;
+        match parser.tokens.next() {
+            None => Ok(set),
+            Some(_) => Err(VersionError::BadlyFormed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Version::*, VersionConstraint, VersionError::*};
+    use super::VersionSet;
+
+    #[test]
+    fn parses_and_matches_sets() {
+        let set: VersionSet = ">=13, <=16 except >=14.0, <=14.2".parse().unwrap();
+        assert!(!set.matches(Post10(12, 9)));
+        assert!(set.matches(Post10(13, 0)));
+        assert!(!set.matches(Post10(14, 0)));
+        assert!(!set.matches(Post10(14, 2)));
+        assert!(set.matches(Post10(14, 3)));
+        assert!(set.matches(Post10(16, 4)));
+        assert!(!set.matches(Post10(17, 0)));
+    }
+
+    #[test]
+    fn precedence_and_grouping() {
+        // Difference binds more tightly than union...
+        let set: VersionSet = "12 || 13 except 13.1".parse().unwrap();
+        assert!(set.matches(Post10(12, 1)));
+        assert!(!set.matches(Post10(13, 1)));
+        // ... unless grouped.
+        let set: VersionSet = "(12 || 13) except 12.1".parse().unwrap();
+        assert!(!set.matches(Post10(12, 1)));
+        assert!(set.matches(Post10(13, 1)));
+        // Intersection binds more tightly than difference.
+        let set: VersionSet = ">=12 except 13 && <=13.2".parse().unwrap();
+        assert!(set.matches(Post10(13, 3)));
+        assert!(!set.matches(Post10(13, 2)));
+    }
+
+    #[test]
+    fn empty_set_expression_matches_everything() {
+        for s in ["", " ", "*"] {
+            let set: VersionSet = s.parse().unwrap();
+            assert_eq!(VersionSet::default(), set);
+            assert!(set.matches(Pre10(9, 6, 24)));
+        }
+    }
+
+    #[test]
+    fn parse_returns_error_when_set_is_invalid() {
+        for s in [
+            "13 ||",
+            "|| 13",
+            "(13",
+            "13)",
+            "13 except",
+            "()",
+            "13 && && 14",
+        ] {
+            assert_eq!(Err(BadlyFormed), s.parse::<VersionSet>(), "{s:?}");
+        }
+        assert_eq!(Err(Missing), "13 || >=".parse::<VersionSet>());
+    }
+
+    #[test]
+    fn set_operations() {
+        let constraint = |s: &str| VersionSet::from(s.parse::<VersionConstraint>().unwrap());
+        let set = constraint("13")
+            .union(constraint("15"))
+            .intersection(constraint(">=13.2"))
+            .difference(constraint("15.0"));
+        assert!(!set.matches(Post10(13, 1)));
+        assert!(set.matches(Post10(13, 2)));
+        assert!(!set.matches(Post10(14, 2)));
+        assert!(!set.matches(Post10(15, 0)));
+        assert!(set.matches(Post10(15, 1)));
+        assert!(!VersionSet::Union(vec![]).matches(Post10(15, 1)));
+        assert!(VersionSet::Intersection(vec![]).matches(Post10(15, 1)));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in [
+            "=15",
+            "=12 || =13",
+            ">=13, <=16 except >=14.0, <=14.2",
+            "(=12 || =13) except =12.1",
+            "(>=12 except =13) && <=15",
+            "* except *",
+        ] {
+            let set: VersionSet = s.parse().unwrap();
+            assert_eq!(s, set.to_string());
+            assert_eq!(Ok(set.clone()), set.to_string().parse());
+        }
+    }
+}